@@ -0,0 +1,102 @@
+//! QEMU settings for the host architecture, which guests share.
+
+#[cfg(target_arch = "x86_64")]
+pub const QEMU_RUNNER: &str = "qemu-system-x86_64";
+#[cfg(target_arch = "mips")]
+pub const QEMU_RUNNER: &str = "qemu-system-mips";
+#[cfg(target_arch = "powerpc")]
+pub const QEMU_RUNNER: &str = "qemu-system-ppc";
+#[cfg(target_arch = "powerpc64")]
+pub const QEMU_RUNNER: &str = "qemu-system-ppc64";
+#[cfg(target_arch = "arm")]
+pub const QEMU_RUNNER: &str = "qemu-system-arm";
+#[cfg(target_arch = "aarch64")]
+pub const QEMU_RUNNER: &str = "qemu-system-aarch64";
+#[cfg(target_arch = "riscv64")]
+pub const QEMU_RUNNER: &str = "qemu-system-riscv64";
+#[cfg(target_arch = "s390x")]
+pub const QEMU_RUNNER: &str = "qemu-system-s390x";
+
+#[cfg(target_arch = "x86_64")]
+pub const MACHINE: &str = "q35";
+#[cfg(any(
+    target_arch = "mips",
+    target_arch = "powerpc",
+    target_arch = "powerpc64",
+    target_arch = "arm",
+    target_arch = "aarch64"
+))]
+pub const MACHINE: &str = "virt,highmem=on";
+#[cfg(target_arch = "riscv64")]
+pub const MACHINE: &str = "virt";
+#[cfg(target_arch = "s390x")]
+pub const MACHINE: &str = "s390-ccw-virtio";
+
+#[cfg(target_os = "macos")]
+pub const ACCEL: &str = "hvf";
+#[cfg(not(target_os = "macos"))]
+pub const ACCEL: &str = "kvm";
+
+/// How virtio devices attach: channel I/O on s390x, PCI everywhere else.
+#[cfg(not(target_arch = "s390x"))]
+const VIRTIO_TRANSPORT: &str = "pci";
+#[cfg(target_arch = "s390x")]
+const VIRTIO_TRANSPORT: &str = "ccw";
+
+/// The boot disk sits on virtio-mmio where the machine type provides it.
+#[cfg(any(target_arch = "arm", target_arch = "aarch64", target_arch = "riscv64"))]
+pub const BLOCK_DEVICE: &str = "virtio-blk-device";
+#[cfg(not(any(target_arch = "arm", target_arch = "aarch64", target_arch = "riscv64")))]
+pub const BLOCK_DEVICE: &str = "virtio-blk-pci";
+
+/// Architecture names gdb knows guests by.
+#[cfg(target_arch = "x86_64")]
+pub const GDB_ARCH: &str = "i386:x86-64";
+#[cfg(target_arch = "mips")]
+pub const GDB_ARCH: &str = "mips";
+#[cfg(target_arch = "powerpc")]
+pub const GDB_ARCH: &str = "powerpc:common";
+#[cfg(target_arch = "powerpc64")]
+pub const GDB_ARCH: &str = "powerpc:common64";
+#[cfg(target_arch = "arm")]
+pub const GDB_ARCH: &str = "arm";
+#[cfg(target_arch = "aarch64")]
+pub const GDB_ARCH: &str = "aarch64";
+#[cfg(target_arch = "riscv64")]
+pub const GDB_ARCH: &str = "riscv:rv64";
+#[cfg(target_arch = "s390x")]
+pub const GDB_ARCH: &str = "s390:64-bit";
+
+/// s390x machines have no USB controller to attach input devices to.
+pub const HAS_USB: bool = cfg!(not(target_arch = "s390x"));
+
+/// Only x86 guests can boot from QEMU's SeaBIOS instead of UEFI firmware.
+pub const HAS_BIOS: bool = cfg!(target_arch = "x86_64");
+
+/// Name of a virtio device on the transport of this architecture, e.g. `virtio-net-pci`.
+pub fn virtio(device: &str) -> String {
+    format!("virtio-{device}-{VIRTIO_TRANSPORT}")
+}
+
+/// Whether guests need firmware from `RHEA_UEFI_PATH` to boot.
+pub const NEEDS_FIRMWARE: bool = cfg!(not(any(target_arch = "riscv64", target_arch = "s390x")));
+
+/// Firmware arguments. UEFI blobs load as the BIOS; riscv64 boots OpenSBI and hands over to
+/// an optional payload such as U-Boot, and s390x uses the firmware built into QEMU.
+pub fn firmware_args(firmware: Option<&str>) -> Vec<String> {
+    let Some(firmware) = firmware else {
+        return if cfg!(target_arch = "riscv64") {
+            vec!["-bios".into(), "default".into()]
+        } else {
+            Vec::new()
+        };
+    };
+    let firmware = firmware.to_string();
+    if cfg!(target_arch = "riscv64") {
+        vec!["-bios".into(), "default".into(), "-kernel".into(), firmware]
+    } else if cfg!(target_arch = "s390x") {
+        Vec::new()
+    } else {
+        vec!["-bios".into(), firmware]
+    }
+}
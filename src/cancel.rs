@@ -0,0 +1,44 @@
+use std::{
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Once,
+    },
+};
+
+/// What a process killed by Ctrl-C exits with, 128 plus SIGINT.
+const INTERRUPTED: i32 = 130;
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+static WATCHING: AtomicBool = AtomicBool::new(false);
+static HANDLER: Once = Once::new();
+
+/// Catches Ctrl-C while the returned guard lives, so a long-running operation can clean up
+/// instead of dying. Otherwise Ctrl-C exits as usual.
+pub fn watch() -> Watch {
+    HANDLER.call_once(|| {
+        let _ = ctrlc::set_handler(|| {
+            if WATCHING.load(Ordering::SeqCst) {
+                CANCELLED.store(true, Ordering::SeqCst);
+            } else {
+                process::exit(INTERRUPTED);
+            }
+        });
+    });
+    CANCELLED.store(false, Ordering::SeqCst);
+    WATCHING.store(true, Ordering::SeqCst);
+    Watch
+}
+
+pub fn cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+/// Catches Ctrl-C until dropped, see `watch`.
+pub struct Watch;
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        WATCHING.store(false, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,80 @@
+use crate::{error::Error, result::Result};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Where the expected SHA-256 digest of an image comes from.
+pub enum Checksum {
+    Sha256(String),
+    /// A `sha256sum`-style checksums file (e.g. `SHA256SUMS`) listing the image by file name.
+    File(PathBuf),
+}
+
+impl Checksum {
+    pub fn from_args(sha256: Option<String>, checksums: Option<PathBuf>) -> Option<Self> {
+        sha256.map(Self::Sha256).or(checksums.map(Self::File))
+    }
+
+    /// The digest the image at `path` is expected to have.
+    pub fn expected(&self, path: &Path) -> Result<String> {
+        match self {
+            Self::Sha256(digest) => Ok(digest.trim().to_ascii_lowercase()),
+            Self::File(checksums) => {
+                let file_name = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .ok_or(Error::InvalidPath { path: path.into() })?;
+                lookup(&fs::read_to_string(checksums)?, file_name)
+                    .ok_or(Error::MissingChecksum { path: path.into() })
+            }
+        }
+    }
+}
+
+/// Finds a file in either the GNU (`<digest>  <name>`, `*` marking binary mode) or BSD
+/// (`SHA256 (<name>) = <digest>`) checksums format.
+fn lookup(checksums: &str, file_name: &str) -> Option<String> {
+    checksums.lines().find_map(|line| {
+        let line = line.trim();
+        if let Some(rest) = line.strip_prefix("SHA256 (") {
+            let (name, digest) = rest.split_once(") = ")?;
+            return (name == file_name).then(|| digest.to_ascii_lowercase());
+        }
+        let (digest, name) = line.split_once(char::is_whitespace)?;
+        let name = name.trim_start().trim_start_matches('*');
+        let name = name.rsplit('/').next().unwrap_or(name);
+        (name == file_name).then(|| digest.to_ascii_lowercase())
+    })
+}
+
+/// Hashes a file, reporting the fraction read so far.
+pub fn sha256<P, F>(path: P, mut progress: F) -> Result<String>
+where
+    P: AsRef<Path>,
+    F: FnMut(f64),
+{
+    let mut file = File::open(&path)?;
+    let total = file.metadata()?.len().max(1);
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut read = 0;
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+        read += n as u64;
+        progress(read as f64 / total as f64);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
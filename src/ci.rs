@@ -0,0 +1,106 @@
+use crate::{
+    error::Error, image::ImageFormat, probe, provision::Step, result::Result, size::Size,
+    state::State,
+};
+use std::{
+    env, fs,
+    path::Path,
+    process,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+pub struct Workspace {
+    state: State,
+    machines: Vec<String>,
+}
+
+impl Workspace {
+    pub fn new() -> Result<Self> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos())
+            .unwrap_or_default();
+        let root = env::temp_dir().join(format!("rhea-ci-{}-{nanos}", process::id()));
+        Ok(Self {
+            state: State::load(root)?,
+            machines: Vec::new(),
+        })
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut State {
+        &mut self.state
+    }
+
+    pub fn root(&self) -> &Path {
+        self.state.path()
+    }
+
+    pub fn boot(
+        &mut self,
+        name: &str,
+        image: &Path,
+        format: ImageFormat,
+        cores: usize,
+        ram: Size,
+    ) -> Result<()> {
+        let port = probe::free_port().ok_or(Error::NoFreePort)?;
+        self.state.add_machine_overlay(name, image, format, port)?;
+        self.machines.push(name.into());
+        self.state.machine(name).cores(cores).ram(ram).spawn()?;
+        self.state.wait_ssh(name, false)?;
+        Ok(())
+    }
+
+    pub fn run(&self, name: &str, username: Option<String>, command: &[String]) -> Result<i32> {
+        Ok(self
+            .state
+            .exec(name, false, username, command)?
+            .code()
+            .unwrap_or(-1))
+    }
+
+    /// Runs a script or command, returning its exit code.
+    pub fn job(
+        &self,
+        name: &str,
+        username: Option<String>,
+        step: &Step,
+        timeout: Option<Duration>,
+    ) -> Result<i32> {
+        Ok(self
+            .state
+            .run_step(name, false, username, step, timeout)?
+            .code()
+            .unwrap_or(-1))
+    }
+
+    pub fn collect(
+        &self,
+        name: &str,
+        username: Option<String>,
+        artifacts: &[String],
+        output: &Path,
+    ) -> Result<()> {
+        fs::create_dir_all(output)?;
+        for artifact in artifacts {
+            self.state
+                .copy_from(name, false, username.clone(), artifact, output)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        for name in &self.machines {
+            if self.state.machine_in_use(name).unwrap_or(false) {
+                let _ = self.state.stop(name, false);
+            }
+        }
+        let _ = fs::remove_dir_all(self.root());
+    }
+}
@@ -1,11 +1,76 @@
-use clap::{Parser, Subcommand};
+use crate::tables::{OutputFormat, View};
+use clap::{ArgAction, Parser, Subcommand};
+use clap_complete::Shell;
+use rhea::{
+    firmware::FirmwareMode, foreign::Source, image::ImageFormat, input::InputDevice,
+    network::Network, os::GuestOs, profile, progress::ProgressFormat, remote::Remote,
+    restart::RestartPolicy, size::Size, start::BootDevice, time,
+};
+use std::io::{self, IsTerminal};
 use std::path::PathBuf;
+use std::time::Duration;
+use tracing_subscriber::filter::LevelFilter;
 
 #[derive(Parser)]
 #[command(author, version)]
 pub struct Args {
+    #[command(flatten)]
+    pub global: GlobalArgs,
+
     #[clap(subcommand)]
-    pub subcommand: Subcommands,
+    pub command: Command,
+}
+
+// Options that apply to every subcommand.
+#[derive(clap::Args)]
+pub struct GlobalArgs {
+    /// Report progress on stderr (none, bar, json) [default: bar if stderr is a terminal]
+    #[arg(long, global = true)]
+    pub progress: Option<ProgressFormat>,
+
+    /// Only report errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Log executed commands (-v), or everything (-vv)
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Log level (off, error, warn, info, debug, trace), overriding -q and -v
+    #[arg(long, global = true)]
+    pub log_level: Option<LevelFilter>,
+
+    /// Output format for listings
+    #[arg(short, long, global = true, value_enum, default_value_t = OutputFormat::Table)]
+    pub output: OutputFormat,
+
+    /// Sort listings by a column, descending if prefixed with - (e.g. size, -last-boot)
+    #[arg(long, global = true, allow_hyphen_values = true)]
+    pub sort: Option<String>,
+
+    /// Only show these columns in listings (e.g. name,port,status)
+    #[arg(long, global = true, value_delimiter = ',')]
+    pub columns: Vec<String>,
+
+    /// Don't ask for confirmation before destroying anything
+    #[arg(short, long, global = true)]
+    pub yes: bool,
+
+    /// Run QEMU on a remote host added with `remote add`
+    #[arg(long = "remote", value_name = "REMOTE", global = true)]
+    pub host: Option<String>,
+
+    /// Use the resources of another profile, kept in a state directory of its own
+    #[arg(long, global = true, default_value = profile::DEFAULT)]
+    pub profile: String,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    #[command(flatten)]
+    Stateful(Box<Subcommands>),
+    #[command(flatten)]
+    Standalone(StandaloneSubcommands),
 }
 
 #[derive(Subcommand)]
@@ -16,15 +81,55 @@ pub enum Subcommands {
         #[arg(value_parser)]
         name: String,
 
-        /// Size of the disk (GB)
+        /// Size of the disk (e.g. 512M, 64G, 1T)
+        #[arg(value_parser)]
+        size: Size,
+
+        /// What the disk is for
+        #[arg(long)]
+        description: Option<String>,
+    },
+    /// Import an existing image as a disk
+    ImportDisk {
+        /// Name of the disk
+        #[arg(value_parser)]
+        name: String,
+
+        /// Path to the image to import
         #[arg(value_parser)]
-        size: usize,
+        path: PathBuf,
+
+        /// Format of the image (qcow2, raw, vmdk, vdi)
+        #[arg(short, long, default_value_t = ImageFormat::Qcow2)]
+        format: ImageFormat,
+
+        /// Verify the image against this SHA-256 digest before using it
+        #[arg(long, conflicts_with = "checksums")]
+        sha256: Option<String>,
+
+        /// Verify the image against its entry in a checksums file (e.g. SHA256SUMS)
+        #[arg(long)]
+        checksums: Option<PathBuf>,
     },
     /// Remove a disk
     RemoveDisk {
+        /// Names of the disks, namespaces ending in / (e.g. project/) or patterns like 'ci-*'
+        #[arg(value_parser, required = true)]
+        names: Vec<String>,
+    },
+    /// Resize a disk
+    ResizeDisk {
         /// Name of the disk
         #[arg(value_parser)]
         name: String,
+
+        /// New size of the disk (e.g. 512M, 64G, 1T)
+        #[arg(value_parser)]
+        size: Size,
+
+        /// Allow shrinking the disk
+        #[arg(long, default_value_t = false)]
+        allow_shrink: bool,
     },
     /// Add a virtual machine
     AddMachine {
@@ -36,27 +141,396 @@ pub enum Subcommands {
         #[arg(value_parser)]
         iso: PathBuf,
 
-        /// Size of the virtual machine (GB)
-        #[arg(short, long, value_parser, default_value_t = 128)]
-        size: usize,
+        /// Size of the virtual machine (e.g. 512M, 64G, 1T) [default: 128G]
+        #[arg(short, long, value_parser)]
+        size: Option<Size>,
+
+        /// Number of allocated cores [default: 4]
+        #[arg(short, long, value_parser)]
+        cores: Option<usize>,
+
+        /// Amount of allocated RAM (e.g. 512M, 4G) [default: 4G]
+        #[arg(short, long, value_parser)]
+        ram: Option<Size>,
+
+        /// Port to assign the virtual machine
+        #[arg(short, long, value_parser, default_value_t = 8192)]
+        port: u16,
+
+        /// Stop the virtual machine after this long (e.g. 30m, 2h, 14d)
+        #[arg(long, value_parser = time::parse_duration)]
+        ttl: Option<Duration>,
+
+        /// Remove the virtual machine once its TTL expires
+        #[arg(long, default_value_t = false)]
+        remove_on_expiry: bool,
+
+        /// Take cores, RAM and size from a preset (see `rhea presets list`)
+        #[arg(long)]
+        preset: Option<String>,
+
+        /// Verify the image against this SHA-256 digest before using it
+        #[arg(long, conflicts_with = "checksums")]
+        sha256: Option<String>,
+
+        /// Verify the image against its entry in a checksums file (e.g. SHA256SUMS)
+        #[arg(long)]
+        checksums: Option<PathBuf>,
+
+        /// What the virtual machine is for
+        #[arg(long)]
+        description: Option<String>,
+
+        /// Script or command to run over SSH after the first boot (repeatable, run in order)
+        #[arg(long = "provision", value_name = "STEP")]
+        provision: Vec<String>,
+
+        /// Cut the guest off from the host and internet, except for forwarded ports
+        #[arg(long, default_value_t = false)]
+        isolated: bool,
+
+        /// Host an isolated guest may still connect to, at 10.0.2.100:<port> (repeatable)
+        #[arg(long, value_name = "HOST:PORT", requires = "isolated")]
+        allow: Vec<String>,
+
+        /// Boot from UEFI firmware or, for legacy x86 images, SeaBIOS (uefi, bios)
+        #[arg(long, default_value_t = FirmwareMode::Uefi)]
+        firmware: FirmwareMode,
+
+        /// What the guest runs, instead of guessing from the image (e.g. linux, windows, freebsd)
+        #[arg(long)]
+        os: Option<GuestOs>,
+    },
+    /// Import an existing image as a virtual machine
+    ImportMachine {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+
+        /// Path to the image to import
+        #[arg(value_parser)]
+        path: PathBuf,
+
+        /// Format of the image (qcow2, raw, vmdk, vdi)
+        #[arg(short, long, default_value_t = ImageFormat::Qcow2)]
+        format: ImageFormat,
+
+        /// Port to assign the virtual machine
+        #[arg(short, long, value_parser, default_value_t = 8192)]
+        port: u16,
+
+        /// Verify the image against this SHA-256 digest before using it
+        #[arg(long, conflicts_with = "checksums")]
+        sha256: Option<String>,
+
+        /// Verify the image against its entry in a checksums file (e.g. SHA256SUMS)
+        #[arg(long)]
+        checksums: Option<PathBuf>,
+
+        /// Boot from UEFI firmware or, for legacy x86 images, SeaBIOS (uefi, bios)
+        #[arg(long, default_value_t = FirmwareMode::Uefi)]
+        firmware: FirmwareMode,
+
+        /// What the guest runs, instead of guessing from the image (e.g. linux, windows, freebsd)
+        #[arg(long)]
+        os: Option<GuestOs>,
+    },
+    /// Recreate the image of a virtual machine, keeping its port and settings
+    Rebuild {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+
+        /// Image to install from, booting the virtual machine once rebuilt
+        #[arg(long, conflicts_with = "image")]
+        iso: Option<PathBuf>,
+
+        /// Disk image to rebuild from instead of a blank disk
+        #[arg(long)]
+        image: Option<PathBuf>,
+
+        /// Format of the disk image (qcow2, raw, vmdk, vdi)
+        #[arg(short, long, default_value_t = ImageFormat::Qcow2)]
+        format: ImageFormat,
+
+        /// Reset probes, timeouts and other settings to their defaults
+        #[arg(long, default_value_t = false)]
+        reset: bool,
 
         /// Number of allocated cores
         #[arg(short, long, value_parser, default_value_t = 4)]
         cores: usize,
 
-        /// Amount of allocated RAM (GB)
-        #[arg(short, long, value_parser, default_value_t = 4)]
-        ram: usize,
+        /// Amount of allocated RAM (e.g. 512M, 4G)
+        #[arg(short, long, value_parser, default_value_t = Size::gigabytes(4))]
+        ram: Size,
 
-        /// Port to assign the virtual machine
+        /// Verify the image against this SHA-256 digest before using it
+        #[arg(long, conflicts_with = "checksums")]
+        sha256: Option<String>,
+
+        /// Verify the image against its entry in a checksums file (e.g. SHA256SUMS)
+        #[arg(long)]
+        checksums: Option<PathBuf>,
+
+        /// Take a checkpoint snapshot first, to undo with `rhea rollback`
+        #[arg(long, default_value_t = false)]
+        checkpoint: bool,
+    },
+    /// Import virtual machines from another tool (utm, libvirt, multipass)
+    ImportFrom {
+        /// Tool to import from (utm, libvirt, multipass)
+        #[arg(value_parser)]
+        source: Source,
+
+        /// Only import the virtual machine with this name
+        #[arg(short, long)]
+        name: Option<String>,
+
+        /// First port to assign imported virtual machines
+        #[arg(short, long, value_parser, default_value_t = 8192)]
+        port: u16,
+    },
+    /// Import a Vagrant box or OVA appliance as a virtual machine
+    ImportBox {
+        /// Path to the .box or .ova file
+        #[arg(value_parser)]
+        path: PathBuf,
+
+        /// Name of the virtual machine (default: the file name without its extension)
+        #[arg(short, long)]
+        name: Option<String>,
+
+        /// First port to try assigning the virtual machine
         #[arg(short, long, value_parser, default_value_t = 8192)]
         port: u16,
     },
     /// Remove a virtual machine
     RemoveMachine {
+        /// Names of the virtual machines, namespaces ending in / (e.g. project/) or patterns like 'ci-*'
+        #[arg(value_parser, required = true)]
+        names: Vec<String>,
+    },
+    /// Resize a virtual machine
+    ResizeMachine {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+
+        /// New size of the virtual machine (e.g. 512M, 64G, 1T)
+        #[arg(value_parser)]
+        size: Size,
+
+        /// Allow shrinking the virtual machine
+        #[arg(long, default_value_t = false)]
+        allow_shrink: bool,
+
+        /// Take a checkpoint snapshot first, to undo with `rhea rollback`
+        #[arg(long, default_value_t = false)]
+        checkpoint: bool,
+    },
+    /// Restore a virtual machine to a checkpoint taken with --checkpoint
+    Rollback {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+
+        /// Checkpoint to restore (default: the latest)
+        #[arg(value_parser)]
+        checkpoint: Option<String>,
+    },
+    /// Change settings of a virtual machine
+    SetMachine {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+
+        /// Seconds to watch for early exits after a background start
+        #[arg(long, value_parser)]
+        start_grace: Option<u64>,
+
+        /// Seconds to wait for SSH to come up before connecting
+        #[arg(long, value_parser)]
+        ssh_wait: Option<u64>,
+
+        /// Seconds to wait for the virtual machine to shut down
+        #[arg(long, value_parser)]
+        shutdown_timeout: Option<u64>,
+
+        /// Seconds to wait for a QMP command to complete
+        #[arg(long, value_parser)]
+        qmp_timeout: Option<u64>,
+
+        /// Number of attempts for retried operations
+        #[arg(long, value_parser)]
+        retries: Option<u32>,
+
+        /// Seconds to wait between retried attempts
+        #[arg(long, value_parser)]
+        retry_delay: Option<u64>,
+
+        /// Additional network (user, shared, bridged:<iface>, tap:<iface>)
+        #[arg(long)]
+        network: Option<Network>,
+
+        /// Pointer device for graphical guests (tablet, mouse)
+        #[arg(long)]
+        input: Option<InputDevice>,
+
+        /// Boot from UEFI firmware or, for legacy x86 images, SeaBIOS (uefi, bios)
+        #[arg(long)]
+        firmware: Option<FirmwareMode>,
+
+        /// What the guest runs, instead of guessing from the image (e.g. linux, windows, freebsd)
+        #[arg(long)]
+        os: Option<GuestOs>,
+
+        /// When `rhea supervise` restarts the machine (never, on-crash, always)
+        #[arg(long)]
+        restart: Option<RestartPolicy>,
+
+        /// Cut the guest off from the host and internet, except for forwarded ports (true, false)
+        #[arg(long, value_name = "BOOL")]
+        isolated: Option<bool>,
+
+        /// Replace the hosts an isolated guest may connect to, at 10.0.2.100:<port>
+        #[arg(long, value_name = "HOST:PORT")]
+        allow: Vec<String>,
+
+        /// Show desktop notifications for the machine (true, false)
+        #[arg(long, value_name = "BOOL")]
+        notify_desktop: Option<bool>,
+
+        /// Post notifications for the machine to a webhook, such as a Slack incoming webhook
+        #[arg(long, value_name = "URL")]
+        notify_webhook: Option<String>,
+
+        /// Seconds an operation on the machine has to take for its completion to be announced
+        #[arg(long, value_name = "SECONDS")]
+        notify_after: Option<u64>,
+
+        /// Replace the provisioning steps: scripts or commands run over SSH after the first boot
+        #[arg(long = "provision", value_name = "STEP")]
+        provision: Vec<String>,
+
+        /// QEMU to run the machine with instead of the configured one, empty to use that again
+        #[arg(long, value_name = "PATH")]
+        qemu: Option<String>,
+
+        /// Command to run QEMU under, such as "nice -n 10", empty to use the configured one
+        #[arg(long, value_name = "COMMAND")]
+        qemu_wrapper: Option<String>,
+    },
+    /// Export a virtual machine to a flattened image or archive
+    Export {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+
+        /// Destination path (.qcow2 or .tar.zst)
+        #[arg(value_parser)]
+        destination: PathBuf,
+
+        /// Cores to record in the manifest
+        #[arg(short, long, value_parser, default_value_t = 4)]
+        cores: usize,
+
+        /// RAM to record in the manifest (e.g. 512M, 4G)
+        #[arg(short, long, value_parser, default_value_t = Size::gigabytes(4))]
+        ram: Size,
+
+        /// Compress the exported image
+        #[arg(long, default_value_t = false)]
+        compress: bool,
+
+        /// Export a snapshot instead of a virtual machine
+        #[arg(short, long, default_value_t = false)]
+        snapshot: bool,
+    },
+    /// Print a libvirt domain XML for a virtual machine
+    ExportLibvirt {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+
+        /// Cores to allocate for the domain
+        #[arg(short, long, value_parser, default_value_t = 4)]
+        cores: usize,
+
+        /// RAM to allocate for the domain (e.g. 512M, 4G)
+        #[arg(short, long, value_parser, default_value_t = Size::gigabytes(4))]
+        ram: Size,
+
+        /// Names of disks to attach to the domain
+        #[arg(short, long, value_delimiter = ',')]
+        disks: Vec<String>,
+
+        /// Export a snapshot instead of a virtual machine
+        #[arg(short, long, default_value_t = false)]
+        snapshot: bool,
+    },
+    /// Import a virtual machine exported with `rhea export`
+    Import {
+        /// Path to the exported image or archive
+        #[arg(value_parser)]
+        path: PathBuf,
+
+        /// Name of the virtual machine (default: name in the manifest)
+        #[arg(short, long)]
+        name: Option<String>,
+    },
+    /// Push a virtual machine to an OCI registry for others to pull
+    PushImage {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+
+        /// Where to push it (e.g. ghcr.io/team/golden:ubuntu-24.04)
+        #[arg(value_parser)]
+        reference: String,
+
+        /// Cores to record in the manifest
+        #[arg(short, long, value_parser, default_value_t = 4)]
+        cores: usize,
+
+        /// RAM to record in the manifest (e.g. 512M, 4G)
+        #[arg(short, long, value_parser, default_value_t = Size::gigabytes(4))]
+        ram: Size,
+
+        /// Push a snapshot instead of a virtual machine
+        #[arg(short, long, default_value_t = false)]
+        snapshot: bool,
+    },
+    /// Pull a virtual machine pushed with `push-image`
+    PullImage {
+        /// Where to pull it from (e.g. ghcr.io/team/golden:ubuntu-24.04)
+        #[arg(value_parser)]
+        reference: String,
+
+        /// Name of the virtual machine (default: name in the manifest)
+        #[arg(short, long)]
+        name: Option<String>,
+    },
+    /// Copy a stopped virtual machine to a remote host, sending only what changed since the last sync
+    Sync {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+
+        /// Name of the remote host
+        #[arg(value_parser)]
+        remote: String,
+    },
+    /// Copy a stopped virtual machine into another profile, e.g. to keep one made in a scratch
+    /// profile
+    Copy {
         /// Name of the virtual machine
         #[arg(value_parser)]
         name: String,
+
+        /// Profile to copy the virtual machine into
+        #[arg(long)]
+        to_profile: String,
     },
     /// Add a snapshot of a virtual machine
     AddSnapshot {
@@ -67,89 +541,1140 @@ pub enum Subcommands {
         /// Name of the base virtual machine
         #[arg(value_parser)]
         base: String,
+
+        /// Use a snapshot as the base instead of a virtual machine
+        #[arg(short, long, default_value_t = false)]
+        snapshot: bool,
+
+        /// Copy the whole disk instead of creating a linked snapshot
+        #[arg(long, default_value_t = false)]
+        full: bool,
+
+        /// What the snapshot is for
+        #[arg(long)]
+        description: Option<String>,
+
+        /// Port to assign the snapshot [default: the first free one after its base's]
+        #[arg(short, long, value_parser)]
+        port: Option<u16>,
+
+        /// Cores to start the snapshot with, unless `rhea start` is given some
+        #[arg(short, long, value_parser)]
+        cores: Option<usize>,
+
+        /// RAM to start the snapshot with (e.g. 512M, 4G), unless `rhea start` is given some
+        #[arg(short, long, value_parser)]
+        ram: Option<Size>,
     },
     /// Remove a snapshot
     RemoveSnapshot {
-        /// Name of the snapshot
+        /// Names of the snapshots, namespaces ending in / (e.g. project/) or patterns like 'ci-*'
+        #[arg(value_parser, required = true)]
+        names: Vec<String>,
+    },
+    /// Add a readiness probe to a virtual machine
+    AddProbe {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        machine: String,
+
+        /// Name of the probe
         #[arg(value_parser)]
         name: String,
+
+        /// Host port to forward to the probed guest port
+        #[arg(short, long, value_parser)]
+        port: u16,
+
+        /// Guest port to probe
+        #[arg(short, long, value_parser)]
+        guest_port: u16,
+
+        /// Probe for an HTTP 200 on this path instead of an open port
+        #[arg(long)]
+        http: Option<String>,
     },
-    /// Print information about a disk
-    Disk {
-        /// Name of the disk
+    /// Remove a readiness probe from a virtual machine
+    RemoveProbe {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        machine: String,
+
+        /// Name of the probe
         #[arg(value_parser)]
         name: String,
     },
+    /// Print information about a disk
+    Disk {
+        /// Names of the disks, namespaces ending in / or patterns like 'data-*'
+        #[arg(value_parser, required = true)]
+        names: Vec<String>,
+    },
     /// Print information about all disks
-    Disks,
+    Disks {
+        /// Only show disks in this namespace (e.g. project/) or matching a pattern
+        #[arg(value_parser)]
+        prefix: Option<String>,
+    },
     /// Print information about a virtual machine
     Machine {
         /// Name of the virtual machine
         #[arg(value_parser)]
         name: String,
+
+        /// How many of the most recent boots to list (tables only)
+        #[arg(long, value_name = "N", default_value_t = 5)]
+        boots: usize,
+    },
+    /// Set or print what a virtual machine, snapshot or disk is for
+    Describe {
+        /// Name of the virtual machine, snapshot or disk
+        #[arg(value_parser)]
+        name: String,
+
+        /// New description (an empty one clears it); prints the current one if omitted
+        #[arg(value_parser)]
+        description: Option<String>,
+
+        /// Describe a snapshot instead of a virtual machine
+        #[arg(short, long, default_value_t = false, conflicts_with = "disk")]
+        snapshot: bool,
+
+        /// Describe a disk instead of a virtual machine
+        #[arg(long, default_value_t = false)]
+        disk: bool,
     },
     /// Print information about all virtual machines
-    Machines,
+    Machines {
+        /// Only show virtual machines in this namespace (e.g. project/) or matching a pattern
+        #[arg(value_parser)]
+        prefix: Option<String>,
+
+        /// Only show machines with this metadata (KEY=VALUE, repeatable)
+        #[arg(long, value_parser = parse_meta)]
+        meta: Vec<(String, String)>,
+
+        /// Refresh the listing every few seconds (default: 2), highlighting status changes
+        #[arg(long, value_name = "SECONDS", num_args = 0..=1, default_missing_value = "2")]
+        watch: Option<u64>,
+    },
+    /// Manage metadata of a virtual machine (owner, purpose, ...)
+    Meta {
+        #[command(subcommand)]
+        subcommand: MetaSubcommands,
+    },
     /// Print information about a snapshot
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
     Snapshot {
-        /// Name of the snapshot
-        #[arg(value_parser)]
-        name: String,
+        /// Names of the snapshots, namespaces ending in / or patterns like 'ci-*'
+        #[arg(value_parser, required = true)]
+        names: Vec<String>,
+
+        #[command(subcommand)]
+        subcommand: Option<SnapshotSubcommands>,
     },
     /// Print information about all snapshots
-    Snapshots,
+    Snapshots {
+        /// Only show snapshots in this namespace (e.g. project/) or matching a pattern
+        #[arg(value_parser)]
+        prefix: Option<String>,
+    },
+    /// Print the snapshot chains of a virtual machine as a tree
+    SnapshotTree {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        machine: String,
+    },
     /// Run a virtual machine
-    Start {
+    Start(StartArgs),
+    /// Attach gdb (or lldb) to a virtual machine started with --gdb
+    Debug {
         /// Name of the virtual machine
         #[arg(value_parser)]
         name: String,
 
-        /// Cores to allocate for the virtual machine
-        #[arg(short, long, value_parser, default_value_t = 4)]
-        cores: usize,
-
-        /// RAM to allocate for the virtual machine (GB)
-        #[arg(short, long, value_parser, default_value_t = 4)]
-        ram: usize,
-
-        /// Run virtual machine in foreground.
-        #[arg(short, long, default_value_t = false)]
-        foreground: bool,
+        /// Kernel image with debug symbols (e.g. vmlinux)
+        #[arg(long)]
+        symbols: Option<PathBuf>,
 
-        /// Names of disks to attach to the virtual machine
-        #[arg(short, long, value_delimiter = ',')]
-        disks: Vec<String>,
+        /// Use lldb instead of gdb
+        #[arg(long, default_value_t = false)]
+        lldb: bool,
 
-        /// Start a snapshot instead of a virtual machine
+        /// Debug a snapshot instead of a virtual machine
         #[arg(short, long, default_value_t = false)]
         snapshot: bool,
     },
-    /// Stop a virtual machine
-    Stop {
+    /// Write a hosts file naming running virtual machines `<name>.<domain>` and print its path
+    Hosts,
+    /// Manage presets of cores, RAM and size for new virtual machines
+    Presets {
+        #[command(subcommand)]
+        subcommand: PresetSubcommands,
+    },
+    /// Restore the most recently removed disk, virtual machine or snapshot
+    Undo,
+    /// Manage removed disks, virtual machines and snapshots
+    Trash {
+        #[command(subcommand)]
+        subcommand: TrashSubcommands,
+    },
+    /// Inspect reports collected when virtual machines crashed
+    Crashes {
+        #[command(subcommand)]
+        subcommand: CrashSubcommands,
+    },
+    /// Print the log of commands that changed state
+    History {
+        /// Only show commands naming this virtual machine, snapshot or disk
+        #[arg(value_parser)]
+        name: Option<String>,
+
+        /// Only show the most recent entries
+        #[arg(short = 'n', long)]
+        limit: Option<usize>,
+    },
+    /// Stop and remove virtual machines and snapshots
+    Prune {
+        /// Prune those whose TTL has expired
+        #[arg(long, required = true)]
+        expired: bool,
+    },
+    /// Print the IP addresses of a running virtual machine
+    Ip {
         /// Name of the virtual machine
         #[arg(value_parser)]
         name: String,
 
-        /// Stop a snapshot instead of a virtual machine
+        /// Print addresses of a snapshot instead of a virtual machine
         #[arg(short, long, default_value_t = false)]
         snapshot: bool,
     },
-    /// Connect to a virtual machine
-    Connect {
-        /// Enable SSH agent forwarding
-        #[arg(short = 'A', long, default_value_t = false)]
-        forward_keys: bool,
-
-        /// Username (default: $USER)
-        #[arg(short, long)]
-        username: Option<String>,
-
+    /// Print the host ports forwarded to each virtual machine and who is holding them
+    Ports,
+    /// Print an overview of all virtual machines, or the readiness of one
+    Status {
         /// Name of the virtual machine
         #[arg(value_parser)]
-        name: String,
+        name: Option<String>,
 
-        /// Connect to a snapshot instead of a virtual machine
+        /// Print readiness of a snapshot instead of a virtual machine
         #[arg(short, long, default_value_t = false)]
         snapshot: bool,
+
+        /// Refresh the listing every few seconds (default: 2), highlighting status changes
+        #[arg(long, value_name = "SECONDS", num_args = 0..=1, default_missing_value = "2")]
+        watch: Option<u64>,
+    },
+    /// Print the CPUs, RAM and storage of the host, and how much virtual machines claim
+    Host,
+    /// Summarize the CPU, RAM and disk a virtual machine used, as sampled by `rhea supervise`
+    Usage {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+
+        /// Only consider samples from this far back (e.g. 12h, 7d)
+        #[arg(long, value_parser = time::parse_duration)]
+        since: Option<Duration>,
+
+        /// Summarize a snapshot instead of a virtual machine
+        #[arg(short, long, default_value_t = false)]
+        snapshot: bool,
+    },
+    /// Attach to the serial console of a running virtual machine (detach with Ctrl-])
+    Console {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+
+        /// Attach to a snapshot instead of a virtual machine
+        #[arg(short, long, default_value_t = false)]
+        snapshot: bool,
+    },
+    /// Print the serial console log of a virtual machine
+    Logs {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+
+        /// Keep printing the log as it grows
+        #[arg(short, long, default_value_t = false)]
+        follow: bool,
+
+        /// Print the log of a snapshot instead of a virtual machine
+        #[arg(short, long, default_value_t = false)]
+        snapshot: bool,
+
+        /// Only print lines logged within this long (e.g. 30m, 2h), which needs timestamps
+        #[arg(long, value_parser = time::parse_duration)]
+        since: Option<Duration>,
+
+        /// Only print lines containing this text
+        #[arg(long)]
+        grep: Option<String>,
+    },
+    /// Stop a virtual machine
+    Stop(StopArgs),
+    /// Run the provisioning steps of a running virtual machine
+    Provision {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+
+        /// Run the steps even if they already ran
+        #[arg(long, default_value_t = false)]
+        again: bool,
+
+        /// Username (default: the guest OS's default user, else $USER)
+        #[arg(short, long)]
+        username: Option<String>,
+    },
+    /// Stop a virtual machine and start it again with the same cores, RAM and disks
+    Restart {
+        /// Names of the virtual machines, namespaces ending in / or patterns like 'ci-*'
+        #[arg(value_parser, required = true)]
+        names: Vec<String>,
+
+        /// Restart a snapshot instead of a virtual machine
+        #[arg(short, long, default_value_t = false)]
+        snapshot: bool,
+    },
+    /// Hard reset a running virtual machine, like pressing its reset button
+    Reset {
+        /// Names of the virtual machines, namespaces ending in / or patterns like 'ci-*'
+        #[arg(value_parser, required = true)]
+        names: Vec<String>,
+
+        /// Reset a snapshot instead of a virtual machine
+        #[arg(short, long, default_value_t = false)]
+        snapshot: bool,
+    },
+    /// Generate an SSH config and VS Code Remote-SSH workspace for a virtual machine
+    Devcontainer {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+
+        /// Username (default: the guest OS's default user, else $USER)
+        #[arg(short, long)]
+        username: Option<String>,
+
+        /// Folder to open in the virtual machine (default: home directory)
+        #[arg(short, long)]
+        folder: Option<String>,
+
+        /// Directory to write the workspace file to
+        #[arg(short, long, default_value = ".")]
+        directory: PathBuf,
+
+        /// Wait for the virtual machine to accept SSH connections
+        #[arg(short, long, default_value_t = false)]
+        wait: bool,
+
+        /// Use a snapshot instead of a virtual machine
+        #[arg(short, long, default_value_t = false)]
+        snapshot: bool,
+    },
+    /// Mount a guest directory on the host with sshfs
+    Mount {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+
+        /// Directory in the virtual machine to mount
+        #[arg(value_parser)]
+        remote: String,
+
+        /// Local directory to mount it on
+        #[arg(value_parser)]
+        mountpoint: PathBuf,
+
+        /// Username (default: the guest OS's default user, else $USER)
+        #[arg(short, long)]
+        username: Option<String>,
+
+        /// Mount from a snapshot instead of a virtual machine
+        #[arg(short, long, default_value_t = false)]
+        snapshot: bool,
+    },
+    /// Unmount a directory mounted with `rhea mount`
+    Umount {
+        /// Local directory to unmount
+        #[arg(value_parser)]
+        mountpoint: PathBuf,
+    },
+    /// Print SSH config Host blocks for virtual machines
+    SshConfig {
+        /// Names of the virtual machines (default: all)
+        #[arg(value_parser)]
+        names: Vec<String>,
+
+        /// Username (default: the guest OS's default user, else $USER)
+        #[arg(short, long)]
+        username: Option<String>,
+
+        /// Private key to authenticate with
+        #[arg(short, long)]
+        identity: Option<PathBuf>,
+
+        /// Host to jump through, for virtual machines on another host
+        #[arg(short = 'J', long)]
+        proxy_jump: Option<String>,
+
+        /// Write an Include-able file per virtual machine instead of printing
+        #[arg(short, long, default_value_t = false)]
+        write: bool,
+    },
+    /// Boot a throwaway virtual machine from an image, run a command, and tear it down
+    Ci {
+        /// Virtual machine, disk or image file to boot a copy of
+        #[arg(value_parser)]
+        image: String,
+
+        /// Format of the image (qcow2, raw, vmdk, vdi)
+        #[arg(short, long, default_value_t = ImageFormat::Qcow2)]
+        format: ImageFormat,
+
+        /// Cores to allocate for the virtual machine
+        #[arg(short, long, value_parser, default_value_t = 4)]
+        cores: usize,
+
+        /// RAM to allocate for the virtual machine (e.g. 512M, 4G)
+        #[arg(short, long, value_parser, default_value_t = Size::gigabytes(4))]
+        ram: Size,
+
+        /// Username (default: the guest OS's default user, else $USER)
+        #[arg(short, long)]
+        username: Option<String>,
+
+        /// Guest paths to collect after the command runs
+        #[arg(short, long, value_delimiter = ',')]
+        artifacts: Vec<String>,
+
+        /// Directory to collect artifacts into
+        #[arg(short = 'D', long, default_value = "artifacts")]
+        artifacts_dir: PathBuf,
+
+        /// Local script to run in the virtual machine instead of a command
+        #[arg(short, long, conflicts_with = "command")]
+        script: Option<PathBuf>,
+
+        /// Give up on the job after this long (e.g. 30m, 2h), exiting with code 7
+        #[arg(short, long, value_parser = time::parse_duration)]
+        timeout: Option<Duration>,
+
+        /// Command to run in the virtual machine
+        #[arg(last = true, required_unless_present = "script")]
+        command: Vec<String>,
+    },
+    /// Boot a temporary copy of a virtual machine, disk or image, open a shell or run a command,
+    /// and throw it away
+    Run {
+        /// Virtual machine, disk or image file to boot a copy of
+        #[arg(short, long)]
+        image: String,
+
+        /// Format of an image file (qcow2, raw, vmdk, vdi)
+        #[arg(short, long, default_value_t = ImageFormat::Qcow2)]
+        format: ImageFormat,
+
+        /// Cores to allocate for the virtual machine
+        #[arg(short, long, value_parser, default_value_t = 2)]
+        cores: usize,
+
+        /// RAM to allocate for the virtual machine (e.g. 512M, 4G)
+        #[arg(short, long, value_parser, default_value_t = Size::gigabytes(2))]
+        ram: Size,
+
+        /// Username (default: the guest OS's default user, else $USER)
+        #[arg(short, long)]
+        username: Option<String>,
+
+        /// Command to run instead of a shell
+        #[arg(last = true)]
+        command: Vec<String>,
+    },
+    /// Manage SSH keys of virtual machines
+    Key {
+        #[clap(subcommand)]
+        subcommand: KeySubcommands,
+    },
+    /// Limit the rate of, delay or drop traffic to a virtual machine on a tap network
+    Shape {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+
+        /// Rate limit in tc's notation (e.g. 384kbit, 2mbit)
+        #[arg(long)]
+        rate: Option<String>,
+
+        /// Delay in milliseconds
+        #[arg(long)]
+        delay: Option<u64>,
+
+        /// Percent of packets to drop
+        #[arg(long)]
+        loss: Option<f64>,
+
+        /// Drop previous settings instead of merging with them
+        #[arg(long, default_value_t = false)]
+        clear: bool,
+    },
+    /// Manage virtio-serial channels exposed to the host as unix sockets
+    Channel {
+        #[command(subcommand)]
+        subcommand: ChannelSubcommands,
+    },
+    /// Capture the traffic of a running virtual machine to a pcap file
+    Pcap {
+        #[command(subcommand)]
+        subcommand: PcapSubcommands,
+    },
+    /// Manage the CD-ROM drive of a virtual machine
+    Iso {
+        #[clap(subcommand)]
+        subcommand: IsoSubcommands,
+    },
+    /// Manage remote hosts to run virtual machines on
+    Remote {
+        #[clap(subcommand)]
+        subcommand: RemoteSubcommands,
+    },
+    /// Connect to a virtual machine
+    Connect(ConnectArgs),
+}
+
+// Subcommands that run without loading the state.
+#[derive(Subcommand)]
+pub enum StandaloneSubcommands {
+    /// Watch virtual machines, restarting them according to their restart policy and sampling
+    /// their resource usage
+    Supervise {
+        /// Seconds between checks
+        #[arg(long, default_value_t = 5)]
+        interval: u64,
+    },
+    /// Manage the saved state
+    State {
+        #[clap(subcommand)]
+        subcommand: StateSubcommands,
+    },
+    /// Print a completion script for a shell
+    Completions {
+        /// Shell to complete for
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Print names of existing resources that complete a command line
+    #[command(hide = true)]
+    Complete {
+        /// Words of the command line after the program name
+        #[arg(last = true)]
+        words: Vec<String>,
+    },
+    /// Append what is piped in to a console log, timestamping and rotating it as configured
+    #[command(hide = true)]
+    LogWriter {
+        /// Log to append to
+        path: PathBuf,
+
+        /// Prefix each line with the host time
+        #[arg(long, default_value_t = false)]
+        timestamps: bool,
+
+        /// Rotate the log once it is larger than this
+        #[arg(long, value_parser)]
+        max_size: Option<Size>,
+
+        /// Rotated logs to keep
+        #[arg(long, value_parser)]
+        keep: Option<usize>,
+    },
+}
+
+/// Arguments of `rhea start` and `rhea snapshot start`.
+#[derive(clap::Args)]
+pub struct StartArgs {
+    /// Names of the virtual machines, or snapshots as snapshot/<name>, namespaces ending in /
+    /// or patterns like 'ci-*'
+    #[arg(value_parser, required = true)]
+    pub names: Vec<String>,
+
+    /// Cores to allocate for the virtual machine [default: its own, or 4]
+    #[arg(short, long, value_parser)]
+    pub cores: Option<usize>,
+
+    /// RAM to allocate for the virtual machine (e.g. 512M, 4G) [default: its own, or 4G]
+    #[arg(short, long, value_parser)]
+    pub ram: Option<Size>,
+
+    /// Run virtual machine in foreground.
+    #[arg(short, long, default_value_t = false)]
+    pub foreground: bool,
+
+    /// Names of disks to attach to the virtual machine
+    #[arg(short, long, value_delimiter = ',')]
+    pub disks: Vec<String>,
+
+    /// Deprecated: address snapshots as snapshot/<name>, or use `rhea snapshot start`
+    #[arg(short, long, default_value_t = false, hide = true)]
+    pub snapshot: bool,
+
+    /// Wait for all readiness probes to pass
+    #[arg(short, long, default_value_t = false)]
+    pub wait: bool,
+
+    /// Seconds to wait for readiness probes
+    #[arg(short, long, value_parser, default_value_t = 300)]
+    pub timeout: u64,
+
+    /// Stop the virtual machine after this long (e.g. 30m, 2h, 14d)
+    #[arg(long, value_parser = time::parse_duration)]
+    pub ttl: Option<Duration>,
+
+    /// Remove the virtual machine once its TTL expires
+    #[arg(long, default_value_t = false)]
+    pub remove_on_expiry: bool,
+
+    /// Boot even if linked snapshots are backed by the virtual machine or quotas are exceeded
+    #[arg(long, default_value_t = false)]
+    pub force: bool,
+
+    /// Kernel to boot directly instead of the bootloader on the disk
+    #[arg(long)]
+    pub kernel: Option<PathBuf>,
+
+    /// Initial ramdisk for the kernel
+    #[arg(long)]
+    pub initrd: Option<PathBuf>,
+
+    /// Kernel command line (e.g. "root=/dev/vda2 console=ttyAMA0")
+    #[arg(long)]
+    pub append: Option<String>,
+
+    /// Store the given kernel options as defaults for the virtual machine
+    #[arg(long, default_value_t = false)]
+    pub save: bool,
+
+    /// Device to boot from first (cdrom, disk)
+    #[arg(long)]
+    pub boot: Option<BootDevice>,
+
+    /// Only succeed once QEMU answers on its QMP socket
+    #[arg(long, default_value_t = false)]
+    pub wait_qmp: bool,
+
+    /// Take a checkpoint snapshot first, to undo with `rhea rollback`
+    #[arg(long, default_value_t = false, conflicts_with = "snapshot")]
+    pub checkpoint: bool,
+
+    /// Don't run pending provisioning steps once the virtual machine is up
+    #[arg(long, default_value_t = false)]
+    pub no_provision: bool,
+
+    /// Listen for GDB on localhost (default port: 1234), see `rhea debug`
+    #[arg(long, value_name = "PORT", num_args = 0..=1, default_missing_value = "1234", value_parser = parse_gdb_port)]
+    pub gdb: Option<u16>,
+
+    /// Hold the CPUs at startup until the debugger continues them
+    #[arg(long, default_value_t = false, requires = "gdb")]
+    pub freeze: bool,
+
+    /// Capture guest traffic to a pcap file from boot, see `rhea pcap`
+    #[arg(long, value_name = "FILE")]
+    pub pcap: Option<PathBuf>,
+
+    /// Boot this many throwaway overlays of the machine or snapshot, each on a free port,
+    /// which are removed once they stop
+    #[arg(
+        long,
+        value_name = "COUNT",
+        conflicts_with_all = ["foreground", "disks", "checkpoint", "save", "gdb", "pcap"]
+    )]
+    pub instances: Option<usize>,
+
+    /// Boot a single throwaway overlay, like --instances 1
+    #[arg(long, default_value_t = false, conflicts_with = "instances")]
+    pub ephemeral: bool,
+}
+
+/// Arguments of `rhea stop` and `rhea snapshot stop`.
+#[derive(clap::Args)]
+pub struct StopArgs {
+    /// Names of the virtual machines, or snapshots as snapshot/<name>, namespaces ending in /
+    /// or patterns like 'ci-*'
+    #[arg(value_parser, required_unless_present = "all")]
+    pub names: Vec<String>,
+
+    /// Deprecated: address snapshots as snapshot/<name>, or use `rhea snapshot stop`
+    #[arg(short, long, default_value_t = false, hide = true)]
+    pub snapshot: bool,
+
+    /// Stop every running virtual machine and snapshot, snapshots first (only snapshots under
+    /// `rhea snapshot stop`)
+    #[arg(short, long, default_value_t = false, conflicts_with_all = ["names", "snapshot"])]
+    pub all: bool,
+
+    /// Suspend to disk instead of shutting down; the next start resumes
+    #[arg(long, default_value_t = false)]
+    pub save_state: bool,
+}
+
+/// Arguments of `rhea connect` and `rhea snapshot connect`.
+#[derive(clap::Args)]
+pub struct ConnectArgs {
+    /// Enable SSH agent forwarding
+    #[arg(short = 'A', long, default_value_t = false)]
+    pub forward_keys: bool,
+
+    /// Enable X11 forwarding
+    #[arg(short = 'X', long, default_value_t = false)]
+    pub x11: bool,
+
+    /// Forward a local port (ssh -L)
+    #[arg(short = 'L', long = "local")]
+    pub local_forwards: Vec<String>,
+
+    /// Forward a remote port (ssh -R)
+    #[arg(short = 'R', long = "remote-forward")]
+    pub remote_forwards: Vec<String>,
+
+    /// Open a SOCKS proxy on a local port (ssh -D)
+    #[arg(short = 'D', long = "dynamic")]
+    pub dynamic_forwards: Vec<String>,
+
+    /// Private key to authenticate with
+    #[arg(short, long)]
+    pub identity: Option<PathBuf>,
+
+    /// Host to jump through (ssh -J)
+    #[arg(short = 'J', long)]
+    pub jump: Option<String>,
+
+    /// Option to pass through to ssh (ssh -o)
+    #[arg(short = 'O', long = "ssh-option")]
+    pub options: Vec<String>,
+
+    /// Connect with mosh instead of ssh
+    #[arg(long, default_value_t = false)]
+    pub mosh: bool,
+
+    /// Command to run instead of a login shell (e.g. "tmux new -A -s main")
+    #[arg(short = 'C', long)]
+    pub command: Option<String>,
+
+    /// Store the given options as defaults for the virtual machine
+    #[arg(long, default_value_t = false)]
+    pub save: bool,
+
+    /// Username (default: the guest OS's default user, else $USER)
+    #[arg(short, long)]
+    pub username: Option<String>,
+
+    /// Name of the virtual machine, or of a snapshot as snapshot/<name>
+    #[arg(value_parser)]
+    pub name: String,
+
+    /// Deprecated: address snapshots as snapshot/<name>, or use `rhea snapshot connect`
+    #[arg(short, long, default_value_t = false, hide = true)]
+    pub snapshot: bool,
+}
+
+impl GlobalArgs {
+    pub fn log_level(&self) -> LevelFilter {
+        self.log_level.unwrap_or(match (self.quiet, self.verbose) {
+            (true, _) => LevelFilter::ERROR,
+            (false, 0) => LevelFilter::WARN,
+            (false, 1) => LevelFilter::DEBUG,
+            (false, _) => LevelFilter::TRACE,
+        })
+    }
+
+    pub fn progress(&self) -> ProgressFormat {
+        match self.progress {
+            _ if self.quiet => ProgressFormat::None,
+            Some(progress) => progress,
+            None if io::stderr().is_terminal() => ProgressFormat::Bar,
+            None => ProgressFormat::None,
+        }
+    }
+
+    pub fn view(&self) -> View {
+        View {
+            format: self.output,
+            sort: self.sort.clone(),
+            columns: self.columns.clone(),
+            highlight: Vec::new(),
+        }
+    }
+}
+
+impl Subcommands {
+    /// Whether the subcommand can run without holding the state lock, as it never saves the
+    /// state.
+    pub fn lock_free(&self) -> bool {
+        matches!(
+            self,
+            Self::ExportLibvirt { .. }
+                | Self::Export { .. }
+                | Self::PushImage { .. }
+                | Self::Disk { .. }
+                | Self::Disks { .. }
+                | Self::Machine { .. }
+                | Self::Machines { .. }
+                | Self::Describe {
+                    description: None,
+                    ..
+                }
+                | Self::Meta {
+                    subcommand: MetaSubcommands::Get { .. } | MetaSubcommands::List { .. }
+                }
+                | Self::Snapshot {
+                    subcommand: None
+                        | Some(
+                            SnapshotSubcommands::Info { .. }
+                                | SnapshotSubcommands::Diff { .. }
+                                | SnapshotSubcommands::Stop(_)
+                                | SnapshotSubcommands::Connect(ConnectArgs { save: false, .. })
+                        ),
+                    ..
+                }
+                | Self::Snapshots { .. }
+                | Self::SnapshotTree { .. }
+                | Self::Status { .. }
+                | Self::Ip { .. }
+                | Self::Ports
+                | Self::Hosts
+                | Self::History { .. }
+                | Self::Crashes { .. }
+                | Self::Presets { .. }
+                | Self::Trash {
+                    subcommand: TrashSubcommands::List
+                }
+                | Self::Console { .. }
+                | Self::Channel {
+                    subcommand: ChannelSubcommands::List { .. }
+                }
+                | Self::Debug { .. }
+                | Self::Usage { .. }
+                | Self::Host
+                | Self::Pcap { .. }
+                | Self::Logs { .. }
+                | Self::Stop(_)
+                | Self::Reset { .. }
+                | Self::Devcontainer { .. }
+                | Self::SshConfig { .. }
+                | Self::Mount { .. }
+                | Self::Umount { .. }
+                | Self::Ci { .. }
+                | Self::Run { .. }
+                | Self::Connect(ConnectArgs { save: false, .. })
+                | Self::Remote {
+                    subcommand: RemoteSubcommands::List
+                }
+        )
+    }
+
+    /// Whether the subcommand changes anything, be it the state, a running virtual machine or
+    /// files rhea keeps, which is what the history records.
+    pub fn mutates(&self) -> bool {
+        !self.lock_free()
+            || matches!(
+                self,
+                Self::Stop(_)
+                    | Self::Reset { .. }
+                    | Self::Snapshot {
+                        subcommand: Some(SnapshotSubcommands::Stop(_)),
+                        ..
+                    }
+                    | Self::Pcap {
+                        subcommand: PcapSubcommands::Start { .. } | PcapSubcommands::Stop { .. }
+                    }
+                    | Self::Devcontainer { .. }
+                    | Self::SshConfig { write: true, .. }
+                    | Self::Mount { .. }
+                    | Self::Umount { .. }
+                    | Self::Ci { .. }
+                    | Self::Run { .. }
+            )
+    }
+}
+
+#[derive(Subcommand)]
+pub enum SnapshotSubcommands {
+    /// Run snapshots
+    Start(StartArgs),
+    /// Stop snapshots
+    Stop(StopArgs),
+    /// Connect to a snapshot
+    Connect(ConnectArgs),
+    /// Print the backing file, sizes and creation time of a snapshot
+    Info {
+        /// Name of the snapshot
+        #[arg(value_parser)]
+        name: String,
+    },
+    /// Compare how much two snapshots hold and where their chains meet
+    Diff {
+        /// Name of the first snapshot
+        #[arg(value_parser)]
+        name: String,
+
+        /// Name of the second snapshot
+        #[arg(value_parser)]
+        other: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum KeySubcommands {
+    /// Generate a keypair for a virtual machine and use it when connecting
+    Generate {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+
+        /// Replace an existing keypair
+        #[arg(long, default_value_t = false)]
+        force: bool,
+    },
+    /// Copy a public key into a running virtual machine with ssh-copy-id
+    Add {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+
+        /// Username (default: the guest OS's default user, else $USER)
+        #[arg(short, long)]
+        username: Option<String>,
+
+        /// Private key to add (default: the key of the virtual machine)
+        #[arg(short, long)]
+        identity: Option<PathBuf>,
+
+        /// Add the key through a running snapshot of the virtual machine
+        #[arg(short, long, default_value_t = false)]
+        snapshot: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ChannelSubcommands {
+    /// Add a channel, which the guest sees as /dev/virtio-ports/<name> from the next start
+    Add {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        machine: String,
+
+        /// Name of the channel (letters, digits, '.', '-' and '_')
+        #[arg(value_parser)]
+        name: String,
+    },
+    /// Remove a channel
+    Remove {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        machine: String,
+
+        /// Name of the channel
+        #[arg(value_parser)]
+        name: String,
+    },
+    /// List the channels of a virtual machine and their host sockets
+    List {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        machine: String,
+
+        /// List the sockets of a snapshot of the virtual machine instead
+        #[arg(short, long, default_value_t = false)]
+        snapshot: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum PcapSubcommands {
+    /// Start capturing the NIC carrying guest traffic (the extra one, if any)
+    Start {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+
+        /// File to write to (default: next to the virtual machine's log)
+        #[arg(long)]
+        file: Option<PathBuf>,
+
+        /// Capture a snapshot instead of a virtual machine
+        #[arg(short, long, default_value_t = false)]
+        snapshot: bool,
+    },
+    /// Stop capturing and print the file written to
+    Stop {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+
+        /// Stop capturing a snapshot instead of a virtual machine
+        #[arg(short, long, default_value_t = false)]
+        snapshot: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum IsoSubcommands {
+    /// Insert an image, replacing the current one (takes effect immediately if running)
+    Attach {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+
+        /// Image to insert
+        #[arg(value_parser)]
+        path: PathBuf,
+
+        /// Verify the image against this SHA-256 digest before using it
+        #[arg(long, conflicts_with = "checksums")]
+        sha256: Option<String>,
+
+        /// Verify the image against its entry in a checksums file (e.g. SHA256SUMS)
+        #[arg(long)]
+        checksums: Option<PathBuf>,
+    },
+    /// Eject the current image
+    Eject {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum StateSubcommands {
+    /// Restore the state from a backup
+    Restore {
+        /// Backup to restore (1 is the most recent)
+        #[arg(short, long, value_parser, default_value_t = 1)]
+        backup: usize,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum MetaSubcommands {
+    /// Set a metadata key
+    Set {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+
+        /// Key to set
+        #[arg(value_parser)]
+        key: String,
+
+        /// Value to store
+        #[arg(value_parser)]
+        value: String,
+    },
+    /// Print the value of a metadata key
+    Get {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+
+        /// Key to print
+        #[arg(value_parser)]
+        key: String,
+    },
+    /// Remove a metadata key
+    Unset {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+
+        /// Key to remove
+        #[arg(value_parser)]
+        key: String,
+    },
+    /// Print all metadata of a virtual machine
+    List {
+        /// Name of the virtual machine
+        #[arg(value_parser)]
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum PresetSubcommands {
+    /// List built-in and configured presets
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum TrashSubcommands {
+    /// List removed resources that can still be restored
+    List,
+    /// Restore the most recently removed resource with a name
+    Restore {
+        /// Name of the disk, virtual machine or snapshot
+        #[arg(value_parser)]
+        name: String,
+    },
+    /// Permanently delete removed resources
+    Empty {
+        /// Only delete those older than the retention window
+        #[arg(long, default_value_t = false)]
+        expired: bool,
+    },
+}
+
+fn parse_meta(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(key, value)| (key.into(), value.into()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got {s}"))
+}
+
+#[derive(Subcommand)]
+pub enum CrashSubcommands {
+    /// List crash reports
+    List {
+        /// Only list crashes of this virtual machine or snapshot
+        #[arg(value_parser)]
+        name: Option<String>,
+    },
+    /// Print a crash report and the end of the log it collected
+    Show {
+        /// ID of the crash report
+        #[arg(value_parser)]
+        id: String,
+    },
+}
+
+/// Ports for `--gdb` may be written like QEMU's `-gdb tcp::1234`, with a leading colon.
+fn parse_gdb_port(s: &str) -> Result<u16, String> {
+    let port = s.strip_prefix(':').unwrap_or(s);
+    port.parse()
+        .map_err(|_| format!("expected a port number, got {s}"))
+}
+
+#[derive(Subcommand)]
+pub enum RemoteSubcommands {
+    /// Add a remote host
+    Add {
+        /// Name of the remote host
+        #[arg(value_parser)]
+        name: String,
+
+        /// Location of the remote host (ssh://[user@]host[:port][/path])
+        #[arg(value_parser)]
+        url: Remote,
+    },
+    /// Remove a remote host
+    Remove {
+        /// Name of the remote host
+        #[arg(value_parser)]
+        name: String,
     },
+    /// List remote hosts
+    List,
 }
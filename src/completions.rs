@@ -0,0 +1,194 @@
+use crate::cli::Args;
+use clap::{Arg, ArgAction, Command, CommandFactory};
+use clap_complete::Shell;
+use path_macro::path;
+use rhea::{profile, result::Result, state::State};
+use std::path::PathBuf;
+
+const BASH: &str = r#"
+_rhea_names() {
+    local names
+    names="$(rhea complete -- "${COMP_WORDS[@]:1:COMP_CWORD}" 2>/dev/null)"
+    if [[ -n "$names" ]]; then
+        COMPREPLY=($(compgen -W "$names" -- "${COMP_WORDS[COMP_CWORD]}"))
+    else
+        _rhea "$@"
+    fi
+}
+
+complete -F _rhea_names -o bashdefault -o default rhea
+"#;
+
+const ZSH: &str = r#"
+_rhea_names() {
+    local -a names
+    names=(${(f)"$(rhea complete -- "${(@)words[2,$CURRENT]}" 2>/dev/null)"})
+    if (( $#names )); then
+        compadd -a names
+    else
+        _rhea "$@"
+    fi
+}
+
+compdef _rhea_names rhea
+"#;
+
+const FISH: &str = r#"
+complete -c rhea -a '(rhea complete -- (commandline -opc)[2..-1] (commandline -ct) 2>/dev/null)'
+"#;
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Disk,
+    Machine,
+    Snapshot,
+    Remote,
+}
+
+/// A completion script for `shell`, completing names of existing resources through
+/// `rhea complete`.
+pub fn script(shell: Shell) -> String {
+    let mut buf = Vec::new();
+    clap_complete::generate(shell, &mut Args::command(), "rhea", &mut buf);
+
+    let mut script = String::from_utf8_lossy(&buf).into_owned();
+    script.push_str(match shell {
+        Shell::Bash => BASH,
+        Shell::Zsh => ZSH,
+        Shell::Fish => FISH,
+        _ => "",
+    });
+    script
+}
+
+/// Names of existing resources that complete the last of `words`, a command line without the
+/// program name.
+pub fn names(path: PathBuf, words: &[String]) -> Result<Vec<String>> {
+    let Some((current, words)) = words.split_last() else {
+        return Ok(Vec::new());
+    };
+
+    let mut command = Args::command();
+    command.build();
+
+    let Some(kind) = kind(&command, words) else {
+        return Ok(Vec::new());
+    };
+
+    let value_of = |name: &str| {
+        words
+            .iter()
+            .position(|word| word == name)
+            .and_then(|index| words.get(index + 1))
+    };
+    let path = match value_of("--profile") {
+        Some(name) => profile::path(&path, name)?,
+        None => path,
+    };
+    let remote = value_of("--remote");
+    let state = match (kind, remote) {
+        (Kind::Remote, _) | (_, None) => State::load_read_only(path)?,
+        (_, Some(remote)) => State::load_read_only(path![path / "remotes" / remote])?,
+    };
+
+    let names: Vec<String> = match kind {
+        Kind::Disk => state.disks().map(|disk| disk.name.clone()).collect(),
+        Kind::Machine => state
+            .machines()
+            .map(|machine| machine.name.clone())
+            .collect(),
+        Kind::Snapshot => state
+            .snapshots()
+            .map(|snapshot| snapshot.name.clone())
+            .collect(),
+        Kind::Remote => state.remotes().map(|(name, _)| name.clone()).collect(),
+    };
+    Ok(names
+        .into_iter()
+        .filter(|name| name.starts_with(current.as_str()))
+        .collect())
+}
+
+/// The kind of resource named by the argument that follows `words`.
+fn kind(command: &Command, words: &[String]) -> Option<Kind> {
+    let mut words = words.iter().peekable();
+
+    let mut names = Vec::new();
+    let mut command = command;
+    while let Some(subcommand) = words
+        .peek()
+        .and_then(|word| command.find_subcommand(word.as_str()))
+    {
+        names.push(subcommand.get_name());
+        command = subcommand;
+        words.next();
+    }
+    let words = words.collect::<Vec<_>>();
+
+    let snapshot = words
+        .iter()
+        .any(|word| *word == "-s" || *word == "--snapshot");
+
+    let mut position = 0;
+    let mut pending: Option<&Arg> = None;
+    for word in &words {
+        if pending.take().is_some() {
+            continue;
+        }
+        if let Some(arg) = option(command, word) {
+            if takes_value(arg) && !word.contains('=') {
+                pending = Some(arg);
+            }
+            continue;
+        }
+        position += 1;
+    }
+
+    let arg = match pending {
+        Some(arg) => arg,
+        None => command.get_positionals().nth(position).or_else(|| {
+            command
+                .get_positionals()
+                .last()
+                .filter(|arg| takes_many(arg))
+        })?,
+    };
+
+    let path = names.join(" ");
+    match arg.get_id().as_str() {
+        "host" => Some(Kind::Remote),
+        "remote" if path == "sync" => Some(Kind::Remote),
+        "image" if path == "run" => Some(Kind::Machine),
+        "disks" => Some(Kind::Disk),
+        "name" if path.starts_with("remote") => Some(Kind::Remote),
+        "name" if path.starts_with("add-") || path.starts_with("import") => None,
+        "name" if path.contains("probe") => None,
+        "name" | "names" | "machine" | "base" if snapshot => Some(Kind::Snapshot),
+        "name" | "names" if path.contains("disk") => Some(Kind::Disk),
+        "name" | "names" | "other" if path.contains("snapshot") => Some(Kind::Snapshot),
+        "name" | "names" | "machine" | "base" => Some(Kind::Machine),
+        _ => None,
+    }
+}
+
+fn option<'a>(command: &'a Command, word: &str) -> Option<&'a Arg> {
+    if let Some(long) = word.strip_prefix("--") {
+        let long = long.split_once('=').map_or(long, |(long, _)| long);
+        command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(long))
+    } else {
+        let short = word.strip_prefix('-')?.chars().next()?;
+        command
+            .get_arguments()
+            .find(|arg| arg.get_short() == Some(short))
+    }
+}
+
+fn takes_value(arg: &Arg) -> bool {
+    matches!(arg.get_action(), ArgAction::Set | ArgAction::Append)
+}
+
+fn takes_many(arg: &Arg) -> bool {
+    matches!(arg.get_action(), ArgAction::Append)
+}
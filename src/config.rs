@@ -0,0 +1,330 @@
+use crate::{arch, error::IoContext, image, preset::Preset, result::Result, size::Size};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+const DEFAULT_START_GRACE: u64 = 2;
+const DEFAULT_SSH_WAIT: u64 = 60;
+const DEFAULT_SHUTDOWN: u64 = 30;
+const DEFAULT_QMP: u64 = 5;
+const DEFAULT_RETRY_ATTEMPTS: u32 = 3;
+const DEFAULT_RETRY_DELAY: u64 = 1;
+const DEFAULT_DOMAIN: &str = "rhea.local";
+const DEFAULT_TRASH_RETENTION_DAYS: u64 = 7;
+const DEFAULT_CHECKPOINTS_KEPT: usize = 3;
+const DEFAULT_NOTIFY_AFTER: u64 = 60;
+const DEFAULT_CRASH_LOG_KIB: u64 = 64;
+const DEFAULT_LOGS_KEPT: usize = 3;
+const DEFAULT_USAGE_INTERVAL: u64 = 60;
+// A week of samples at the default interval.
+const DEFAULT_USAGE_SAMPLES: u64 = 10080;
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Timeouts {
+    pub start_grace: Option<u64>,
+    pub ssh_wait: Option<u64>,
+    pub shutdown: Option<u64>,
+    pub qmp: Option<u64>,
+}
+
+impl Timeouts {
+    pub fn is_empty(&self) -> bool {
+        self.start_grace.is_none()
+            && self.ssh_wait.is_none()
+            && self.shutdown.is_none()
+            && self.qmp.is_none()
+    }
+
+    pub fn or(&self, fallback: &Timeouts) -> Timeouts {
+        Timeouts {
+            start_grace: self.start_grace.or(fallback.start_grace),
+            ssh_wait: self.ssh_wait.or(fallback.ssh_wait),
+            shutdown: self.shutdown.or(fallback.shutdown),
+            qmp: self.qmp.or(fallback.qmp),
+        }
+    }
+
+    pub fn start_grace(&self) -> Duration {
+        Duration::from_secs(self.start_grace.unwrap_or(DEFAULT_START_GRACE))
+    }
+
+    pub fn ssh_wait(&self) -> Duration {
+        Duration::from_secs(self.ssh_wait.unwrap_or(DEFAULT_SSH_WAIT))
+    }
+
+    pub fn shutdown(&self) -> Duration {
+        Duration::from_secs(self.shutdown.unwrap_or(DEFAULT_SHUTDOWN))
+    }
+
+    pub fn qmp(&self) -> Duration {
+        Duration::from_secs(self.qmp.unwrap_or(DEFAULT_QMP))
+    }
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Retry {
+    pub attempts: Option<u32>,
+    pub delay: Option<u64>,
+}
+
+impl Retry {
+    pub fn is_empty(&self) -> bool {
+        self.attempts.is_none() && self.delay.is_none()
+    }
+
+    pub fn or(&self, fallback: &Retry) -> Retry {
+        Retry {
+            attempts: self.attempts.or(fallback.attempts),
+            delay: self.delay.or(fallback.delay),
+        }
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts.unwrap_or(DEFAULT_RETRY_ATTEMPTS).max(1)
+    }
+
+    pub fn delay(&self) -> Duration {
+        Duration::from_secs(self.delay.unwrap_or(DEFAULT_RETRY_DELAY))
+    }
+}
+
+/// What is collected into a crash report when a virtual machine crashes.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Crashes {
+    /// How much of the end of the log to keep.
+    pub log_kib: Option<u64>,
+    /// Dump guest memory over QMP when QEMU outlives the crash.
+    #[serde(default)]
+    pub dump_memory: bool,
+}
+
+impl Crashes {
+    pub fn log_bytes(&self) -> u64 {
+        self.log_kib.unwrap_or(DEFAULT_CRASH_LOG_KIB) * 1024
+    }
+}
+
+/// How the console logs of virtual machines are written: each line prefixed with the host time
+/// it arrived at, and rotated once larger than `max_size`, keeping `keep` rotated logs.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Logs {
+    #[serde(default)]
+    pub timestamps: bool,
+    pub max_size: Option<Size>,
+    pub keep: Option<usize>,
+}
+
+impl Logs {
+    /// Whether logs are written by rhea rather than by QEMU directly.
+    pub fn is_managed(&self) -> bool {
+        self.timestamps || self.max_size.is_some()
+    }
+
+    pub fn keep(&self) -> usize {
+        self.keep.unwrap_or(DEFAULT_LOGS_KEPT).max(1)
+    }
+}
+
+/// Where crashes, long operations finishing and failed backups are announced.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Notifications {
+    pub desktop: Option<bool>,
+    pub webhook: Option<String>,
+    /// Seconds an operation has to take for its completion to be announced.
+    pub after: Option<u64>,
+}
+
+impl Notifications {
+    pub fn is_empty(&self) -> bool {
+        self.desktop.is_none() && self.webhook.is_none() && self.after.is_none()
+    }
+
+    pub fn or(&self, fallback: &Notifications) -> Notifications {
+        Notifications {
+            desktop: self.desktop.or(fallback.desktop),
+            webhook: self.webhook.clone().or_else(|| fallback.webhook.clone()),
+            after: self.after.or(fallback.after),
+        }
+    }
+
+    pub fn desktop(&self) -> bool {
+        self.desktop.unwrap_or(false)
+    }
+
+    pub fn after(&self) -> Duration {
+        Duration::from_secs(self.after.unwrap_or(DEFAULT_NOTIFY_AFTER))
+    }
+}
+
+/// Limits on the resources of all running virtual machines combined.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Quotas {
+    pub max_cores: Option<usize>,
+    pub max_ram: Option<Size>,
+}
+
+/// Publishing running virtual machines as `<name>.<domain>` in a generated hosts file.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Dns {
+    #[serde(default)]
+    pub enabled: bool,
+    pub domain: Option<String>,
+}
+
+impl Dns {
+    pub fn domain(&self) -> &str {
+        self.domain.as_deref().unwrap_or(DEFAULT_DOMAIN)
+    }
+}
+
+/// How long removed resources are kept around for restoring.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Trash {
+    pub retention_days: Option<u64>,
+}
+
+impl Trash {
+    pub fn retention(&self) -> Duration {
+        Duration::from_secs(
+            self.retention_days.unwrap_or(DEFAULT_TRASH_RETENTION_DAYS) * 24 * 60 * 60,
+        )
+    }
+}
+
+/// How often `rhea supervise` samples the resources used by running virtual machines, and how
+/// many samples are kept per machine.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Sampling {
+    pub interval: Option<u64>,
+    pub samples: Option<u64>,
+}
+
+impl Sampling {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval.unwrap_or(DEFAULT_USAGE_INTERVAL).max(1))
+    }
+
+    pub fn samples(&self) -> u64 {
+        self.samples.unwrap_or(DEFAULT_USAGE_SAMPLES).max(1)
+    }
+}
+
+/// How many checkpoints of each virtual machine are kept before the oldest are removed.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Checkpoints {
+    pub keep: Option<usize>,
+}
+
+impl Checkpoints {
+    pub fn keep(&self) -> usize {
+        self.keep.unwrap_or(DEFAULT_CHECKPOINTS_KEPT).max(1)
+    }
+}
+
+/// QEMU and qemu-img to run instead of the first ones in PATH, and a command such as
+/// `nice -n 10` that QEMU runs under.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Binaries {
+    pub qemu: Option<String>,
+    pub qemu_img: Option<String>,
+    pub wrapper: Option<Vec<String>>,
+}
+
+impl Binaries {
+    pub fn qemu(&self) -> &str {
+        self.qemu.as_deref().unwrap_or(arch::QEMU_RUNNER)
+    }
+
+    pub fn qemu_img(&self) -> &str {
+        self.qemu_img.as_deref().unwrap_or(image::QEMU_IMAGER)
+    }
+
+    pub fn wrapper(&self) -> &[String] {
+        self.wrapper.as_deref().unwrap_or_default()
+    }
+
+    /// The wrapper followed by QEMU, which together start a virtual machine.
+    pub fn qemu_command(&self) -> Vec<String> {
+        let mut command = self.wrapper().to_vec();
+        command.push(self.qemu().into());
+        command
+    }
+}
+
+/// The UEFI firmware guests boot with, discovered by `firmware::discover` unless set.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Firmware {
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Config {
+    #[serde(default)]
+    pub timeouts: Timeouts,
+    #[serde(default)]
+    pub retry: Retry,
+    #[serde(default)]
+    pub quotas: Quotas,
+    #[serde(default)]
+    pub dns: Dns,
+    #[serde(default)]
+    pub trash: Trash,
+    #[serde(default)]
+    pub checkpoints: Checkpoints,
+    #[serde(default)]
+    pub notifications: Notifications,
+    #[serde(default)]
+    pub crashes: Crashes,
+    #[serde(default)]
+    pub logs: Logs,
+    #[serde(default)]
+    pub usage: Sampling,
+    #[serde(default)]
+    pub binaries: Binaries,
+    #[serde(default)]
+    pub firmware: Firmware,
+    #[serde(default)]
+    pub presets: BTreeMap<String, Preset>,
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        if fs::metadata(&path).is_err() {
+            return Ok(Self::default());
+        }
+        Ok(toml::from_str(&fs::read_to_string(&path).at(path)?)?)
+    }
+
+    /// Records discovered firmware in the config at `path`, appending to it so that the rest of
+    /// the file stays as the user wrote it.
+    pub fn remember_firmware<P: AsRef<Path>>(path: P, firmware: &Path) -> Result<()> {
+        let existing = fs::read_to_string(&path).unwrap_or_default();
+        if existing.contains("[firmware]") {
+            return Ok(());
+        }
+        let section = toml::to_string(&BTreeMap::from([(
+            "firmware",
+            Firmware {
+                path: Some(firmware.into()),
+            },
+        )]))?;
+        let separator = if existing.is_empty() || existing.ends_with("\n\n") {
+            ""
+        } else if existing.ends_with('\n') {
+            "\n"
+        } else {
+            "\n\n"
+        };
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| write!(file, "{separator}{section}"))
+            .at(path)
+    }
+}
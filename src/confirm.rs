@@ -0,0 +1,23 @@
+use anyhow::Result;
+use rhea::error::Error;
+use std::io::{self, IsTerminal, Write};
+
+/// Asks on the terminal before destroying something, unless `yes` was given. Without a terminal
+/// to ask on, refuses rather than guessing.
+pub fn confirm(yes: bool, description: &str) -> Result<()> {
+    if yes {
+        return Ok(());
+    }
+    if !io::stdin().is_terminal() {
+        return Err(Error::Unconfirmed.into());
+    }
+
+    eprint!("{description} [y/N] ");
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    match answer.trim() {
+        "y" | "Y" | "yes" => Ok(()),
+        _ => Err(Error::Cancelled.into()),
+    }
+}
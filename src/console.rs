@@ -0,0 +1,51 @@
+use crate::{exec::Traced, result::Result};
+use std::{
+    io::{self, Read, Write},
+    net::Shutdown,
+    os::unix::net::UnixStream,
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+};
+
+pub const ESCAPE: u8 = 0x1d;
+
+fn stty(args: &[&str]) -> Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .traced()?
+        .output()?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().into())
+}
+
+pub fn attach(path: &Path) -> Result<()> {
+    let stream = UnixStream::connect(path)?;
+    let mut reader = stream.try_clone()?;
+
+    let saved = stty(&["-g"])?;
+    stty(&["raw", "-echo"])?;
+
+    thread::spawn(move || {
+        let mut stdout = io::stdout();
+        let mut buf = [0; 1024];
+        while let Ok(n @ 1..) = reader.read(&mut buf) {
+            if stdout.write_all(&buf[..n]).and(stdout.flush()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let result = (|| -> Result<()> {
+        let mut writer = &stream;
+        let mut byte = [0];
+        while io::stdin().read(&mut byte)? == 1 && byte[0] != ESCAPE {
+            writer.write_all(&byte)?;
+        }
+        Ok(())
+    })();
+
+    stream.shutdown(Shutdown::Both).ok();
+    stty(&[&saved])?;
+    result
+}
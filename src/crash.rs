@@ -0,0 +1,54 @@
+use crate::{error::IoContext, result::Result, size::Size};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+pub const REPORT_PATH: &str = "report.toml";
+pub const LOG_PATH: &str = "log.txt";
+pub const MEMORY_PATH: &str = "memory.elf";
+
+/// What was known about a virtual machine or snapshot when its QEMU process exited abnormally
+/// or its guest crashed, kept in a directory of its own next to the collected artifacts.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Report {
+    pub id: String,
+    pub name: String,
+    pub snapshot: bool,
+    /// The QEMU run state (e.g. `guest-panicked`), or `exited` if the process was gone.
+    pub reason: String,
+    pub pid: u32,
+    pub started_at: u64,
+    pub detected_at: u64,
+    pub cores: usize,
+    pub ram: Size,
+    #[serde(default)]
+    pub memory_dump: bool,
+}
+
+impl Report {
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let path = dir.as_ref().join(REPORT_PATH);
+        Ok(toml::from_str(&fs::read_to_string(&path).at(path)?)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, dir: P) -> Result<()> {
+        let path = dir.as_ref().join(REPORT_PATH);
+        fs::write(&path, toml::to_string(self)?).at(path)?;
+        Ok(())
+    }
+}
+
+/// The last `bytes` of a file, or nothing if it doesn't exist.
+pub fn tail<P: AsRef<Path>>(path: P, bytes: u64) -> Result<Vec<u8>> {
+    let Ok(mut file) = File::open(&path) else {
+        return Ok(Vec::new());
+    };
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(bytes)))?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+    Ok(tail)
+}
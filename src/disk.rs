@@ -1,10 +1,34 @@
+use crate::{size::Size, time};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
 #[derive(Clone, Deserialize, Serialize)]
 pub struct Disk {
     pub name: String,
-    pub size: usize,
+    pub size: Size,
+    /// Free-text note from `rhea add-disk --description` or `rhea describe`, shown in `rhea disk`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Digest of the verified image the disk was imported from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<u64>,
+    #[serde(default)]
+    pub last_started_at: Option<u64>,
+}
+
+impl Disk {
+    pub fn new(name: &str, size: Size) -> Self {
+        Self {
+            name: name.into(),
+            size,
+            description: None,
+            sha256: None,
+            created_at: Some(time::now()),
+            last_started_at: None,
+        }
+    }
 }
 
 impl fmt::Display for Disk {
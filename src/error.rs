@@ -1,4 +1,8 @@
-use std::{env, io, path::PathBuf};
+use crate::result::Result;
+use std::{
+    env, io,
+    path::{Path, PathBuf},
+};
 use thiserror::Error;
 use toml::{de, ser};
 
@@ -7,6 +11,19 @@ pub enum Error {
     #[error("io error")]
     IO(#[from] io::Error),
 
+    #[error("failed to access {path:?}")]
+    File {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("{command} failed: {stderr}")]
+    CommandFailed { command: String, stderr: String },
+
+    #[error("port {port} already used by {owner}")]
+    PortInUse { port: u16, owner: String },
+
     #[error("invalid path: {path:?}")]
     InvalidPath { path: PathBuf },
 
@@ -22,6 +39,37 @@ pub enum Error {
     #[error("invalid disk: {name}")]
     InvalidDisk { name: String },
 
+    #[error("shrinking not allowed: {name}")]
+    ShrinkNotAllowed { name: String },
+
+    #[error("failed to start {name}: {message}")]
+    StartFailed { name: String, message: String },
+
+    #[error("provisioning {name} failed at `{step}` with exit code {code}")]
+    ProvisionFailed {
+        name: String,
+        step: String,
+        code: i32,
+    },
+
+    #[error("timed out waiting for ssh: {name}")]
+    SshTimeout { name: String },
+
+    #[error("no GDB stub: {name}")]
+    GdbDisabled { name: String },
+
+    #[error("already capturing traffic of: {name}")]
+    CaptureActive { name: String },
+
+    #[error("not capturing traffic of: {name}")]
+    CaptureInactive { name: String },
+
+    #[error("timed out running job: {name}")]
+    JobTimeout { name: String },
+
+    #[error("timed out waiting for shutdown: {name}")]
+    ShutdownTimeout { name: String },
+
     #[error("machine exists: {name}")]
     MachineExists { name: String },
 
@@ -43,18 +91,329 @@ pub enum Error {
     #[error("snapshot not in use: {name}")]
     SnapshotNotInUse { name: String },
 
+    #[error("snapshot has children: {name}")]
+    SnapshotHasChildren { name: String },
+
     #[error("invalid snapshot: {name}")]
     InvalidSnapshot { name: String },
 
+    #[error("invalid checkpoint: {name}")]
+    InvalidCheckpoint { name: String },
+
+    #[error("no checkpoints of machine: {name}")]
+    MissingCheckpoint { name: String },
+
+    #[error("probe exists: {name}")]
+    ProbeExists { name: String },
+
+    #[error("invalid probe: {name}")]
+    InvalidProbe { name: String },
+
+    #[error("timed out waiting for probes: {name}")]
+    ProbeTimeout { name: String },
+
+    #[error("channel exists: {name}")]
+    ChannelExists { name: String },
+
+    #[error("invalid channel: {name}")]
+    InvalidChannel { name: String },
+
+    #[error("no metadata key {key} on machine: {name}")]
+    InvalidMetaKey { name: String, key: String },
+
+    #[error("invalid crash report: {id}")]
+    InvalidCrash { id: String },
+
+    #[error("nothing in the trash named: {name}")]
+    InvalidTrash { name: String },
+
+    #[error("the trash is empty")]
+    TrashEmpty,
+
+    #[error("invalid image format: {format}")]
+    InvalidImageFormat { format: String },
+
+    #[error("invalid image: {path:?}")]
+    InvalidImage { path: PathBuf },
+
+    #[error("invalid progress format: {format}")]
+    InvalidProgressFormat { format: String },
+
+    #[error("checksum mismatch for {path:?}: expected {expected}, got {actual}")]
+    ChecksumMismatch {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("no checksum listed for: {path:?}")]
+    MissingChecksum { path: PathBuf },
+
+    #[error("failed to transfer image: {reference}")]
+    RegistryFailed { reference: String },
+
+    #[error("can't sync a machine backed by another image: {name}")]
+    SyncBacked { name: String },
+
+    #[error("invalid archive: {path:?}")]
+    InvalidArchive { path: PathBuf },
+
+    #[error("no free port available")]
+    NoFreePort,
+
+    #[error("no UEFI firmware found")]
+    MissingFirmware,
+
+    #[error("invalid version: {version}")]
+    InvalidVersion { version: String },
+
+    #[error("{feature} needs QEMU {required} or newer, but {installed} is installed")]
+    QemuTooOld {
+        feature: String,
+        required: String,
+        installed: String,
+    },
+
+    #[error("invalid duration: {duration}")]
+    InvalidDuration { duration: String },
+
+    #[error("invalid import source: {tool}")]
+    InvalidSource { tool: String },
+
+    #[error("invalid network: {network} (expected user, shared, bridged:<iface> or tap:<iface>)")]
+    InvalidNetwork { network: String },
+
+    #[error("invalid forward: {forward} (expected <host>:<port>)")]
+    InvalidForward { forward: String },
+
+    #[error("isolated machines can only use the user network: {name}")]
+    IsolatedNetwork { name: String },
+
+    #[error("invalid network shaping: {shaping}")]
+    InvalidShaping { shaping: String },
+
+    #[error("network shaping needs a tap network: {name}")]
+    ShapingUnsupported { name: String },
+
+    #[error("operation cancelled")]
+    Cancelled,
+
+    #[error("confirmation required (pass --yes to skip the prompt)")]
+    Unconfirmed,
+
+    #[error("no state backup: {backup}")]
+    InvalidBackup { backup: usize },
+
+    #[error("unsupported state version: {version}")]
+    InvalidStateVersion { version: u32 },
+
+    #[error("state locked by process {pid}")]
+    StateLocked { pid: u32 },
+
+    #[error("state loaded read-only")]
+    StateReadOnly,
+
+    #[error("invalid size: {size} (expected e.g. 512M, 64G, 1T)")]
+    InvalidSize { size: String },
+
+    #[error("backs linked snapshots (use --force to boot anyway): {name}")]
+    BackingInUse { name: String },
+
+    #[error("machine has linked snapshots: {name}")]
+    MachineHasSnapshots { name: String },
+
+    #[error("key exists (use --force to replace it): {name}")]
+    KeyExists { name: String },
+
+    #[error("no key for machine (generate one with `rhea key generate`): {name}")]
+    MissingKey { name: String },
+
+    #[error("failed to generate key: {name}")]
+    KeyGenerationFailed { name: String },
+
+    #[error("failed to add key: {name}")]
+    KeyInjectionFailed { name: String },
+
+    #[error("failed to mount: {path:?}")]
+    MountFailed { path: PathBuf },
+
+    #[error("failed to unmount: {path:?}")]
+    UnmountFailed { path: PathBuf },
+
+    #[error("invalid remote: {remote}")]
+    InvalidRemote { remote: String },
+
+    #[error("remote already exists: {name}")]
+    RemoteExists { name: String },
+
+    #[error("not supported on a remote host: {operation}")]
+    RemoteUnsupported { operation: String },
+
+    #[error("quota exceeded: {used} of at most {limit} {resource} would be in use")]
+    QuotaExceeded {
+        resource: String,
+        used: String,
+        limit: String,
+    },
+
+    #[error("--initrd and --append require --kernel")]
+    MissingKernel,
+
+    #[error("qmp command failed: {command}: {message}")]
+    QmpFailed { command: String, message: String },
+
+    #[error("invalid boot device: {device} (expected cdrom or disk)")]
+    InvalidBootDevice { device: String },
+
+    #[error("invalid input device: {device} (expected tablet or mouse)")]
+    InvalidInputDevice { device: String },
+
+    #[error("invalid name: {name} (expected slash-separated segments, e.g. project/web-1)")]
+    InvalidName { name: String },
+
+    #[error("invalid profile: {profile} (expected a name without slashes)")]
+    InvalidProfile { profile: String },
+
+    #[error("can't copy into the profile it is already in: {profile}")]
+    SameProfile { profile: String },
+
+    #[error("nothing matches {pattern}")]
+    NoMatches { pattern: String },
+
+    #[error("{flag} only applies to a single virtual machine")]
+    SingleTarget { flag: String },
+
+    #[error("invalid guest OS: {os} (expected e.g. linux, ubuntu, windows or freebsd)")]
+    InvalidGuestOs { os: String },
+
+    #[error("invalid firmware mode: {mode} (expected bios or uefi)")]
+    InvalidFirmwareMode { mode: String },
+
+    #[error("legacy BIOS boot is only available for x86_64 guests")]
+    BiosUnsupported,
+
+    #[error("invalid preset: {preset} (expected e.g. 2c/2G/32G)")]
+    InvalidPreset { preset: String },
+
+    #[error("no such preset: {name}")]
+    MissingPreset { name: String },
+
+    #[error("invalid restart policy: {policy} (expected never, on-crash or always)")]
+    InvalidRestartPolicy { policy: String },
+
+    #[error("missing dependency (is it installed and on $PATH?): {program}")]
+    MissingDependency { program: String },
+
+    #[cfg(feature = "async")]
+    #[error(transparent)]
+    Join(#[from] tokio::task::JoinError),
+
     #[error("missing environment variable")]
     MissingEnvVar(#[from] env::VarError),
 
-    #[error("deserialization error")]
+    #[error("deserialization error: {0}")]
     Deserialization(#[from] de::Error),
 
     #[error("serialization error")]
     Serialization(#[from] ser::Error),
 
+    #[error("json error")]
+    Json(#[from] serde_json::Error),
+
     #[error("unknown error")]
     Unknown,
 }
+
+impl Error {
+    /// A suggestion for what to do about the error, if there is an obvious one.
+    pub fn hint(&self) -> Option<String> {
+        Some(match self {
+            Self::InvalidDisk { .. } => "run `rhea disks` to list disks".into(),
+            Self::InvalidMachine { .. } => "run `rhea machines` to list virtual machines".into(),
+            Self::InvalidSnapshot { .. } => "run `rhea snapshots` to list snapshots".into(),
+            Self::NoMatches { .. } => {
+                "quote patterns so the shell leaves them alone, and list names with `rhea \
+                 machines`, `rhea snapshots` or `rhea disks`"
+                    .into()
+            }
+            Self::DiskInUse { .. } => {
+                "stop the virtual machine it is attached to (see `rhea status`)".into()
+            }
+            Self::MachineInUse { name } => format!("stop it first with `rhea stop {name}`"),
+            Self::SnapshotInUse { name } => {
+                format!("stop it first with `rhea stop snapshot/{name}`")
+            }
+            Self::MachineHasSnapshots { name } => {
+                format!("remove the snapshots listed by `rhea snapshot-tree {name}` first")
+            }
+            Self::MachineNotInUse { name } => format!("start it with `rhea start {name}`"),
+            Self::SnapshotNotInUse { name } => {
+                format!("start it with `rhea start snapshot/{name}`")
+            }
+            Self::PortInUse { .. } => {
+                "run `rhea ports` to see which ports are taken, and pass --port when adding \
+                 virtual machines to pick a free one"
+                    .into()
+            }
+            Self::StartFailed { name, .. } => format!("see `rhea logs {name}` for QEMU's output"),
+            Self::ProvisionFailed { name, .. } => {
+                format!("fix the step, then run `rhea provision {name}` to retry")
+            }
+            Self::SshTimeout { name } | Self::ProbeTimeout { name } => format!(
+                "check that the guest booted with `rhea console {name}` or `rhea logs {name}`"
+            ),
+            Self::ShutdownTimeout { name } => format!(
+                "the guest may ignore ACPI shutdown; give it longer with \
+                 `rhea set-machine {name} --shutdown-timeout <seconds>`"
+            ),
+            Self::StateLocked { .. } => {
+                "another rhea command is running; wait for it to finish".into()
+            }
+            Self::QuotaExceeded { .. } => {
+                "stop other virtual machines, raise the quotas in config.toml or pass --force"
+                    .into()
+            }
+            Self::MissingFirmware => {
+                "set RHEA_UEFI_PATH, or `path` under [firmware] in config.toml, to the UEFI \
+                 firmware to boot with (see the README)"
+                    .into()
+            }
+            Self::MissingCheckpoint { .. } => {
+                "pass --checkpoint to start, resize-machine or rebuild to take one".into()
+            }
+            Self::RegistryFailed { .. } => {
+                "check the reference, and log in to the registry with `oras login`".into()
+            }
+            Self::SyncBacked { .. } => {
+                "export the machine and import it on the remote host instead".into()
+            }
+            Self::GdbDisabled { .. } => "restart it with `rhea start --gdb`".into(),
+            Self::CaptureActive { name } => format!("stop it first with `rhea pcap stop {name}`"),
+            Self::ShapingUnsupported { name } => {
+                format!("give it one with `rhea set-machine {name} --network tap:<iface>`")
+            }
+            Self::IsolatedNetwork { name } => format!(
+                "drop the extra network with `rhea set-machine {name} --network user`, or \
+                 pass --isolated false"
+            ),
+            Self::QemuTooOld { .. } => "upgrade QEMU (`rhea host` shows what is installed)".into(),
+            Self::SameProfile { .. } => "pass a different profile with --to-profile".into(),
+            Self::NoFreePort => "remove unused virtual machines to free up ports".into(),
+            _ => return None,
+        })
+    }
+}
+
+/// Attaches the file an I/O error happened on.
+pub trait IoContext<T> {
+    fn at<P: AsRef<Path>>(self, path: P) -> Result<T>;
+}
+
+impl<T> IoContext<T> for io::Result<T> {
+    fn at<P: AsRef<Path>>(self, path: P) -> Result<T> {
+        self.map_err(|source| Error::File {
+            path: path.as_ref().into(),
+            source,
+        })
+    }
+}
@@ -0,0 +1,35 @@
+use crate::{error::Error, result::Result};
+use std::{env, ffi::OsStr, path::Path, process::Command};
+
+/// Logs external commands before they are executed, failing early if the program is missing.
+pub trait Traced {
+    fn traced(&mut self) -> Result<&mut Self>;
+}
+
+impl Traced for Command {
+    fn traced(&mut self) -> Result<&mut Self> {
+        tracing::debug!(
+            program = %self.get_program().to_string_lossy(),
+            args = ?self
+                .get_args()
+                .map(|arg| arg.to_string_lossy())
+                .collect::<Vec<_>>(),
+            "executing"
+        );
+        if !installed(self.get_program()) {
+            return Err(Error::MissingDependency {
+                program: self.get_program().to_string_lossy().into(),
+            });
+        }
+        Ok(self)
+    }
+}
+
+fn installed(program: &OsStr) -> bool {
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        return path.exists();
+    }
+    env::var_os("PATH")
+        .is_some_and(|paths| env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+}
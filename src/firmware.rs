@@ -0,0 +1,115 @@
+//! The firmware guests boot from, and finding UEFI firmware so that `RHEA_UEFI_PATH` is only
+//! needed where it is somewhere unusual.
+
+use crate::{arch, error::Error, version};
+use path_macro::path;
+use serde::{Deserialize, Serialize};
+use std::{fmt, fs, iter, path::PathBuf, str::FromStr};
+
+/// Firmware QEMU installs under `<prefix>/share/qemu`, as Homebrew and Nix do.
+#[cfg(target_arch = "x86_64")]
+const BUNDLED: &[&str] = &["edk2-x86_64-code.fd"];
+#[cfg(target_arch = "aarch64")]
+const BUNDLED: &[&str] = &["edk2-aarch64-code.fd"];
+#[cfg(target_arch = "arm")]
+const BUNDLED: &[&str] = &["edk2-arm-code.fd"];
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "arm")))]
+const BUNDLED: &[&str] = &[];
+
+/// Where distributions package firmware separately from QEMU.
+#[cfg(target_arch = "x86_64")]
+const SYSTEM: &[&str] = &[
+    "/usr/share/OVMF/OVMF_CODE.fd",
+    "/usr/share/ovmf/OVMF.fd",
+    "/usr/share/edk2/ovmf/OVMF_CODE.fd",
+    "/usr/share/edk2/x64/OVMF_CODE.fd",
+];
+#[cfg(target_arch = "aarch64")]
+const SYSTEM: &[&str] = &[
+    "/usr/share/AAVMF/AAVMF_CODE.fd",
+    "/usr/share/qemu-efi-aarch64/QEMU_EFI.fd",
+    "/usr/share/edk2/aarch64/QEMU_EFI.fd",
+];
+#[cfg(target_arch = "arm")]
+const SYSTEM: &[&str] = &["/usr/share/AAVMF/AAVMF32_CODE.fd"];
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "arm")))]
+const SYSTEM: &[&str] = &[];
+
+/// The first firmware for this architecture found next to `qemu` or in the usual system
+/// locations.
+pub fn discover(qemu: &str) -> Option<PathBuf> {
+    prefixes(qemu)
+        .into_iter()
+        .flat_map(|prefix| {
+            BUNDLED
+                .iter()
+                .map(move |name| path![prefix / "share" / "qemu" / name])
+        })
+        .chain(SYSTEM.iter().map(PathBuf::from))
+        .find(|path| path.is_file())
+}
+
+/// Install prefixes of QEMU, both as found in PATH and with symlinks resolved, which leads from
+/// Homebrew's `bin` into its cellar and from a Nix profile into the store.
+fn prefixes(qemu: &str) -> Vec<PathBuf> {
+    let Some(binary) = version::which(qemu) else {
+        return Vec::new();
+    };
+    let resolved = fs::canonicalize(&binary).ok();
+    iter::once(binary)
+        .chain(resolved)
+        .filter_map(|binary| Some(binary.parent()?.parent()?.to_path_buf()))
+        .collect()
+}
+
+/// How a virtual machine boots: from UEFI firmware, or from QEMU's SeaBIOS for legacy x86
+/// guests that don't boot under OVMF.
+#[derive(Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum FirmwareMode {
+    Bios,
+    #[default]
+    Uefi,
+}
+
+impl FirmwareMode {
+    pub fn is_uefi(&self) -> bool {
+        *self == Self::Uefi
+    }
+}
+
+impl FromStr for FirmwareMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bios" if arch::HAS_BIOS => Ok(Self::Bios),
+            "bios" => Err(Error::BiosUnsupported),
+            "uefi" => Ok(Self::Uefi),
+            _ => Err(Error::InvalidFirmwareMode { mode: s.into() }),
+        }
+    }
+}
+
+impl TryFrom<String> for FirmwareMode {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<FirmwareMode> for String {
+    fn from(mode: FirmwareMode) -> Self {
+        mode.to_string()
+    }
+}
+
+impl fmt::Display for FirmwareMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bios => write!(f, "bios"),
+            Self::Uefi => write!(f, "uefi"),
+        }
+    }
+}
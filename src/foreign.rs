@@ -0,0 +1,277 @@
+use crate::{error::Error, exec::Traced, result::Result, size::Size};
+use path_macro::path;
+use std::{
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+};
+
+const UTM_DOCUMENTS_PATH: &str = "Library/Containers/com.utmapp.UTM/Data/Documents";
+const MULTIPASS_INSTANCE_PATHS: [&str; 2] = [
+    "/var/snap/multipass/common/data/multipassd/vault/instances",
+    "/var/root/Library/Application Support/multipassd/qemu/vault/instances",
+];
+const DISK_EXTENSIONS: [&str; 4] = ["qcow2", "img", "raw", "vmdk"];
+// OVF resource types of virtual hardware items.
+const OVF_CPU: &str = "3";
+const OVF_MEMORY: &str = "4";
+
+#[derive(Clone, Copy)]
+pub enum Source {
+    Utm,
+    Libvirt,
+    Multipass,
+}
+
+impl FromStr for Source {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "utm" => Ok(Self::Utm),
+            "libvirt" => Ok(Self::Libvirt),
+            "multipass" => Ok(Self::Multipass),
+            _ => Err(Error::InvalidSource { tool: s.into() }),
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Utm => "utm",
+                Self::Libvirt => "libvirt",
+                Self::Multipass => "multipass",
+            }
+        )
+    }
+}
+
+pub struct ForeignMachine {
+    pub name: String,
+    pub disk: PathBuf,
+    pub cores: Option<usize>,
+    pub ram: Option<Size>,
+}
+
+impl Source {
+    pub fn discover(&self) -> Result<Vec<ForeignMachine>> {
+        match self {
+            Self::Utm => discover_utm(),
+            Self::Libvirt => discover_libvirt(),
+            Self::Multipass => discover_multipass(),
+        }
+    }
+}
+
+fn first_disk(dir: &Path) -> Option<PathBuf> {
+    let mut disks = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| DISK_EXTENSIONS.contains(&ext))
+        })
+        .collect::<Vec<_>>();
+    disks.sort();
+    disks.into_iter().next()
+}
+
+fn between<'a>(haystack: &'a str, start: &str, end: &str) -> Option<&'a str> {
+    let rest = &haystack[haystack.find(start)? + start.len()..];
+    Some(&rest[..rest.find(end)?])
+}
+
+fn plist_integer(plist: &str, key: &str) -> Option<usize> {
+    let rest = &plist[plist.find(&format!("<key>{key}</key>"))?..];
+    between(rest, "<integer>", "</integer>")?
+        .trim()
+        .parse()
+        .ok()
+}
+
+fn discover_utm() -> Result<Vec<ForeignMachine>> {
+    let documents = path![env::var("HOME")? / UTM_DOCUMENTS_PATH];
+    let Ok(entries) = fs::read_dir(documents) else {
+        return Ok(Vec::new());
+    };
+
+    let mut machines = Vec::new();
+    for bundle in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+        if bundle.extension().and_then(|ext| ext.to_str()) != Some("utm") {
+            continue;
+        }
+        let Some(disk) = first_disk(&path![bundle / "Data"]) else {
+            continue;
+        };
+        let plist = fs::read_to_string(path![bundle / "config.plist"]).unwrap_or_default();
+        machines.push(ForeignMachine {
+            name: bundle
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or_default()
+                .into(),
+            disk,
+            cores: plist_integer(&plist, "CPUCount"),
+            ram: plist_integer(&plist, "MemorySize").map(|mib| Size::bytes((mib as u64) << 20)),
+        });
+    }
+    Ok(machines)
+}
+
+fn discover_libvirt() -> Result<Vec<ForeignMachine>> {
+    let output = Command::new("virsh")
+        .args(["list", "--all", "--name"])
+        .traced()?
+        .output()?;
+
+    let mut machines = Vec::new();
+    for name in String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        let xml = String::from_utf8_lossy(
+            &Command::new("virsh")
+                .args(["dumpxml", name])
+                .traced()?
+                .output()?
+                .stdout,
+        )
+        .into_owned();
+        let Some(disk) = xml
+            .split("<disk ")
+            .skip(1)
+            .filter(|disk| disk.contains("device='disk'"))
+            .find_map(|disk| between(disk, "<source file='", "'"))
+        else {
+            continue;
+        };
+        machines.push(ForeignMachine {
+            name: name.into(),
+            disk: disk.into(),
+            cores: between(&xml, "<vcpu", "</vcpu>")
+                .and_then(|vcpu| vcpu.rsplit('>').next())
+                .and_then(|vcpu| vcpu.trim().parse().ok()),
+            ram: between(&xml, "<memory unit='KiB'>", "</memory>")
+                .and_then(|kib| kib.trim().parse::<u64>().ok())
+                .map(|kib| Size::bytes(kib << 10)),
+        });
+    }
+    Ok(machines)
+}
+
+fn discover_multipass() -> Result<Vec<ForeignMachine>> {
+    let output = Command::new("multipass")
+        .args(["info", "--all", "--format", "json"])
+        .traced()?
+        .output()?;
+    let info: serde_json::Value =
+        serde_json::from_slice(&output.stdout).unwrap_or(serde_json::Value::Null);
+
+    let Some(instances) = info["info"].as_object() else {
+        return Ok(Vec::new());
+    };
+
+    let mut machines = Vec::new();
+    for (name, instance) in instances {
+        let Some(disk) = MULTIPASS_INSTANCE_PATHS
+            .iter()
+            .find_map(|dir| first_disk(&path![dir / name]))
+        else {
+            continue;
+        };
+        machines.push(ForeignMachine {
+            name: name.clone(),
+            disk,
+            cores: instance["cpu_count"]
+                .as_str()
+                .and_then(|cores| cores.parse().ok()),
+            ram: instance["memory"]["total"].as_u64().map(Size::bytes),
+        });
+    }
+    Ok(machines)
+}
+
+/// Unpacks a Vagrant box or OVA into `staging`, finding its disk and the cores and RAM its OVF
+/// descriptor or Vagrantfile asks for.
+pub fn unpack_box(archive: &Path, staging: &Path) -> Result<ForeignMachine> {
+    // tar works out gzip compression, which boxes often use, on its own.
+    let status = Command::new("tar")
+        .arg("-xf")
+        .arg(archive)
+        .arg("-C")
+        .arg(staging)
+        .traced()?
+        .status()?;
+    let invalid = || Error::InvalidArchive {
+        path: archive.into(),
+    };
+    if !status.success() {
+        return Err(invalid());
+    }
+
+    let disk = first_disk(staging).ok_or_else(invalid)?;
+    let name = archive
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default()
+        .into();
+
+    let ovf = fs::read_dir(staging)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .find(|path| path.extension().and_then(|ext| ext.to_str()) == Some("ovf"))
+        .and_then(|path| fs::read_to_string(path).ok());
+    if let Some(ovf) = ovf {
+        return Ok(ForeignMachine {
+            name,
+            disk,
+            cores: ovf_quantity(&ovf, OVF_CPU).map(|(cores, _)| cores as usize),
+            ram: ovf_quantity(&ovf, OVF_MEMORY).map(|(quantity, units)| {
+                // Memory is in megabytes unless the descriptor says otherwise.
+                let shift = if units.contains("2^30") || units.starts_with("Giga") {
+                    30
+                } else {
+                    20
+                };
+                Size::bytes(quantity << shift)
+            }),
+        });
+    }
+
+    let vagrantfile = fs::read_to_string(path![staging / "Vagrantfile"]).unwrap_or_default();
+    Ok(ForeignMachine {
+        name,
+        disk,
+        cores: vagrant_setting(&vagrantfile, "cpus").map(|cores| cores as usize),
+        ram: vagrant_setting(&vagrantfile, "memory").map(|mib| Size::bytes(mib << 20)),
+    })
+}
+
+/// The quantity and allocation units of the first OVF virtual hardware item of a resource type.
+fn ovf_quantity<'a>(ovf: &'a str, resource_type: &str) -> Option<(u64, &'a str)> {
+    let item = ovf
+        .split("</Item>")
+        .find(|item| between(item, "ResourceType>", "<").map(str::trim) == Some(resource_type))?;
+    let quantity = between(item, "VirtualQuantity>", "<")?
+        .trim()
+        .parse()
+        .ok()?;
+    let units = between(item, "AllocationUnits>", "<").unwrap_or_default();
+    Some((quantity, units))
+}
+
+/// A provider setting such as `v.memory = 2048` in a box's bundled Vagrantfile.
+fn vagrant_setting(vagrantfile: &str, key: &str) -> Option<u64> {
+    vagrantfile.lines().find_map(|line| {
+        let (lhs, rhs) = line.split_once('=')?;
+        lhs.trim()
+            .ends_with(&format!(".{key}"))
+            .then(|| rhs.trim().trim_matches('"').parse().ok())?
+    })
+}
@@ -0,0 +1,50 @@
+use crate::{result::Result, time};
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+/// One command that changed something, as recorded in the append-only history log.
+#[derive(Deserialize, Serialize)]
+pub struct Entry {
+    pub at: u64,
+    pub user: String,
+    pub command: Vec<String>,
+    pub result: String,
+}
+
+impl Entry {
+    pub fn new(command: Vec<String>, result: String) -> Self {
+        Self {
+            at: time::now(),
+            user: env::var("USER").unwrap_or_else(|_| "unknown".into()),
+            command,
+            result,
+        }
+    }
+
+    /// Whether the command named the given resource.
+    pub fn involves(&self, name: &str) -> bool {
+        self.command.iter().any(|arg| arg == name)
+    }
+}
+
+pub fn append<P: AsRef<Path>>(path: P, entry: &Entry) -> Result<()> {
+    let mut log = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(log, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+/// Reads the log, oldest first, skipping lines that can't be parsed.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Entry>> {
+    if fs::metadata(&path).is_err() {
+        return Ok(Vec::new());
+    }
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
@@ -0,0 +1,343 @@
+use crate::{
+    cancel,
+    error::Error,
+    exec::Traced,
+    progress::{self, Event, ProgressFormat},
+    remote::{self, Remote},
+    result::Result,
+    size::Size,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, fs,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    str::FromStr,
+    thread,
+    time::Duration,
+};
+
+pub(crate) const QEMU_IMAGER: &str = "qemu-img";
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    Qcow2,
+    Raw,
+    Vmdk,
+    Vdi,
+}
+
+impl FromStr for ImageFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "qcow2" => Ok(Self::Qcow2),
+            "raw" => Ok(Self::Raw),
+            "vmdk" => Ok(Self::Vmdk),
+            "vdi" => Ok(Self::Vdi),
+            _ => Err(Error::InvalidImageFormat { format: s.into() }),
+        }
+    }
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Qcow2 => "qcow2",
+                Self::Raw => "raw",
+                Self::Vmdk => "vmdk",
+                Self::Vdi => "vdi",
+            }
+        )
+    }
+}
+
+/// What `ImageBackend::info` reports about an image.
+#[derive(Clone)]
+pub struct ImageInfo {
+    pub format: ImageFormat,
+    pub virtual_size: Size,
+    pub backing: Option<PathBuf>,
+}
+
+/// The operations `State` performs on disk images, so that images can be managed by something
+/// other than qemu-img (or by a mock in tests).
+pub trait ImageBackend: Send + Sync {
+    /// Creates a blank qcow2 image of `size` at `path`.
+    fn create(&self, path: &Path, size: Size) -> Result<()>;
+
+    /// Creates a qcow2 overlay at `path` backed by `backing`, reporting progress for `name`.
+    fn snapshot(&self, name: &str, backing: &Path, format: ImageFormat, path: &Path) -> Result<()>;
+
+    /// Converts `src` to a qcow2 image at `dst`, reporting progress for `name`.
+    fn convert(
+        &self,
+        name: &str,
+        src: &Path,
+        format: ImageFormat,
+        dst: &Path,
+        compress: bool,
+    ) -> Result<()>;
+
+    /// Points the overlay at `path` to a new backing image without copying any data.
+    fn rebase(&self, path: &Path, backing: &Path, format: ImageFormat) -> Result<()>;
+
+    fn resize(&self, path: &Path, size: Size, shrink: bool) -> Result<()>;
+
+    fn info(&self, path: &Path) -> Result<ImageInfo>;
+
+    /// Bytes of guest data written to the image itself rather than read from its backing chain.
+    fn allocated(&self, path: &Path) -> Result<u64>;
+
+    /// Checks an image for corruption, returning whether it is consistent.
+    fn check(&self, path: &Path) -> Result<bool>;
+}
+
+/// The default backend, running qemu-img locally or on a remote host.
+pub struct QemuImg<'a> {
+    remote: Option<&'a Remote>,
+    program: &'a str,
+    progress: ProgressFormat,
+}
+
+impl<'a> QemuImg<'a> {
+    pub fn new(remote: Option<&'a Remote>, program: &'a str, progress: ProgressFormat) -> Self {
+        Self {
+            remote,
+            program,
+            progress,
+        }
+    }
+
+    fn command(&self) -> Command {
+        remote::command(self.remote, self.program)
+    }
+
+    /// Runs qemu-img, reporting `-p` progress if requested and removing `output` if it fails or
+    /// is cancelled with Ctrl-C.
+    fn run(&self, operation: &str, name: &str, mut cmd: Command, output: &Path) -> Result<bool> {
+        let _watch = cancel::watch();
+
+        let mut child = cmd.stdout(Stdio::piped()).traced()?.spawn()?;
+        let stdout = child.stdout.take();
+        let progress = self.progress;
+        let (operation, name) = (operation.to_string(), name.to_string());
+        let reporter = thread::spawn(move || {
+            for chunk in stdout
+                .into_iter()
+                .flat_map(|stdout| BufReader::new(stdout).split(b'\r'))
+                .map_while(|chunk| chunk.ok())
+            {
+                if let Some(percent) =
+                    progress::parse_qemu_img_progress(&String::from_utf8_lossy(&chunk))
+                {
+                    progress.emit(&Event::Progress {
+                        operation: &operation,
+                        name: &name,
+                        percent,
+                    });
+                }
+            }
+        });
+
+        let status = loop {
+            if cancel::cancelled() {
+                child.kill().ok();
+                child.wait()?;
+                break None;
+            }
+            if let Some(status) = child.try_wait()? {
+                break Some(status);
+            }
+            thread::sleep(POLL_INTERVAL);
+        };
+        reporter.join().ok();
+
+        match status {
+            Some(status) if !cancel::cancelled() => {
+                if !status.success() {
+                    self.remove(output);
+                }
+                Ok(status.success())
+            }
+            _ => {
+                self.remove(output);
+                Err(Error::Cancelled)
+            }
+        }
+    }
+
+    /// Best-effort removal of a partially written image.
+    fn remove(&self, path: &Path) {
+        match self.remote {
+            Some(remote) => {
+                if let Ok(cmd) = remote::command(Some(remote), "rm")
+                    .arg("-f")
+                    .arg(path)
+                    .traced()
+                {
+                    cmd.status().ok();
+                }
+            }
+            None => {
+                fs::remove_file(path).ok();
+            }
+        }
+    }
+}
+
+impl ImageBackend for QemuImg<'_> {
+    fn create(&self, path: &Path, size: Size) -> Result<()> {
+        let status = self
+            .command()
+            .arg("create")
+            .args(["-f", "qcow2"])
+            .arg(path)
+            .arg(format!("{}", size.as_bytes()))
+            .traced()?
+            .status()?;
+        if !status.success() {
+            return Err(Error::InvalidImage { path: path.into() });
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self, name: &str, backing: &Path, format: ImageFormat, path: &Path) -> Result<()> {
+        let mut cmd = self.command();
+        cmd.arg("create")
+            .args(["-f", "qcow2"])
+            .arg("-b")
+            .arg(backing)
+            .args(["-F", &format!("{format}")])
+            .arg(path);
+        if !self.run("snapshot", name, cmd, path)? {
+            return Err(Error::InvalidImage {
+                path: backing.into(),
+            });
+        }
+        Ok(())
+    }
+
+    fn convert(
+        &self,
+        name: &str,
+        src: &Path,
+        format: ImageFormat,
+        dst: &Path,
+        compress: bool,
+    ) -> Result<()> {
+        let mut cmd = self.command();
+        cmd.arg("convert")
+            .args(["-f", &format!("{format}")])
+            .args(["-O", "qcow2"]);
+        if compress {
+            cmd.arg("-c");
+        }
+        if self.progress != ProgressFormat::None {
+            cmd.arg("-p");
+        }
+        cmd.arg(src).arg(dst);
+
+        if !self.run("convert", name, cmd, dst)? {
+            return Err(Error::InvalidImage { path: src.into() });
+        }
+        Ok(())
+    }
+
+    fn rebase(&self, path: &Path, backing: &Path, format: ImageFormat) -> Result<()> {
+        let status = self
+            .command()
+            .arg("rebase")
+            .arg("-u")
+            .arg("-b")
+            .arg(backing)
+            .args(["-F", &format!("{format}")])
+            .arg(path)
+            .traced()?
+            .status()?;
+        if !status.success() {
+            return Err(Error::InvalidImage { path: path.into() });
+        }
+        Ok(())
+    }
+
+    fn resize(&self, path: &Path, size: Size, shrink: bool) -> Result<()> {
+        let mut cmd = self.command();
+        cmd.arg("resize");
+        if shrink {
+            cmd.arg("--shrink");
+        }
+        let status = cmd
+            .arg(path)
+            .arg(format!("{}", size.as_bytes()))
+            .traced()?
+            .status()?;
+        if !status.success() {
+            return Err(Error::InvalidImage { path: path.into() });
+        }
+        Ok(())
+    }
+
+    fn info(&self, path: &Path) -> Result<ImageInfo> {
+        let output = self
+            .command()
+            .arg("info")
+            .args(["--output", "json"])
+            .arg(path)
+            .traced()?
+            .output()?;
+        if !output.status.success() {
+            return Err(Error::CommandFailed {
+                command: "qemu-img info".into(),
+                stderr: String::from_utf8_lossy(&output.stderr).trim().into(),
+            });
+        }
+        let invalid = || Error::InvalidImage { path: path.into() };
+        let info: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+        Ok(ImageInfo {
+            format: info["format"].as_str().ok_or_else(invalid)?.parse()?,
+            virtual_size: Size::bytes(info["virtual-size"].as_u64().ok_or_else(invalid)?),
+            backing: info["backing-filename"].as_str().map(PathBuf::from),
+        })
+    }
+
+    fn allocated(&self, path: &Path) -> Result<u64> {
+        let output = self
+            .command()
+            .arg("map")
+            .args(["--output", "json"])
+            .arg(path)
+            .traced()?
+            .output()?;
+        if !output.status.success() {
+            return Err(Error::CommandFailed {
+                command: "qemu-img map".into(),
+                stderr: String::from_utf8_lossy(&output.stderr).trim().into(),
+            });
+        }
+        let extents: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout)?;
+        Ok(extents
+            .iter()
+            .filter(|extent| extent["depth"] == 0 && extent["data"] == true)
+            .filter_map(|extent| extent["length"].as_u64())
+            .sum())
+    }
+
+    fn check(&self, path: &Path) -> Result<bool> {
+        Ok(self
+            .command()
+            .arg("check")
+            .arg(path)
+            .traced()?
+            .output()?
+            .status
+            .success())
+    }
+}
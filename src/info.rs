@@ -0,0 +1,75 @@
+use crate::{
+    firmware::FirmwareMode, network::Network, os::GuestOs, restart::RestartPolicy, size::Size,
+    status::MachineStatus,
+};
+use serde::Serialize;
+use std::{collections::BTreeMap, path::PathBuf};
+
+/// A disk as `rhea disks` sees it, returned by `State::describe_disk`.
+#[derive(Clone, Serialize)]
+pub struct DiskInfo {
+    pub name: String,
+    pub size: Size,
+    pub path: PathBuf,
+    /// Bytes allocated to the image on the host.
+    pub size_on_disk: u64,
+    pub in_use: bool,
+    pub description: Option<String>,
+    pub sha256: Option<String>,
+    pub created_at: Option<u64>,
+    pub last_started_at: Option<u64>,
+}
+
+/// A virtual machine as `rhea machines` sees it, returned by `State::describe_machine`.
+#[derive(Clone, Serialize)]
+pub struct MachineInfo {
+    pub name: String,
+    pub port: u16,
+    pub size: Size,
+    pub network: Network,
+    pub isolated: bool,
+    pub firmware: FirmwareMode,
+    pub os: Option<GuestOs>,
+    pub status: MachineStatus,
+    /// The QEMU process, if the machine is running.
+    pub pid: Option<u32>,
+    pub path: PathBuf,
+    pub log_path: PathBuf,
+    /// Bytes allocated to the image on the host.
+    pub size_on_disk: u64,
+    pub restart: RestartPolicy,
+    pub iso: Option<PathBuf>,
+    pub description: Option<String>,
+    pub sha256: Option<String>,
+    pub created_at: Option<u64>,
+    pub last_started_at: Option<u64>,
+    pub meta: BTreeMap<String, String>,
+}
+
+/// A snapshot as `rhea snapshots` sees it, returned by `State::describe_snapshot`.
+#[derive(Clone, Serialize)]
+pub struct SnapshotInfo {
+    pub name: String,
+    pub base: String,
+    pub parent: Option<String>,
+    pub port: u16,
+    pub size: Size,
+    /// Cores and RAM the snapshot starts with unless told otherwise.
+    pub cores: Option<usize>,
+    pub ram: Option<Size>,
+    pub full: bool,
+    pub description: Option<String>,
+    /// See `State::snapshot_stale`.
+    pub stale: Option<bool>,
+    pub status: MachineStatus,
+    /// The QEMU process, if the snapshot is running.
+    pub pid: Option<u32>,
+    pub path: PathBuf,
+    /// The image the snapshot is backed by, unless it is a full copy.
+    pub backing_path: Option<PathBuf>,
+    pub log_path: PathBuf,
+    /// Bytes allocated to the image on the host.
+    pub size_on_disk: u64,
+    pub created_at: Option<u64>,
+    pub last_started_at: Option<u64>,
+}
@@ -0,0 +1,73 @@
+use crate::{arch, error::Error};
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// Pointer device for graphical guests. A tablet reports absolute positions, so the pointer
+/// follows the host cursor without being grabbed; a mouse is relative and needs a grab.
+#[derive(Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum InputDevice {
+    #[default]
+    Tablet,
+    Mouse,
+}
+
+impl InputDevice {
+    pub fn is_tablet(&self) -> bool {
+        *self == Self::Tablet
+    }
+
+    /// Arguments for a USB controller with the pointer device and a keyboard on it.
+    pub fn qemu_args(&self) -> Vec<String> {
+        if !arch::HAS_USB {
+            return Vec::new();
+        }
+        let pointer = match self {
+            Self::Tablet => "usb-tablet",
+            Self::Mouse => "usb-mouse",
+        };
+        vec![
+            "-device".into(),
+            "qemu-xhci,id=usb0".into(),
+            "-device".into(),
+            format!("{pointer},bus=usb0.0"),
+            "-device".into(),
+            "usb-kbd,bus=usb0.0".into(),
+        ]
+    }
+}
+
+impl FromStr for InputDevice {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tablet" => Ok(Self::Tablet),
+            "mouse" => Ok(Self::Mouse),
+            _ => Err(Error::InvalidInputDevice { device: s.into() }),
+        }
+    }
+}
+
+impl TryFrom<String> for InputDevice {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<InputDevice> for String {
+    fn from(device: InputDevice) -> Self {
+        device.to_string()
+    }
+}
+
+impl fmt::Display for InputDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tablet => write!(f, "tablet"),
+            Self::Mouse => write!(f, "mouse"),
+        }
+    }
+}
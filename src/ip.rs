@@ -0,0 +1,133 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::{fmt, fs, net::IpAddr};
+
+#[cfg(target_os = "macos")]
+const LEASE_PATHS: [&str; 1] = ["/var/db/dhcpd_leases"];
+#[cfg(not(target_os = "macos"))]
+const LEASE_PATHS: [&str; 1] = ["/var/lib/libvirt/dnsmasq/virbr0.status"];
+const ARP_PATH: &str = "/proc/net/arp";
+
+/// Where a guest address was learned from.
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressSource {
+    Agent,
+    Lease,
+    Arp,
+}
+
+impl fmt::Display for AddressSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Agent => write!(f, "agent"),
+            Self::Lease => write!(f, "lease"),
+            Self::Arp => write!(f, "arp"),
+        }
+    }
+}
+
+pub struct GuestAddress {
+    pub interface: Option<String>,
+    pub address: IpAddr,
+    pub source: AddressSource,
+}
+
+/// Addresses from the guest agent's `guest-network-get-interfaces`, leaving out loopback.
+pub fn from_agent(interfaces: &Value) -> Vec<GuestAddress> {
+    let mut addresses = Vec::new();
+    for interface in interfaces.as_array().into_iter().flatten() {
+        let name = interface["name"].as_str().map(String::from);
+        for address in interface["ip-addresses"].as_array().into_iter().flatten() {
+            let Some(address) = address["ip-address"]
+                .as_str()
+                .and_then(|address| address.parse::<IpAddr>().ok())
+                .filter(|address| !address.is_loopback())
+            else {
+                continue;
+            };
+            addresses.push(GuestAddress {
+                interface: name.clone(),
+                address,
+                source: AddressSource::Agent,
+            });
+        }
+    }
+    addresses
+}
+
+/// Addresses the host handed out to or has seen from the given MAC address.
+pub fn from_host(mac: &str) -> Vec<GuestAddress> {
+    let Some(mac) = parse_mac(mac) else {
+        return Vec::new();
+    };
+    let mut addresses = LEASE_PATHS
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .flat_map(|leases| parse_leases(&leases, mac))
+        .map(|address| GuestAddress {
+            interface: None,
+            address,
+            source: AddressSource::Lease,
+        })
+        .collect::<Vec<_>>();
+    if let Ok(arp) = fs::read_to_string(ARP_PATH) {
+        for address in parse_arp(&arp, mac) {
+            if !addresses.iter().any(|known| known.address == address) {
+                addresses.push(GuestAddress {
+                    interface: None,
+                    address,
+                    source: AddressSource::Arp,
+                });
+            }
+        }
+    }
+    addresses
+}
+
+/// Parses a MAC address, accepting the unpadded octets macOS writes (e.g. `52:54:0:0:4:d2`).
+fn parse_mac(mac: &str) -> Option<[u8; 6]> {
+    let octets = mac
+        .split(':')
+        .map(|octet| u8::from_str_radix(octet, 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+    octets.try_into().ok()
+}
+
+/// Both the macOS `dhcpd_leases` blocks and the libvirt JSON status list the address before the
+/// MAC address within each entry.
+fn parse_leases(leases: &str, mac: [u8; 6]) -> Vec<IpAddr> {
+    if let Ok(Value::Array(entries)) = serde_json::from_str(leases) {
+        return entries
+            .iter()
+            .filter(|entry| entry["mac-address"].as_str().and_then(parse_mac) == Some(mac))
+            .filter_map(|entry| entry["ip-address"].as_str()?.parse().ok())
+            .collect();
+    }
+
+    let mut addresses = Vec::new();
+    let mut address: Option<IpAddr> = None;
+    for line in leases.lines().map(str::trim) {
+        if let Some(ip) = line.strip_prefix("ip_address=") {
+            address = ip.parse().ok();
+        } else if let Some(hw) = line.strip_prefix("hw_address=") {
+            let hw = hw.split_once(',').map_or(hw, |(_, hw)| hw);
+            if parse_mac(hw) == Some(mac) {
+                addresses.extend(address.take());
+            }
+        }
+    }
+    addresses
+}
+
+fn parse_arp(arp: &str, mac: [u8; 6]) -> Vec<IpAddr> {
+    arp.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            (fields.get(3).copied().and_then(parse_mac) == Some(mac))
+                .then(|| fields[0].parse().ok())
+                .flatten()
+        })
+        .collect()
+}
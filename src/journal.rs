@@ -0,0 +1,80 @@
+use crate::{result::Result, runtime::Runtime, size::Size, time};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+/// How a boot ended, as far as rhea could tell.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExitReason {
+    /// Stopped with `rhea stop`, or by the guest powering itself off.
+    Stopped,
+    /// Suspended to disk.
+    Suspended,
+    /// Exited without being asked to.
+    Crashed,
+    /// Exited before it finished starting.
+    Failed,
+}
+
+impl fmt::Display for ExitReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stopped => write!(f, "stopped"),
+            Self::Suspended => write!(f, "suspended"),
+            Self::Crashed => write!(f, "crashed"),
+            Self::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// One boot of a virtual machine or snapshot, as recorded in its append-only journal.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Boot {
+    pub started_at: u64,
+    /// When the exit was noticed, which for a crash can be some time after it happened.
+    pub stopped_at: u64,
+    pub reason: ExitReason,
+    pub cores: usize,
+    pub ram: Size,
+    #[serde(default)]
+    pub disks: Vec<String>,
+}
+
+impl Boot {
+    pub fn new(runtime: &Runtime, reason: ExitReason) -> Self {
+        Self {
+            started_at: runtime.started_at,
+            stopped_at: time::now(),
+            reason,
+            cores: runtime.cores,
+            ram: runtime.ram,
+            disks: runtime.disks.clone(),
+        }
+    }
+
+    pub fn uptime(&self) -> u64 {
+        self.stopped_at.saturating_sub(self.started_at)
+    }
+}
+
+pub fn append<P: AsRef<Path>>(path: P, boot: &Boot) -> Result<()> {
+    let mut journal = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(journal, "{}", serde_json::to_string(boot)?)?;
+    Ok(())
+}
+
+/// Reads the journal, oldest first, skipping lines that can't be parsed.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Boot>> {
+    if fs::metadata(&path).is_err() {
+        return Ok(Vec::new());
+    }
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
@@ -0,0 +1,49 @@
+use crate::{error::Error, result::Result};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// A kernel to boot directly instead of going through the firmware and bootloader.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct KernelBoot {
+    pub kernel: Option<PathBuf>,
+    pub initrd: Option<PathBuf>,
+    pub append: Option<String>,
+}
+
+impl KernelBoot {
+    pub fn is_empty(&self) -> bool {
+        self.kernel.is_none() && self.initrd.is_none() && self.append.is_none()
+    }
+
+    pub fn or(&self, fallback: &KernelBoot) -> KernelBoot {
+        if self.is_empty() {
+            fallback.clone()
+        } else {
+            self.clone()
+        }
+    }
+
+    pub fn qemu_args(&self) -> Result<Vec<String>> {
+        let Some(kernel) = &self.kernel else {
+            return if self.is_empty() {
+                Ok(Vec::new())
+            } else {
+                Err(Error::MissingKernel)
+            };
+        };
+
+        let path = |path: &PathBuf| {
+            path.to_str()
+                .map(String::from)
+                .ok_or(Error::InvalidPath { path: path.clone() })
+        };
+        let mut args = vec!["-kernel".into(), path(kernel)?];
+        if let Some(initrd) = &self.initrd {
+            args.extend(["-initrd".into(), path(initrd)?]);
+        }
+        if let Some(append) = &self.append {
+            args.extend(["-append".into(), append.clone()]);
+        }
+        Ok(args)
+    }
+}
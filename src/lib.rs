@@ -1,6 +1,64 @@
+pub mod arch;
+pub mod cancel;
+pub mod checksum;
+pub mod ci;
+pub mod config;
+pub mod console;
+pub mod crash;
 pub mod disk;
 pub mod error;
+pub mod exec;
+pub mod firmware;
+pub mod foreign;
+pub mod history;
+pub mod image;
+pub mod info;
+pub mod input;
+pub mod ip;
+pub mod journal;
+pub mod kernel;
+pub mod libvirt;
+pub mod logs;
 pub mod machine;
+pub mod manifest;
+pub mod migration;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod namespace;
+pub mod network;
+pub mod notify;
+pub mod os;
+pub mod ports;
+pub mod preset;
+pub mod probe;
+pub mod process;
+pub mod profile;
+pub mod progress;
+pub mod provision;
+pub mod qmp;
+pub mod reference;
+pub mod registry;
+pub mod remote;
+pub mod restart;
 pub mod result;
+pub mod runtime;
+pub mod shaping;
+pub mod shared;
+pub mod size;
 pub mod snapshot;
+pub mod ssh;
+pub mod start;
 pub mod state;
+pub mod status;
+pub mod supervisor;
+pub mod system;
+#[cfg(feature = "async")]
+pub mod tasks;
+pub mod time;
+pub mod trash;
+pub mod usage;
+pub mod version;
+
+pub use error::Error;
+pub use result::Result;
+pub use state::State;
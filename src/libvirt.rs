@@ -0,0 +1,101 @@
+use crate::size::Size;
+use std::{env::consts::ARCH, path::Path};
+
+fn arch() -> &'static str {
+    match ARCH {
+        "powerpc" => "ppc",
+        "powerpc64" => "ppc64",
+        "arm" => "armv7l",
+        arch => arch,
+    }
+}
+
+fn machine_type() -> &'static str {
+    match ARCH {
+        "x86_64" => "q35",
+        "aarch64" | "arm" => "virt",
+        _ => "",
+    }
+}
+
+/// Escapes text for use in XML content and quoted attributes.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '\'' => escaped.push_str("&apos;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The device name of the disk at `index`, going on from `vdz` to `vdaa` as Linux does.
+fn disk_name(index: usize) -> String {
+    let mut letters = Vec::new();
+    let mut n = index + 1;
+    while n > 0 {
+        n -= 1;
+        letters.push(char::from(b'a' + (n % 26) as u8));
+        n /= 26;
+    }
+    letters
+        .into_iter()
+        .rev()
+        .fold("vd".into(), |mut name, letter| {
+            name.push(letter);
+            name
+        })
+}
+
+fn disk_xml(path: &Path, index: usize) -> String {
+    format!(
+        "    <disk type='file' device='disk'>\n      <driver name='qemu' type='qcow2'/>\n      <source file='{}'/>\n      <target dev='{}' bus='virtio'/>\n    </disk>\n",
+        escape(&path.to_string_lossy()),
+        disk_name(index)
+    )
+}
+
+pub fn domain_xml<P: AsRef<Path>>(name: &str, disks: &[P], cores: usize, ram: Size) -> String {
+    let machine = match machine_type() {
+        "" => String::new(),
+        machine => format!(" machine='{machine}'"),
+    };
+    let disks = disks
+        .iter()
+        .enumerate()
+        .map(|(index, disk)| disk_xml(disk.as_ref(), index))
+        .collect::<String>();
+    let name = escape(name);
+
+    format!(
+        "<domain type='kvm'>\n  <name>{name}</name>\n  <memory unit='KiB'>{}</memory>\n  <vcpu>{cores}</vcpu>\n  <os firmware='efi'>\n    <type arch='{}'{machine}>hvm</type>\n  </os>\n  <cpu mode='host-passthrough'/>\n  <devices>\n{disks}    <interface type='network'>\n      <source network='default'/>\n      <model type='virtio'/>\n    </interface>\n    <serial type='pty'/>\n    <console type='pty'/>\n  </devices>\n</domain>\n",
+        ram.as_kilobytes(),
+        arch()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disks_are_named_past_z() {
+        assert_eq!(disk_name(0), "vda");
+        assert_eq!(disk_name(25), "vdz");
+        assert_eq!(disk_name(26), "vdaa");
+        assert_eq!(disk_name(27), "vdab");
+        assert_eq!(disk_name(26 * 27), "vdaaa");
+    }
+
+    #[test]
+    fn names_and_paths_are_escaped() {
+        let xml = domain_xml("a<b>&c", &["/tmp/it's.qcow2"], 1, Size::gigabytes(1));
+        assert!(xml.contains("<name>a&lt;b&gt;&amp;c</name>"));
+        assert!(xml.contains("<source file='/tmp/it&apos;s.qcow2'/>"));
+    }
+}
@@ -0,0 +1,206 @@
+//! Console logs that rhea writes itself rather than leaving to QEMU, so that each line carries the
+//! host time it arrived at and guests that run for days don't fill the disk.
+
+use crate::{config::Logs, error::IoContext, result::Result, time};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+/// The hidden subcommand that the output of a virtual machine is piped through.
+pub const WRITER_SUBCOMMAND: &str = "log-writer";
+
+/// Where the log is rotated to, `<log>.1` being the most recent.
+pub fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(format!(".{n}"));
+    path.into()
+}
+
+/// The rotated logs that exist, oldest first, followed by the log itself.
+pub fn files(path: &Path) -> Vec<PathBuf> {
+    let mut files = (1..)
+        .map(|n| rotated_path(path, n))
+        .take_while(|path| path.exists())
+        .collect::<Vec<_>>();
+    files.reverse();
+    files.push(path.into());
+    files
+}
+
+/// Moves the log to `<log>.1`, shifting the rotated logs along and removing those beyond `keep`.
+pub fn rotate(path: &Path, keep: usize) -> Result<()> {
+    for n in (keep..).take_while(|&n| rotated_path(path, n).exists()) {
+        fs::remove_file(rotated_path(path, n))?;
+    }
+    for n in (1..keep).rev() {
+        let from = rotated_path(path, n);
+        if from.exists() {
+            fs::rename(&from, rotated_path(path, n + 1)).at(&from)?;
+        }
+    }
+    fs::rename(path, rotated_path(path, 1)).at(path)?;
+    Ok(())
+}
+
+/// Appends `input` to the log at `path` until the input ends, as `rhea log-writer` does for a
+/// running virtual machine.
+pub fn write<R: BufRead>(mut input: R, path: &Path, config: &Logs) -> Result<()> {
+    let open = || {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .at(path)
+    };
+    let mut log = open()?;
+    let mut size = log.metadata()?.len();
+    let mut line_start = true;
+    loop {
+        let chunk = input.fill_buf()?;
+        if chunk.is_empty() {
+            return Ok(());
+        }
+
+        // Output is written as it arrives rather than a line at a time, so that prompts show up.
+        let mut output = Vec::with_capacity(chunk.len());
+        for part in chunk.split_inclusive(|&b| b == b'\n') {
+            if config.timestamps && line_start {
+                output.extend_from_slice(time::format_timestamp(time::now_millis()).as_bytes());
+                output.push(b' ');
+            }
+            output.extend_from_slice(part);
+            line_start = part.ends_with(b"\n");
+
+            // Logs are only rotated between lines, so that each starts with its timestamp.
+            if config.max_size.is_some_and(|max_size| {
+                line_start && size + output.len() as u64 >= max_size.as_bytes()
+            }) {
+                log.write_all(&output)?;
+                output.clear();
+                rotate(path, config.keep())?;
+                log = open()?;
+                size = 0;
+            }
+        }
+        let consumed = chunk.len();
+        input.consume(consumed);
+
+        log.write_all(&output)?;
+        size += output.len() as u64;
+    }
+}
+
+/// The host time a line was written at, if it was timestamped.
+pub fn timestamp(line: &[u8]) -> Option<u64> {
+    let prefix = line.get(..time::TIMESTAMP_LEN)?;
+    time::parse_timestamp(std::str::from_utf8(prefix).ok()?)
+}
+
+/// Which lines of a log `rhea logs` prints.
+#[derive(Clone, Default)]
+pub struct Filter {
+    since: Option<u64>,
+    grep: Option<Vec<u8>>,
+    last: Option<u64>,
+}
+
+impl Filter {
+    /// Lines written at or after `since`, in milliseconds since the epoch, that contain `grep`.
+    pub fn new(since: Option<u64>, grep: Option<&str>) -> Self {
+        Self {
+            since,
+            grep: grep.map(|grep| grep.as_bytes().to_vec()),
+            last: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.since.is_none() && self.grep.is_none()
+    }
+
+    /// Whether to print a line. Lines without a timestamp, such as those from before timestamps
+    /// were enabled, count as written when the last timestamped line before them was.
+    pub fn matches(&mut self, line: &[u8]) -> bool {
+        if let Some(timestamp) = timestamp(line) {
+            self.last = Some(timestamp);
+        }
+        let recent = self
+            .since
+            .is_none_or(|since| self.last.is_some_and(|last| last >= since));
+        let found = self.grep.as_ref().is_none_or(|grep| {
+            grep.is_empty() || line.windows(grep.len()).any(|window| window == grep)
+        });
+        recent && found
+    }
+}
+
+/// Prints the log at `path` after its rotated logs, only the lines `filter` matches, and keeps
+/// printing it as it grows if `follow`, across rotations.
+pub fn print<W: Write>(
+    path: &Path,
+    mut filter: Filter,
+    follow: bool,
+    poll: Duration,
+    out: &mut W,
+) -> Result<()> {
+    let mut pending = Vec::new();
+    let files = files(path);
+    let Some((current, rotated)) = files.split_last() else {
+        return Ok(());
+    };
+    for file in rotated {
+        let mut reader = BufReader::new(File::open(file).at(file)?);
+        copy_lines(&mut reader, &mut pending, &mut filter, out)?;
+    }
+
+    let mut reader = BufReader::new(File::open(current).at(current)?);
+    loop {
+        copy_lines(&mut reader, &mut pending, &mut filter, out)?;
+        if !follow {
+            if !pending.is_empty() && filter.matches(&pending) {
+                out.write_all(&pending)?;
+            }
+            return Ok(());
+        }
+        out.flush()?;
+
+        // Once the log has been rotated, whatever was written to it before is read to the end
+        // before moving on to its successor.
+        let rotated = fs::metadata(current).is_ok_and(|metadata| {
+            metadata.ino() != reader.get_ref().metadata().map_or(0, |m| m.ino())
+        });
+        if rotated {
+            copy_lines(&mut reader, &mut pending, &mut filter, out)?;
+            reader = BufReader::new(File::open(current).at(current)?);
+            continue;
+        }
+        thread::sleep(poll);
+    }
+}
+
+/// Copies the complete lines `filter` matches from `reader` to `out`, keeping an unfinished last
+/// line in `pending` unless nothing is filtered, in which case it is printed as is.
+fn copy_lines<R: BufRead, W: Write>(
+    reader: &mut R,
+    pending: &mut Vec<u8>,
+    filter: &mut Filter,
+    out: &mut W,
+) -> Result<()> {
+    loop {
+        if reader.read_until(b'\n', pending)? == 0 {
+            return Ok(());
+        }
+        if !pending.ends_with(b"\n") && !filter.is_empty() {
+            return Ok(());
+        }
+        if filter.matches(pending) {
+            out.write_all(pending)?;
+        }
+        pending.clear();
+    }
+}
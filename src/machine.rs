@@ -1,11 +1,138 @@
+use crate::{
+    config::{Notifications, Retry, Timeouts},
+    firmware::FirmwareMode,
+    input::InputDevice,
+    kernel::KernelBoot,
+    network::Network,
+    os::GuestOs,
+    probe::Probe,
+    restart::RestartPolicy,
+    shaping::Shaping,
+    size::Size,
+    ssh::ConnectOptions,
+    time::{self, Expiry},
+};
 use serde::{Deserialize, Serialize};
-use std::fmt;
+use std::{collections::BTreeMap, fmt, path::PathBuf};
 
 #[derive(Deserialize, Serialize, Clone)]
 pub struct Machine {
     pub name: String,
     pub port: u16,
-    pub size: usize,
+    pub size: Size,
+    /// Cores and RAM to start with when `rhea start` isn't given any, such as those an imported
+    /// image asks for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cores: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ram: Option<Size>,
+    #[serde(default, skip_serializing_if = "Network::is_user")]
+    pub network: Network,
+    #[serde(default, skip_serializing_if = "InputDevice::is_tablet")]
+    pub input: InputDevice,
+    #[serde(default, skip_serializing_if = "FirmwareMode::is_uefi")]
+    pub firmware: FirmwareMode,
+    /// What the guest runs, guessed from its installer or image unless given, see `GuestOs`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os: Option<GuestOs>,
+    #[serde(default)]
+    pub identity: Option<PathBuf>,
+    #[serde(default)]
+    pub iso: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "RestartPolicy::is_never")]
+    pub restart: RestartPolicy,
+    /// Whether the guest is cut off from the host and internet, see `network::isolation_args`.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub isolated: bool,
+    /// Host `host:port`s an isolated guest may still connect to.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+    /// Set with `rhea describe`, or to where the machine came from by `rhea import-box`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Scripts or commands run over SSH after the first boot, see `State::provision`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub provision: Vec<String>,
+    /// Named virtio-serial ports exposed to the host as unix sockets.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub channels: Vec<String>,
+    /// QEMU to run instead of the configured one, see `State::binaries`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qemu: Option<String>,
+    /// Command QEMU runs under instead of the configured wrapper, empty for none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wrapper: Option<Vec<String>>,
+    /// Digest of the verified ISO or image the machine was created from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<u64>,
+    #[serde(default)]
+    pub last_started_at: Option<u64>,
+    #[serde(default)]
+    pub provisioned_at: Option<u64>,
+    // TOML needs tables after plain values.
+    #[serde(default)]
+    pub expiry: Option<Expiry>,
+    #[serde(default)]
+    pub probes: BTreeMap<String, Probe>,
+    #[serde(default, skip_serializing_if = "Timeouts::is_empty")]
+    pub timeouts: Timeouts,
+    #[serde(default, skip_serializing_if = "Retry::is_empty")]
+    pub retry: Retry,
+    #[serde(default, skip_serializing_if = "Notifications::is_empty")]
+    pub notifications: Notifications,
+    #[serde(default, skip_serializing_if = "Shaping::is_empty")]
+    pub shaping: Shaping,
+    #[serde(default, skip_serializing_if = "ConnectOptions::is_empty")]
+    pub connect: ConnectOptions,
+    #[serde(default, skip_serializing_if = "KernelBoot::is_empty")]
+    pub boot: KernelBoot,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub meta: BTreeMap<String, String>,
+}
+
+impl Machine {
+    pub fn new(name: &str, port: u16, size: Size) -> Self {
+        Self {
+            name: name.into(),
+            port,
+            size,
+            cores: None,
+            ram: None,
+            probes: BTreeMap::new(),
+            timeouts: Timeouts::default(),
+            retry: Retry::default(),
+            notifications: Notifications::default(),
+            shaping: Shaping::default(),
+            expiry: None,
+            network: Network::User,
+            input: InputDevice::Tablet,
+            firmware: FirmwareMode::Uefi,
+            os: None,
+            identity: None,
+            connect: ConnectOptions::default(),
+            boot: KernelBoot::default(),
+            iso: None,
+            restart: RestartPolicy::Never,
+            isolated: false,
+            allow: Vec::new(),
+            meta: BTreeMap::new(),
+            description: None,
+            sha256: None,
+            provision: Vec::new(),
+            channels: Vec::new(),
+            qemu: None,
+            wrapper: None,
+            created_at: Some(time::now()),
+            last_started_at: None,
+            provisioned_at: None,
+        }
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
 }
 
 impl fmt::Display for Machine {
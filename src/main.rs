@@ -1,28 +1,413 @@
 mod cli;
-use cli::{Args, Subcommands};
+use cli::{
+    Args, ChannelSubcommands, Command, ConnectArgs, CrashSubcommands, GlobalArgs, IsoSubcommands,
+    KeySubcommands, MetaSubcommands, PcapSubcommands, PresetSubcommands, RemoteSubcommands,
+    SnapshotSubcommands, StandaloneSubcommands, StartArgs, StateSubcommands, StopArgs, Subcommands,
+    TrashSubcommands,
+};
+
+mod completions;
+
+mod confirm;
+use confirm::confirm;
 
 mod tables;
-use tables::{DiskTable, MachineTable, SnapshotTable};
+use tables::{
+    AddressTable, BootTable, ChannelTable, CrashTable, DiskTable, HistoryTable, HostTable,
+    MachineTable, PortTable, PresetTable, ProbeTable, RemoteTable, SnapshotDetailTable,
+    SnapshotTable, SnapshotTree, StatusTable, TrashTable, UsageTable,
+};
+
+mod watch;
 
 use anyhow::Result;
 use clap::Parser;
 use path_macro::path;
-use rhea::state::State;
-use std::env;
+use rhea::{
+    checksum::Checksum,
+    ci::Workspace,
+    config::{Logs, Notifications, Retry, Timeouts},
+    error::Error,
+    history,
+    image::ImageFormat,
+    kernel::KernelBoot,
+    logs::{self, Filter},
+    namespace,
+    os::GuestOs,
+    preset::Preset,
+    probe::Probe,
+    profile,
+    progress::ProgressFormat,
+    provision::Step,
+    reference::ResourceRef,
+    shaping::Shaping,
+    snapshot::Snapshot,
+    ssh::{self, ConnectOptions},
+    state::{Stamps, State},
+    supervisor,
+    time::{self, Expiry},
+};
+use serde_json::json;
+use std::{
+    env, fs, io,
+    path::{self, PathBuf},
+    process,
+    time::Duration,
+};
+
+/// Exit codes for failures that scripts may want to tell apart.
+mod exit {
+    pub const SUCCESS: i32 = 0;
+    pub const FAILURE: i32 = 1;
+    pub const NOT_FOUND: i32 = 3;
+    pub const IN_USE: i32 = 4;
+    pub const DEPENDENCY_MISSING: i32 = 5;
+    pub const QEMU_FAILED: i32 = 6;
+    pub const TIMEOUT: i32 = 7;
+    pub const EXISTS: i32 = 8;
+    pub const NOT_RUNNING: i32 = 9;
+    pub const QUOTA_EXCEEDED: i32 = 10;
+    pub const CANCELLED: i32 = 130;
+}
+
+fn exit_code(err: &anyhow::Error) -> i32 {
+    let Some(err) = err.downcast_ref::<Error>() else {
+        return exit::FAILURE;
+    };
+    match err {
+        Error::InvalidDisk { .. }
+        | Error::InvalidMachine { .. }
+        | Error::InvalidSnapshot { .. }
+        | Error::InvalidCheckpoint { .. }
+        | Error::MissingCheckpoint { .. }
+        | Error::InvalidProbe { .. }
+        | Error::InvalidChannel { .. }
+        | Error::InvalidBackup { .. }
+        | Error::InvalidRemote { .. }
+        | Error::InvalidMetaKey { .. }
+        | Error::InvalidTrash { .. }
+        | Error::InvalidCrash { .. }
+        | Error::MissingPreset { .. }
+        | Error::MissingChecksum { .. }
+        | Error::TrashEmpty
+        | Error::NoMatches { .. }
+        | Error::MissingKey { .. } => exit::NOT_FOUND,
+        Error::DiskInUse { .. }
+        | Error::MachineInUse { .. }
+        | Error::SnapshotInUse { .. }
+        | Error::SnapshotHasChildren { .. }
+        | Error::BackingInUse { .. }
+        | Error::MachineHasSnapshots { .. }
+        | Error::PortInUse { .. }
+        | Error::CaptureActive { .. }
+        | Error::StateLocked { .. } => exit::IN_USE,
+        Error::MissingDependency { .. } | Error::MissingFirmware | Error::QemuTooOld { .. } => {
+            exit::DEPENDENCY_MISSING
+        }
+        Error::StartFailed { .. } | Error::InvalidImage { .. } | Error::ChecksumMismatch { .. } => {
+            exit::QEMU_FAILED
+        }
+        Error::SshTimeout { .. }
+        | Error::ShutdownTimeout { .. }
+        | Error::ProbeTimeout { .. }
+        | Error::JobTimeout { .. } => exit::TIMEOUT,
+        Error::DiskExists { .. }
+        | Error::MachineExists { .. }
+        | Error::SnapshotExists { .. }
+        | Error::ProbeExists { .. }
+        | Error::ChannelExists { .. }
+        | Error::KeyExists { .. }
+        | Error::RemoteExists { .. } => exit::EXISTS,
+        Error::DiskNotInUse { .. }
+        | Error::MachineNotInUse { .. }
+        | Error::SnapshotNotInUse { .. }
+        | Error::CaptureInactive { .. } => exit::NOT_RUNNING,
+        Error::QuotaExceeded { .. } => exit::QUOTA_EXCEEDED,
+        Error::Cancelled | Error::Unconfirmed => exit::CANCELLED,
+        _ => exit::FAILURE,
+    }
+}
+
+/// The image of a virtual machine or disk named `image`, or else the image file at that path.
+fn template(state: &State, image: String, format: ImageFormat) -> Result<(PathBuf, ImageFormat)> {
+    match state.template_image(&image) {
+        Ok(path) => Ok((path, ImageFormat::Qcow2)),
+        Err(Error::InvalidDisk { .. }) => Ok((PathBuf::from(image), format)),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn names(snapshots: &[&Snapshot]) -> String {
+    snapshots
+        .iter()
+        .map(|snapshot| snapshot.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The names `patterns` stand for among `names`, expanding namespaces and patterns and printing
+/// what each matched. Plain names are kept for the command to reject if they don't exist.
+fn resolve<'a, I>(patterns: &[String], names: I, quiet: bool) -> Result<Vec<String>>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let names = names.into_iter().collect::<Vec<_>>();
+    let mut resolved = Vec::new();
+    for pattern in patterns {
+        let matched = namespace::expand(pattern, names.iter().copied());
+        if !namespace::is_name(pattern) {
+            if matched.is_empty() {
+                return Err(Error::NoMatches {
+                    pattern: pattern.clone(),
+                }
+                .into());
+            }
+            if !quiet {
+                eprintln!("{pattern} matched {}", matched.join(", "));
+            }
+        }
+        for name in matched {
+            if !resolved.contains(&name) {
+                resolved.push(name);
+            }
+        }
+    }
+    Ok(resolved)
+}
+
+/// The virtual machines and snapshots `patterns` stand for, where bare names refer to snapshots
+/// if `snapshot` is set, printing what each namespace or pattern matched.
+fn resolve_resources(
+    state: &State,
+    patterns: &[String],
+    snapshot: bool,
+    quiet: bool,
+) -> Result<Vec<ResourceRef>> {
+    let mut resolved = Vec::new();
+    for pattern in patterns {
+        let matched = state.resolve(pattern, snapshot)?;
+        if !quiet && !namespace::is_name(pattern) {
+            let names = matched.iter().map(ToString::to_string).collect::<Vec<_>>();
+            eprintln!("{pattern} matched {}", names.join(", "));
+        }
+        for resource in matched {
+            if !resolved.contains(&resource) {
+                resolved.push(resource);
+            }
+        }
+    }
+    Ok(resolved)
+}
+
+/// Whether the deprecated `--snapshot` flag was given, warning about it if so.
+fn snapshot_flag(snapshot: bool) -> bool {
+    if snapshot {
+        tracing::warn!("--snapshot is deprecated, address snapshots as snapshot/<name> instead");
+    }
+    snapshot
+}
+
+/// The state directory of the default profile, which holds those of the others.
+fn state_root() -> Result<PathBuf> {
+    Ok(path![env::var("HOME")? / ".config" / "rhea"])
+}
+
+fn main() {
+    match run() {
+        Ok(exit::SUCCESS) => {}
+        Ok(code) => process::exit(code),
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            if let Some(hint) = err.downcast_ref::<Error>().and_then(Error::hint) {
+                eprintln!("hint: {hint}");
+            }
+            process::exit(exit_code(&err));
+        }
+    }
+}
+
+/// Runs the command line, returning the exit code of a job run by `rhea ci` or `rhea run`, or
+/// else success.
+fn run() -> Result<i32> {
+    let Args { global, command } = Args::parse();
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+    tracing_subscriber::fmt()
+        .with_max_level(global.log_level())
+        .with_writer(io::stderr)
+        .init();
+    let progress = global.progress();
 
-    let path = path![env::var("HOME")? / ".config" / "rhea"];
-    let mut state = State::load(path)?;
+    let path = profile::path(&state_root()?, &global.profile)?;
 
-    match args.subcommand {
-        Subcommands::AddDisk { name, size } => {
+    let subcommand = match command {
+        Command::Stateful(subcommand) => *subcommand,
+        Command::Standalone(subcommand) => {
+            run_standalone(&global, subcommand, path, progress)?;
+            return Ok(exit::SUCCESS);
+        }
+    };
+
+    let mut state = State::load(path.clone())?;
+
+    // Machines on a remote host keep their metadata in a local state of their own.
+    if let Some(host) = &global.host {
+        state = state.remote_state(host)?;
+    }
+    state.set_progress(progress);
+
+    let mutates = subcommand.mutates();
+    if mutates {
+        tidy(&mut state);
+    }
+    if subcommand.lock_free() {
+        state.unlock();
+    }
+    if !mutates {
+        return dispatch(&global, subcommand, &mut state, progress);
+    }
+
+    let command = env::args().skip(1).collect::<Vec<_>>();
+    let result = dispatch(&global, subcommand, &mut state, progress);
+    record(&state, command, &result);
+    result
+}
+
+/// Records a command that changed something in the history, with how it ended.
+fn record(state: &State, command: Vec<String>, result: &Result<i32>) {
+    let outcome = match result {
+        Ok(exit::SUCCESS) => "ok".into(),
+        Ok(code) => format!("exited with {code}"),
+        Err(err) => err.to_string(),
+    };
+    if let Err(err) = state.record_history(&history::Entry::new(command, outcome)) {
+        tracing::warn!("failed to record history: {err}");
+    }
+}
+
+/// Runs the subcommands that don't load the state, such as restoring it from a backup when it
+/// can't be loaded.
+fn run_standalone(
+    global: &GlobalArgs,
+    subcommand: StandaloneSubcommands,
+    path: PathBuf,
+    progress: ProgressFormat,
+) -> Result<()> {
+    match subcommand {
+        StandaloneSubcommands::Completions { shell } => {
+            print!("{}", completions::script(shell));
+        }
+        StandaloneSubcommands::Complete { words } => {
+            for name in completions::names(path, &words)? {
+                println!("{name}");
+            }
+        }
+        StandaloneSubcommands::LogWriter {
+            path,
+            timestamps,
+            max_size,
+            keep,
+        } => {
+            let config = Logs {
+                timestamps,
+                max_size,
+                keep,
+            };
+            logs::write(io::stdin().lock(), &path, &config)?;
+        }
+        StandaloneSubcommands::Supervise { interval } => {
+            if global.host.is_some() {
+                return Err(Error::RemoteUnsupported {
+                    operation: "supervise".into(),
+                }
+                .into());
+            }
+            supervisor::run(path, progress, Duration::from_secs(interval))?;
+        }
+        StandaloneSubcommands::State {
+            subcommand: StateSubcommands::Restore { backup },
+        } => {
+            State::restore(path, backup)?;
+            if !global.quiet {
+                eprintln!("restored state from backup {backup}");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reaps expired resources and stopped instances, and empties the trash of what is past its
+/// retention. None of it is worth failing the command that was asked for.
+fn tidy(state: &mut State) {
+    let expired = state.reap_expired();
+    let stopped = state.reap_instances().unwrap_or_else(|err| {
+        tracing::warn!("failed to remove stopped instances: {err}");
+        Vec::new()
+    });
+    if !expired.is_empty() || !stopped.is_empty() {
+        if let Err(err) = state.save() {
+            tracing::warn!("failed to save state: {err}");
+        }
+        for name in expired {
+            tracing::warn!("expired: {name}");
+        }
+        for name in stopped {
+            tracing::info!("removed stopped instance: {name}");
+        }
+    }
+    if let Err(err) = state.empty_trash(false) {
+        tracing::warn!("failed to empty the trash: {err}");
+    }
+}
+
+fn dispatch(
+    global: &GlobalArgs,
+    subcommand: Subcommands,
+    state: &mut State,
+    progress: ProgressFormat,
+) -> Result<i32> {
+    let view = global.view();
+    match subcommand {
+        Subcommands::AddDisk {
+            name,
+            size,
+            description,
+        } => {
             state.add_disk(&name, size)?;
+            state.set_disk_description(&name, description)?;
             state.save()?;
         }
-        Subcommands::RemoveDisk { name } => {
-            state.remove_disk(&name)?;
+        Subcommands::ImportDisk {
+            name,
+            path,
+            format,
+            sha256,
+            checksums,
+        } => {
+            let digest = Checksum::from_args(sha256, checksums)
+                .map(|checksum| state.verify(&name, &path, &checksum))
+                .transpose()?;
+            state.import_disk(&name, &path, format)?;
+            state.set_disk_sha256(&name, digest)?;
+            state.save()?;
+        }
+        Subcommands::RemoveDisk { names } => {
+            let disks = state.disks().map(|disk| disk.name.as_str());
+            let names = resolve(&names, disks, global.quiet)?;
+            for name in &names {
+                state.get_disk(name)?;
+            }
+            confirm(global.yes, &format!("Remove disk {}?", names.join(", ")))?;
+            for name in &names {
+                state.remove_disk(name)?;
+            }
+            state.save()?;
+        }
+        Subcommands::ResizeDisk {
+            name,
+            size,
+            allow_shrink,
+        } => {
+            state.resize_disk(&name, size, allow_shrink)?;
             state.save()?;
         }
         Subcommands::AddMachine {
@@ -32,63 +417,1255 @@ fn main() -> Result<()> {
             cores,
             ram,
             port,
+            ttl,
+            remove_on_expiry,
+            preset,
+            sha256,
+            checksums,
+            description,
+            provision,
+            isolated,
+            allow,
+            firmware,
+            os,
         } => {
+            let digest = Checksum::from_args(sha256, checksums)
+                .map(|checksum| state.verify(&name, &iso, &checksum))
+                .transpose()?;
+            let preset = match preset {
+                Some(preset) => state.preset(&preset)?,
+                None => Preset::default(),
+            };
+            let size = size.unwrap_or(preset.size);
+            let cores = cores.unwrap_or(preset.cores);
+            let ram = ram.unwrap_or(preset.ram);
             state.add_machine(&name, port, size)?;
+            state.set_machine_sha256(&name, digest)?;
+            state.set_machine_description(&name, description)?;
+            state.set_machine_provision(&name, provision)?;
+            state.set_machine_isolation(&name, Some(isolated), allow)?;
+            state.set_machine_firmware(&name, firmware)?;
+            state.set_machine_os(&name, os.or_else(|| GuestOs::detect(&iso)))?;
+            if let Some(ttl) = ttl {
+                state.set_expiry(&name, false, Some(Expiry::after(ttl, remove_on_expiry)?))?;
+            }
+            let stamps = state.mark_started(&name, false, &[])?;
             state.save()?;
-            state.start(&name, cores, ram, true, false, &[], Some(iso))?;
+            state.unlock();
+            let started = state
+                .machine(&name)
+                .cores(cores)
+                .ram(ram)
+                .foreground(true)
+                .iso(iso)
+                .spawn();
+            if let Err(err) = started {
+                take_back_starts(state, vec![stamps]);
+                return Err(err.into());
+            }
         }
-        Subcommands::RemoveMachine { name } => {
-            state.remove_machine(&name)?;
+        Subcommands::ImportMachine {
+            name,
+            path,
+            format,
+            port,
+            sha256,
+            checksums,
+            firmware,
+            os,
+        } => {
+            let digest = Checksum::from_args(sha256, checksums)
+                .map(|checksum| state.verify(&name, &path, &checksum))
+                .transpose()?;
+            state.import_machine(&name, &path, format, port)?;
+            state.set_machine_sha256(&name, digest)?;
+            state.set_machine_firmware(&name, firmware)?;
+            state.set_machine_os(&name, os.or_else(|| GuestOs::detect(&path)))?;
             state.save()?;
         }
-        Subcommands::AddSnapshot { name, base } => {
-            state.add_snapshot(&name, &base)?;
+        Subcommands::Rebuild {
+            name,
+            iso,
+            image,
+            format,
+            reset,
+            cores,
+            ram,
+            sha256,
+            checksums,
+            checkpoint,
+        } => {
+            state.get_machine(&name)?;
+            let mut description =
+                format!("Rebuild virtual machine {name}? Everything on its disk is lost for good.");
+            let linked = state.linked_snapshots(&name, false);
+            if !linked.is_empty() {
+                description.push_str(&format!(
+                    "\nThese snapshots are based on it and will no longer boot: {}",
+                    names(&linked)
+                ));
+            }
+            confirm(global.yes, &description)?;
+            let digest = match (
+                Checksum::from_args(sha256, checksums),
+                iso.as_ref().or(image.as_ref()),
+            ) {
+                (Some(checksum), Some(source)) => Some(state.verify(&name, source, &checksum)?),
+                _ => None,
+            };
+            if checkpoint {
+                let checkpoint = state.checkpoint(&name)?;
+                eprintln!("took checkpoint {checkpoint}");
+            }
+            state.rebuild_machine(&name, image.as_deref().map(|image| (image, format)), reset)?;
+            state.set_machine_sha256(&name, digest)?;
             state.save()?;
+            if let Some(iso) = iso {
+                let stamps = state.mark_started(&name, false, &[])?;
+                state.save()?;
+                state.unlock();
+                let started = state
+                    .machine(&name)
+                    .cores(cores)
+                    .ram(ram)
+                    .foreground(true)
+                    .iso(iso)
+                    .spawn();
+                if let Err(err) = started {
+                    take_back_starts(state, vec![stamps]);
+                    return Err(err.into());
+                }
+            }
         }
-        Subcommands::RemoveSnapshot { name } => {
-            state.remove_snapshot(&name)?;
+        Subcommands::ImportFrom { source, name, port } => {
+            let imported = state.import_from(source, name.as_deref(), port)?;
             state.save()?;
+            for machine in imported {
+                println!(
+                    "imported {} (cores: {}, ram: {})",
+                    machine.name,
+                    machine.cores.map_or("?".into(), |cores| cores.to_string()),
+                    machine.ram.map_or("?".into(), |ram| ram.to_string())
+                );
+            }
         }
-        Subcommands::Disk { name } => {
-            println!("{}", DiskTable::filtered(&state, &[&name]));
+        Subcommands::ImportBox { path, name, port } => {
+            let machine = state.import_box(&path, name.as_deref(), port)?;
+            state.save()?;
+            println!(
+                "imported {} (cores: {}, ram: {})",
+                machine.name,
+                machine.cores.map_or("?".into(), |cores| cores.to_string()),
+                machine.ram.map_or("?".into(), |ram| ram.to_string())
+            );
         }
-        Subcommands::Disks => {
-            println!("{}", DiskTable::new(&state));
+        Subcommands::RemoveMachine { names: machines } => {
+            let all = state.machines().map(|machine| machine.name.as_str());
+            let machines = resolve(&machines, all, global.quiet)?;
+            let mut description = format!("Remove virtual machine {}?", machines.join(", "));
+            for name in &machines {
+                state.get_machine(name)?;
+                let linked = state.linked_snapshots(name, false);
+                if !linked.is_empty() {
+                    description.push_str(&format!(
+                        "\nThese snapshots are based on {name} and will no longer boot: {}",
+                        names(&linked)
+                    ));
+                }
+            }
+            confirm(global.yes, &description)?;
+            for name in &machines {
+                state.remove_machine(name)?;
+            }
+            state.save()?;
         }
-        Subcommands::Machine { name } => {
-            println!("{}", MachineTable::filtered(&state, &[&name]));
+        Subcommands::ResizeMachine {
+            name,
+            size,
+            allow_shrink,
+            checkpoint,
+        } => {
+            if checkpoint {
+                let checkpoint = state.checkpoint(&name)?;
+                eprintln!("took checkpoint {checkpoint}");
+            }
+            state.resize_machine(&name, size, allow_shrink)?;
+            state.save()?;
         }
-        Subcommands::Machines => {
-            println!("{}", MachineTable::new(&state));
+        Subcommands::Rollback { name, checkpoint } => {
+            let checkpoint = match checkpoint {
+                Some(checkpoint) => checkpoint,
+                None => state
+                    .checkpoints(&name)
+                    .last()
+                    .map(|snapshot| snapshot.name.clone())
+                    .ok_or(Error::MissingCheckpoint { name: name.clone() })?,
+            };
+            confirm(
+                global.yes,
+                &format!("Roll back virtual machine {name} to {checkpoint}? Changes since then are lost."),
+            )?;
+            state.rollback(&name, &checkpoint)?;
+            state.save()?;
         }
-        Subcommands::Snapshot { name } => {
-            println!("{}", SnapshotTable::filtered(&state, &[&name]));
+        Subcommands::SetMachine {
+            name,
+            start_grace,
+            ssh_wait,
+            shutdown_timeout,
+            qmp_timeout,
+            retries,
+            retry_delay,
+            network,
+            input,
+            firmware,
+            os,
+            restart,
+            isolated,
+            allow,
+            notify_desktop,
+            notify_webhook,
+            notify_after,
+            provision,
+            qemu,
+            qemu_wrapper,
+        } => {
+            state.set_machine_timeouts(
+                &name,
+                Timeouts {
+                    start_grace,
+                    ssh_wait,
+                    shutdown: shutdown_timeout,
+                    qmp: qmp_timeout,
+                },
+            )?;
+            state.set_machine_retry(
+                &name,
+                Retry {
+                    attempts: retries,
+                    delay: retry_delay,
+                },
+            )?;
+            state.set_machine_notifications(
+                &name,
+                Notifications {
+                    desktop: notify_desktop,
+                    webhook: notify_webhook,
+                    after: notify_after,
+                },
+            )?;
+            state.set_machine_isolation(&name, isolated, allow)?;
+            if let Some(network) = network {
+                state.set_machine_network(&name, network)?;
+            }
+            if let Some(input) = input {
+                state.set_machine_input(&name, input)?;
+            }
+            if let Some(firmware) = firmware {
+                state.set_machine_firmware(&name, firmware)?;
+            }
+            if os.is_some() {
+                state.set_machine_os(&name, os)?;
+            }
+            if let Some(restart) = restart {
+                state.set_machine_restart(&name, restart)?;
+            }
+            if !provision.is_empty() {
+                state.set_machine_provision(&name, provision)?;
+            }
+            state.set_machine_binaries(&name, qemu, qemu_wrapper)?;
+            state.save()?;
         }
-        Subcommands::Snapshots => {
-            println!("{}", SnapshotTable::new(&state));
+        Subcommands::Export {
+            name,
+            destination,
+            cores,
+            ram,
+            compress,
+            snapshot,
+        } => {
+            state.export(&name, snapshot, &destination, cores, ram, compress)?;
         }
-        Subcommands::Start {
+        Subcommands::ExportLibvirt {
             name,
             cores,
             ram,
-            foreground,
             disks,
             snapshot,
         } => {
-            state.start(&name, cores, ram, foreground, snapshot, &disks, None)?;
+            print!(
+                "{}",
+                state.export_libvirt(&name, snapshot, cores, ram, &disks)?
+            );
+        }
+        Subcommands::Import { path, name } => {
+            let manifest = state.import(&path, name.as_deref())?;
+            state.save()?;
+            println!(
+                "imported {} (cores: {}, ram: {})",
+                name.unwrap_or(manifest.name),
+                manifest.cores,
+                manifest.ram
+            );
+        }
+        Subcommands::PushImage {
+            name,
+            reference,
+            cores,
+            ram,
+            snapshot,
+        } => state.push_image(&name, snapshot, &reference, cores, ram)?,
+        Subcommands::PullImage { reference, name } => {
+            let manifest = state.pull_image(&reference, name.as_deref())?;
+            state.save()?;
+            println!(
+                "imported {} (cores: {}, ram: {})",
+                name.unwrap_or(manifest.name),
+                manifest.cores,
+                manifest.ram
+            );
+        }
+        Subcommands::Copy { name, to_profile } => {
+            let target_path = profile::path(&state_root()?, &to_profile)?;
+            if target_path == state.path() {
+                return Err(Error::SameProfile {
+                    profile: to_profile,
+                }
+                .into());
+            }
+            let mut target = State::load(target_path)?;
+            target.set_progress(progress);
+            // Only the machine stays locked while its image is copied.
+            state.unlock();
+            state.copy_machine(&name, &mut target)?;
+            target.save()?;
+            eprintln!("copied {name} to profile {to_profile}");
+        }
+        Subcommands::Sync { name, remote } => {
+            let mut target = state.remote_state(&remote)?;
+            state.unlock();
+            state.sync_machine(&name, &mut target)?;
+            target.save()?;
+            eprintln!("synced {name} to {remote}");
+        }
+        Subcommands::AddSnapshot {
+            name,
+            base,
+            snapshot,
+            full,
+            description,
+            port,
+            cores,
+            ram,
+        } => {
+            state.add_snapshot(&name, &base, snapshot, full)?;
+            let port = match port {
+                Some(port) => port,
+                None => state.next_port(state.get_snapshot(&name)?.port)?,
+            };
+            state.set_snapshot_resources(&name, Some(port), cores, ram)?;
+            state.set_snapshot_description(&name, description)?;
+            state.save()?;
+        }
+        Subcommands::RemoveSnapshot { names } => {
+            let snapshots = state.snapshots().map(|snapshot| snapshot.name.as_str());
+            let names = state.children_first(resolve(&names, snapshots, global.quiet)?);
+            for name in &names {
+                state.get_snapshot(name)?;
+            }
+            confirm(
+                global.yes,
+                &format!("Remove snapshot {}?", names.join(", ")),
+            )?;
+            for name in &names {
+                state.remove_snapshot(name)?;
+            }
+            state.save()?;
+        }
+        Subcommands::AddProbe {
+            machine,
+            name,
+            port,
+            guest_port,
+            http,
+        } => {
+            state.add_probe(
+                &machine,
+                &name,
+                Probe {
+                    host_port: port,
+                    guest_port,
+                    path: http,
+                },
+            )?;
+            state.save()?;
+        }
+        Subcommands::RemoveProbe { machine, name } => {
+            state.remove_probe(&machine, &name)?;
+            state.save()?;
+        }
+        Subcommands::Disk { names } => {
+            let disks = state.disks().map(|disk| disk.name.as_str());
+            let names = resolve(&names, disks, global.quiet)?;
+            let names = names.iter().map(String::as_str).collect::<Vec<_>>();
+            println!("{}", DiskTable::filtered(state, &names, view.detailed())?);
         }
-        Subcommands::Stop { name, snapshot } => {
-            state.stop(&name, snapshot)?;
+        Subcommands::Disks { prefix } => {
+            println!("{}", DiskTable::new(state, view)?.within(prefix.as_deref()));
         }
-        Subcommands::Connect {
-            forward_keys,
+        Subcommands::Machine { name, boots } => {
+            let boots = BootTable::new(state, &name, boots, view.clone())?;
+            println!(
+                "{}",
+                MachineTable::filtered(state, &[&name], view.detailed())?
+            );
+            if !boots.is_empty() {
+                println!("\n{boots}");
+            }
+        }
+        Subcommands::Describe {
+            name,
+            description: Some(description),
+            snapshot,
+            disk,
+        } => {
+            let description = (!description.is_empty()).then_some(description);
+            if disk {
+                state.set_disk_description(&name, description)?;
+            } else if snapshot {
+                state.set_snapshot_description(&name, description)?;
+            } else {
+                state.set_machine_description(&name, description)?;
+            }
+            state.save()?;
+        }
+        Subcommands::Describe {
+            name,
+            description: None,
+            snapshot,
+            disk,
+        } => {
+            let description = if disk {
+                &state.get_disk(&name)?.description
+            } else if snapshot {
+                &state.get_snapshot(&name)?.description
+            } else {
+                &state.get_machine(&name)?.description
+            };
+            if let Some(description) = description {
+                println!("{description}");
+            }
+        }
+        Subcommands::Machines {
+            prefix,
+            meta,
+            watch,
+        } => {
+            let table = |state: &State, view| -> Result<MachineTable> {
+                Ok(MachineTable::new(state, view)?
+                    .within(prefix.as_deref())
+                    .matching(&meta))
+            };
+            match watch {
+                Some(interval) => {
+                    watch::watch(state, Duration::from_secs(interval), view, |state, view| {
+                        Ok(table(state, view)?.to_string())
+                    })?
+                }
+                None => println!("{}", table(state, view)?),
+            }
+        }
+        Subcommands::Meta { subcommand } => match subcommand {
+            MetaSubcommands::Set { name, key, value } => {
+                state.set_machine_meta(&name, &key, &value)?;
+                state.save()?;
+            }
+            MetaSubcommands::Get { name, key } => {
+                println!("{}", state.machine_meta(&name, &key)?);
+            }
+            MetaSubcommands::Unset { name, key } => {
+                state.unset_machine_meta(&name, &key)?;
+                state.save()?;
+            }
+            MetaSubcommands::List { name } => {
+                for (key, value) in &state.get_machine(&name)?.meta {
+                    println!("{key}={value}");
+                }
+            }
+        },
+        Subcommands::Snapshot {
+            names,
+            subcommand: None,
+        } => {
+            let snapshots = state.snapshots().map(|snapshot| snapshot.name.as_str());
+            let names = resolve(&names, snapshots, global.quiet)?;
+            let names = names.iter().map(String::as_str).collect::<Vec<_>>();
+            println!(
+                "{}",
+                SnapshotTable::filtered(state, &names, view.detailed())?
+            );
+        }
+        Subcommands::Snapshot {
+            subcommand: Some(subcommand),
+            ..
+        } => match subcommand {
+            SnapshotSubcommands::Info { name } => {
+                println!("{}", SnapshotDetailTable::new(state, &name, view)?);
+            }
+            SnapshotSubcommands::Diff { name, other } => {
+                println!("{}", SnapshotDetailTable::diff(state, &name, &other, view)?);
+            }
+            SnapshotSubcommands::Start(start) => start_resources(state, start, true, global.quiet)?,
+            SnapshotSubcommands::Stop(stop) => stop_resources(state, stop, true, global.quiet)?,
+            SnapshotSubcommands::Connect(connect) => connect_resource(state, connect, true)?,
+        },
+        Subcommands::Snapshots { prefix } => {
+            println!(
+                "{}",
+                SnapshotTable::new(state, view)?.within(prefix.as_deref())
+            );
+        }
+        Subcommands::SnapshotTree { machine } => {
+            println!("{}", SnapshotTree::new(state, &machine, global.output)?);
+        }
+        Subcommands::Start(start) => {
+            let snapshot = snapshot_flag(start.snapshot);
+            start_resources(state, start, snapshot, global.quiet)?;
+        }
+        Subcommands::Debug {
+            name,
+            symbols,
+            lldb,
+            snapshot,
+        } => state.debug(&name, snapshot, symbols.as_deref(), lldb)?,
+        Subcommands::Provision {
+            name,
+            again,
+            username,
+        } => {
+            if !again && state.get_machine(&name)?.provisioned_at.is_some() {
+                eprintln!("{name} is already provisioned, pass --again to run the steps anyway");
+                return Ok(exit::SUCCESS);
+            }
+            state.unlock();
+            state.provision(&name, username)?;
+            state.lock()?;
+            state.mark_provisioned(&name)?;
+            state.save()?;
+        }
+        Subcommands::Status {
+            name,
+            snapshot,
+            watch,
+        } => {
+            let render = |state: &State, view| -> Result<String> {
+                Ok(match &name {
+                    Some(name) => ProbeTable::new(state, name, snapshot, view)?.to_string(),
+                    None => StatusTable::new(state, view)?.to_string(),
+                })
+            };
+            match watch {
+                Some(interval) => watch::watch(state, Duration::from_secs(interval), view, render)?,
+                None => println!("{}", render(state, view)?),
+            }
+        }
+        Subcommands::Console { name, snapshot } => {
+            if !global.quiet {
+                eprintln!("attached to {name}, detach with Ctrl-]");
+            }
+            state.console(&name, snapshot)?;
+        }
+        Subcommands::Logs {
+            name,
+            follow,
+            snapshot,
+            since,
+            grep,
+        } => {
+            let since =
+                since.map(|since| time::now_millis().saturating_sub(since.as_millis() as u64));
+            state.logs(&name, snapshot, follow, Filter::new(since, grep.as_deref()))?;
+        }
+        Subcommands::Presets {
+            subcommand: PresetSubcommands::List,
+        } => {
+            println!("{}", PresetTable::new(&state.presets(), view));
+        }
+        Subcommands::Undo => {
+            let name = state.restore_trashed(None)?;
+            state.save()?;
+            println!("restored {name}");
+        }
+        Subcommands::Crashes { subcommand } => match subcommand {
+            CrashSubcommands::List { name } => {
+                let mut reports = state.crashes()?;
+                if let Some(name) = name {
+                    reports.retain(|report| report.name == name);
+                }
+                println!("{}", CrashTable::new(&reports, view));
+            }
+            CrashSubcommands::Show { id } => {
+                let (report, log) = state.crash(&id)?;
+                println!("{}", CrashTable::show(&report, log, view));
+            }
+        },
+        Subcommands::Trash { subcommand } => match subcommand {
+            TrashSubcommands::List => {
+                println!("{}", TrashTable::new(&state.trashed()?, view));
+            }
+            TrashSubcommands::Restore { name } => {
+                state.restore_trashed(Some(&name))?;
+                state.save()?;
+                println!("restored {name}");
+            }
+            TrashSubcommands::Empty { expired } => {
+                if !expired {
+                    let trashed = state.trashed()?;
+                    if trashed.is_empty() {
+                        return Ok(exit::SUCCESS);
+                    }
+                    confirm(
+                        global.yes,
+                        &format!(
+                            "Permanently delete {}?",
+                            trashed
+                                .iter()
+                                .map(|trashed| trashed.resource.name())
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ),
+                    )?;
+                }
+                for name in state.empty_trash(!expired)? {
+                    println!("deleted {name}");
+                }
+            }
+        },
+        Subcommands::History { name, limit } => {
+            let mut entries = state.history()?;
+            if let Some(name) = name {
+                entries.retain(|entry| entry.involves(&name));
+            }
+            if let Some(limit) = limit {
+                entries.drain(..entries.len().saturating_sub(limit));
+            }
+            println!("{}", HistoryTable::new(&entries, view));
+        }
+        Subcommands::Prune { expired } => {
+            if expired {
+                let doomed = state.expired();
+                if doomed.is_empty() {
+                    return Ok(exit::SUCCESS);
+                }
+                confirm(
+                    global.yes,
+                    &format!(
+                        "Stop and remove {}?",
+                        doomed
+                            .iter()
+                            .map(|(name, _)| name.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                )?;
+                for name in state.prune_expired()? {
+                    println!("removed {name}");
+                }
+                state.save()?;
+            }
+        }
+        Subcommands::Hosts => {
+            println!("{}", state.write_hosts()?.display());
+        }
+        Subcommands::Ip { name, snapshot } => {
+            let addresses = state.guest_addresses(&name, snapshot)?;
+            println!("{}", AddressTable::new(&addresses, view));
+        }
+        Subcommands::Ports => println!("{}", PortTable::new(&state.ports()?, view)),
+        Subcommands::Stop(stop) => {
+            let snapshot = snapshot_flag(stop.snapshot);
+            stop_resources(state, stop, snapshot, global.quiet)?;
+        }
+        Subcommands::Restart { names, snapshot } => {
+            let resources = resolve_resources(state, &names, snapshot, global.quiet)?;
+            let mut stamps = Vec::new();
+            for resource in &resources {
+                let disks = state
+                    .runtime(&resource.name, resource.snapshot)?
+                    .map(|runtime| runtime.disks)
+                    .unwrap_or_default();
+                stamps.push(state.mark_started(&resource.name, resource.snapshot, &disks)?);
+            }
+            state.save()?;
+            state.unlock();
+            for (i, resource) in resources.iter().enumerate() {
+                if let Err(err) = state.restart(&resource.name, resource.snapshot) {
+                    take_back_starts(state, stamps.split_off(i));
+                    return Err(err.into());
+                }
+            }
+        }
+        Subcommands::Reset { names, snapshot } => {
+            for resource in resolve_resources(state, &names, snapshot, global.quiet)? {
+                state.reset(&resource.name, resource.snapshot)?;
+            }
+        }
+        Subcommands::Devcontainer {
+            name,
+            username,
+            folder,
+            directory,
+            wait,
+            snapshot,
+        } => {
+            let username = state.ssh_user(&name, snapshot, username)?;
+            let config = state.write_ssh_config(&name, snapshot, &username, None, None)?;
+
+            let authority = format!("ssh-remote+{}", ssh::host_alias(&name));
+            let folder = folder.unwrap_or_else(|| format!("/home/{username}"));
+            let workspace = path![directory / namespace::file_name(&name, "code-workspace")];
+            fs::write(
+                &workspace,
+                serde_json::to_string_pretty(&json!({
+                    "folders": [{ "uri": format!("vscode-remote://{authority}{folder}") }],
+                    "remoteAuthority": authority,
+                }))?,
+            )?;
+
+            if wait {
+                state.wait_ssh(&name, snapshot)?;
+            }
+
+            println!("wrote {}", config.display());
+            println!("wrote {}", workspace.display());
+            println!(
+                "add `Include {}/*.conf` to ~/.ssh/config, then open the workspace in VS Code",
+                config.parent().unwrap_or(&config).display()
+            );
+        }
+        Subcommands::Mount {
+            name,
+            remote,
+            mountpoint,
+            username,
+            snapshot,
+        } => {
+            state.mount(&name, snapshot, username, &remote, &mountpoint)?;
+        }
+        Subcommands::Umount { mountpoint } => {
+            state.umount(&mountpoint)?;
+        }
+        Subcommands::SshConfig {
+            names,
             username,
+            identity,
+            proxy_jump,
+            write,
+        } => {
+            let names = if names.is_empty() {
+                state
+                    .machines()
+                    .map(|machine| machine.name.clone())
+                    .collect()
+            } else {
+                names
+            };
+
+            for name in &names {
+                let username = state.ssh_user(name, false, username.clone())?;
+                if write {
+                    let config = state.write_ssh_config(
+                        name,
+                        false,
+                        &username,
+                        identity.as_deref(),
+                        proxy_jump.as_deref(),
+                    )?;
+                    println!("wrote {}", config.display());
+                } else {
+                    print!(
+                        "{}",
+                        state.ssh_host_block(
+                            name,
+                            false,
+                            &username,
+                            identity.as_deref(),
+                            proxy_jump.as_deref()
+                        )?
+                    );
+                }
+            }
+
+            if write {
+                println!(
+                    "add `Include {}/*.conf` to ~/.ssh/config",
+                    state.ssh_dir_path().display()
+                );
+            }
+        }
+        Subcommands::Ci {
+            image,
+            format,
+            cores,
+            ram,
+            username,
+            artifacts,
+            artifacts_dir,
+            script,
+            timeout,
+            command,
+        } => {
+            let (image, format) = template(state, image, format)?;
+            let command = command.join(" ");
+            let step = match &script {
+                Some(script) => Step::Script(script),
+                None => Step::Command(&command),
+            };
+            let code = {
+                let mut workspace = Workspace::new()?;
+                workspace.state_mut().set_progress(progress);
+                workspace.boot("ci", &image, format, cores, ram)?;
+                // Artifacts of a job that timed out are still worth a look.
+                let code = workspace.job("ci", username.clone(), &step, timeout);
+                workspace.collect("ci", username, &artifacts, &artifacts_dir)?;
+                code?
+            };
+            return Ok(code);
+        }
+        Subcommands::Run {
+            image,
+            format,
+            cores,
+            ram,
+            username,
+            command,
+        } => {
+            let (image, format) = template(state, image, format)?;
+            let code = {
+                let mut workspace = Workspace::new()?;
+                workspace.state_mut().set_progress(progress);
+                workspace.boot("run", &image, format, cores, ram)?;
+                workspace.run("run", username, &command)?
+            };
+            return Ok(code);
+        }
+        Subcommands::Key { subcommand } => match subcommand {
+            KeySubcommands::Generate { name, force } => {
+                let path = state.generate_key(&name, force)?;
+                state.save()?;
+                println!("wrote {}", path.display());
+            }
+            KeySubcommands::Add {
+                name,
+                username,
+                identity,
+                snapshot,
+            } => {
+                state.add_key(&name, snapshot, username, identity)?;
+                state.save()?;
+            }
+        },
+        Subcommands::Shape {
+            name,
+            rate,
+            delay,
+            loss,
+            clear,
+        } => {
+            let shaping = Shaping { rate, delay, loss };
+            state.set_machine_shaping(&name, shaping, clear)?;
+            state.save()?;
+        }
+        Subcommands::Host => println!("{}", HostTable::new(state, view)?),
+        Subcommands::Usage {
             name,
+            since,
             snapshot,
         } => {
-            state.connect(&name, username, forward_keys, snapshot)?;
+            println!("{}", UsageTable::new(state, &name, snapshot, since, view)?);
+        }
+        Subcommands::Pcap { subcommand } => match subcommand {
+            PcapSubcommands::Start {
+                name,
+                file,
+                snapshot,
+            } => {
+                let file = match file {
+                    Some(file) => path::absolute(file)?,
+                    None => state.pcap_path(&name, snapshot),
+                };
+                state.start_pcap(&name, snapshot, &file)?;
+                eprintln!("capturing traffic of {name} to {}", file.display());
+            }
+            PcapSubcommands::Stop { name, snapshot } => {
+                println!("{}", state.stop_pcap(&name, snapshot)?.display());
+            }
+        },
+        Subcommands::Channel { subcommand } => match subcommand {
+            ChannelSubcommands::Add { machine, name } => {
+                state.add_channel(&machine, &name)?;
+                state.save()?;
+            }
+            ChannelSubcommands::Remove { machine, name } => {
+                state.remove_channel(&machine, &name)?;
+                state.save()?;
+            }
+            ChannelSubcommands::List { machine, snapshot } => {
+                println!("{}", ChannelTable::new(state, &machine, snapshot, view)?);
+            }
+        },
+        Subcommands::Iso { subcommand } => match subcommand {
+            IsoSubcommands::Attach {
+                name,
+                path,
+                sha256,
+                checksums,
+            } => {
+                if let Some(checksum) = Checksum::from_args(sha256, checksums) {
+                    state.verify(&name, &path, &checksum)?;
+                }
+                state.attach_iso(&name, &path)?;
+                state.save()?;
+            }
+            IsoSubcommands::Eject { name } => {
+                state.eject_iso(&name)?;
+                state.save()?;
+            }
+        },
+        Subcommands::Remote { subcommand } => match subcommand {
+            RemoteSubcommands::Add { name, url } => {
+                state.add_remote(&name, url)?;
+                state.save()?;
+            }
+            RemoteSubcommands::Remove { name } => {
+                state.remove_remote(&name)?;
+                state.save()?;
+            }
+            RemoteSubcommands::List => {
+                println!("{}", RemoteTable::new(state, view));
+            }
+        },
+        Subcommands::Connect(connect) => {
+            let snapshot = snapshot_flag(connect.snapshot);
+            connect_resource(state, connect, snapshot)?;
         }
     };
 
+    Ok(exit::SUCCESS)
+}
+
+/// Takes back the stamps of starts that failed or never happened, so that LAST BOOT only shows
+/// boots that did. Failing to is only worth a warning next to the error of the start.
+fn take_back_starts(state: &mut State, stamps: Vec<Stamps>) {
+    let taken_back = state.lock().and_then(|()| {
+        for stamps in stamps {
+            state.unmark_started(stamps)?;
+        }
+        state.save()
+    });
+    state.unlock();
+    if let Err(err) = taken_back {
+        tracing::warn!("failed to take back the start: {err}");
+    }
+}
+
+/// Starts virtual machines and snapshots, recording all of them under one lock before any boots.
+fn start_resources(state: &mut State, start: StartArgs, snapshot: bool, quiet: bool) -> Result<()> {
+    let StartArgs {
+        names,
+        cores,
+        ram,
+        foreground,
+        disks,
+        snapshot: _,
+        wait,
+        timeout,
+        ttl,
+        remove_on_expiry,
+        force,
+        kernel,
+        initrd,
+        append,
+        save,
+        boot: boot_device,
+        wait_qmp,
+        checkpoint,
+        no_provision,
+        gdb,
+        freeze,
+        pcap,
+        instances,
+        ephemeral,
+    } = start;
+
+    let resources = resolve_resources(state, &names, snapshot, quiet)?;
+    if resources.len() > 1 {
+        // These would have every virtual machine fight over one terminal, disk or port.
+        for (flag, given) in [
+            ("--foreground", foreground),
+            ("--disks", !disks.is_empty()),
+            ("--gdb", gdb.is_some()),
+            ("--pcap", pcap.is_some()),
+        ] {
+            if given {
+                return Err(Error::SingleTarget { flag: flag.into() }.into());
+            }
+        }
+    }
+    let boot = KernelBoot {
+        kernel,
+        initrd,
+        append,
+    };
+
+    let count = instances.unwrap_or(usize::from(ephemeral));
+    if count > 0 {
+        let mut instances = Vec::new();
+        for resource in &resources {
+            instances.extend(state.add_instances(&resource.name, resource.snapshot, count)?);
+        }
+        for instance in &instances {
+            if let Some(ttl) = ttl {
+                state.set_expiry(instance, true, Some(Expiry::after(ttl, false)?))?;
+            }
+        }
+        state.save()?;
+        state.unlock();
+        // Instances only count as started once they run, so that nobody reaps them before.
+        for instance in &instances {
+            let (cores, ram) = state.start_resources(instance, true, cores, ram)?;
+            state
+                .machine(instance)
+                .cores(cores)
+                .ram(ram)
+                .snapshot(true)
+                .force(force)
+                .boot(boot.clone())
+                .boot_device(boot_device)
+                .wait_qmp(wait_qmp)
+                .spawn()?;
+            state.lock()?;
+            state.mark_started(instance, true, &[])?;
+            state.save()?;
+            state.unlock();
+            println!("{}", ResourceRef::snapshot(instance));
+        }
+        if wait {
+            for instance in &instances {
+                state.wait_ready(instance, true, Duration::from_secs(timeout))?;
+            }
+        }
+        return Ok(());
+    }
+
+    let pcap = pcap.map(path::absolute).transpose()?;
+    let mut targets = Vec::new();
+    let mut stamps = Vec::new();
+    for ResourceRef { name, snapshot } in resources {
+        if checkpoint && snapshot {
+            tracing::warn!("not taking a checkpoint of snapshot {name}");
+        } else if checkpoint {
+            let checkpoint = state.checkpoint(&name)?;
+            eprintln!("took checkpoint {checkpoint}");
+        }
+        if save && !snapshot {
+            state.set_machine_boot(&name, boot.clone())?;
+        }
+        if let Some(ttl) = ttl {
+            state.set_expiry(&name, snapshot, Some(Expiry::after(ttl, remove_on_expiry)?))?;
+        } else if state
+            .expiry(&name, snapshot)?
+            .is_some_and(|expiry| expiry.expired())
+        {
+            state.set_expiry(&name, snapshot, None)?;
+        }
+        if snapshot && state.snapshot_stale(&name)? == Some(true) {
+            tracing::warn!("the base of {name} was modified after the snapshot was taken");
+        }
+        let (cores, ram) = state.start_resources(&name, snapshot, cores, ram)?;
+        stamps.push(state.mark_started(&name, snapshot, &disks)?);
+        targets.push((ResourceRef { name, snapshot }, cores, ram));
+    }
+    state.save()?;
+    state.unlock();
+
+    for (i, (resource, cores, ram)) in targets.iter().enumerate() {
+        let started = state
+            .machine(&resource.name)
+            .cores(*cores)
+            .ram(*ram)
+            .foreground(foreground)
+            .snapshot(resource.snapshot)
+            .force(force)
+            .disks(disks.clone())
+            .boot(boot.clone())
+            .boot_device(boot_device)
+            .wait_qmp(wait_qmp)
+            .gdb(gdb)
+            .freeze(freeze)
+            .pcap(pcap.clone())
+            .spawn();
+        if let Err(err) = started {
+            take_back_starts(state, stamps.split_off(i));
+            return Err(err.into());
+        }
+    }
+    if wait && !foreground {
+        for (resource, ..) in &targets {
+            state.wait_ready(
+                &resource.name,
+                resource.snapshot,
+                Duration::from_secs(timeout),
+            )?;
+        }
+    }
+    // A frozen guest won't come up until someone continues it in the debugger.
+    for (resource, ..) in &targets {
+        if !resource.snapshot
+            && !foreground
+            && !freeze
+            && !no_provision
+            && state.needs_provisioning(&resource.name)?
+        {
+            state.provision(&resource.name, None)?;
+            state.lock()?;
+            state.mark_provisioned(&resource.name)?;
+            state.save()?;
+            state.unlock();
+        }
+    }
     Ok(())
 }
+
+/// Stops virtual machines and snapshots, each before whatever backs it. Namespaces and patterns
+/// only pick out what is running, while stopping one by name that isn't is still an error.
+fn stop_resources(state: &mut State, stop: StopArgs, snapshot: bool, quiet: bool) -> Result<()> {
+    let StopArgs {
+        names,
+        snapshot: _,
+        all,
+        save_state,
+    } = stop;
+
+    let running = state.running()?;
+    let targets = if all {
+        // Under `rhea snapshot stop`, only snapshots.
+        running
+            .into_iter()
+            .filter(|(_, is_snapshot)| *is_snapshot || !snapshot)
+            .collect()
+    } else {
+        let named = names
+            .iter()
+            .map(|arg| ResourceRef::parse(arg, snapshot))
+            .filter(|resource| namespace::is_name(&resource.name))
+            .collect::<Vec<_>>();
+        let (snapshots, machines): (Vec<_>, Vec<_>) =
+            resolve_resources(state, &names, snapshot, quiet)?
+                .into_iter()
+                .filter(|resource| {
+                    named.contains(resource)
+                        || running.contains(&(resource.name.clone(), resource.snapshot))
+                })
+                .partition(|resource| resource.snapshot);
+        let snapshots = snapshots
+            .into_iter()
+            .map(|resource| resource.name)
+            .collect();
+        state
+            .children_first(snapshots)
+            .into_iter()
+            .map(|name| (name, true))
+            .chain(machines.into_iter().map(|resource| (resource.name, false)))
+            .collect::<Vec<_>>()
+    };
+    let bulk = all || targets.len() > 1;
+
+    let mut failed = None;
+    for (name, snapshot) in targets {
+        let result = if save_state {
+            state.suspend(&name, snapshot)
+        } else {
+            state.stop(&name, snapshot)
+        };
+        // Keep going so one stuck guest doesn't leave the rest to be hard-killed.
+        match result {
+            Err(err) if bulk => {
+                tracing::error!("failed to stop {name}: {err}");
+                failed = Some(err);
+            }
+            result => result?,
+        }
+    }
+    match failed {
+        Some(err) => Err(err.into()),
+        None => Ok(()),
+    }
+}
+
+/// Connects to a virtual machine or snapshot; saved options belong to the virtual machine, and
+/// to the base of a snapshot.
+fn connect_resource(state: &mut State, connect: ConnectArgs, snapshot: bool) -> Result<()> {
+    let ConnectArgs {
+        forward_keys,
+        x11,
+        local_forwards,
+        remote_forwards,
+        dynamic_forwards,
+        identity,
+        jump,
+        options,
+        mosh,
+        command,
+        save,
+        username,
+        name,
+        snapshot: _,
+    } = connect;
+
+    let ResourceRef { name, snapshot } = ResourceRef::parse(&name, snapshot);
+    let options = ConnectOptions {
+        forward_agent: forward_keys,
+        x11,
+        local_forwards,
+        remote_forwards,
+        dynamic_forwards,
+        identity,
+        jump,
+        options,
+        mosh,
+        command,
+    };
+    if save {
+        let machine = if snapshot {
+            state.get_snapshot(&name)?.base.clone()
+        } else {
+            name.clone()
+        };
+        state.set_machine_connect(&machine, options.clone())?;
+        state.save()?;
+        state.unlock();
+    }
+    state.connect(&name, username, snapshot, &options)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn failed_jobs_are_recorded() {
+        let dir = TempDir::new().unwrap();
+        let state = State::load(dir.path()).unwrap();
+
+        let command = ["run", "ubuntu", "--", "false"].map(String::from).to_vec();
+        record(&state, command.clone(), &Ok(1));
+
+        let history = state.history().unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].command, command);
+        assert_eq!(history[0].result, "exited with 1");
+    }
+}
@@ -0,0 +1,23 @@
+use crate::{result::Result, size::Size};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Manifest {
+    pub name: String,
+    pub port: u16,
+    pub size: Size,
+    pub cores: usize,
+    pub ram: Size,
+}
+
+impl Manifest {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,41 @@
+use crate::{error::Error, result::Result};
+use toml::{value::Table, Value};
+
+/// Current version of the serialized state. Bump this and append to `MIGRATIONS` whenever the
+/// layout of `state.toml` changes in a way serde defaults can't absorb.
+pub const VERSION: u32 = 1;
+
+/// `MIGRATIONS[n]` upgrades a version `n` state to version `n + 1`.
+const MIGRATIONS: [fn(&mut Table); VERSION as usize] = [v0_to_v1];
+
+/// States written before versioning could be missing any of the top-level tables.
+fn v0_to_v1(state: &mut Table) {
+    for table in ["disks", "machines", "snapshots"] {
+        state
+            .entry(table)
+            .or_insert_with(|| Value::Table(Table::new()));
+    }
+}
+
+pub fn migrate(value: &mut Value) -> Result<()> {
+    let state = value
+        .as_table_mut()
+        .ok_or(Error::InvalidStateVersion { version: 0 })?;
+
+    let version = match state.get("version") {
+        Some(version) => version
+            .as_integer()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or(Error::InvalidStateVersion { version: 0 })?,
+        None => 0,
+    };
+    if version > VERSION {
+        return Err(Error::InvalidStateVersion { version });
+    }
+
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(state);
+    }
+    state.insert("version".into(), Value::Integer(VERSION.into()));
+    Ok(())
+}
@@ -0,0 +1,237 @@
+//! In-memory backends for exercising `State` without QEMU or qemu-img installed.
+
+use crate::{
+    error::Error,
+    image::{ImageBackend, ImageFormat, ImageInfo},
+    process::{Process, ProcessBackend},
+    result::Result,
+    size::Size,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+    sync::{Arc, Mutex},
+};
+
+/// Pids handed out by `MockProcesses`, well above anything a real host would use.
+const FIRST_PID: u32 = 1 << 30;
+
+#[derive(Default)]
+struct Processes {
+    next: u32,
+    running: BTreeSet<u32>,
+    reused: BTreeSet<u32>,
+    spawned: Vec<Vec<String>>,
+}
+
+/// Pretends to run processes. Clones share their processes, so a test can keep one to inspect
+/// what was spawned after handing another to `State::set_process_backend`.
+#[derive(Clone, Default)]
+pub struct MockProcesses {
+    processes: Arc<Mutex<Processes>>,
+}
+
+impl MockProcesses {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The command lines spawned so far, program first.
+    pub fn spawned(&self) -> Vec<Vec<String>> {
+        self.processes.lock().unwrap().spawned.clone()
+    }
+
+    pub fn running(&self) -> Vec<u32> {
+        self.processes
+            .lock()
+            .unwrap()
+            .running
+            .iter()
+            .copied()
+            .collect()
+    }
+
+    /// Makes a process exit on its own, as if it crashed.
+    pub fn exit(&self, pid: u32) {
+        self.processes.lock().unwrap().running.remove(&pid);
+    }
+
+    /// Makes a process exit, and an unrelated one take its PID.
+    pub fn reuse(&self, pid: u32) {
+        self.processes.lock().unwrap().reused.insert(pid);
+    }
+}
+
+impl ProcessBackend for MockProcesses {
+    fn spawn(&self, cmd: &mut Command) -> Result<Box<dyn Process>> {
+        let mut processes = self.processes.lock().unwrap();
+        let pid = FIRST_PID + processes.next;
+        processes.next += 1;
+        processes.running.insert(pid);
+        processes.spawned.push(
+            std::iter::once(cmd.get_program())
+                .chain(cmd.get_args())
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+        );
+        Ok(Box::new(MockProcess {
+            pid,
+            processes: Arc::clone(&self.processes),
+        }))
+    }
+
+    fn kill(&self, pid: u32) -> Result<()> {
+        self.exit(pid);
+        Ok(())
+    }
+
+    fn alive(&self, pid: u32) -> bool {
+        self.processes.lock().unwrap().running.contains(&pid)
+    }
+
+    fn started(&self, pid: u32) -> Option<u64> {
+        let processes = self.processes.lock().unwrap();
+        processes
+            .running
+            .contains(&pid)
+            .then(|| u64::from(processes.reused.contains(&pid)))
+    }
+}
+
+struct MockProcess {
+    pid: u32,
+    processes: Arc<Mutex<Processes>>,
+}
+
+impl Process for MockProcess {
+    fn id(&self) -> u32 {
+        self.pid
+    }
+
+    fn try_wait(&mut self) -> Result<Option<ExitStatus>> {
+        let running = self.processes.lock().unwrap().running.contains(&self.pid);
+        Ok((!running).then(|| ExitStatus::from_raw(0)))
+    }
+
+    /// Mock processes never block: waiting on one makes it exit straight away.
+    fn wait(&mut self) -> Result<ExitStatus> {
+        self.processes.lock().unwrap().running.remove(&self.pid);
+        Ok(ExitStatus::from_raw(0))
+    }
+}
+
+/// Pretends to manage images, writing empty files in their place and keeping their details in
+/// memory. Clones share their images.
+#[derive(Clone, Default)]
+pub struct MockImages {
+    images: Arc<Mutex<BTreeMap<PathBuf, ImageInfo>>>,
+}
+
+impl MockImages {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, path: &Path) -> Option<ImageInfo> {
+        self.images.lock().unwrap().get(path).cloned()
+    }
+
+    fn insert(&self, path: &Path, info: ImageInfo) -> Result<()> {
+        fs::write(path, [])?;
+        self.images.lock().unwrap().insert(path.into(), info);
+        Ok(())
+    }
+}
+
+impl ImageBackend for MockImages {
+    fn create(&self, path: &Path, size: Size) -> Result<()> {
+        self.insert(
+            path,
+            ImageInfo {
+                format: ImageFormat::Qcow2,
+                virtual_size: size,
+                backing: None,
+            },
+        )
+    }
+
+    fn snapshot(
+        &self,
+        _name: &str,
+        backing: &Path,
+        _format: ImageFormat,
+        path: &Path,
+    ) -> Result<()> {
+        let info = self.info(backing)?;
+        self.insert(
+            path,
+            ImageInfo {
+                format: ImageFormat::Qcow2,
+                backing: Some(backing.into()),
+                ..info
+            },
+        )
+    }
+
+    /// Images that weren't made by this backend are taken to be as large as their files.
+    fn convert(
+        &self,
+        _name: &str,
+        src: &Path,
+        _format: ImageFormat,
+        dst: &Path,
+        _compress: bool,
+    ) -> Result<()> {
+        let virtual_size = match self.get(src) {
+            Some(info) => info.virtual_size,
+            None => Size::bytes(
+                fs::metadata(src)
+                    .map_err(|_| Error::InvalidImage { path: src.into() })?
+                    .len(),
+            ),
+        };
+        self.insert(
+            dst,
+            ImageInfo {
+                format: ImageFormat::Qcow2,
+                virtual_size,
+                backing: None,
+            },
+        )
+    }
+
+    fn rebase(&self, path: &Path, backing: &Path, _format: ImageFormat) -> Result<()> {
+        let mut images = self.images.lock().unwrap();
+        let info = images
+            .get_mut(path)
+            .ok_or(Error::InvalidImage { path: path.into() })?;
+        info.backing = Some(backing.into());
+        Ok(())
+    }
+
+    fn resize(&self, path: &Path, size: Size, _shrink: bool) -> Result<()> {
+        let mut images = self.images.lock().unwrap();
+        let info = images
+            .get_mut(path)
+            .ok_or(Error::InvalidImage { path: path.into() })?;
+        info.virtual_size = size;
+        Ok(())
+    }
+
+    fn info(&self, path: &Path) -> Result<ImageInfo> {
+        self.get(path)
+            .ok_or(Error::InvalidImage { path: path.into() })
+    }
+
+    /// Nothing is ever written to mock images.
+    fn allocated(&self, path: &Path) -> Result<u64> {
+        self.info(path).map(|_| 0)
+    }
+
+    fn check(&self, path: &Path) -> Result<bool> {
+        Ok(self.get(path).is_some())
+    }
+}
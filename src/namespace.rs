@@ -0,0 +1,212 @@
+//! Names like `project/web-1`, which group resources under a prefix without a separate grouping
+//! mechanism.
+
+use crate::{error::Error, reference, result::Result};
+
+/// Separates the namespaces of a name from each other and from its last segment.
+pub const SEPARATOR: char = '/';
+
+/// Checks that a new name can be stored, grouped and matched: no empty, `.` or `..` segments, no
+/// `%`, which is reserved for encoding names in file names, nothing that makes it a pattern and
+/// no `machine/` or `snapshot/` namespace, which address resources of that kind.
+pub fn validate(name: &str) -> Result<()> {
+    let valid = name
+        .split(SEPARATOR)
+        .all(|segment| !matches!(segment, "" | "." | ".."))
+        && !name.contains('%')
+        && !is_pattern(name)
+        && !reference::is_reserved(name);
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidName { name: name.into() })
+    }
+}
+
+/// The name as a single file name, so that namespaced resources sit next to the rest rather than
+/// in directories of their own. Names without namespaces are unchanged.
+pub fn encode(name: &str) -> String {
+    name.replace(SEPARATOR, "%2F")
+}
+
+/// The file name of a resource's file with the given extension.
+pub fn file_name(name: &str, extension: &str) -> String {
+    format!("{}.{extension}", encode(name))
+}
+
+/// Whether the argument names a namespace, e.g. `project/`, rather than a single resource.
+pub fn is_prefix(arg: &str) -> bool {
+    arg.ends_with(SEPARATOR)
+}
+
+/// Whether `name` is in the namespace `prefix` or one nested in it.
+pub fn contains(prefix: &str, name: &str) -> bool {
+    name.starts_with(prefix)
+}
+
+/// The namespace an argument like `project` or `project/` names, with its trailing separator.
+pub fn prefix(arg: &str) -> String {
+    format!("{}{SEPARATOR}", arg.trim_end_matches(SEPARATOR))
+}
+
+/// Whether the argument is a shell-style pattern like `ci-*` rather than a name.
+pub fn is_pattern(arg: &str) -> bool {
+    arg.contains(['*', '?', '['])
+}
+
+/// Whether `name` matches a shell-style pattern: `*` matches any characters and `?` any one,
+/// neither across a separator, and `[...]` one of a set such as `[0-9]` or `[!a]`.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    glob(
+        &pattern.chars().collect::<Vec<_>>(),
+        &name.chars().collect::<Vec<_>>(),
+    )
+}
+
+fn glob(pattern: &[char], name: &[char]) -> bool {
+    let next = name.first().filter(|&&c| c != SEPARATOR);
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len())
+            .take_while(|&n| !name[..n].contains(&SEPARATOR))
+            .any(|n| glob(rest, &name[n..])),
+        Some(('?', rest)) => next.is_some() && glob(rest, &name[1..]),
+        Some(('[', rest)) => match class(rest) {
+            Some((negated, set, rest)) => {
+                next.is_some_and(|&c| in_set(set, c) != negated) && glob(rest, &name[1..])
+            }
+            None => name.first() == Some(&'[') && glob(rest, &name[1..]),
+        },
+        Some((c, rest)) => name.first() == Some(c) && glob(rest, &name[1..]),
+    }
+}
+
+/// Splits a character class following its `[` into whether it is negated, its members and the
+/// rest of the pattern, or `None` if it isn't closed and the `[` is meant literally.
+fn class(pattern: &[char]) -> Option<(bool, &[char], &[char])> {
+    let (negated, pattern) = match pattern.split_first() {
+        Some(('!' | '^', rest)) => (true, rest),
+        _ => (false, pattern),
+    };
+    // A `]` right after the opening bracket is a member rather than the end.
+    let end = pattern.iter().skip(1).position(|&c| c == ']')? + 1;
+    Some((negated, &pattern[..end], &pattern[end + 1..]))
+}
+
+fn in_set(set: &[char], c: char) -> bool {
+    let mut set = set;
+    while let Some((&first, rest)) = set.split_first() {
+        match rest {
+            ['-', last, rest @ ..] => {
+                if (first..=*last).contains(&c) {
+                    return true;
+                }
+                set = rest;
+            }
+            _ => {
+                if first == c {
+                    return true;
+                }
+                set = rest;
+            }
+        }
+    }
+    false
+}
+
+/// Whether the argument names a single resource rather than a namespace or pattern.
+pub fn is_name(arg: &str) -> bool {
+    !is_prefix(arg) && !is_pattern(arg)
+}
+
+/// The names an argument stands for: those in the namespace it names, those matching it if it
+/// is a pattern, or else just itself.
+pub fn expand<'a, I>(arg: &str, names: I) -> Vec<String>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    if is_prefix(arg) {
+        names
+            .into_iter()
+            .filter(|name| contains(arg, name))
+            .map(String::from)
+            .collect()
+    } else if is_pattern(arg) {
+        names
+            .into_iter()
+            .filter(|name| matches(arg, name))
+            .map(String::from)
+            .collect()
+    } else {
+        vec![arg.into()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn question_marks_match_one_character() {
+        assert!(matches("web-?", "web-1"));
+        assert!(!matches("web-?", "web-"));
+        assert!(!matches("web-?", "web-12"));
+        assert!(!matches("a?b", "a/b"));
+    }
+
+    #[test]
+    fn classes_match_one_of_a_set() {
+        assert!(matches("web-[0-9]", "web-3"));
+        assert!(!matches("web-[0-9]", "web-x"));
+        assert!(matches("[!a]x", "bx"));
+        assert!(!matches("[!a]x", "ax"));
+        assert!(matches("[^a]x", "bx"));
+        assert!(!matches("[!a]x", "/x"));
+    }
+
+    #[test]
+    fn leading_brackets_are_members() {
+        assert!(matches("[]a]", "]"));
+        assert!(matches("[]a]", "a"));
+        assert!(!matches("[]a]", "b"));
+        assert!(matches("[!]]", "a"));
+        assert!(!matches("[!]]", "]"));
+    }
+
+    #[test]
+    fn unclosed_brackets_are_literal() {
+        assert!(matches("web[1", "web[1"));
+        assert!(!matches("web[1", "web1"));
+        assert!(matches("web[*", "web[12"));
+    }
+
+    #[test]
+    fn stars_stay_within_a_namespace() {
+        assert!(matches("ci-*", "ci-"));
+        assert!(matches("ci-*", "ci-build"));
+        assert!(!matches("ci-*", "ci-build/1"));
+        assert!(matches("*/web", "project/web"));
+        assert!(!matches("*/web", "a/b/web"));
+        assert!(matches("*/*/web", "a/b/web"));
+    }
+
+    #[test]
+    fn names_are_validated() {
+        assert!(validate("project/web-1").is_ok());
+        for name in [
+            "",
+            "a//b",
+            "a/",
+            "./a",
+            "a/..",
+            "a%2Fb",
+            "ci-*",
+            "machine/a",
+        ] {
+            assert!(
+                matches!(validate(name), Err(Error::InvalidName { .. })),
+                "{name}"
+            );
+        }
+    }
+}
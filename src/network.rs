@@ -0,0 +1,135 @@
+use crate::{arch, error::Error};
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// QEMU's id for the user-mode NIC used for SSH.
+pub const USER_NETDEV: &str = "net0";
+/// QEMU's id for the extra NIC, if the machine has one.
+pub const EXTRA_NETDEV: &str = "net1";
+
+/// Address an isolated guest connects to for the hosts it is allowed to reach.
+pub const GATEWAY_ADDR: &str = "10.0.2.100";
+
+#[cfg(target_os = "linux")]
+const SHARED_BRIDGE: &str = "virbr0";
+
+#[derive(Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum Network {
+    #[default]
+    User,
+    Shared,
+    Bridged(String),
+    Tap(String),
+}
+
+impl Network {
+    pub fn is_user(&self) -> bool {
+        *self == Self::User
+    }
+
+    /// The NIC that carries the guest's traffic: the extra one if there is one.
+    pub fn capture_netdev(&self) -> &'static str {
+        match self.netdev() {
+            Some(_) => EXTRA_NETDEV,
+            None => USER_NETDEV,
+        }
+    }
+
+    fn netdev(&self) -> Option<String> {
+        match self {
+            Self::User => None,
+            #[cfg(target_os = "macos")]
+            Self::Shared => Some(format!("vmnet-shared,id={EXTRA_NETDEV}")),
+            #[cfg(target_os = "macos")]
+            Self::Bridged(iface) => Some(format!("vmnet-bridged,id={EXTRA_NETDEV},ifname={iface}")),
+            #[cfg(target_os = "linux")]
+            Self::Shared => Some(format!("bridge,id={EXTRA_NETDEV},br={SHARED_BRIDGE}")),
+            #[cfg(target_os = "linux")]
+            Self::Bridged(bridge) => Some(format!("bridge,id={EXTRA_NETDEV},br={bridge}")),
+            #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+            Self::Shared | Self::Bridged(_) => None,
+            Self::Tap(iface) => Some(format!(
+                "tap,id={EXTRA_NETDEV},ifname={iface},script=no,downscript=no"
+            )),
+        }
+    }
+
+    /// Arguments for an extra NIC on top of the user-mode one used for SSH.
+    pub fn qemu_args(&self, mac: &str) -> Vec<String> {
+        match self.netdev() {
+            Some(netdev) => vec![
+                "-netdev".into(),
+                netdev,
+                "-device".into(),
+                format!("{},netdev={EXTRA_NETDEV},mac={mac}", arch::virtio("net")),
+            ],
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Options for the user-mode NIC of an isolated machine: no outbound connections, except to
+/// each allowed `host:port`, which the guest reaches at `GATEWAY_ADDR` on the same port.
+pub fn isolation_args(allow: &[String]) -> Result<String, Error> {
+    let mut args = String::from(",restrict=on");
+    for forward in allow {
+        let port = forward
+            .rsplit_once(':')
+            .filter(|(host, _)| !host.is_empty())
+            .and_then(|(_, port)| port.parse::<u16>().ok())
+            .ok_or(Error::InvalidForward {
+                forward: forward.clone(),
+            })?;
+        args.push_str(&format!(
+            ",guestfwd=tcp:{GATEWAY_ADDR}:{port}-tcp:{forward}"
+        ));
+    }
+    Ok(args)
+}
+
+/// A stable MAC address for the extra NIC, derived from the machine's unique SSH port so its
+/// DHCP lease can be found again.
+pub fn mac_address(port: u16) -> String {
+    let [high, low] = port.to_be_bytes();
+    format!("52:54:00:00:{high:02x}:{low:02x}")
+}
+
+impl FromStr for Network {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "user" => Ok(Self::User),
+            None if s == "shared" => Ok(Self::Shared),
+            Some(("bridged", iface)) if !iface.is_empty() => Ok(Self::Bridged(iface.into())),
+            Some(("tap", iface)) if !iface.is_empty() => Ok(Self::Tap(iface.into())),
+            _ => Err(Error::InvalidNetwork { network: s.into() }),
+        }
+    }
+}
+
+impl TryFrom<String> for Network {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Network> for String {
+    fn from(network: Network) -> Self {
+        network.to_string()
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::User => write!(f, "user"),
+            Self::Shared => write!(f, "shared"),
+            Self::Bridged(iface) => write!(f, "bridged:{iface}"),
+            Self::Tap(iface) => write!(f, "tap:{iface}"),
+        }
+    }
+}
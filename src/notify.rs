@@ -0,0 +1,58 @@
+use crate::{config::Notifications, error::Error, exec::Traced, result::Result};
+use serde_json::json;
+use std::process::Command;
+
+const WEBHOOK_TIMEOUT: &str = "10";
+
+/// Sends a notification everywhere `notifications` asks for. Failing to deliver one is logged
+/// rather than failing whatever it was about.
+pub fn send(notifications: &Notifications, message: &str) {
+    if notifications.desktop() {
+        if let Err(err) = desktop(message) {
+            tracing::warn!("failed to show notification: {err}");
+        }
+    }
+    if let Some(url) = &notifications.webhook {
+        if let Err(err) = webhook(url, message) {
+            tracing::warn!("failed to send notification: {err}");
+        }
+    }
+}
+
+/// Notification center on macOS, notify-send anywhere else.
+fn desktop(message: &str) -> Result<()> {
+    let mut cmd = if cfg!(target_os = "macos") {
+        // JSON string literals are valid AppleScript ones.
+        let mut cmd = Command::new("osascript");
+        cmd.arg("-e").arg(format!(
+            "display notification {} with title \"rhea\"",
+            json!(message)
+        ));
+        cmd
+    } else {
+        let mut cmd = Command::new("notify-send");
+        cmd.args(["rhea", message]);
+        cmd
+    };
+    run(&mut cmd)
+}
+
+/// Posts `{"text": message}`, which Slack incoming webhooks and most chat services accept.
+fn webhook(url: &str, message: &str) -> Result<()> {
+    run(Command::new("curl")
+        .args(["-fsS", "--max-time", WEBHOOK_TIMEOUT])
+        .args(["-H", "Content-Type: application/json"])
+        .args(["-d", &json!({ "text": message }).to_string()])
+        .arg(url))
+}
+
+fn run(cmd: &mut Command) -> Result<()> {
+    let output = cmd.traced()?.output()?;
+    if !output.status.success() {
+        return Err(Error::CommandFailed {
+            command: cmd.get_program().to_string_lossy().into(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().into(),
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,183 @@
+//! Guessing the operating system of a guest from its installer or image, so that guests which
+//! lack virtio drivers or have a fixed account work without extra flags.
+
+use crate::{arch, error::Error};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+    str::FromStr,
+};
+
+/// The primary volume descriptor of an ISO 9660 image, at sector 16, which starts with `CD001`
+/// after a type byte and has the volume identifier 40 bytes in.
+const ISO_DESCRIPTOR: u64 = 16 * 2048;
+const ISO_LABEL_LEN: usize = 32;
+
+/// What a guest runs. Linux distributions are only told apart for cloud images, whose accounts
+/// are fixed; installed from an ISO, they are all `Linux`.
+#[derive(Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum GuestOs {
+    Linux,
+    Ubuntu,
+    Debian,
+    Fedora,
+    Alpine,
+    Windows,
+    FreeBsd,
+    OpenBsd,
+    NetBsd,
+}
+
+impl GuestOs {
+    /// Guesses the guest from the name of its installer or image and, for ISOs, the volume
+    /// label, e.g. `CCCOMA_X64FRE_EN-US_DV9` for Windows.
+    pub fn detect(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        let label = iso_label(path).unwrap_or_default().to_lowercase();
+        let hint = format!("{name} {label}");
+        let cloud = name.contains("cloud");
+
+        let has = |needles: &[&str]| needles.iter().any(|needle| hint.contains(needle));
+        if has(&[
+            "windows", "win10", "win11", "cccoma", "x64fre", "x86fre", "a64fre",
+        ]) {
+            Some(Self::Windows)
+        } else if has(&["freebsd"]) {
+            Some(Self::FreeBsd)
+        } else if has(&["openbsd"]) {
+            Some(Self::OpenBsd)
+        } else if has(&["netbsd"]) {
+            Some(Self::NetBsd)
+        } else if cloud && has(&["ubuntu"]) {
+            Some(Self::Ubuntu)
+        } else if cloud && has(&["debian"]) {
+            Some(Self::Debian)
+        } else if cloud && has(&["fedora"]) {
+            Some(Self::Fedora)
+        } else if cloud && has(&["alpine"]) {
+            Some(Self::Alpine)
+        } else if has(&[
+            "linux",
+            "ubuntu",
+            "debian",
+            "fedora",
+            "alpine",
+            "archlinux",
+            "centos",
+            "rocky",
+            "alma",
+            "opensuse",
+            "nixos",
+        ]) {
+            Some(Self::Linux)
+        } else {
+            None
+        }
+    }
+
+    /// The device the boot disk attaches as, where it can't be virtio.
+    pub fn block_device(&self) -> Option<&'static str> {
+        // Windows only ships virtio drivers separately; q35 has an AHCI controller built in.
+        (*self == Self::Windows && arch::HAS_BIOS).then_some("ide-hd")
+    }
+
+    /// The model of the user-mode NIC.
+    pub fn nic_model(&self) -> String {
+        match self {
+            Self::Windows => "e1000".into(),
+            _ => arch::virtio("net"),
+        }
+    }
+
+    /// Features added to `-cpu host`, such as the Hyper-V enlightenments Windows runs faster
+    /// with under KVM.
+    pub fn cpu_flags(&self) -> &'static str {
+        if *self == Self::Windows && arch::HAS_BIOS && arch::ACCEL == "kvm" {
+            ",hv_relaxed,hv_vapic,hv_spinlocks=0x1fff,hv_time"
+        } else {
+            ""
+        }
+    }
+
+    /// The account images of the guest come with, used when no username is given.
+    pub fn default_user(&self) -> Option<&'static str> {
+        match self {
+            Self::Ubuntu => Some("ubuntu"),
+            Self::Debian => Some("debian"),
+            Self::Fedora => Some("fedora"),
+            Self::Alpine => Some("alpine"),
+            Self::FreeBsd => Some("freebsd"),
+            Self::Windows => Some("Administrator"),
+            Self::Linux | Self::OpenBsd | Self::NetBsd => None,
+        }
+    }
+}
+
+/// The volume identifier of an ISO 9660 image, `None` for anything else.
+fn iso_label(path: &Path) -> Option<String> {
+    let mut file = File::open(path).ok()?;
+    file.seek(SeekFrom::Start(ISO_DESCRIPTOR + 1)).ok()?;
+    let mut magic = [0; 5];
+    file.read_exact(&mut magic).ok()?;
+    if &magic != b"CD001" {
+        return None;
+    }
+    file.seek(SeekFrom::Start(ISO_DESCRIPTOR + 40)).ok()?;
+    let mut label = [0; ISO_LABEL_LEN];
+    file.read_exact(&mut label).ok()?;
+    Some(String::from_utf8_lossy(&label).trim().into())
+}
+
+impl FromStr for GuestOs {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linux" => Ok(Self::Linux),
+            "ubuntu" => Ok(Self::Ubuntu),
+            "debian" => Ok(Self::Debian),
+            "fedora" => Ok(Self::Fedora),
+            "alpine" => Ok(Self::Alpine),
+            "windows" => Ok(Self::Windows),
+            "freebsd" => Ok(Self::FreeBsd),
+            "openbsd" => Ok(Self::OpenBsd),
+            "netbsd" => Ok(Self::NetBsd),
+            _ => Err(Error::InvalidGuestOs { os: s.into() }),
+        }
+    }
+}
+
+impl TryFrom<String> for GuestOs {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<GuestOs> for String {
+    fn from(os: GuestOs) -> Self {
+        os.to_string()
+    }
+}
+
+impl fmt::Display for GuestOs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let os = match self {
+            Self::Linux => "linux",
+            Self::Ubuntu => "ubuntu",
+            Self::Debian => "debian",
+            Self::Fedora => "fedora",
+            Self::Alpine => "alpine",
+            Self::Windows => "windows",
+            Self::FreeBsd => "freebsd",
+            Self::OpenBsd => "openbsd",
+            Self::NetBsd => "netbsd",
+        };
+        write!(f, "{os}")
+    }
+}
@@ -0,0 +1,102 @@
+use serde::Serialize;
+use std::{fmt, fs, net::TcpListener, path::Path, process::Command};
+
+const PROC_NET_TCP: [&str; 2] = ["/proc/net/tcp", "/proc/net/tcp6"];
+const TCP_LISTEN: &str = "0A";
+
+/// How a port assigned to a machine looks from the host.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PortStatus {
+    /// The machine is stopped and nothing else holds the port.
+    Free,
+    /// The machine is running and holds the port.
+    Ok,
+    /// The machine is running but nothing is listening on the port.
+    NotListening,
+    /// Another process holds the port.
+    Conflict,
+    /// The port is assigned to more than one machine.
+    Duplicate,
+}
+
+impl fmt::Display for PortStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Free => "free",
+                Self::Ok => "ok",
+                Self::NotListening => "not listening",
+                Self::Conflict => "conflict",
+                Self::Duplicate => "duplicate",
+            }
+        )
+    }
+}
+
+/// A host port forwarded to a machine: its SSH port or a probe.
+pub struct PortUse {
+    pub name: String,
+    pub snapshot: bool,
+    pub forward: String,
+    pub port: u16,
+    pub guest_port: u16,
+    pub pid: Option<u32>,
+    pub status: PortStatus,
+}
+
+pub fn bound(port: u16) -> bool {
+    TcpListener::bind(("127.0.0.1", port)).is_err()
+}
+
+/// The process listening on a TCP port, if it can be seen: other users' processes usually can't.
+pub fn listener(port: u16) -> Option<u32> {
+    if Path::new(PROC_NET_TCP[0]).exists() {
+        let inode = PROC_NET_TCP
+            .iter()
+            .filter_map(|path| fs::read_to_string(path).ok())
+            .find_map(|table| listening_inode(&table, port))?;
+        return socket_owner(&inode);
+    }
+
+    let output = Command::new("lsof")
+        .args(["-nP", "-t", "-sTCP:LISTEN"])
+        .arg(format!("-iTCP:{port}"))
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.trim().parse().ok())
+}
+
+/// Finds the socket inode listening on `port` in a `/proc/net/tcp` table.
+fn listening_inode(table: &str, port: u16) -> Option<String> {
+    table.lines().skip(1).find_map(|line| {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        let (_, local_port) = fields.get(1)?.rsplit_once(':')?;
+        (u16::from_str_radix(local_port, 16).ok()? == port && *fields.get(3)? == TCP_LISTEN)
+            .then(|| fields.get(9).map(|inode| inode.to_string()))
+            .flatten()
+    })
+}
+
+fn socket_owner(inode: &str) -> Option<u32> {
+    let socket = format!("socket:[{inode}]");
+    fs::read_dir("/proc")
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| Some((entry.file_name().to_str()?.parse::<u32>().ok()?, entry)))
+        .find(|(_, entry)| {
+            fs::read_dir(entry.path().join("fd"))
+                .into_iter()
+                .flatten()
+                .filter_map(|fd| fd.ok())
+                .any(|fd| {
+                    fs::read_link(fd.path())
+                        .is_ok_and(|target| target.as_os_str() == socket.as_str())
+                })
+        })
+        .map(|(pid, _)| pid)
+}
@@ -0,0 +1,89 @@
+use crate::{error::Error, size::Size};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+/// Cores, RAM and disk size to create a virtual machine with, written as e.g. `2c/2G/32G`.
+#[derive(Clone, Copy, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Preset {
+    pub cores: usize,
+    pub ram: Size,
+    pub size: Size,
+}
+
+impl Default for Preset {
+    fn default() -> Self {
+        Self {
+            cores: 4,
+            ram: Size::gigabytes(4),
+            size: Size::gigabytes(128),
+        }
+    }
+}
+
+/// Presets available without any configuration; the config can override or add to them.
+pub fn builtin() -> BTreeMap<String, Preset> {
+    BTreeMap::from([
+        (
+            "small".into(),
+            Preset {
+                cores: 2,
+                ram: Size::gigabytes(2),
+                size: Size::gigabytes(32),
+            },
+        ),
+        ("medium".into(), Preset::default()),
+        (
+            "large".into(),
+            Preset {
+                cores: 8,
+                ram: Size::gigabytes(16),
+                size: Size::gigabytes(256),
+            },
+        ),
+    ])
+}
+
+impl FromStr for Preset {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidPreset { preset: s.into() };
+
+        let mut parts = s.split('/');
+        let (Some(cores), Some(ram), Some(size), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        Ok(Self {
+            cores: cores
+                .trim()
+                .strip_suffix('c')
+                .and_then(|cores| cores.parse().ok())
+                .ok_or_else(invalid)?,
+            ram: ram.parse().map_err(|_| invalid())?,
+            size: size.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl TryFrom<String> for Preset {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Preset> for String {
+    fn from(preset: Preset) -> Self {
+        preset.to_string()
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}c/{}/{}", self.cores, self.ram, self.size)
+    }
+}
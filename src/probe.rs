@@ -0,0 +1,111 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    time::Duration,
+};
+
+const PROBE_CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long a peer that has nothing to say has to keep the connection open to count as there.
+const PROBE_SETTLE_TIMEOUT: Duration = Duration::from_millis(200);
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Probe {
+    pub host_port: u16,
+    pub guest_port: u16,
+    pub path: Option<String>,
+}
+
+fn connect(port: u16) -> Option<TcpStream> {
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let stream = TcpStream::connect_timeout(&addr, PROBE_CONNECT_TIMEOUT).ok()?;
+    stream.set_read_timeout(Some(PROBE_CONNECT_TIMEOUT)).ok()?;
+    stream.set_write_timeout(Some(PROBE_CONNECT_TIMEOUT)).ok()?;
+    Some(stream)
+}
+
+/// Whether something in the guest answers on a forwarded port. QEMU accepts connections to
+/// forwarded ports as soon as it is up, and only closes them once the guest refuses, so a
+/// connection alone proves nothing: the peer has to send something or keep it open.
+fn answered(stream: &mut TcpStream) -> bool {
+    if stream.set_read_timeout(Some(PROBE_SETTLE_TIMEOUT)).is_err() {
+        return false;
+    }
+    match stream.read(&mut [0; 1]) {
+        Ok(n) => n > 0,
+        Err(err) => matches!(
+            err.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        ),
+    }
+}
+
+pub fn port_open(port: u16) -> bool {
+    connect(port).is_some_and(|mut stream| answered(&mut stream))
+}
+
+/// Whether sshd in the guest answers on a forwarded port, by sending its version banner.
+pub fn ssh_ready(port: u16) -> bool {
+    let mut banner = [0; 4];
+    connect(port)
+        .is_some_and(|mut stream| stream.read_exact(&mut banner).is_ok() && &banner == b"SSH-")
+}
+
+pub fn free_port() -> Option<u16> {
+    TcpListener::bind(("127.0.0.1", 0))
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .ok()
+}
+
+impl Probe {
+    pub fn kind(&self) -> &'static str {
+        if self.path.is_some() {
+            "http"
+        } else {
+            "tcp"
+        }
+    }
+
+    pub fn ready(&self) -> bool {
+        let Some(mut stream) = connect(self.host_port) else {
+            return false;
+        };
+
+        let Some(path) = &self.path else {
+            return answered(&mut stream);
+        };
+
+        let request = format!("GET {path} HTTP/1.0\r\nHost: localhost\r\n\r\n");
+        if stream.write_all(request.as_bytes()).is_err() {
+            return false;
+        }
+
+        let mut response = [0; 64];
+        let Ok(n) = stream.read(&mut response) else {
+            return false;
+        };
+
+        String::from_utf8_lossy(&response[..n])
+            .split_whitespace()
+            .nth(1)
+            == Some("200")
+    }
+}
+
+impl fmt::Display for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (host port: {}, guest port: {})",
+            self.kind(),
+            self.host_port,
+            self.guest_port
+        )?;
+        if let Some(path) = &self.path {
+            write!(f, " {path}")?;
+        }
+        Ok(())
+    }
+}
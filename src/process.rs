@@ -0,0 +1,114 @@
+use crate::{exec::Traced, result::Result};
+use std::{
+    fmt, fs, io,
+    os::unix::process::CommandExt,
+    process::{Child, Command, ExitStatus},
+};
+
+/// A QEMU process, or the ssh session running it on a remote host.
+pub trait Process: Send {
+    fn id(&self) -> u32;
+
+    /// The exit status if the process has exited, without waiting for it.
+    fn try_wait(&mut self) -> Result<Option<ExitStatus>>;
+
+    fn wait(&mut self) -> Result<ExitStatus>;
+}
+
+impl Process for Child {
+    fn id(&self) -> u32 {
+        Child::id(self)
+    }
+
+    fn try_wait(&mut self) -> Result<Option<ExitStatus>> {
+        Ok(Child::try_wait(self)?)
+    }
+
+    fn wait(&mut self) -> Result<ExitStatus> {
+        Ok(Child::wait(self)?)
+    }
+}
+
+/// Runs `cmd` in a new session, so it has no controlling terminal and survives the terminal
+/// (or SSH login) it was started from closing.
+pub fn detach(cmd: &mut Command) {
+    // setsid is async-signal-safe, and only fails if the child already leads a process group.
+    unsafe {
+        cmd.pre_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
+}
+
+/// How `State` starts, signals and checks on the processes running its machines, so that they
+/// can be run by something other than the host (or by a mock in tests).
+pub trait ProcessBackend: Send + Sync {
+    fn spawn(&self, cmd: &mut Command) -> Result<Box<dyn Process>>;
+
+    /// Asks a process to shut down.
+    fn kill(&self, pid: u32) -> Result<()>;
+
+    fn alive(&self, pid: u32) -> bool;
+
+    /// When a process started, to tell it from a later one given the same PID, if the backend
+    /// can tell.
+    fn started(&self, pid: u32) -> Option<u64>;
+}
+
+/// The default backend, running processes on this host.
+pub struct Host;
+
+impl ProcessBackend for Host {
+    fn spawn(&self, cmd: &mut Command) -> Result<Box<dyn Process>> {
+        Ok(Box::new(cmd.traced()?.spawn()?))
+    }
+
+    fn kill(&self, pid: u32) -> Result<()> {
+        Command::new("kill")
+            .arg(format!("{pid}"))
+            .traced()?
+            .spawn()?
+            .wait()?;
+        Ok(())
+    }
+
+    fn alive(&self, pid: u32) -> bool {
+        let Ok(pid) = libc::pid_t::try_from(pid) else {
+            return false;
+        };
+        // A long-running rhea (like `rhea supervise`) is the parent of what it started, which
+        // lingers as a zombie after exiting until it is reaped here.
+        if unsafe { libc::waitpid(pid, std::ptr::null_mut(), libc::WNOHANG) } == pid {
+            return false;
+        }
+        if zombie(pid) {
+            return false;
+        }
+        // Signal 0 only checks that the process exists; EPERM means it exists but isn't ours.
+        let alive = unsafe { libc::kill(pid, 0) } == 0;
+        alive || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+
+    /// The start time in /proc, in clock ticks since the host booted.
+    fn started(&self, pid: u32) -> Option<u64> {
+        stat(pid)?.get(STARTTIME_FIELD)?.parse().ok()
+    }
+}
+
+/// The position of the start time among the fields of /proc/<pid>/stat from the state on.
+const STARTTIME_FIELD: usize = 19;
+
+/// The fields of /proc/<pid>/stat from the state on, which only Linux has.
+fn stat(pid: impl fmt::Display) -> Option<Vec<String>> {
+    // The state follows the parenthesized command name, which may itself contain spaces.
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    let (_, rest) = stat.rsplit_once(')')?;
+    Some(rest.split_whitespace().map(String::from).collect())
+}
+
+/// Whether a process has exited and is waiting for another process to reap it, which only
+/// Linux can tell.
+fn zombie(pid: libc::pid_t) -> bool {
+    stat(pid).is_some_and(|fields| fields.first().is_some_and(|state| state == "Z"))
+}
@@ -0,0 +1,25 @@
+//! Profiles: sets of resources kept in state directories of their own, such as a scratch profile
+//! for experiments next to the default, long-lived one.
+
+use crate::{error::Error, result::Result};
+use path_macro::path;
+use std::path::{Path, PathBuf};
+
+/// The profile used unless `--profile` picks another, kept in the state directory itself.
+pub const DEFAULT: &str = "default";
+
+const DIR_PATH: &str = "profiles";
+
+/// The state directory of a profile, next to the remote states under the default profile's
+/// `root`.
+pub fn path(root: &Path, profile: &str) -> Result<PathBuf> {
+    if profile == DEFAULT {
+        return Ok(root.into());
+    }
+    if profile.is_empty() || profile.contains(['/', '%']) || matches!(profile, "." | "..") {
+        return Err(Error::InvalidProfile {
+            profile: profile.into(),
+        });
+    }
+    Ok(path![root / DIR_PATH / profile])
+}
@@ -0,0 +1,155 @@
+use crate::error::Error;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fmt,
+    str::FromStr,
+    sync::{Mutex, OnceLock, PoisonError},
+    time::Duration,
+};
+
+const BAR_TEMPLATE: &str = "{msg} [{bar:30}] {percent:>3}% ({eta})";
+const SPINNER_TEMPLATE: &str = "{spinner} {msg} ({elapsed})";
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Bars currently drawn, keyed by operation and name so concurrent operations each get one.
+struct Bars {
+    multi: MultiProgress,
+    bars: Mutex<HashMap<(String, String), ProgressBar>>,
+}
+
+fn bars() -> &'static Bars {
+    static BARS: OnceLock<Bars> = OnceLock::new();
+    BARS.get_or_init(|| Bars {
+        multi: MultiProgress::new(),
+        bars: Mutex::new(HashMap::new()),
+    })
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProgressFormat {
+    #[default]
+    None,
+    Bar,
+    Json,
+}
+
+impl FromStr for ProgressFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "bar" => Ok(Self::Bar),
+            "json" => Ok(Self::Json),
+            _ => Err(Error::InvalidProgressFormat { format: s.into() }),
+        }
+    }
+}
+
+impl fmt::Display for ProgressFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::None => "none",
+                Self::Bar => "bar",
+                Self::Json => "json",
+            }
+        )
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum Event<'a> {
+    Start {
+        operation: &'a str,
+        name: &'a str,
+    },
+    Progress {
+        operation: &'a str,
+        name: &'a str,
+        percent: f64,
+    },
+    Finish {
+        operation: &'a str,
+        name: &'a str,
+    },
+    Error {
+        operation: &'a str,
+        name: &'a str,
+        message: String,
+    },
+}
+
+impl ProgressFormat {
+    pub fn emit(&self, event: &Event) {
+        match self {
+            Self::None => {}
+            Self::Bar => draw_bar(event),
+            Self::Json => {
+                if let Ok(line) = serde_json::to_string(event) {
+                    eprintln!("{line}");
+                }
+            }
+        }
+    }
+}
+
+fn draw_bar(event: &Event) {
+    let Bars { multi, bars } = bars();
+    let mut bars = bars.lock().unwrap_or_else(PoisonError::into_inner);
+
+    match event {
+        Event::Start { operation, name } => {
+            let bar = multi.add(ProgressBar::new_spinner());
+            if let Ok(style) = ProgressStyle::with_template(SPINNER_TEMPLATE) {
+                bar.set_style(style);
+            }
+            bar.set_message(format!("{operation} {name}"));
+            bar.enable_steady_tick(TICK_INTERVAL);
+            bars.insert((operation.to_string(), name.to_string()), bar);
+        }
+        Event::Progress {
+            operation,
+            name,
+            percent,
+        } => {
+            let bar = bars
+                .entry((operation.to_string(), name.to_string()))
+                .or_insert_with(|| {
+                    let bar = multi.add(ProgressBar::new_spinner());
+                    bar.set_message(format!("{operation} {name}"));
+                    bar
+                });
+            if bar.length().is_none() {
+                bar.disable_steady_tick();
+                bar.set_length(100);
+                if let Ok(style) = ProgressStyle::with_template(BAR_TEMPLATE) {
+                    bar.set_style(style.progress_chars("#>-"));
+                }
+            }
+            bar.set_position(*percent as u64);
+        }
+        Event::Finish { operation, name }
+        | Event::Error {
+            operation, name, ..
+        } => {
+            if let Some(bar) = bars.remove(&(operation.to_string(), name.to_string())) {
+                bar.finish_and_clear();
+            }
+        }
+    }
+}
+
+pub fn parse_qemu_img_progress(line: &str) -> Option<f64> {
+    line.trim()
+        .strip_prefix('(')?
+        .split_once('/')?
+        .0
+        .parse()
+        .ok()
+}
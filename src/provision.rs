@@ -0,0 +1,21 @@
+use std::path::Path;
+
+/// A step run in a guest to set it up after its first boot.
+pub enum Step<'a> {
+    /// A script on the host, piped to `sh` in the guest.
+    Script(&'a Path),
+    /// A command run by the guest's shell.
+    Command(&'a str),
+}
+
+impl<'a> Step<'a> {
+    /// Steps naming a file on the host are scripts, anything else is an inline command.
+    pub fn parse(step: &'a str) -> Self {
+        let path = Path::new(step);
+        if path.is_file() {
+            Self::Script(path)
+        } else {
+            Self::Command(step)
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use crate::{error::Error, result::Result};
+use serde_json::{json, Value};
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::Path,
+    process,
+    time::Duration,
+};
+
+/// A connection to the QEMU Machine Protocol socket of a running virtual machine.
+pub struct Qmp {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Qmp {
+    pub fn connect(path: &Path, timeout: Duration) -> Result<Self> {
+        let stream = UnixStream::connect(path)?;
+        stream.set_read_timeout(Some(timeout))?;
+        let mut qmp = Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        };
+
+        // The server greets first and only accepts commands once capabilities are negotiated.
+        qmp.read()?;
+        qmp.execute("qmp_capabilities", json!({}))?;
+        Ok(qmp)
+    }
+
+    /// Connects to a guest agent socket, which speaks the same protocol without a greeting.
+    pub fn connect_agent(path: &Path, timeout: Duration) -> Result<Self> {
+        let stream = UnixStream::connect(path)?;
+        stream.set_read_timeout(Some(timeout))?;
+        let mut agent = Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        };
+
+        // Replies to requests from earlier connections may still be queued; skip past them.
+        let id = process::id();
+        writeln!(
+            agent.writer,
+            "{}",
+            json!({ "execute": "guest-sync", "arguments": { "id": id } })
+        )?;
+        while agent.read()?["return"] != id {}
+        Ok(agent)
+    }
+
+    /// Changes how long to wait for a reply, for commands that take longer than usual.
+    pub fn set_timeout(&self, timeout: Duration) -> Result<()> {
+        self.writer.set_read_timeout(Some(timeout))?;
+        Ok(())
+    }
+
+    /// Runs a command, returning its result and skipping any events sent in the meantime.
+    pub fn execute(&mut self, command: &str, arguments: Value) -> Result<Value> {
+        let request = json!({ "execute": command, "arguments": arguments });
+        writeln!(self.writer, "{request}")?;
+
+        loop {
+            let mut response = self.read()?;
+            if let Some(result) = response.get_mut("return") {
+                return Ok(result.take());
+            }
+            if let Some(error) = response.get("error") {
+                return Err(Error::QmpFailed {
+                    command: command.into(),
+                    message: error["desc"].as_str().unwrap_or_default().into(),
+                });
+            }
+        }
+    }
+
+    fn read(&mut self) -> Result<Value> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(Error::QmpFailed {
+                command: "read".into(),
+                message: "connection closed".into(),
+            });
+        }
+        Ok(serde_json::from_str(&line)?)
+    }
+}
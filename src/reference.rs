@@ -0,0 +1,62 @@
+//! Addressing a virtual machine or a snapshot with a single argument, such as `web` or
+//! `snapshot/web-1`, rather than a name and a flag saying which of the two it is.
+
+use std::fmt;
+
+/// Spelled out in front of a name to say what it refers to; neither can start a name.
+pub const MACHINE_PREFIX: &str = "machine/";
+pub const SNAPSHOT_PREFIX: &str = "snapshot/";
+
+/// A virtual machine or snapshot by name. The name may still be a namespace or pattern until it
+/// is resolved against the state.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResourceRef {
+    pub name: String,
+    pub snapshot: bool,
+}
+
+impl ResourceRef {
+    pub fn machine(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            snapshot: false,
+        }
+    }
+
+    pub fn snapshot(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            snapshot: true,
+        }
+    }
+
+    /// Reads `snapshot/<name>` or `machine/<name>`; a bare name refers to a snapshot only where
+    /// the command is about snapshots.
+    pub fn parse(arg: &str, snapshot: bool) -> Self {
+        if let Some(name) = arg.strip_prefix(SNAPSHOT_PREFIX) {
+            Self::snapshot(name)
+        } else if let Some(name) = arg.strip_prefix(MACHINE_PREFIX) {
+            Self::machine(name)
+        } else {
+            Self {
+                name: arg.into(),
+                snapshot,
+            }
+        }
+    }
+}
+
+/// Whether a name would be read as a reference rather than as itself.
+pub fn is_reserved(name: &str) -> bool {
+    name.starts_with(MACHINE_PREFIX) || name.starts_with(SNAPSHOT_PREFIX)
+}
+
+impl fmt::Display for ResourceRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.snapshot {
+            write!(f, "{SNAPSHOT_PREFIX}{}", self.name)
+        } else {
+            write!(f, "{}", self.name)
+        }
+    }
+}
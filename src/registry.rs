@@ -0,0 +1,44 @@
+use crate::{error::Error, exec::Traced, result::Result};
+use std::{path::Path, process::Command};
+
+const ARTIFACT_TYPE: &str = "application/vnd.rhea.machine.v1";
+const DISK_MEDIA_TYPE: &str = "application/vnd.rhea.disk.v1.qcow2";
+const MANIFEST_MEDIA_TYPE: &str = "application/vnd.rhea.manifest.v1+toml";
+
+/// Pushes the disk and manifest in `dir` to a registry as an OCI artifact, using `oras` and the
+/// credentials it was logged in with.
+pub fn push(reference: &str, dir: &Path, disk: &str, manifest: &str) -> Result<()> {
+    // Layers are titled after the paths they were pushed from, so these must be relative.
+    let status = Command::new("oras")
+        .current_dir(dir)
+        .arg("push")
+        .args(["--artifact-type", ARTIFACT_TYPE])
+        .arg(reference)
+        .arg(format!("{disk}:{DISK_MEDIA_TYPE}"))
+        .arg(format!("{manifest}:{MANIFEST_MEDIA_TYPE}"))
+        .traced()?
+        .status()?;
+    if !status.success() {
+        return Err(Error::RegistryFailed {
+            reference: reference.into(),
+        });
+    }
+    Ok(())
+}
+
+/// Pulls an artifact pushed with `push` into `dir`.
+pub fn pull(reference: &str, dir: &Path) -> Result<()> {
+    let status = Command::new("oras")
+        .arg("pull")
+        .arg(reference)
+        .arg("-o")
+        .arg(dir)
+        .traced()?
+        .status()?;
+    if !status.success() {
+        return Err(Error::RegistryFailed {
+            reference: reference.into(),
+        });
+    }
+    Ok(())
+}
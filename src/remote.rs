@@ -0,0 +1,192 @@
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use std::{
+    env, fmt,
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+};
+
+const DEFAULT_REMOTE_PATH: &str = ".config/rhea";
+
+/// A host reachable over SSH that runs QEMU on behalf of a local state, written as
+/// `ssh://[user@]host[:port][/path]`. Relative paths are relative to the remote home directory.
+#[derive(Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Remote {
+    pub user: Option<String>,
+    pub host: String,
+    pub port: Option<u16>,
+    pub path: PathBuf,
+}
+
+impl Remote {
+    fn destination(&self) -> String {
+        match &self.user {
+            Some(user) => format!("{user}@{}", self.host),
+            None => self.host.clone(),
+        }
+    }
+
+    /// An ssh command to the remote host, ready for the remote command to be appended.
+    pub fn ssh(&self) -> Command {
+        let mut cmd = Command::new("ssh");
+        cmd.args(["-o", "BatchMode=yes"]);
+        if let Some(port) = self.port {
+            cmd.args(["-p", &format!("{port}")]);
+        }
+        cmd.arg(self.destination());
+        cmd
+    }
+
+    /// Like `ssh`, but keeps the session attached to a terminal so the remote command dies with
+    /// it, and forwards `ports` on the remote localhost to the same ports locally.
+    pub fn ssh_session(&self, ports: &[u16]) -> Command {
+        let mut cmd = Command::new("ssh");
+        cmd.arg("-tt").args(["-o", "ExitOnForwardFailure=yes"]);
+        for port in ports {
+            cmd.args(["-L", &format!("{port}:localhost:{port}")]);
+        }
+        if let Some(port) = self.port {
+            cmd.args(["-p", &format!("{port}")]);
+        }
+        cmd.arg(self.destination());
+        cmd
+    }
+
+    /// An rsync command updating `dest` on the remote host from the local file `src` in place,
+    /// sending only the blocks that differ.
+    pub fn rsync(&self, src: &Path, dest: &Path) -> Command {
+        let mut shell = String::from("ssh -o BatchMode=yes");
+        if let Some(port) = self.port {
+            shell.push_str(&format!(" -p {port}"));
+        }
+        let mut cmd = Command::new("rsync");
+        cmd.args(["--inplace", "--no-whole-file", "--compress"])
+            .args(["-e", &shell])
+            .arg(src)
+            .arg(format!("{}:{}", self.destination(), dest.display()));
+        cmd
+    }
+}
+
+/// A command running `program` on `remote`, or locally if there is none.
+pub fn command(remote: Option<&Remote>, program: &str) -> Command {
+    match remote {
+        Some(remote) => {
+            let mut cmd = remote.ssh();
+            cmd.args(["--", program]);
+            cmd
+        }
+        None => Command::new(expand_home(program)),
+    }
+}
+
+/// `word` with a leading `~/` replaced by the local home directory, as the shell on a remote
+/// host would do for commands run there.
+pub fn expand_home(word: &str) -> String {
+    match (word.strip_prefix("~/"), env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{home}/{rest}"),
+        _ => word.into(),
+    }
+}
+
+impl FromStr for Remote {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidRemote { remote: s.into() };
+
+        let rest = s.strip_prefix("ssh://").ok_or_else(invalid)?;
+        let (authority, path) = match rest.split_once('/') {
+            Some((authority, path)) if !path.is_empty() => (authority, path),
+            Some((authority, _)) => (authority, DEFAULT_REMOTE_PATH),
+            None => (rest, DEFAULT_REMOTE_PATH),
+        };
+        let (user, host) = match authority.split_once('@') {
+            Some((user, host)) => (Some(user.to_string()), host),
+            None => (None, authority),
+        };
+        let (host, port) = match host.split_once(':') {
+            Some((host, port)) => (host, Some(port.parse().map_err(|_| invalid())?)),
+            None => (host, None),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            user,
+            host: host.into(),
+            port,
+            path: path.into(),
+        })
+    }
+}
+
+impl TryFrom<String> for Remote {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Remote> for String {
+    fn from(remote: Remote) -> Self {
+        remote.to_string()
+    }
+}
+
+impl fmt::Display for Remote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ssh://{}", self.destination())?;
+        if let Some(port) = self.port {
+            write!(f, ":{port}")?;
+        }
+        write!(f, "/{}", self.path.display())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remotes_are_parsed_from_urls() {
+        let remote = "ssh://builder@build-1:2222/srv/rhea"
+            .parse::<Remote>()
+            .unwrap();
+        assert_eq!(remote.user.as_deref(), Some("builder"));
+        assert_eq!(remote.host, "build-1");
+        assert_eq!(remote.port, Some(2222));
+        assert_eq!(remote.path, PathBuf::from("srv/rhea"));
+        assert_eq!(remote.to_string(), "ssh://builder@build-1:2222/srv/rhea");
+    }
+
+    #[test]
+    fn remotes_default_to_the_config_directory() {
+        for url in ["ssh://build-1", "ssh://build-1/"] {
+            let remote = url.parse::<Remote>().unwrap();
+            assert_eq!(remote.user, None);
+            assert_eq!(remote.port, None);
+            assert_eq!(remote.path, PathBuf::from(DEFAULT_REMOTE_PATH));
+        }
+    }
+
+    #[test]
+    fn invalid_remotes_are_rejected() {
+        for url in [
+            "build-1",
+            "http://build-1",
+            "ssh://",
+            "ssh://user@",
+            "ssh://build-1:ssh",
+        ] {
+            assert!(
+                matches!(url.parse::<Remote>(), Err(Error::InvalidRemote { .. })),
+                "{url}"
+            );
+        }
+    }
+}
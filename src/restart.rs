@@ -0,0 +1,71 @@
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr, time::Duration};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// When the supervisor brings a machine back up. `on-crash` covers any exit that wasn't asked
+/// for with `rhea stop`; `always` additionally starts the machine when the supervisor starts.
+#[derive(Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum RestartPolicy {
+    #[default]
+    Never,
+    OnCrash,
+    Always,
+}
+
+impl RestartPolicy {
+    pub fn is_never(&self) -> bool {
+        *self == Self::Never
+    }
+}
+
+impl FromStr for RestartPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(Self::Never),
+            "on-crash" => Ok(Self::OnCrash),
+            "always" => Ok(Self::Always),
+            _ => Err(Error::InvalidRestartPolicy { policy: s.into() }),
+        }
+    }
+}
+
+impl TryFrom<String> for RestartPolicy {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<RestartPolicy> for String {
+    fn from(policy: RestartPolicy) -> Self {
+        policy.to_string()
+    }
+}
+
+impl fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Never => "never",
+                Self::OnCrash => "on-crash",
+                Self::Always => "always",
+            }
+        )
+    }
+}
+
+/// Exponential delay between restarts of a machine that keeps crashing.
+pub fn backoff(attempts: u32) -> Duration {
+    INITIAL_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempts))
+        .min(MAX_BACKOFF)
+}
@@ -0,0 +1,43 @@
+use crate::{error::IoContext, journal::ExitReason, result::Result, size::Size};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Runtime {
+    pub pid: u32,
+    /// When the process started, as `ProcessBackend::started` tells, so that a process later
+    /// given the same PID isn't taken for it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub process_started: Option<u64>,
+    pub started_at: u64,
+    pub cores: usize,
+    pub ram: Size,
+    #[serde(default)]
+    pub disks: Vec<String>,
+    /// Port of the GDB stub, if started with one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gdb: Option<u16>,
+    /// File guest traffic is being captured to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pcap: Option<PathBuf>,
+    /// Why the process is going away, set just before rhea asks it to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit: Option<ExitReason>,
+}
+
+impl Runtime {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Option<Self>> {
+        if fs::metadata(&path).is_err() {
+            return Ok(None);
+        }
+        Ok(Some(toml::from_str(&fs::read_to_string(&path).at(path)?)?))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(&path, toml::to_string(self)?).at(path)?;
+        Ok(())
+    }
+}
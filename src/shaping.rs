@@ -0,0 +1,82 @@
+use crate::{error::Error, exec::Traced, result::Result};
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+
+const RATE_UNITS: [&str; 4] = ["gbit", "mbit", "kbit", "bit"];
+
+/// Rate limit, delay and loss applied with `tc netem` to traffic sent to a machine over a tap
+/// device, to simulate slow or unreliable links.
+#[derive(Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct Shaping {
+    /// In tc's notation, e.g. `384kbit` or `2mbit`.
+    pub rate: Option<String>,
+    /// Milliseconds.
+    pub delay: Option<u64>,
+    /// Percent of packets dropped.
+    pub loss: Option<f64>,
+}
+
+impl Shaping {
+    pub fn is_empty(&self) -> bool {
+        self.rate.is_none() && self.delay.is_none() && self.loss.is_none()
+    }
+
+    pub fn or(&self, fallback: &Shaping) -> Shaping {
+        Shaping {
+            rate: self.rate.clone().or_else(|| fallback.rate.clone()),
+            delay: self.delay.or(fallback.delay),
+            loss: self.loss.or(fallback.loss),
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if let Some(rate) = &self.rate {
+            let valid = RATE_UNITS.iter().any(|unit| {
+                rate.strip_suffix(unit)
+                    .is_some_and(|number| !number.is_empty() && number.parse::<u64>().is_ok())
+            });
+            if !valid {
+                return Err(Error::InvalidShaping {
+                    shaping: rate.clone(),
+                });
+            }
+        }
+        if let Some(loss) = self.loss.filter(|loss| !(0.0..=100.0).contains(loss)) {
+            return Err(Error::InvalidShaping {
+                shaping: format!("{loss}%"),
+            });
+        }
+        Ok(())
+    }
+
+    /// Replaces the root qdisc of `iface` with these settings, or removes it if there are none.
+    pub fn apply(&self, iface: &str) -> Result<()> {
+        let mut cmd = Command::new("tc");
+        if self.is_empty() {
+            // Deleting a qdisc that was never added fails, which is fine.
+            cmd.args(["qdisc", "del", "dev", iface, "root"])
+                .traced()?
+                .output()?;
+            return Ok(());
+        }
+
+        cmd.args(["qdisc", "replace", "dev", iface, "root", "netem"]);
+        if let Some(delay) = self.delay {
+            cmd.args(["delay", &format!("{delay}ms")]);
+        }
+        if let Some(loss) = self.loss {
+            cmd.args(["loss", &format!("{loss}%")]);
+        }
+        if let Some(rate) = &self.rate {
+            cmd.args(["rate", rate]);
+        }
+        let output = cmd.traced()?.output()?;
+        if !output.status.success() {
+            return Err(Error::CommandFailed {
+                command: "tc".into(),
+                stderr: String::from_utf8_lossy(&output.stderr).trim().into(),
+            });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use crate::{result::Result, start::StartOptions, state::State};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// A `State` shared between threads, e.g. by a daemon serving requests concurrently.
+///
+/// Starting and stopping only hold the lock for reading while QEMU boots or shuts down, so
+/// listings and status queries are served meanwhile. Changes to the recorded resources hold it
+/// for writing, and are saved before it is released.
+#[derive(Clone)]
+pub struct SharedState {
+    state: Arc<RwLock<State>>,
+}
+
+impl SharedState {
+    pub fn new(state: State) -> Self {
+        Self {
+            state: Arc::new(RwLock::new(state)),
+        }
+    }
+
+    // A panic while holding the lock can at worst leave a change unsaved, so the state is still
+    // usable.
+    pub fn read(&self) -> RwLockReadGuard<'_, State> {
+        self.state.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, State> {
+        self.state.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Applies a change to the state and saves it.
+    pub fn update<T, F>(&self, change: F) -> Result<T>
+    where
+        F: FnOnce(&mut State) -> Result<T>,
+    {
+        let mut state = self.write();
+        let result = change(&mut state)?;
+        state.save()?;
+        Ok(result)
+    }
+
+    /// Starts a virtual machine or snapshot, recording it as started first and taking that back
+    /// if it fails to start.
+    pub fn start(&self, name: &str, options: &StartOptions) -> Result<()> {
+        let stamps =
+            self.update(|state| state.mark_started(name, options.snapshot, &options.disks))?;
+        let result = self.read().start(name, options);
+        if result.is_err() {
+            if let Err(err) = self.update(|state| state.unmark_started(stamps)) {
+                tracing::warn!("failed to take back the start of {name}: {err}");
+            }
+        }
+        result
+    }
+
+    pub fn stop(&self, name: &str, snapshot: bool) -> Result<()> {
+        self.read().stop(name, snapshot)
+    }
+
+    pub fn restart(&self, name: &str, snapshot: bool) -> Result<()> {
+        self.read().restart(name, snapshot)
+    }
+}
+
+impl From<State> for SharedState {
+    fn from(state: State) -> Self {
+        Self::new(state)
+    }
+}
@@ -0,0 +1,153 @@
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+const UNITS: [(&str, u64); 5] = [
+    ("T", 1 << 40),
+    ("G", 1 << 30),
+    ("M", 1 << 20),
+    ("K", 1 << 10),
+    ("B", 1),
+];
+
+/// A size in bytes, written as e.g. `512M`, `64G` or `1T`. Bare numbers are gigabytes, which is
+/// how sizes were given before units were supported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(try_from = "SizeRepr", into = "String")]
+pub struct Size(u64);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SizeRepr {
+    Gigabytes(u64),
+    Text(String),
+}
+
+impl Size {
+    pub const fn bytes(bytes: u64) -> Self {
+        Self(bytes)
+    }
+
+    pub const fn gigabytes(gigabytes: u64) -> Self {
+        Self(gigabytes << 30)
+    }
+
+    pub fn as_bytes(&self) -> u64 {
+        self.0
+    }
+
+    pub fn as_kilobytes(&self) -> u64 {
+        self.0.div_ceil(1 << 10)
+    }
+
+    pub fn as_megabytes(&self) -> u64 {
+        self.0.div_ceil(1 << 20)
+    }
+}
+
+impl FromStr for Size {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidSize { size: s.into() };
+
+        let s = s.trim();
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let number: u64 = number.parse().map_err(|_| invalid())?;
+
+        let unit = unit.trim().to_ascii_uppercase();
+        let unit = unit
+            .strip_suffix("IB")
+            .or_else(|| unit.strip_suffix('B').filter(|unit| !unit.is_empty()))
+            .unwrap_or(&unit);
+        let multiplier = match unit {
+            "" => 1 << 30,
+            unit => {
+                UNITS
+                    .iter()
+                    .find(|(suffix, _)| *suffix == unit)
+                    .ok_or_else(invalid)?
+                    .1
+            }
+        };
+
+        match number.checked_mul(multiplier) {
+            Some(0) | None => Err(invalid()),
+            Some(bytes) => Ok(Self(bytes)),
+        }
+    }
+}
+
+impl TryFrom<SizeRepr> for Size {
+    type Error = Error;
+
+    fn try_from(repr: SizeRepr) -> Result<Self, Self::Error> {
+        match repr {
+            SizeRepr::Gigabytes(gigabytes) => Ok(Self::gigabytes(gigabytes)),
+            SizeRepr::Text(text) => text.parse(),
+        }
+    }
+}
+
+impl From<Size> for String {
+    fn from(size: Size) -> Self {
+        size.to_string()
+    }
+}
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (suffix, multiplier) = UNITS
+            .iter()
+            .find(|(_, multiplier)| self.0.is_multiple_of(*multiplier))
+            .unwrap_or(&UNITS[UNITS.len() - 1]);
+        write!(f, "{}{suffix}", self.0 / multiplier)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn sizes_take_a_unit() {
+        assert_eq!("512M".parse::<Size>().unwrap(), Size::bytes(512 << 20));
+        assert_eq!("64G".parse::<Size>().unwrap(), Size::gigabytes(64));
+        assert_eq!("1T".parse::<Size>().unwrap(), Size::gigabytes(1024));
+        assert_eq!("4KiB".parse::<Size>().unwrap(), Size::bytes(4096));
+        assert_eq!("2 gb".parse::<Size>().unwrap(), Size::gigabytes(2));
+        assert_eq!("100B".parse::<Size>().unwrap(), Size::bytes(100));
+    }
+
+    #[test]
+    fn bare_numbers_are_gigabytes() {
+        assert_eq!("32".parse::<Size>().unwrap(), Size::gigabytes(32));
+        assert_eq!(
+            toml::from_str::<BTreeMap<String, Size>>("size = 32").unwrap()["size"],
+            Size::gigabytes(32)
+        );
+    }
+
+    #[test]
+    fn invalid_sizes_are_rejected() {
+        for size in ["", "G", "0", "0M", "-1G", "1.5G", "5X", "5GG"] {
+            assert!(
+                matches!(size.parse::<Size>(), Err(Error::InvalidSize { .. })),
+                "{size}"
+            );
+        }
+        assert!(matches!(
+            format!("{}T", u64::MAX).parse::<Size>(),
+            Err(Error::InvalidSize { .. })
+        ));
+    }
+
+    #[test]
+    fn sizes_display_in_their_largest_whole_unit() {
+        assert_eq!(Size::gigabytes(1024).to_string(), "1T");
+        assert_eq!(Size::bytes(1536 << 20).to_string(), "1536M");
+        assert_eq!(Size::bytes(100).to_string(), "100B");
+    }
+}
@@ -1,3 +1,4 @@
+use crate::{size::Size, time::Expiry};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
@@ -5,8 +6,38 @@ use std::fmt;
 pub struct Snapshot {
     pub name: String,
     pub base: String,
+    #[serde(default)]
+    pub parent: Option<String>,
     pub port: u16,
-    pub size: usize,
+    pub size: Size,
+    /// Cores and RAM to start with when `rhea start` isn't given any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cores: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ram: Option<Size>,
+    #[serde(default, alias = "created")]
+    pub created_at: Option<u64>,
+    #[serde(default)]
+    pub last_started_at: Option<u64>,
+    #[serde(default)]
+    pub full: bool,
+    /// Taken automatically before a risky operation, see `State::checkpoint`.
+    #[serde(default)]
+    pub checkpoint: bool,
+    /// Started by `rhea start --instances`, and removed once it stops, see
+    /// `State::reap_instances`.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub ephemeral: bool,
+    /// Free-text note shown in `rhea snapshots`, e.g. what state the guest was saved in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    // TOML needs tables after plain values.
+    #[serde(default)]
+    pub expiry: Option<Expiry>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
 }
 
 impl fmt::Display for Snapshot {
@@ -0,0 +1,133 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Options for `rhea connect`, which can also be stored as per-machine defaults.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct ConnectOptions {
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub forward_agent: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub x11: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub local_forwards: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remote_forwards: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dynamic_forwards: Vec<String>,
+    #[serde(default)]
+    pub identity: Option<PathBuf>,
+    #[serde(default)]
+    pub jump: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<String>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub mosh: bool,
+    #[serde(default)]
+    pub command: Option<String>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+fn or_vec(values: &[String], fallback: &[String]) -> Vec<String> {
+    if values.is_empty() {
+        fallback.to_vec()
+    } else {
+        values.to_vec()
+    }
+}
+
+impl ConnectOptions {
+    pub fn is_empty(&self) -> bool {
+        !self.forward_agent
+            && !self.x11
+            && self.local_forwards.is_empty()
+            && self.remote_forwards.is_empty()
+            && self.dynamic_forwards.is_empty()
+            && self.identity.is_none()
+            && self.jump.is_none()
+            && self.options.is_empty()
+            && !self.mosh
+            && self.command.is_none()
+    }
+
+    pub fn or(&self, fallback: &Self) -> Self {
+        Self {
+            forward_agent: self.forward_agent || fallback.forward_agent,
+            x11: self.x11 || fallback.x11,
+            local_forwards: or_vec(&self.local_forwards, &fallback.local_forwards),
+            remote_forwards: or_vec(&self.remote_forwards, &fallback.remote_forwards),
+            dynamic_forwards: or_vec(&self.dynamic_forwards, &fallback.dynamic_forwards),
+            identity: self.identity.clone().or_else(|| fallback.identity.clone()),
+            jump: self.jump.clone().or_else(|| fallback.jump.clone()),
+            options: or_vec(&self.options, &fallback.options),
+            mosh: self.mosh || fallback.mosh,
+            command: self.command.clone().or_else(|| fallback.command.clone()),
+        }
+    }
+
+    /// Arguments for an interactive ssh session.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.command.is_some() {
+            args.push("-t".into());
+        }
+        if self.forward_agent {
+            args.push("-A".into());
+        }
+        if self.x11 {
+            args.push("-X".into());
+        }
+        for (flag, forwards) in [
+            ("-L", &self.local_forwards),
+            ("-R", &self.remote_forwards),
+            ("-D", &self.dynamic_forwards),
+        ] {
+            for forward in forwards {
+                args.extend([flag.into(), forward.clone()]);
+            }
+        }
+        args.extend(self.transport_args());
+        args
+    }
+
+    /// Arguments that only affect how ssh reaches the guest, which mosh also needs.
+    pub fn transport_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(identity) = &self.identity {
+            args.extend(["-i".into(), identity.display().to_string()]);
+        }
+        if let Some(jump) = &self.jump {
+            args.extend(["-J".into(), jump.clone()]);
+        }
+        for option in &self.options {
+            args.extend(["-o".into(), option.clone()]);
+        }
+        args
+    }
+}
+
+pub fn host_alias(name: &str) -> String {
+    format!("rhea-{name}")
+}
+
+pub fn host_block(
+    name: &str,
+    port: u16,
+    user: &str,
+    identity: Option<&Path>,
+    proxy_jump: Option<&str>,
+) -> String {
+    let mut block = format!(
+        "Host {}\n    HostName localhost\n    Port {port}\n    User {user}\n    StrictHostKeyChecking no\n    UserKnownHostsFile /dev/null\n",
+        host_alias(name)
+    );
+    if let Some(identity) = identity {
+        block += &format!("    IdentityFile {}\n", identity.display());
+    }
+    if let Some(proxy_jump) = proxy_jump {
+        block += &format!("    ProxyJump {proxy_jump}\n");
+    }
+    block
+}
@@ -0,0 +1,247 @@
+use crate::{error::Error, kernel::KernelBoot, result::Result, size::Size, state::State};
+use std::{fmt, path::PathBuf, str::FromStr};
+
+const DEFAULT_CORES: usize = 4;
+const DEFAULT_RAM: Size = Size::gigabytes(4);
+
+/// The device the firmware should try first.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum BootDevice {
+    Cdrom,
+    Disk,
+}
+
+impl FromStr for BootDevice {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "cdrom" => Ok(Self::Cdrom),
+            "disk" => Ok(Self::Disk),
+            _ => Err(Error::InvalidBootDevice { device: s.into() }),
+        }
+    }
+}
+
+impl fmt::Display for BootDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Cdrom => "cdrom",
+                Self::Disk => "disk",
+            }
+        )
+    }
+}
+
+#[derive(Clone)]
+pub struct StartOptions {
+    pub cores: usize,
+    pub ram: Size,
+    pub foreground: bool,
+    pub snapshot: bool,
+    pub force: bool,
+    pub disks: Vec<String>,
+    pub iso: Option<PathBuf>,
+    pub boot: KernelBoot,
+    pub boot_device: Option<BootDevice>,
+    pub wait_qmp: bool,
+    /// Port for QEMU's GDB stub to listen on.
+    pub gdb: Option<u16>,
+    /// Hold the CPUs at startup until a debugger continues them.
+    pub freeze: bool,
+    /// Capture guest traffic to this file from the first packet.
+    pub pcap: Option<PathBuf>,
+}
+
+impl Default for StartOptions {
+    fn default() -> Self {
+        Self {
+            cores: DEFAULT_CORES,
+            ram: DEFAULT_RAM,
+            foreground: false,
+            snapshot: false,
+            force: false,
+            disks: Vec::new(),
+            iso: None,
+            boot: KernelBoot::default(),
+            boot_device: None,
+            wait_qmp: false,
+            gdb: None,
+            freeze: false,
+            pcap: None,
+        }
+    }
+}
+
+impl StartOptions {
+    pub fn cores(mut self, cores: usize) -> Self {
+        self.cores = cores;
+        self
+    }
+
+    pub fn ram(mut self, ram: Size) -> Self {
+        self.ram = ram;
+        self
+    }
+
+    pub fn foreground(mut self, foreground: bool) -> Self {
+        self.foreground = foreground;
+        self
+    }
+
+    pub fn snapshot(mut self, snapshot: bool) -> Self {
+        self.snapshot = snapshot;
+        self
+    }
+
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    pub fn disk(mut self, disk: &str) -> Self {
+        self.disks.push(disk.into());
+        self
+    }
+
+    pub fn disks<I, S>(mut self, disks: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.disks.extend(disks.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn iso<P: Into<PathBuf>>(mut self, iso: P) -> Self {
+        self.iso = Some(iso.into());
+        self
+    }
+
+    pub fn boot_device(mut self, boot_device: Option<BootDevice>) -> Self {
+        self.boot_device = boot_device;
+        self
+    }
+
+    /// Boots a kernel directly, falling back to the one stored on the machine if empty.
+    pub fn boot(mut self, boot: KernelBoot) -> Self {
+        self.boot = boot;
+        self
+    }
+
+    /// Only reports success once QEMU answers on QMP.
+    pub fn wait_qmp(mut self, wait_qmp: bool) -> Self {
+        self.wait_qmp = wait_qmp;
+        self
+    }
+
+    pub fn gdb(mut self, gdb: Option<u16>) -> Self {
+        self.gdb = gdb;
+        self
+    }
+
+    pub fn freeze(mut self, freeze: bool) -> Self {
+        self.freeze = freeze;
+        self
+    }
+
+    pub fn pcap(mut self, pcap: Option<PathBuf>) -> Self {
+        self.pcap = pcap;
+        self
+    }
+}
+
+/// Starts a virtual machine or snapshot with `StartOptions` set fluently, e.g.
+/// `state.machine("foo").cores(8).ram(16).disk("scratch").spawn()?`.
+pub struct MachineBuilder<'a> {
+    state: &'a mut State,
+    name: String,
+    options: StartOptions,
+}
+
+impl<'a> MachineBuilder<'a> {
+    pub fn new(state: &'a mut State, name: &str) -> Self {
+        Self {
+            state,
+            name: name.into(),
+            options: StartOptions::default(),
+        }
+    }
+
+    pub fn options(mut self, options: StartOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn cores(self, cores: usize) -> Self {
+        self.map(|options| options.cores(cores))
+    }
+
+    pub fn ram(self, ram: Size) -> Self {
+        self.map(|options| options.ram(ram))
+    }
+
+    pub fn foreground(self, foreground: bool) -> Self {
+        self.map(|options| options.foreground(foreground))
+    }
+
+    pub fn snapshot(self, snapshot: bool) -> Self {
+        self.map(|options| options.snapshot(snapshot))
+    }
+
+    pub fn force(self, force: bool) -> Self {
+        self.map(|options| options.force(force))
+    }
+
+    pub fn disk(self, disk: &str) -> Self {
+        self.map(|options| options.disk(disk))
+    }
+
+    pub fn disks<I, S>(self, disks: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.map(|options| options.disks(disks))
+    }
+
+    pub fn iso<P: Into<PathBuf>>(self, iso: P) -> Self {
+        self.map(|options| options.iso(iso))
+    }
+
+    pub fn boot(self, boot: KernelBoot) -> Self {
+        self.map(|options| options.boot(boot))
+    }
+
+    pub fn boot_device(self, boot_device: Option<BootDevice>) -> Self {
+        self.map(|options| options.boot_device(boot_device))
+    }
+
+    pub fn wait_qmp(self, wait_qmp: bool) -> Self {
+        self.map(|options| options.wait_qmp(wait_qmp))
+    }
+
+    pub fn gdb(self, gdb: Option<u16>) -> Self {
+        self.map(|options| options.gdb(gdb))
+    }
+
+    pub fn freeze(self, freeze: bool) -> Self {
+        self.map(|options| options.freeze(freeze))
+    }
+
+    pub fn pcap(self, pcap: Option<PathBuf>) -> Self {
+        self.map(|options| options.pcap(pcap))
+    }
+
+    pub fn spawn(self) -> Result<()> {
+        self.state.start(&self.name, &self.options)
+    }
+
+    fn map<F: FnOnce(StartOptions) -> StartOptions>(mut self, f: F) -> Self {
+        self.options = f(self.options);
+        self
+    }
+}
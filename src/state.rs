@@ -1,89 +1,394 @@
-use crate::{disk::Disk, error::Error, machine::Machine, result::Result, snapshot::Snapshot};
+use crate::{
+    arch,
+    checksum::{self, Checksum},
+    config::{Binaries, Config, Notifications, Retry, Timeouts},
+    console,
+    crash::{self, Report},
+    disk::Disk,
+    error::{Error, IoContext},
+    exec::Traced,
+    firmware::{self, FirmwareMode},
+    foreign::{self, ForeignMachine, Source},
+    history,
+    image::{ImageBackend, ImageFormat, QemuImg},
+    info::{DiskInfo, MachineInfo, SnapshotInfo},
+    input::InputDevice,
+    ip::{self, GuestAddress},
+    journal::{self, Boot, ExitReason},
+    kernel::KernelBoot,
+    libvirt,
+    logs::{self, Filter},
+    machine::Machine,
+    manifest::Manifest,
+    migration, namespace,
+    network::{self, Network},
+    notify,
+    os::GuestOs,
+    ports::{self, PortStatus, PortUse},
+    preset::{self, Preset},
+    probe::{self, Probe},
+    process::{self, Host, ProcessBackend},
+    progress::{Event, ProgressFormat},
+    provision::Step,
+    qmp::Qmp,
+    reference::ResourceRef,
+    registry,
+    remote::{self, Remote},
+    restart::RestartPolicy,
+    result::Result,
+    runtime::Runtime,
+    shaping::Shaping,
+    size::Size,
+    snapshot::Snapshot,
+    ssh::{self, ConnectOptions},
+    start::{BootDevice, MachineBuilder, StartOptions},
+    status::MachineStatus,
+    system::{self, HostReport, Storage},
+    time::{self, Expiry},
+    trash::{Resource, Trashed},
+    usage::{self, Sample, Summary},
+    version::{self, Feature, Versions},
+};
 use fslock::LockFile;
 use path_macro::path;
-use piper::PipedCommand;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::{
-    collections::{btree_map::Values, BTreeMap},
+    cmp::Reverse,
+    collections::{
+        btree_map::{self, Values},
+        BTreeMap, BTreeSet,
+    },
     env,
-    fmt::Display,
-    fs,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    iter, mem,
+    net::{IpAddr, Ipv4Addr},
+    ops::Deref,
+    os::unix::fs::MetadataExt,
     path::{Path, PathBuf},
-    process::{Command, Stdio},
-    str,
+    process::{Command, ExitStatus, Stdio},
+    sync::OnceLock,
+    thread,
+    time::{Duration, Instant},
 };
 
-#[cfg(target_arch = "x86-64")]
-const QEMU_RUNNER: &str = "qemu-system-x86_64";
-#[cfg(target_arch = "mips")]
-const QEMU_RUNNER: &str = "qemu-system-mips";
-#[cfg(target_arch = "powerpc")]
-const QEMU_RUNNER: &str = "qemu-system-ppc";
-#[cfg(target_arch = "powerpc64")]
-const QEMU_RUNNER: &str = "qemu-system-ppc64";
-#[cfg(target_arch = "arm")]
-const QEMU_RUNNER: &str = "qemu-system-arm";
-#[cfg(target_arch = "aarch64")]
-const QEMU_RUNNER: &str = "qemu-system-aarch64";
-const QEMU_IMAGER: &str = "qemu-img";
 const UEFI_ENV_VAR: &str = "RHEA_UEFI_PATH";
 const STATE_PATH: &str = "state.toml";
-const PROCESS_LOCK_PATH: &str = ".proc.lock";
+const STATE_BACKUPS: usize = 5;
+const CONFIG_PATH: &str = "config.toml";
+const HOSTS_PATH: &str = "hosts";
+const HISTORY_PATH: &str = "history.jsonl";
+const VERSIONS_PATH: &str = ".versions.toml";
+const STATE_LOCK_PATH: &str = ".state.lock";
+const ADMISSION_LOCK_PATH: &str = "admission.lock";
 const DISK_DIR_PATH: &str = "disks";
 const MACHINE_DIR_PATH: &str = "machines";
 const SNAPSHOT_DIR_PATH: &str = "snapshots";
+const SSH_DIR_PATH: &str = "ssh";
+const KEY_DIR_PATH: &str = "keys";
+const RUN_DIR_PATH: &str = "run";
+const LOG_DIR_PATH: &str = "logs";
+const TRASH_DIR_PATH: &str = ".trash";
+const CRASH_DIR_PATH: &str = "crashes";
+const EXPORT_DISK_PATH: &str = "disk.qcow2";
+const AGENT_CHANNEL: &str = "org.qemu.guest_agent.0";
+const EXPORT_MANIFEST_PATH: &str = "manifest.toml";
+const ARCHIVE_EXTENSION: &str = ".tar.zst";
+const PROBE_INTERVAL: Duration = Duration::from_secs(1);
+const SSH_CONNECTION_FAILED: i32 = 255;
+const CDROM_DEVICE: &str = "cdrom0";
+const PCAP_FILTER: &str = "pcap0";
+const SUSPEND_TAG: &str = "rhea-suspend";
+const START_FAILURE_LINES: usize = 5;
 
 #[derive(Deserialize, Serialize)]
 pub struct State {
     #[serde(skip)]
     path: PathBuf,
+    #[serde(skip)]
+    config: Config,
+    #[serde(skip)]
+    progress: ProgressFormat,
+    #[serde(skip)]
+    lock: Option<LockFile>,
+    #[serde(skip)]
+    remote: Option<Remote>,
+    #[serde(skip)]
+    image_backend: Option<Box<dyn ImageBackend>>,
+    #[serde(skip)]
+    process_backend: Option<Box<dyn ProcessBackend>>,
+    #[serde(skip)]
+    versions: OnceLock<Versions>,
+    #[serde(default)]
+    version: u32,
     disks: BTreeMap<String, Disk>,
     machines: BTreeMap<String, Machine>,
     snapshots: BTreeMap<String, Snapshot>,
+    #[serde(default)]
+    remotes: BTreeMap<String, Remote>,
+}
+
+enum Images<'a> {
+    QemuImg(QemuImg<'a>),
+    Custom(&'a dyn ImageBackend),
+}
+
+impl<'a> Deref for Images<'a> {
+    type Target = dyn ImageBackend + 'a;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::QemuImg(backend) => backend,
+            Self::Custom(backend) => *backend,
+        }
+    }
+}
+
+/// When a machine or snapshot and its disks were started before `State::mark_started` stamped
+/// them, so that a start that fails can be taken back with `State::unmark_started`.
+pub struct Stamps {
+    name: String,
+    snapshot: bool,
+    at: Option<u64>,
+    previous: Option<u64>,
+    disks: Vec<(String, Option<u64>)>,
 }
 
 impl State {
+    /// The UEFI firmware to boot with: `RHEA_UEFI_PATH` if set, then the one in config.toml, then
+    /// one discovered next to QEMU, which is written to config.toml for next time. Discovery
+    /// only looks at the local host.
     fn uefi_path(&self) -> Result<PathBuf> {
-        Ok(PathBuf::from(env::var(UEFI_ENV_VAR)?))
+        if let Some(path) = env::var_os(UEFI_ENV_VAR) {
+            return Ok(path.into());
+        }
+        if let Some(path) = &self.config.firmware.path {
+            return Ok(path.clone());
+        }
+        if self.remote.is_some() {
+            return Err(Error::MissingFirmware);
+        }
+        let path = firmware::discover(self.config.binaries.qemu()).ok_or(Error::MissingFirmware)?;
+        Config::remember_firmware(self.config_path(), &path)?;
+        Ok(path)
     }
 
     fn state_path(&self) -> PathBuf {
         path![self.path / STATE_PATH]
     }
 
-    fn process_lock_path(&self) -> PathBuf {
-        path![self.path / PROCESS_LOCK_PATH]
+    fn state_backup_path(&self, backup: usize) -> PathBuf {
+        path![self.path / format!("{}.{}", STATE_PATH, backup)]
+    }
+
+    fn config_path(&self) -> PathBuf {
+        path![self.path / CONFIG_PATH]
+    }
+
+    pub fn hosts_path(&self) -> PathBuf {
+        path![self.path / HOSTS_PATH]
+    }
+
+    fn history_path(&self) -> PathBuf {
+        path![self.path / HISTORY_PATH]
+    }
+
+    pub fn record_history(&self, entry: &history::Entry) -> Result<()> {
+        history::append(self.history_path(), entry)
+    }
+
+    pub fn history(&self) -> Result<Vec<history::Entry>> {
+        history::load(self.history_path())
+    }
+
+    fn state_lock_path(&self) -> PathBuf {
+        path![self.path / STATE_LOCK_PATH]
+    }
+
+    /// Where images live, which is on the remote host when driving one.
+    fn image_root(&self) -> PathBuf {
+        match &self.remote {
+            Some(remote) => remote.path.clone(),
+            None => self.path.clone(),
+        }
     }
 
     fn disk_dir_path(&self) -> PathBuf {
-        path![self.path / DISK_DIR_PATH]
+        path![self.image_root() / DISK_DIR_PATH]
     }
 
     fn disk_path(&self, name: &str) -> PathBuf {
-        path![self.disk_dir_path() / format!("{}.qcow2", name)]
+        path![self.disk_dir_path() / namespace::file_name(name, "qcow2")]
     }
 
     fn machine_dir_path(&self) -> PathBuf {
-        path![self.path / MACHINE_DIR_PATH]
+        path![self.image_root() / MACHINE_DIR_PATH]
     }
 
     fn machine_path(&self, name: &str) -> PathBuf {
-        path![self.machine_dir_path() / format!("{}.qcow2", name)]
+        path![self.machine_dir_path() / namespace::file_name(name, "qcow2")]
     }
 
     fn snapshot_dir_path(&self) -> PathBuf {
-        path![self.path / SNAPSHOT_DIR_PATH]
+        path![self.image_root() / SNAPSHOT_DIR_PATH]
     }
 
     fn snapshot_path(&self, name: &str) -> PathBuf {
-        path![self.snapshot_dir_path() / format!("{}.qcow2", name)]
+        path![self.snapshot_dir_path() / namespace::file_name(name, "qcow2")]
+    }
+
+    /// Removed images; on a remote host they stay there while their records are kept locally.
+    fn trash_dir_path(&self) -> PathBuf {
+        path![self.image_root() / TRASH_DIR_PATH]
+    }
+
+    fn trash_image_path(&self, id: &str) -> PathBuf {
+        path![self.trash_dir_path() / format!("{}.qcow2", id)]
+    }
+
+    fn trash_record_path(&self, id: &str) -> PathBuf {
+        path![self.path / TRASH_DIR_PATH / format!("{}.json", id)]
+    }
+
+    pub fn ssh_dir_path(&self) -> PathBuf {
+        path![self.path / SSH_DIR_PATH]
+    }
+
+    fn key_dir_path(&self) -> PathBuf {
+        path![self.path / KEY_DIR_PATH]
+    }
+
+    fn key_path(&self, name: &str) -> PathBuf {
+        path![self.key_dir_path() / namespace::encode(name)]
+    }
+
+    pub fn ssh_config_path(&self, name: &str) -> PathBuf {
+        path![self.ssh_dir_path() / namespace::file_name(name, "conf")]
+    }
+
+    fn run_dir_path(&self, snapshot: bool) -> PathBuf {
+        path![
+            self.path
+                / RUN_DIR_PATH
+                / if snapshot {
+                    SNAPSHOT_DIR_PATH
+                } else {
+                    MACHINE_DIR_PATH
+                }
+        ]
+    }
+
+    fn lock_path(&self, name: &str, snapshot: bool) -> PathBuf {
+        path![self.run_dir_path(snapshot) / namespace::file_name(name, "lock")]
+    }
+
+    fn disk_lock_path(&self, name: &str) -> PathBuf {
+        path![self.path / RUN_DIR_PATH / DISK_DIR_PATH / namespace::file_name(name, "lock")]
+    }
+
+    fn admission_lock_path(&self) -> PathBuf {
+        path![self.path / RUN_DIR_PATH / ADMISSION_LOCK_PATH]
+    }
+
+    fn runtime_path(&self, name: &str, snapshot: bool) -> PathBuf {
+        path![self.run_dir_path(snapshot) / namespace::file_name(name, "toml")]
+    }
+
+    /// Where the runtime record of a machine that exited without `rhea stop` is kept.
+    fn exited_path(&self, name: &str, snapshot: bool) -> PathBuf {
+        path![self.run_dir_path(snapshot) / namespace::file_name(name, "exited.toml")]
+    }
+
+    /// Where the runtime record of a machine suspended to disk is kept until it is resumed.
+    fn suspended_path(&self, name: &str, snapshot: bool) -> PathBuf {
+        path![self.run_dir_path(snapshot) / namespace::file_name(name, "suspended.toml")]
+    }
+
+    fn console_path(&self, name: &str, snapshot: bool) -> PathBuf {
+        path![self.run_dir_path(snapshot) / namespace::file_name(name, "sock")]
+    }
+
+    fn qmp_path(&self, name: &str, snapshot: bool) -> PathBuf {
+        path![self.run_dir_path(snapshot) / namespace::file_name(name, "qmp")]
+    }
+
+    fn channel_path(&self, name: &str, snapshot: bool, channel: &str) -> PathBuf {
+        path![self.run_dir_path(snapshot) / namespace::file_name(name, &format!("{channel}.sock"))]
+    }
+
+    fn agent_path(&self, name: &str, snapshot: bool) -> PathBuf {
+        path![self.run_dir_path(snapshot) / namespace::file_name(name, "agent")]
+    }
+
+    fn log_dir_path(&self, snapshot: bool) -> PathBuf {
+        path![
+            self.path
+                / LOG_DIR_PATH
+                / if snapshot {
+                    SNAPSHOT_DIR_PATH
+                } else {
+                    MACHINE_DIR_PATH
+                }
+        ]
+    }
+
+    pub fn log_path(&self, name: &str, snapshot: bool) -> PathBuf {
+        path![self.log_dir_path(snapshot) / namespace::file_name(name, "log")]
+    }
+
+    /// Where guest traffic is captured to unless another file is given.
+    pub fn pcap_path(&self, name: &str, snapshot: bool) -> PathBuf {
+        path![self.log_dir_path(snapshot) / namespace::file_name(name, "pcap")]
+    }
+
+    fn crash_dir_path(&self) -> PathBuf {
+        path![self.path / CRASH_DIR_PATH]
+    }
+
+    fn crash_path(&self, id: &str) -> PathBuf {
+        path![self.crash_dir_path() / id]
+    }
+
+    fn journal_path(&self, name: &str, snapshot: bool) -> PathBuf {
+        path![self.log_dir_path(snapshot) / namespace::file_name(name, "journal.jsonl")]
+    }
+
+    fn versions_path(&self) -> PathBuf {
+        path![self.path / VERSIONS_PATH]
+    }
+
+    fn usage_path(&self, name: &str, snapshot: bool) -> PathBuf {
+        path![self.log_dir_path(snapshot) / namespace::file_name(name, "usage")]
+    }
+
+    /// Past boots of a virtual machine or snapshot, oldest first.
+    pub fn boots(&self, name: &str, snapshot: bool) -> Result<Vec<Boot>> {
+        if snapshot {
+            self.get_snapshot(name)?;
+        } else {
+            self.get_machine(name)?;
+        }
+        journal::load(self.journal_path(name, snapshot))
     }
 
     fn setup(&self) -> Result<()> {
-        fs::create_dir_all(&self.path)?;
-        fs::create_dir_all(self.disk_dir_path())?;
-        fs::create_dir_all(self.machine_dir_path())?;
-        fs::create_dir_all(self.snapshot_dir_path())?;
+        for dir in [
+            self.path.clone(),
+            self.disk_dir_path(),
+            self.machine_dir_path(),
+            self.snapshot_dir_path(),
+            self.trash_dir_path(),
+            self.ssh_dir_path(),
+            self.key_dir_path(),
+            self.run_dir_path(false),
+            self.run_dir_path(true),
+            path![self.path / RUN_DIR_PATH / DISK_DIR_PATH],
+            self.log_dir_path(false),
+            self.log_dir_path(true),
+        ] {
+            fs::create_dir_all(&dir).at(dir)?;
+        }
         Ok(())
     }
 
@@ -91,392 +396,3938 @@ impl State {
     where
         P: AsRef<Path> + Into<PathBuf>,
     {
-        let state = Self {
+        let mut state = Self {
             path: path.into(),
+            config: Config::default(),
+            progress: ProgressFormat::default(),
+            lock: None,
+            remote: None,
+            image_backend: None,
+            process_backend: None,
+            versions: OnceLock::new(),
+            version: migration::VERSION,
             disks: BTreeMap::new(),
             machines: BTreeMap::new(),
             snapshots: BTreeMap::new(),
+            remotes: BTreeMap::new(),
         };
         state.setup()?;
+        state.config = Config::load(state.config_path())?;
         Ok(state)
     }
 
+    /// Loads the state and holds the state lock until it is dropped or unlocked.
     pub fn load<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path> + Into<PathBuf> + Clone,
+    {
+        let lock = State::new(path.clone())?.acquire_lock()?;
+        let mut state = State::load_read_only(path)?;
+        state.lock = Some(lock);
+        Ok(state)
+    }
+
+    /// Loads the state without locking it. The result cannot be saved.
+    pub fn load_read_only<P>(path: P) -> Result<Self>
     where
         P: AsRef<Path> + Into<PathBuf> + Clone,
     {
         let mut state = State::new(path.clone())?;
-        if fs::metadata(&state.state_path()).is_ok() {
-            state = toml::from_str(&fs::read_to_string(state.state_path())?)?;
+        if fs::metadata(state.state_path()).is_ok() {
+            let config = state.config.clone();
+            state = Self::parse(&fs::read_to_string(state.state_path()).at(state.state_path())?)?;
             state.path = path.into();
+            state.config = config;
         }
         Ok(state)
     }
 
-    pub fn save(&self) -> Result<()> {
-        fs::write(self.state_path(), toml::to_string(self)?)?;
-        Ok(())
+    fn acquire_lock(&self) -> Result<LockFile> {
+        let mut lock = LockFile::open(&self.state_lock_path()).at(self.state_lock_path())?;
+        if !lock.try_lock_with_pid()? {
+            let pid = fs::read_to_string(self.state_lock_path())?
+                .trim()
+                .parse()
+                .unwrap_or_default();
+            return Err(Error::StateLocked { pid });
+        }
+        Ok(lock)
     }
 
-    fn allocate_qcow2<P>(&self, name: P, size: usize) -> Result<()>
-    where
-        P: AsRef<Path> + Display,
-    {
-        Command::new(QEMU_IMAGER)
-            .arg("create")
-            .args(["-f", "qcow2"])
-            .arg(&format!("{name}"))
-            .arg(&format!("{size}G"))
-            .spawn()?
-            .wait()?;
+    /// Reads the resources again, e.g. to pick up changes made by other commands while watching.
+    pub fn reload(&mut self) -> Result<()> {
+        let state = State::load_read_only(self.path.clone())?;
+        self.disks = state.disks;
+        self.machines = state.machines;
+        self.snapshots = state.snapshots;
+        self.remotes = state.remotes;
         Ok(())
     }
 
-    fn base_qemu_command<P: AsRef<Path>>(
-        &self,
-        resource: P,
-        port: u16,
-        cores: usize,
-        ram: usize,
-    ) -> Result<Command> {
-        let mut cmd = Command::new(QEMU_RUNNER);
-        cmd.args(["-M", "virt,highmem=on"])
-            .args(["-accel", "hvf"])
-            .args(["-cpu", "host"])
-            .args(["-smp", &format!("{}", cores)])
-            .args(["-m", &format!("{}G", ram)])
-            .args([
-                "-bios",
-                self.uefi_path()?.to_str().ok_or(Error::InvalidPath {
-                    path: self.uefi_path()?,
-                })?,
-            ])
-            .args([
-                "-drive",
-                &format!(
-                    "file={},if=none,cache=writethrough,id=hd0",
-                    resource.as_ref().to_str().ok_or(Error::InvalidPath {
-                        path: resource.as_ref().into()
-                    })?
-                ),
-            ])
-            .args(["-device", "virtio-gpu-pci"])
-            .args(["-device", "virtio-blk-device,drive=hd0"])
-            .args(["-net", &format!("user,hostfwd=tcp::{port}-:22")])
-            .args(["-net", "nic"])
-            .arg("-nographic");
-        Ok(cmd)
+    /// Takes the state lock again after `unlock`, reading the resources again since others may
+    /// have changed them meanwhile.
+    pub fn lock(&mut self) -> Result<()> {
+        self.lock = Some(self.acquire_lock()?);
+        self.reload()
     }
 
-    fn get_process_lock(&self) -> Result<LockFile> {
-        let mut lock = LockFile::open(&self.process_lock_path())?;
-        lock.lock()?;
-        Ok(lock)
+    /// Releases the state lock, e.g. before a long-running operation that no longer saves.
+    pub fn unlock(&mut self) {
+        self.lock = None;
     }
 
-    fn resource_in_use<P: AsRef<Path>>(&self, resource: P) -> Result<bool> {
-        let mut lock = self.get_process_lock()?;
-
-        let in_use = PipedCommand::run(format!(
-            "ps aux | grep -v grep | grep {}",
-            resource.as_ref().to_str().ok_or(Error::InvalidPath {
-                path: resource.as_ref().into()
-            })?
-        ))?
-        .status
-        .code()
-            == Some(0);
+    fn parse(contents: &str) -> Result<Self> {
+        let mut value = toml::from_str(contents)?;
+        migration::migrate(&mut value)?;
+        Ok(value.try_into()?)
+    }
 
-        lock.unlock()?;
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
 
-        Ok(in_use)
+    pub fn set_progress(&mut self, progress: ProgressFormat) {
+        self.progress = progress;
     }
 
-    pub fn disk_in_use(&self, name: &str) -> Result<bool> {
-        if !self.disks.contains_key(name) {
-            return Err(Error::InvalidDisk { name: name.into() });
+    /// Drives QEMU on a remote host, keeping this state as the local metadata for it.
+    pub fn set_remote(&mut self, remote: Remote) -> Result<()> {
+        let mut cmd = remote.ssh();
+        cmd.args(["--", "mkdir", "-p"]);
+        self.remote = Some(remote);
+        for dir in [
+            self.disk_dir_path(),
+            self.machine_dir_path(),
+            self.snapshot_dir_path(),
+            self.trash_dir_path(),
+        ] {
+            cmd.arg(dir);
         }
-        self.resource_in_use(self.disk_path(name))
+        cmd.traced()?.status()?;
+        Ok(())
     }
 
-    pub fn machine_in_use(&self, name: &str) -> Result<bool> {
-        if !self.machines.contains_key(name) {
-            return Err(Error::InvalidMachine { name: name.into() });
+    pub fn add_remote(&mut self, name: &str, remote: Remote) -> Result<()> {
+        if self.remotes.contains_key(name) {
+            return Err(Error::RemoteExists { name: name.into() });
         }
-        self.resource_in_use(self.machine_path(name))
+        self.remotes.insert(name.into(), remote);
+        Ok(())
     }
 
-    pub fn snapshot_in_use(&self, name: &str) -> Result<bool> {
-        if !self.snapshots.contains_key(name) {
-            return Err(Error::InvalidSnapshot { name: name.into() });
-        }
-        self.resource_in_use(self.snapshot_path(name))
+    pub fn get_remote(&self, name: &str) -> Result<&Remote> {
+        self.remotes.get(name).ok_or(Error::InvalidRemote {
+            remote: name.into(),
+        })
     }
 
-    pub fn add_disk(&mut self, name: &str, size: usize) -> Result<()> {
-        if self.disks.contains_key(name) {
-            return Err(Error::DiskExists { name: name.into() });
-        }
+    pub fn remove_remote(&mut self, name: &str) -> Result<()> {
+        self.remotes.remove(name).ok_or(Error::InvalidRemote {
+            remote: name.into(),
+        })?;
+        Ok(())
+    }
 
-        self.disks.insert(
-            name.into(),
-            Disk {
-                name: name.into(),
-                size,
-            },
-        );
+    /// The local state keeping the metadata of machines on a remote host.
+    pub fn remote_state(&self, name: &str) -> Result<State> {
+        let remote = self.get_remote(name)?.clone();
+        let mut state = State::load(path![self.path / "remotes" / name])?;
+        state.set_remote(remote)?;
+        Ok(state)
+    }
 
-        self.allocate_qcow2(
-            self.disk_path(name).to_str().ok_or(Error::InvalidPath {
-                path: self.disk_path(name),
-            })?,
-            size,
-        )
+    pub fn remotes(&self) -> btree_map::Iter<'_, String, Remote> {
+        self.remotes.iter()
     }
 
-    pub fn get_disk(&self, name: &str) -> Result<&Disk> {
-        self.disks
-            .get(name)
-            .ok_or(Error::InvalidDisk { name: name.into() })
+    /// A command running `program` locally, or on the remote host if there is one.
+    fn command(&self, program: &str) -> Command {
+        remote::command(self.remote.as_ref(), program)
     }
 
-    pub fn remove_disk(&mut self, name: &str) -> Result<()> {
-        if self.disk_in_use(name)? {
-            return Err(Error::DiskInUse { name: name.into() });
-        }
-        self.disks.remove(name);
-        Ok(())
+    /// Replaces qemu-img with another way of managing images.
+    pub fn set_image_backend(&mut self, backend: Box<dyn ImageBackend>) {
+        self.image_backend = Some(backend);
     }
 
-    pub fn add_machine(&mut self, name: &str, port: u16, size: usize) -> Result<()> {
-        if self.machines.contains_key(name) {
-            return Err(Error::MachineExists { name: name.into() });
+    /// Replaces the host with another way of running machines.
+    pub fn set_process_backend(&mut self, backend: Box<dyn ProcessBackend>) {
+        self.process_backend = Some(backend);
+    }
+
+    /// Versions of the configured QEMU and qemu-img where machines run, probed once and cached
+    /// on disk until QEMU changes.
+    pub fn versions(&self) -> Versions {
+        *self
+            .versions
+            .get_or_init(|| self.probe_versions(&self.config.binaries))
+    }
+
+    fn probe_versions(&self, binaries: &Binaries) -> Versions {
+        match &self.remote {
+            Some(remote) => Versions::probe(Some(remote), binaries),
+            None => version::cached(self.versions_path(), binaries),
         }
+    }
 
-        self.machines.insert(
-            name.into(),
-            Machine {
-                name: name.into(),
-                port,
-                size,
-            },
-        );
+    /// Versions of the QEMU a virtual machine runs, which may not be the configured one.
+    fn machine_versions(&self, machine: &Machine) -> Versions {
+        match machine.qemu {
+            Some(_) => self.probe_versions(&self.binaries(machine)),
+            None => self.versions(),
+        }
+    }
 
-        self.allocate_qcow2(
-            self.machine_path(name).to_str().ok_or(Error::InvalidPath {
-                path: self.machine_path(name),
-            })?,
-            size,
-        )
+    /// The binaries a virtual machine runs with: its own QEMU and wrapper if it has them, the
+    /// configured ones otherwise. qemu-img always comes from the config, as images are shared
+    /// between machines through snapshots.
+    pub fn binaries(&self, machine: &Machine) -> Binaries {
+        let config = &self.config.binaries;
+        Binaries {
+            qemu: machine.qemu.clone().or_else(|| config.qemu.clone()),
+            qemu_img: config.qemu_img.clone(),
+            wrapper: machine.wrapper.clone().or_else(|| config.wrapper.clone()),
+        }
     }
 
-    pub fn get_machine(&self, name: &str) -> Result<&Machine> {
-        self.machines
-            .get(name)
-            .ok_or(Error::InvalidMachine { name: name.into() })
+    fn processes(&self) -> &dyn ProcessBackend {
+        self.process_backend.as_deref().unwrap_or(&Host)
     }
 
-    pub fn remove_machine(&mut self, name: &str) -> Result<()> {
-        if self.machine_in_use(name)? {
-            return Err(Error::MachineInUse { name: name.into() });
+    /// The backend managing images, qemu-img unless another has been set.
+    fn images(&self) -> Images<'_> {
+        match &self.image_backend {
+            Some(backend) => Images::Custom(backend.as_ref()),
+            None => Images::QemuImg(QemuImg::new(
+                self.remote.as_ref(),
+                self.config.binaries.qemu_img(),
+                self.progress,
+            )),
         }
-        self.machines.remove(name);
-        Ok(())
     }
 
-    pub fn add_snapshot(&mut self, name: &str, base: &str) -> Result<()> {
-        if self.snapshots.contains_key(name) {
-            return Err(Error::SnapshotExists { name: name.into() });
+    pub(crate) fn track<T, F>(&self, operation: &str, name: &str, f: F) -> Result<T>
+    where
+        F: FnOnce() -> Result<T>,
+    {
+        self.progress.emit(&Event::Start { operation, name });
+        let started = Instant::now();
+        let result = f();
+        match &result {
+            Ok(_) => self.progress.emit(&Event::Finish { operation, name }),
+            Err(err) => self.progress.emit(&Event::Error {
+                operation,
+                name,
+                message: err.to_string(),
+            }),
         }
 
-        let machine = self.get_machine(base)?;
+        let notifications = self.notifications(name);
+        let elapsed = started.elapsed();
+        if elapsed >= notifications.after() {
+            let took = time::format_duration(elapsed.as_secs());
+            let message = match &result {
+                Ok(_) => format!("{operation} {name} finished after {took}"),
+                Err(err) => format!("{operation} {name} failed after {took}: {err}"),
+            };
+            notify::send(&notifications, &message);
+        }
+        result
+    }
 
-        self.snapshots.insert(
-            name.into(),
-            Snapshot {
-                name: name.into(),
-                base: base.into(),
-                port: machine.port,
-                size: machine.size,
-            },
-        );
+    pub fn save(&self) -> Result<()> {
+        if self.lock.is_none() {
+            return Err(Error::StateReadOnly);
+        }
 
-        Command::new(QEMU_IMAGER)
-            .arg("create")
-            .args(["-f", "qcow2"])
-            .args([
-                "-b",
-                &format!(
-                    "{}",
-                    self.machine_path(base).to_str().ok_or(Error::InvalidPath {
-                        path: self.machine_path(base)
-                    })?
-                ),
-            ])
-            .args(["-F", "qcow2"])
-            .arg(&format!(
-                "{}",
-                self.snapshot_path(name)
-                    .to_str()
-                    .ok_or(Error::InvalidPath {
-                        path: self.snapshot_path(name)
-                    })?
-            ))
-            .spawn()?
-            .wait()?;
+        let tmp_path = path![self.path / format!("{}.tmp", STATE_PATH)];
+        let mut tmp = File::create(&tmp_path).at(&tmp_path)?;
+        tmp.write_all(toml::to_string(self)?.as_bytes())?;
+        tmp.sync_all()?;
 
-        Ok(())
-    }
+        if self.state_path().exists() {
+            if let Err(err) = self.back_up() {
+                notify::send(
+                    &self.config.notifications,
+                    &format!("failed to back up the state: {err}"),
+                );
+                return Err(err);
+            }
+        }
 
-    pub fn get_snapshot(&self, name: &str) -> Result<&Snapshot> {
-        self.snapshots
-            .get(name)
-            .ok_or(Error::InvalidSnapshot { name: name.into() })
+        fs::rename(&tmp_path, self.state_path()).at(self.state_path())?;
+        Ok(())
     }
 
-    pub fn remove_snapshot(&mut self, name: &str) -> Result<()> {
-        if self.snapshot_in_use(name)? {
-            return Err(Error::SnapshotInUse { name: name.into() });
+    fn back_up(&self) -> Result<()> {
+        for backup in (1..STATE_BACKUPS).rev() {
+            if self.state_backup_path(backup).exists() {
+                fs::rename(
+                    self.state_backup_path(backup),
+                    self.state_backup_path(backup + 1),
+                )?;
+            }
         }
-        self.snapshots.remove(name);
+        fs::copy(self.state_path(), self.state_backup_path(1))?;
         Ok(())
     }
 
-    pub fn disks(&self) -> Values<String, Disk> {
-        self.disks.values()
+    /// Replaces the current state with a backup, keeping the current state as the newest backup.
+    pub fn restore<P>(path: P, backup: usize) -> Result<()>
+    where
+        P: AsRef<Path> + Into<PathBuf> + Clone,
+    {
+        let mut state = State::new(path.clone())?;
+        let lock = state.acquire_lock()?;
+        let backup_path = state.state_backup_path(backup);
+        if !backup_path.exists() {
+            return Err(Error::InvalidBackup { backup });
+        }
+
+        let config = state.config.clone();
+        state = Self::parse(&fs::read_to_string(backup_path)?)?;
+        state.path = path.into();
+        state.config = config;
+        state.lock = Some(lock);
+        state.save()
     }
 
-    pub fn machines(&self) -> Values<String, Machine> {
-        self.machines.values()
+    /// Best-effort removal of an image.
+    fn remove_image(&self, path: &Path) {
+        match &self.remote {
+            Some(_) => {
+                if let Ok(cmd) = self.command("rm").arg("-f").arg(path).traced() {
+                    cmd.status().ok();
+                }
+            }
+            None => {
+                fs::remove_file(path).ok();
+            }
+        }
     }
 
-    pub fn snapshots(&self) -> Values<String, Snapshot> {
-        self.snapshots.values()
+    fn rename_image(&self, from: &Path, to: &Path) -> Result<()> {
+        match &self.remote {
+            Some(_) => {
+                self.command("mv").arg(from).arg(to).traced()?.status()?;
+            }
+            None => fs::rename(from, to)?,
+        }
+        Ok(())
     }
 
-    pub fn start(
-        &mut self,
-        name: &str,
+    fn base_qemu_command<P: AsRef<Path>>(
+        &self,
+        resource: P,
+        port: u16,
+        machine: &Machine,
         cores: usize,
-        ram: usize,
-        foreground: bool,
-        snapshot: bool,
-        disks: &[String],
-        iso: Option<PathBuf>,
-    ) -> Result<()> {
-        let (resource, port) = if snapshot {
-            let snapshot = self.get_snapshot(name)?;
-            (self.snapshot_path(&snapshot.name), snapshot.port)
-        } else {
-            let machine = self.get_machine(name)?;
-            (self.machine_path(&machine.name), machine.port)
+        ram: Size,
+        boot_device: Option<BootDevice>,
+    ) -> Result<Command> {
+        let probes = &machine.probes;
+        let mut user_options = probes
+            .values()
+            .map(|probe| format!(",hostfwd=tcp::{}-:{}", probe.host_port, probe.guest_port))
+            .collect::<String>();
+        if machine.isolated {
+            user_options.push_str(&network::isolation_args(&machine.allow)?);
+        }
+
+        let qemu = self.binaries(machine).qemu_command();
+        let mut cmd = match &self.remote {
+            Some(remote) => {
+                let forwards = iter::once(port)
+                    .chain(probes.values().map(|probe| probe.host_port))
+                    .collect::<Vec<_>>();
+                let mut cmd = remote.ssh_session(&forwards);
+                cmd.arg("--").args(&qemu);
+                cmd
+            }
+            None => {
+                let mut qemu = qemu.iter().map(|word| remote::expand_home(word));
+                let mut cmd = Command::new(qemu.next().unwrap_or_default());
+                cmd.args(qemu);
+                cmd
+            }
+        };
+        let firmware = match machine.firmware {
+            FirmwareMode::Bios => None,
+            FirmwareMode::Uefi if arch::NEEDS_FIRMWARE => Some(self.uefi_path()?),
+            FirmwareMode::Uefi => self.uefi_path().ok(),
+        };
+        let firmware = firmware
+            .as_ref()
+            .map(|firmware| {
+                firmware.to_str().ok_or(Error::InvalidPath {
+                    path: firmware.clone(),
+                })
+            })
+            .transpose()?;
+
+        if self.machine_versions(machine).supports(Feature::Accel) {
+            cmd.args(["-M", arch::MACHINE])
+                .args(["-accel", arch::ACCEL]);
+        } else {
+            cmd.args(["-M", &format!("{},accel={}", arch::MACHINE, arch::ACCEL)]);
+        }
+        let os = machine.os;
+        cmd.args([
+            "-cpu",
+            &format!("host{}", os.map_or("", |os| os.cpu_flags())),
+        ])
+        .args(["-smp", &format!("{}", cores)])
+        .args(["-m", &format!("{}M", ram.as_megabytes())])
+        .args(arch::firmware_args(firmware))
+        .args([
+            "-drive",
+            &format!(
+                "file={},if=none,cache=writethrough,id=hd0",
+                resource.as_ref().to_str().ok_or(Error::InvalidPath {
+                    path: resource.as_ref().into()
+                })?
+            ),
+        ])
+        .args(["-device", &arch::virtio("gpu")])
+        .args([
+            "-device",
+            &format!(
+                "{},drive=hd0{}",
+                os.and_then(|os| os.block_device())
+                    .unwrap_or(arch::BLOCK_DEVICE),
+                if boot_device == Some(BootDevice::Disk) {
+                    ",bootindex=0"
+                } else {
+                    ""
+                }
+            ),
+        ])
+        .args([
+            "-net",
+            &format!(
+                "user,id={},hostfwd=tcp::{port}-:22,hostfwd=udp::{port}-:{port}{user_options}",
+                network::USER_NETDEV
+            ),
+        ])
+        .args([
+            "-net",
+            &os.map_or_else(
+                || "nic".into(),
+                |os| format!("nic,model={}", os.nic_model()),
+            ),
+        ])
+        .args(machine.network.qemu_args(&network::mac_address(port)))
+        .arg("-nographic");
+        Ok(cmd)
+    }
+
+    /// Takes the lock of a single virtual machine or snapshot, held while it is started or its
+    /// image is worked on, so that others can be started meanwhile. Waits for whoever holds it.
+    pub fn lock_resource(&self, name: &str, snapshot: bool) -> Result<LockFile> {
+        wait_for_lock(&self.lock_path(name, snapshot), name)
+    }
+
+    /// Takes the lock of a disk, held while a virtual machine attaching it is started.
+    pub fn lock_disk(&self, name: &str) -> Result<LockFile> {
+        wait_for_lock(&self.disk_lock_path(name), name)
+    }
+
+    /// Takes the lock of a virtual machine or snapshot if nobody else holds it.
+    fn try_lock_resource(&self, name: &str, snapshot: bool) -> Result<Option<LockFile>> {
+        let path = self.lock_path(name, snapshot);
+        let mut lock = LockFile::open(&path).at(&path)?;
+        Ok(lock.try_lock_with_pid()?.then_some(lock))
+    }
+
+    /// The runtime record of a virtual machine or snapshot whose process is alive. Once the
+    /// process exits, the record stays until `record_exit` handles it.
+    pub fn runtime(&self, name: &str, snapshot: bool) -> Result<Option<Runtime>> {
+        Ok(Runtime::load(self.runtime_path(name, snapshot))?
+            .filter(|runtime| self.process_alive(runtime)))
+    }
+
+    /// Whether the process of a runtime record is alive, and not another that got its PID after
+    /// it exited.
+    fn process_alive(&self, runtime: &Runtime) -> bool {
+        let processes = self.processes();
+        processes.alive(runtime.pid)
+            && runtime
+                .process_started
+                .is_none_or(|started| processes.started(runtime.pid) == Some(started))
+    }
+
+    /// Handles a virtual machine or snapshot whose process has exited, unless someone else is
+    /// working on it and will handle it themselves.
+    fn reconcile(&self, name: &str, snapshot: bool) -> Result<()> {
+        match self.try_lock_resource(name, snapshot)? {
+            Some(_lock) => self.record_exit(name, snapshot),
+            None => Ok(()),
+        }
+    }
+
+    /// Journals the boot of a virtual machine or snapshot whose process has exited and removes
+    /// its runtime record. A process nobody asked to exit crashed: its record is kept as the
+    /// exited one, and the crash is reported and notified. Only called with the resource lock
+    /// held, so that each exit is handled once.
+    fn record_exit(&self, name: &str, snapshot: bool) -> Result<()> {
+        let path = self.runtime_path(name, snapshot);
+        let Some(runtime) = Runtime::load(&path)? else {
+            return Ok(());
+        };
+        if self.process_alive(&runtime) {
+            return Ok(());
+        }
+
+        match runtime.exit {
+            Some(_) => fs::remove_file(&path).at(&path)?,
+            None => fs::rename(path, self.exited_path(name, snapshot))?,
+        }
+        let reason = runtime.exit.unwrap_or(ExitReason::Crashed);
+        journal::append(
+            self.journal_path(name, snapshot),
+            &Boot::new(&runtime, reason),
+        )?;
+        if runtime.exit.is_none() {
+            if let Err(err) = self.report_crash(name, snapshot, &runtime, "exited") {
+                tracing::warn!("failed to collect a crash report of {name}: {err}");
+            }
+            notify::send(&self.notifications(name), &format!("{name} crashed"));
+        }
+        // Machines that have gone away shouldn't stay resolvable.
+        self.refresh_hosts()
+    }
+
+    /// The runtime record of a machine that exited unexpectedly since it was last started.
+    pub fn exited(&self, name: &str, snapshot: bool) -> Result<Option<Runtime>> {
+        if self.runtime(name, snapshot)?.is_some() {
+            return Ok(None);
+        }
+        // A crash nobody has handled yet still has its runtime record in place.
+        match Runtime::load(self.runtime_path(name, snapshot))? {
+            Some(runtime) if runtime.exit.is_none() => Ok(Some(runtime)),
+            _ => Runtime::load(self.exited_path(name, snapshot)),
+        }
+    }
+
+    fn runtimes(&self) -> Result<Vec<Runtime>> {
+        let mut runtimes = Vec::new();
+        for name in self.machines.keys() {
+            runtimes.extend(self.runtime(name, false)?);
+        }
+        for name in self.snapshots.keys() {
+            runtimes.extend(self.runtime(name, true)?);
+        }
+        Ok(runtimes)
+    }
+
+    /// The status of a virtual machine or snapshot, asking QEMU over QMP if it is running.
+    pub fn status(&self, name: &str, snapshot: bool) -> Result<MachineStatus> {
+        if snapshot {
+            self.get_snapshot(name)?;
+        } else {
+            self.get_machine(name)?;
+        }
+        self.reconcile(name, snapshot)?;
+
+        if self.runtime(name, snapshot)?.is_none() {
+            return Ok(if self.exited(name, snapshot)?.is_some() {
+                MachineStatus::Crashed
+            } else if self.suspended_path(name, snapshot).exists() {
+                MachineStatus::Saved
+            } else {
+                MachineStatus::Stopped
+            });
+        }
+
+        // Machines started before QMP was enabled, or on a remote host, can only be seen running.
+        let run_state = self
+            .qmp(name, snapshot)
+            .and_then(|mut qmp| qmp.execute("query-status", json!({})))
+            .ok()
+            .and_then(|status| status["status"].as_str().map(String::from));
+        let status = run_state
+            .as_deref()
+            .map_or(MachineStatus::Running, MachineStatus::from_run_state);
+
+        if let (MachineStatus::Crashed, Some(run_state), Some(runtime)) =
+            (status, &run_state, self.runtime(name, snapshot)?)
+        {
+            if let Err(err) = self.report_crash(name, snapshot, &runtime, run_state) {
+                tracing::warn!("failed to collect a crash report of {name}: {err}");
+            }
+        }
+
+        // A guest that powered off while QEMU stayed around is cleaned up like a stopped one.
+        if status == MachineStatus::Stopped {
+            self.quit(name, snapshot)?;
+        }
+        Ok(status)
+    }
+
+    /// Collects the end of the log, and guest memory if configured and QEMU is still up, into a
+    /// crash report. Each boot is reported at most once.
+    fn report_crash(
+        &self,
+        name: &str,
+        snapshot: bool,
+        runtime: &Runtime,
+        reason: &str,
+    ) -> Result<()> {
+        let id = format!("{}-{}", namespace::encode(name), runtime.started_at);
+        let dir = self.crash_path(&id);
+        if dir.exists() {
+            return Ok(());
+        }
+        fs::create_dir_all(&dir).at(&dir)?;
+
+        let log = crash::tail(
+            self.log_path(name, snapshot),
+            self.config.crashes.log_bytes(),
+        )?;
+        fs::write(path![dir / crash::LOG_PATH], log)?;
+
+        let mut memory_dump = false;
+        if self.config.crashes.dump_memory && self.process_alive(runtime) {
+            let memory = path![dir / crash::MEMORY_PATH];
+            let mut qmp = self.qmp(name, snapshot)?;
+            qmp.set_timeout(self.timeouts(name, snapshot)?.shutdown())?;
+            qmp.execute(
+                "dump-guest-memory",
+                json!({ "paging": false, "protocol": format!("file:{}", memory.display()) }),
+            )?;
+            memory_dump = true;
+        }
+
+        Report {
+            id,
+            name: name.into(),
+            snapshot,
+            reason: reason.into(),
+            pid: runtime.pid,
+            started_at: runtime.started_at,
+            detected_at: time::now(),
+            cores: runtime.cores,
+            ram: runtime.ram,
+            memory_dump,
+        }
+        .save(&dir)
+    }
+
+    /// Crash reports, oldest first.
+    pub fn crashes(&self) -> Result<Vec<Report>> {
+        let Ok(entries) = fs::read_dir(self.crash_dir_path()) else {
+            return Ok(Vec::new());
+        };
+        let mut reports = Vec::new();
+        for entry in entries {
+            if let Ok(report) = Report::load(entry?.path()) {
+                reports.push(report);
+            }
+        }
+        reports.sort_by_key(|report| report.detected_at);
+        Ok(reports)
+    }
+
+    /// A crash report with the end of the log it collected.
+    pub fn crash(&self, id: &str) -> Result<(Report, String)> {
+        let dir = self.crash_path(id);
+        let report = Report::load(&dir).map_err(|_| Error::InvalidCrash { id: id.into() })?;
+        let log = fs::read(path![dir / crash::LOG_PATH]).unwrap_or_default();
+        Ok((report, String::from_utf8_lossy(&log).into()))
+    }
+
+    /// Asks the QEMU process of a machine whose guest has shut down to exit, recording the
+    /// boot as stopped.
+    fn quit(&self, name: &str, snapshot: bool) -> Result<()> {
+        let Some(runtime) = self.runtime(name, snapshot)? else {
+            return Ok(());
+        };
+        Runtime {
+            exit: Some(ExitReason::Stopped),
+            ..runtime
+        }
+        .save(self.runtime_path(name, snapshot))?;
+        self.qmp(name, snapshot)?.execute("quit", json!({}))?;
+
+        let deadline = Instant::now() + self.timeouts(name, snapshot)?.shutdown();
+        while Instant::now() < deadline {
+            if self.runtime(name, snapshot)?.is_none() {
+                let _lock = self.lock_resource(name, snapshot)?;
+                return self.record_exit(name, snapshot);
+            }
+            thread::sleep(PROBE_INTERVAL);
+        }
+        Err(Error::ShutdownTimeout { name: name.into() })
+    }
+
+    pub fn disk_in_use(&self, name: &str) -> Result<bool> {
+        if !self.disks.contains_key(name) {
+            return Err(Error::InvalidDisk { name: name.into() });
+        }
+        Ok(self
+            .runtimes()?
+            .iter()
+            .any(|runtime| runtime.disks.iter().any(|disk| disk == name)))
+    }
+
+    pub fn machine_in_use(&self, name: &str) -> Result<bool> {
+        if !self.machines.contains_key(name) {
+            return Err(Error::InvalidMachine { name: name.into() });
+        }
+        Ok(self.runtime(name, false)?.is_some())
+    }
+
+    pub fn snapshot_in_use(&self, name: &str) -> Result<bool> {
+        if !self.snapshots.contains_key(name) {
+            return Err(Error::InvalidSnapshot { name: name.into() });
+        }
+        Ok(self.runtime(name, true)?.is_some())
+    }
+
+    pub fn disk_usage(&self, name: &str, snapshot: bool) -> Result<u64> {
+        let path = if snapshot {
+            self.snapshot_path(&self.get_snapshot(name)?.name)
+        } else {
+            self.machine_path(&self.get_machine(name)?.name)
+        };
+        Ok(allocated(&path))
+    }
+
+    /// Fails before QEMU is spawned if the SSH port is held, which QEMU only reports in its log.
+    fn ensure_port_free(&self, port: u16) -> Result<()> {
+        // Ports on a remote host can't be checked from here.
+        if self.remote.is_some() || !ports::bound(port) {
+            return Ok(());
+        }
+
+        let machines = self
+            .machines
+            .values()
+            .filter(|machine| machine.port == port)
+            .map(|machine| (&machine.name, false));
+        let snapshots = self
+            .snapshots
+            .values()
+            .filter(|snapshot| snapshot.port == port)
+            .map(|snapshot| (&snapshot.name, true));
+        let mut owner = None;
+        for (name, snapshot) in machines.chain(snapshots) {
+            if self.runtime(name, snapshot)?.is_some() {
+                let kind = if snapshot { "snapshot" } else { "machine" };
+                owner = Some(format!("{kind} '{name}'"));
+                break;
+            }
+        }
+        let owner = owner.unwrap_or_else(|| match ports::listener(port) {
+            Some(pid) => format!("process {pid}"),
+            None => "another process".into(),
+        });
+        Err(Error::PortInUse { port, owner })
+    }
+
+    /// The image of a stopped virtual machine, or of a disk not attached anywhere, for
+    /// temporary overlays to be booted on top of.
+    pub fn template_image(&self, name: &str) -> Result<PathBuf> {
+        if self.machines.contains_key(name) {
+            if self.machine_in_use(name)? {
+                return Err(Error::MachineInUse { name: name.into() });
+            }
+            return Ok(self.machine_path(name));
+        }
+        if self.disk_in_use(name)? {
+            return Err(Error::DiskInUse { name: name.into() });
+        }
+        Ok(self.disk_path(name))
+    }
+
+    pub fn describe_disk(&self, name: &str) -> Result<DiskInfo> {
+        let disk = self.get_disk(name)?;
+        let path = self.disk_path(name);
+        Ok(DiskInfo {
+            name: disk.name.clone(),
+            size: disk.size,
+            size_on_disk: allocated(&path),
+            path,
+            in_use: self.disk_in_use(name)?,
+            description: disk.description.clone(),
+            sha256: disk.sha256.clone(),
+            created_at: disk.created_at,
+            last_started_at: disk.last_started_at,
+        })
+    }
+
+    pub fn describe_machine(&self, name: &str) -> Result<MachineInfo> {
+        let machine = self.get_machine(name)?;
+        let path = self.machine_path(name);
+        Ok(MachineInfo {
+            name: machine.name.clone(),
+            port: machine.port,
+            size: machine.size,
+            network: machine.network.clone(),
+            isolated: machine.isolated,
+            firmware: machine.firmware,
+            os: machine.os,
+            status: self.status(name, false)?,
+            pid: self.runtime(name, false)?.map(|runtime| runtime.pid),
+            size_on_disk: allocated(&path),
+            path,
+            log_path: self.log_path(name, false),
+            restart: machine.restart,
+            iso: machine.iso.clone(),
+            description: machine.description.clone(),
+            sha256: machine.sha256.clone(),
+            created_at: machine.created_at,
+            last_started_at: machine.last_started_at,
+            meta: machine.meta.clone(),
+        })
+    }
+
+    pub fn describe_snapshot(&self, name: &str) -> Result<SnapshotInfo> {
+        let snapshot = self.get_snapshot(name)?;
+        let path = self.snapshot_path(name);
+        Ok(SnapshotInfo {
+            name: snapshot.name.clone(),
+            base: snapshot.base.clone(),
+            parent: snapshot.parent.clone(),
+            port: snapshot.port,
+            size: snapshot.size,
+            cores: snapshot.cores,
+            ram: snapshot.ram,
+            full: snapshot.full,
+            description: snapshot.description.clone(),
+            stale: self.snapshot_stale(name)?,
+            status: self.status(name, true)?,
+            pid: self.runtime(name, true)?.map(|runtime| runtime.pid),
+            size_on_disk: allocated(&path),
+            path,
+            backing_path: (!snapshot.full).then(|| self.backing_path(snapshot)),
+            log_path: self.log_path(name, true),
+            created_at: snapshot.created_at,
+            last_started_at: snapshot.last_started_at,
+        })
+    }
+
+    pub fn add_disk(&mut self, name: &str, size: Size) -> Result<()> {
+        namespace::validate(name)?;
+        if self.disks.contains_key(name) {
+            return Err(Error::DiskExists { name: name.into() });
+        }
+
+        self.disks.insert(name.into(), Disk::new(name, size));
+
+        self.track("create", name, || {
+            self.images().create(&self.disk_path(name), size)
+        })
+    }
+
+    pub fn import_disk(&mut self, name: &str, src: &Path, format: ImageFormat) -> Result<()> {
+        namespace::validate(name)?;
+        if self.disks.contains_key(name) {
+            return Err(Error::DiskExists { name: name.into() });
+        }
+
+        self.track("convert", name, || {
+            self.images()
+                .convert(name, src, format, &self.disk_path(name), false)
+        })?;
+        let size = self.images().info(&self.disk_path(name))?.virtual_size;
+
+        self.disks.insert(name.into(), Disk::new(name, size));
+
+        Ok(())
+    }
+
+    pub fn get_disk(&self, name: &str) -> Result<&Disk> {
+        self.disks
+            .get(name)
+            .ok_or(Error::InvalidDisk { name: name.into() })
+    }
+
+    pub fn remove_disk(&mut self, name: &str) -> Result<()> {
+        let _lock = self.lock_disk(name)?;
+        if self.disk_in_use(name)? {
+            return Err(Error::DiskInUse { name: name.into() });
+        }
+        let disk = self.get_disk(name)?.clone();
+        self.trash(Resource::Disk(disk), &self.disk_path(name))?;
+        self.disks.remove(name);
+        Ok(())
+    }
+
+    pub fn resize_disk(&mut self, name: &str, size: Size, allow_shrink: bool) -> Result<()> {
+        let _lock = self.lock_disk(name)?;
+        if self.disk_in_use(name)? {
+            return Err(Error::DiskInUse { name: name.into() });
+        }
+
+        let shrink = size < self.get_disk(name)?.size;
+        if shrink && !allow_shrink {
+            return Err(Error::ShrinkNotAllowed { name: name.into() });
+        }
+
+        self.track("resize", name, || {
+            self.images().resize(&self.disk_path(name), size, shrink)
+        })?;
+
+        if let Some(disk) = self.disks.get_mut(name) {
+            disk.size = size;
+        }
+
+        Ok(())
+    }
+
+    pub fn add_machine(&mut self, name: &str, port: u16, size: Size) -> Result<()> {
+        namespace::validate(name)?;
+        if self.machines.contains_key(name) {
+            return Err(Error::MachineExists { name: name.into() });
+        }
+
+        self.machines
+            .insert(name.into(), Machine::new(name, port, size));
+
+        self.track("create", name, || {
+            self.images().create(&self.machine_path(name), size)
+        })
+    }
+
+    pub fn import_machine(
+        &mut self,
+        name: &str,
+        src: &Path,
+        format: ImageFormat,
+        port: u16,
+    ) -> Result<()> {
+        namespace::validate(name)?;
+        if self.machines.contains_key(name) {
+            return Err(Error::MachineExists { name: name.into() });
+        }
+
+        self.track("convert", name, || {
+            self.images()
+                .convert(name, src, format, &self.machine_path(name), false)
+        })?;
+        let size = self.images().info(&self.machine_path(name))?.virtual_size;
+
+        self.machines
+            .insert(name.into(), Machine::new(name, port, size));
+
+        Ok(())
+    }
+
+    /// Recreates the image of a machine, blank or from `image`, keeping its port and settings
+    /// unless `reset` is set. The old image is only replaced once the new one is complete.
+    pub fn rebuild_machine(
+        &mut self,
+        name: &str,
+        image: Option<(&Path, ImageFormat)>,
+        reset: bool,
+    ) -> Result<()> {
+        let _lock = self.lock_resource(name, false)?;
+        if self.machine_in_use(name)? {
+            return Err(Error::MachineInUse { name: name.into() });
+        }
+        if !self.linked_snapshots(name, false).is_empty() {
+            return Err(Error::BackingInUse { name: name.into() });
+        }
+
+        let machine = self.get_machine(name)?.clone();
+        let staging = self.machine_path(&format!("{name}.rebuild"));
+        let mut size = machine.size;
+
+        match image {
+            Some((src, format)) => {
+                self.track("convert", name, || {
+                    self.images().convert(name, src, format, &staging, false)
+                })?;
+                let image_size = self.images().info(&staging)?.virtual_size;
+                if image_size < size {
+                    self.images().resize(&staging, size, false)?;
+                } else {
+                    size = image_size;
+                }
+            }
+            None => self.track("create", name, || self.images().create(&staging, size))?,
+        }
+        self.rename_image(&staging, &self.machine_path(name))?;
+
+        let rebuilt = if reset {
+            Machine::new(name, machine.port, size)
+        } else {
+            Machine {
+                size,
+                created_at: Some(time::now()),
+                last_started_at: None,
+                provisioned_at: None,
+                ..machine
+            }
+        };
+        self.machines.insert(name.into(), rebuilt);
+        Ok(())
+    }
+
+    pub fn add_machine_overlay(
+        &mut self,
+        name: &str,
+        backing: &Path,
+        format: ImageFormat,
+        port: u16,
+    ) -> Result<()> {
+        namespace::validate(name)?;
+        if self.machines.contains_key(name) {
+            return Err(Error::MachineExists { name: name.into() });
+        }
+
+        self.track("create", name, || {
+            self.images()
+                .snapshot(name, backing, format, &self.machine_path(name))
+        })?;
+
+        let size = self.images().info(&self.machine_path(name))?.virtual_size;
+        self.machines
+            .insert(name.into(), Machine::new(name, port, size));
+
+        Ok(())
+    }
+
+    pub fn next_port(&self, from: u16) -> Result<u16> {
+        (from..=u16::MAX)
+            .find(|port| {
+                !self.machines.values().any(|machine| machine.port == *port)
+                    && !self
+                        .snapshots
+                        .values()
+                        .any(|snapshot| snapshot.port == *port)
+            })
+            .ok_or(Error::NoFreePort)
+    }
+
+    pub fn import_from(
+        &mut self,
+        source: Source,
+        only: Option<&str>,
+        port: u16,
+    ) -> Result<Vec<ForeignMachine>> {
+        let mut imported = Vec::new();
+        for foreign in source.discover()? {
+            if only.is_some_and(|only| only != foreign.name) {
+                continue;
+            }
+            if self.machines.contains_key(&foreign.name) {
+                continue;
+            }
+            let format = self.images().info(&foreign.disk)?.format;
+            let port = self.next_port(port)?;
+            self.import_machine(&foreign.name, &foreign.disk, format, port)?;
+            self.set_machine_resources(&foreign.name, foreign.cores, foreign.ram)?;
+            imported.push(foreign);
+        }
+        Ok(imported)
+    }
+
+    pub fn export(
+        &self,
+        name: &str,
+        snapshot: bool,
+        output: &Path,
+        cores: usize,
+        ram: Size,
+        compress: bool,
+    ) -> Result<()> {
+        let _lock = self.lock_resource(name, snapshot)?;
+        let (resource, port, size) = if snapshot {
+            if self.snapshot_in_use(name)? {
+                return Err(Error::SnapshotInUse { name: name.into() });
+            }
+            let snapshot = self.get_snapshot(name)?;
+            (self.snapshot_path(name), snapshot.port, snapshot.size)
+        } else {
+            if self.machine_in_use(name)? {
+                return Err(Error::MachineInUse { name: name.into() });
+            }
+            let machine = self.get_machine(name)?;
+            (self.machine_path(name), machine.port, machine.size)
+        };
+
+        let manifest = Manifest {
+            name: name.into(),
+            port,
+            size,
+            cores,
+            ram,
+        };
+
+        let output_str = output.to_str().ok_or(Error::InvalidPath {
+            path: output.into(),
+        })?;
+
+        let Some(stem) = output_str.strip_suffix(ARCHIVE_EXTENSION) else {
+            self.track("export", name, || {
+                self.images()
+                    .convert(name, &resource, ImageFormat::Qcow2, output, compress)
+            })?;
+            return manifest.save(format!("{output_str}.toml"));
+        };
+
+        let staging = PathBuf::from(format!("{stem}.staging"));
+        fs::create_dir_all(&staging)?;
+
+        let result = self.track("export", name, || {
+            self.images().convert(
+                name,
+                &resource,
+                ImageFormat::Qcow2,
+                &path![staging / EXPORT_DISK_PATH],
+                compress,
+            )?;
+            manifest.save(path![staging / EXPORT_MANIFEST_PATH])?;
+
+            let status = Command::new("tar")
+                .arg("--zstd")
+                .arg("-cf")
+                .arg(output)
+                .arg("-C")
+                .arg(&staging)
+                .args([EXPORT_DISK_PATH, EXPORT_MANIFEST_PATH])
+                .traced()?
+                .spawn()?
+                .wait()?;
+            if !status.success() {
+                return Err(Error::InvalidArchive {
+                    path: output.into(),
+                });
+            }
+            Ok(())
+        });
+
+        fs::remove_dir_all(&staging)?;
+        result
+    }
+
+    pub fn export_libvirt(
+        &self,
+        name: &str,
+        snapshot: bool,
+        cores: usize,
+        ram: Size,
+        disks: &[String],
+    ) -> Result<String> {
+        let mut paths = vec![if snapshot {
+            self.snapshot_path(&self.get_snapshot(name)?.name)
+        } else {
+            self.machine_path(&self.get_machine(name)?.name)
+        }];
+        for disk in disks {
+            paths.push(self.disk_path(&self.get_disk(disk)?.name));
+        }
+        Ok(libvirt::domain_xml(name, &paths, cores, ram))
+    }
+
+    pub fn import(&mut self, archive: &Path, name: Option<&str>) -> Result<Manifest> {
+        let archive_str = archive.to_str().ok_or(Error::InvalidPath {
+            path: archive.into(),
+        })?;
+
+        let Some(stem) = archive_str.strip_suffix(ARCHIVE_EXTENSION) else {
+            let manifest = Manifest::load(format!("{archive_str}.toml"))?;
+            let name = name.unwrap_or(&manifest.name).to_string();
+            self.import_machine(&name, archive, ImageFormat::Qcow2, manifest.port)?;
+            self.set_machine_resources(&name, Some(manifest.cores), Some(manifest.ram))?;
+            return Ok(manifest);
+        };
+
+        let staging = PathBuf::from(format!("{stem}.staging"));
+        fs::create_dir_all(&staging)?;
+
+        let result = self.import_archive(archive, &staging, name);
+
+        fs::remove_dir_all(&staging)?;
+        result
+    }
+
+    fn import_archive(
+        &mut self,
+        archive: &Path,
+        staging: &Path,
+        name: Option<&str>,
+    ) -> Result<Manifest> {
+        let status = Command::new("tar")
+            .arg("--zstd")
+            .arg("-xf")
+            .arg(archive)
+            .arg("-C")
+            .arg(staging)
+            .traced()?
+            .spawn()?
+            .wait()?;
+        if !status.success() {
+            return Err(Error::InvalidArchive {
+                path: archive.into(),
+            });
+        }
+
+        self.import_staged(staging, name)
+    }
+
+    /// Imports an exported disk and manifest unpacked into `staging`.
+    fn import_staged(&mut self, staging: &Path, name: Option<&str>) -> Result<Manifest> {
+        let manifest = Manifest::load(path![staging / EXPORT_MANIFEST_PATH])?;
+        let name = name.unwrap_or(&manifest.name).to_string();
+        self.import_machine(
+            &name,
+            &path![staging / EXPORT_DISK_PATH],
+            ImageFormat::Qcow2,
+            manifest.port,
+        )?;
+        self.set_machine_resources(&name, Some(manifest.cores), Some(manifest.ram))?;
+        Ok(manifest)
+    }
+
+    /// Publishes a virtual machine or snapshot to an OCI registry, as a compressed flattened
+    /// disk and the manifest `rhea export` writes.
+    pub fn push_image(
+        &self,
+        name: &str,
+        snapshot: bool,
+        reference: &str,
+        cores: usize,
+        ram: Size,
+    ) -> Result<()> {
+        let staging = path![self.path / format!(".push-{}", std::process::id())];
+        fs::create_dir_all(&staging).at(&staging)?;
+
+        let disk = path![staging / EXPORT_DISK_PATH];
+        let result = self
+            .export(name, snapshot, &disk, cores, ram, true)
+            .and_then(|()| {
+                fs::rename(
+                    format!("{}.toml", disk.display()),
+                    path![staging / EXPORT_MANIFEST_PATH],
+                )?;
+                self.track("push", name, || {
+                    registry::push(reference, &staging, EXPORT_DISK_PATH, EXPORT_MANIFEST_PATH)
+                })
+            });
+
+        fs::remove_dir_all(&staging).ok();
+        result
+    }
+
+    /// Imports a virtual machine pushed with `push_image`.
+    pub fn pull_image(&mut self, reference: &str, name: Option<&str>) -> Result<Manifest> {
+        let staging = path![self.path / format!(".pull-{}", std::process::id())];
+        fs::create_dir_all(&staging).at(&staging)?;
+
+        let result = self
+            .track("pull", reference, || registry::pull(reference, &staging))
+            .and_then(|()| self.import_staged(&staging, name));
+
+        fs::remove_dir_all(&staging).ok();
+        result
+    }
+
+    /// Copies a stopped virtual machine to the remote host of `target`, or brings the copy
+    /// already there up to date by sending only the blocks that changed.
+    pub fn sync_machine(&self, name: &str, target: &mut State) -> Result<()> {
+        let _lock = self.lock_resource(name, false)?;
+        if self.machine_in_use(name)? {
+            return Err(Error::MachineInUse { name: name.into() });
+        }
+        let source = self.machine_path(name);
+        if self.images().info(&source)?.backing.is_some() {
+            return Err(Error::SyncBacked { name: name.into() });
+        }
+
+        let port = match target.machines.get(name) {
+            Some(_) if target.machine_in_use(name)? => {
+                return Err(Error::MachineInUse { name: name.into() })
+            }
+            Some(_) if !target.linked_snapshots(name, false).is_empty() => {
+                return Err(Error::BackingInUse { name: name.into() })
+            }
+            Some(copy) => copy.port,
+            None => target.next_port(self.get_machine(name)?.port)?,
+        };
+        let remote = target.remote.clone().ok_or(Error::InvalidRemote {
+            remote: target.path.display().to_string(),
+        })?;
+
+        self.track("sync", name, || {
+            let output = remote
+                .rsync(&source, &target.machine_path(name))
+                .traced()?
+                .output()?;
+            if !output.status.success() {
+                return Err(Error::CommandFailed {
+                    command: "rsync".into(),
+                    stderr: String::from_utf8_lossy(&output.stderr).trim().into(),
+                });
+            }
+            Ok(())
+        })?;
+
+        let machine = Machine {
+            port,
+            ..self.get_machine(name)?.clone()
+        };
+        target.machines.insert(name.into(), machine);
+        Ok(())
+    }
+
+    /// Copies a stopped virtual machine into the state of another profile, flattening its image
+    /// so that it no longer depends on anything in this one and taking along a key kept here.
+    pub fn copy_machine(&self, name: &str, target: &mut State) -> Result<()> {
+        if self.remote.is_some() || target.remote.is_some() {
+            return Err(Error::RemoteUnsupported {
+                operation: "copying to another profile".into(),
+            });
+        }
+        let _lock = self.lock_resource(name, false)?;
+        if self.machine_in_use(name)? {
+            return Err(Error::MachineInUse { name: name.into() });
+        }
+        if target.machines.contains_key(name) {
+            return Err(Error::MachineExists { name: name.into() });
+        }
+
+        let mut machine = self.get_machine(name)?.clone();
+        machine.port = target.next_port(machine.port)?;
+
+        self.track("copy", name, || {
+            self.images().convert(
+                name,
+                &self.machine_path(name),
+                ImageFormat::Qcow2,
+                &target.machine_path(name),
+                false,
+            )
+        })?;
+
+        // Keys generated by `rhea key generate` live in the state directory, unlike ones the
+        // user pointed at.
+        if let Some(identity) = machine
+            .identity
+            .as_mut()
+            .filter(|identity| identity.starts_with(&self.path))
+        {
+            let key = target.key_path(name);
+            fs::copy(&identity, &key).at(&identity)?;
+            fs::copy(identity.with_extension("pub"), key.with_extension("pub")).ok();
+            *identity = key;
+        }
+
+        target.machines.insert(name.into(), machine);
+        Ok(())
+    }
+
+    /// Imports a Vagrant box or OVA as a virtual machine on the first free port from `port`,
+    /// returning the cores and RAM it asks for.
+    pub fn import_box(
+        &mut self,
+        archive: &Path,
+        name: Option<&str>,
+        port: u16,
+    ) -> Result<ForeignMachine> {
+        let staging = path![self.path / format!(".unpack-{}", std::process::id())];
+        fs::create_dir_all(&staging).at(&staging)?;
+
+        let result = foreign::unpack_box(archive, &staging).and_then(|mut foreign| {
+            if let Some(name) = name {
+                foreign.name = name.into();
+            }
+            if self.machines.contains_key(&foreign.name) {
+                return Err(Error::MachineExists { name: foreign.name });
+            }
+            let format = self.images().info(&foreign.disk)?.format;
+            let port = self.next_port(port)?;
+            self.import_machine(&foreign.name, &foreign.disk, format, port)?;
+            Ok(foreign)
+        });
+
+        fs::remove_dir_all(&staging).ok();
+        let foreign = result?;
+        self.set_machine_resources(&foreign.name, foreign.cores, foreign.ram)?;
+        let file = archive.file_name().unwrap_or_default().to_string_lossy();
+        self.set_machine_description(&foreign.name, Some(format!("imported from {file}")))?;
+        Ok(foreign)
+    }
+
+    pub fn get_machine(&self, name: &str) -> Result<&Machine> {
+        self.machines
+            .get(name)
+            .ok_or(Error::InvalidMachine { name: name.into() })
+    }
+
+    pub fn remove_machine(&mut self, name: &str) -> Result<()> {
+        let _lock = self.lock_resource(name, false)?;
+        self.record_exit(name, false)?;
+        if self.machine_in_use(name)? {
+            return Err(Error::MachineInUse { name: name.into() });
+        }
+        if !self.linked_snapshots(name, false).is_empty() {
+            return Err(Error::MachineHasSnapshots { name: name.into() });
+        }
+        let machine = self.get_machine(name)?.clone();
+        self.trash(
+            Resource::Machine(Box::new(machine)),
+            &self.machine_path(name),
+        )?;
+        self.machines.remove(name);
+        Ok(())
+    }
+
+    pub fn set_expiry(&mut self, name: &str, snapshot: bool, expiry: Option<Expiry>) -> Result<()> {
+        if snapshot {
+            self.snapshots
+                .get_mut(name)
+                .ok_or(Error::InvalidSnapshot { name: name.into() })?
+                .expiry = expiry;
+        } else {
+            self.machines
+                .get_mut(name)
+                .ok_or(Error::InvalidMachine { name: name.into() })?
+                .expiry = expiry;
+        }
+        Ok(())
+    }
+
+    pub fn expiry(&self, name: &str, snapshot: bool) -> Result<Option<Expiry>> {
+        Ok(if snapshot {
+            self.get_snapshot(name)?.expiry
+        } else {
+            self.get_machine(name)?.expiry
+        })
+    }
+
+    /// Expired snapshots and machines, snapshots first so their bases can be removed after them.
+    pub fn expired(&self) -> Vec<(String, bool)> {
+        let expired = |expiry: &Option<Expiry>| expiry.is_some_and(|expiry| expiry.expired());
+
+        let snapshots = self
+            .snapshots
+            .values()
+            .filter(|snapshot| expired(&snapshot.expiry))
+            .map(|snapshot| (snapshot.name.clone(), true));
+        let machines = self
+            .machines
+            .values()
+            .filter(|machine| expired(&machine.expiry))
+            .map(|machine| (machine.name.clone(), false));
+        snapshots.chain(machines).collect()
+    }
+
+    /// Stops expired snapshots and machines, and removes those set to be removed on expiry.
+    /// Returns their names. One that can't be stopped or removed is skipped with a warning, so
+    /// that it doesn't hold the others up.
+    pub fn reap_expired(&mut self) -> Vec<String> {
+        let mut reaped = Vec::new();
+        for (name, snapshot) in self.expired() {
+            match self.expire(&name, snapshot) {
+                Ok(true) => reaped.push(name),
+                Ok(false) => {}
+                Err(err) => tracing::warn!("failed to expire {name}: {err}"),
+            }
+        }
+        reaped
+    }
+
+    fn expire(&mut self, name: &str, snapshot: bool) -> Result<bool> {
+        let in_use = if snapshot {
+            self.snapshot_in_use(name)?
+        } else {
+            self.machine_in_use(name)?
+        };
+        let remove = self
+            .expiry(name, snapshot)?
+            .is_some_and(|expiry| expiry.remove);
+
+        if in_use {
+            self.stop(name, snapshot)?;
+        }
+        if remove {
+            if snapshot {
+                self.remove_snapshot(name)?;
+            } else {
+                self.remove_machine(name)?;
+            }
+        }
+        Ok(in_use || remove)
+    }
+
+    /// Adds `count` throwaway snapshots of a machine or snapshot, named `<name>-<n>` and each on
+    /// a free port, which are removed once they stop. Returns their names.
+    pub fn add_instances(
+        &mut self,
+        name: &str,
+        snapshot: bool,
+        count: usize,
+    ) -> Result<Vec<String>> {
+        let mut instances = Vec::new();
+        let mut n = 1;
+        for _ in 0..count {
+            while self.snapshots.contains_key(&format!("{name}-{n}")) {
+                n += 1;
+            }
+            let instance = format!("{name}-{n}");
+            self.add_snapshot(&instance, name, snapshot, false)?;
+            let port = self.next_port(self.get_snapshot(&instance)?.port)?;
+            self.set_snapshot_resources(&instance, Some(port), None, None)?;
+            if let Some(instance) = self.snapshots.get_mut(&instance) {
+                instance.ephemeral = true;
+            }
+            instances.push(instance);
+        }
+        Ok(instances)
+    }
+
+    /// Removes instances that were started and have stopped since, along with their overlays,
+    /// which aren't worth keeping in the trash.
+    pub fn reap_instances(&mut self) -> Result<Vec<String>> {
+        let stopped = self
+            .snapshots
+            .values()
+            .filter(|snapshot| snapshot.ephemeral && snapshot.last_started_at.is_some())
+            .map(|snapshot| snapshot.name.clone())
+            .collect::<Vec<_>>();
+        let mut reaped = Vec::new();
+        for name in stopped {
+            self.reconcile(&name, true)?;
+            if self.snapshot_in_use(&name)? || !self.linked_snapshots(&name, true).is_empty() {
+                continue;
+            }
+            self.remove_image(&self.snapshot_path(&name));
+            self.snapshots.remove(&name);
+            reaped.push(name);
+        }
+        Ok(reaped)
+    }
+
+    /// Stops and removes every expired machine and snapshot, whether or not it was set to be
+    /// removed on expiry.
+    pub fn prune_expired(&mut self) -> Result<Vec<String>> {
+        let mut pruned = Vec::new();
+        for (name, snapshot) in self.expired() {
+            if self.runtime(&name, snapshot)?.is_some() {
+                self.stop(&name, snapshot)?;
+            }
+            if snapshot {
+                self.remove_snapshot(&name)?;
+            } else {
+                self.remove_machine(&name)?;
+            }
+            pruned.push(name);
+        }
+
+        Ok(pruned)
+    }
+
+    pub fn resize_machine(&mut self, name: &str, size: Size, allow_shrink: bool) -> Result<()> {
+        let _lock = self.lock_resource(name, false)?;
+        if self.machine_in_use(name)? {
+            return Err(Error::MachineInUse { name: name.into() });
+        }
+
+        let shrink = size < self.get_machine(name)?.size;
+        if shrink && !allow_shrink {
+            return Err(Error::ShrinkNotAllowed { name: name.into() });
+        }
+
+        self.track("resize", name, || {
+            self.images().resize(&self.machine_path(name), size, shrink)
+        })?;
+
+        if let Some(machine) = self.machines.get_mut(name) {
+            machine.size = size;
+        }
+
+        Ok(())
+    }
+
+    pub fn add_snapshot(
+        &mut self,
+        name: &str,
+        base: &str,
+        from_snapshot: bool,
+        full: bool,
+    ) -> Result<()> {
+        namespace::validate(name)?;
+        if self.snapshots.contains_key(name) {
+            return Err(Error::SnapshotExists { name: name.into() });
+        }
+
+        let (snapshot, backing) = if from_snapshot {
+            let parent = self.get_snapshot(base)?;
+            (
+                Snapshot {
+                    name: name.into(),
+                    base: parent.base.clone(),
+                    parent: (!full).then(|| base.into()),
+                    port: parent.port,
+                    size: parent.size,
+                    cores: parent.cores,
+                    ram: parent.ram,
+                    expiry: None,
+                    created_at: Some(time::now()),
+                    last_started_at: None,
+                    full,
+                    checkpoint: false,
+                    ephemeral: false,
+                    description: None,
+                },
+                self.snapshot_path(base),
+            )
+        } else {
+            let machine = self.get_machine(base)?;
+            (
+                Snapshot {
+                    name: name.into(),
+                    base: base.into(),
+                    parent: None,
+                    port: machine.port,
+                    size: machine.size,
+                    cores: None,
+                    ram: None,
+                    expiry: None,
+                    created_at: Some(time::now()),
+                    last_started_at: None,
+                    full,
+                    checkpoint: false,
+                    ephemeral: false,
+                    description: None,
+                },
+                self.machine_path(base),
+            )
+        };
+
+        let path = self.snapshot_path(name);
+        self.track("snapshot", name, || {
+            if full {
+                return self
+                    .images()
+                    .convert(name, &backing, ImageFormat::Qcow2, &path, false);
+            }
+
+            self.images()
+                .snapshot(name, &backing, ImageFormat::Qcow2, &path)
+        })?;
+
+        self.snapshots.insert(name.into(), snapshot);
+        Ok(())
+    }
+
+    /// Gives a snapshot its own port, so it can run next to its base, and the cores and RAM it
+    /// starts with. `None` leaves a setting be.
+    pub fn set_snapshot_resources(
+        &mut self,
+        name: &str,
+        port: Option<u16>,
+        cores: Option<usize>,
+        ram: Option<Size>,
+    ) -> Result<()> {
+        let snapshot = self
+            .snapshots
+            .get_mut(name)
+            .ok_or(Error::InvalidSnapshot { name: name.into() })?;
+        snapshot.port = port.unwrap_or(snapshot.port);
+        snapshot.cores = cores.or(snapshot.cores);
+        snapshot.ram = ram.or(snapshot.ram);
+        Ok(())
+    }
+
+    /// Sets the cores and RAM a virtual machine starts with. `None` leaves a setting be.
+    pub fn set_machine_resources(
+        &mut self,
+        name: &str,
+        cores: Option<usize>,
+        ram: Option<Size>,
+    ) -> Result<()> {
+        let machine = self
+            .machines
+            .get_mut(name)
+            .ok_or(Error::InvalidMachine { name: name.into() })?;
+        machine.cores = cores.or(machine.cores);
+        machine.ram = ram.or(machine.ram);
+        Ok(())
+    }
+
+    /// Cores and RAM to start with: those asked for, else those of the snapshot or virtual
+    /// machine, else the defaults.
+    pub fn start_resources(
+        &self,
+        name: &str,
+        snapshot: bool,
+        cores: Option<usize>,
+        ram: Option<Size>,
+    ) -> Result<(usize, Size)> {
+        let defaults = StartOptions::default();
+        let (own_cores, own_ram) = if snapshot {
+            let snapshot = self.get_snapshot(name)?;
+            (snapshot.cores, snapshot.ram)
+        } else {
+            let machine = self.get_machine(name)?;
+            (machine.cores, machine.ram)
+        };
+        Ok((
+            cores.or(own_cores).unwrap_or(defaults.cores),
+            ram.or(own_ram).unwrap_or(defaults.ram),
+        ))
+    }
+
+    pub fn get_snapshot(&self, name: &str) -> Result<&Snapshot> {
+        self.snapshots
+            .get(name)
+            .ok_or(Error::InvalidSnapshot { name: name.into() })
+    }
+
+    /// Stamps the machine or snapshot and its attached disks as started now, returning what they
+    /// were stamped with before.
+    pub fn mark_started(&mut self, name: &str, snapshot: bool, disks: &[String]) -> Result<Stamps> {
+        let now = Some(time::now());
+        let mut stamps = Stamps {
+            name: name.into(),
+            snapshot,
+            at: now,
+            previous: mem::replace(self.last_started_at(name, snapshot)?, now),
+            disks: Vec::new(),
+        };
+        for disk in disks {
+            let disk_started_at = &mut self
+                .disks
+                .get_mut(disk)
+                .ok_or(Error::InvalidDisk { name: disk.into() })?
+                .last_started_at;
+            stamps
+                .disks
+                .push((disk.clone(), mem::replace(disk_started_at, now)));
+        }
+        Ok(stamps)
+    }
+
+    /// Puts back the stamps `mark_started` replaced once the start failed, leaving those of
+    /// anything started again since.
+    pub fn unmark_started(&mut self, stamps: Stamps) -> Result<()> {
+        let last_started_at = self.last_started_at(&stamps.name, stamps.snapshot)?;
+        if *last_started_at == stamps.at {
+            *last_started_at = stamps.previous;
+        }
+        for (name, previous) in stamps.disks {
+            if let Some(disk) = self
+                .disks
+                .get_mut(&name)
+                .filter(|disk| disk.last_started_at == stamps.at)
+            {
+                disk.last_started_at = previous;
+            }
+        }
+        Ok(())
+    }
+
+    fn last_started_at(&mut self, name: &str, snapshot: bool) -> Result<&mut Option<u64>> {
+        Ok(if snapshot {
+            &mut self
+                .snapshots
+                .get_mut(name)
+                .ok_or(Error::InvalidSnapshot { name: name.into() })?
+                .last_started_at
+        } else {
+            &mut self
+                .machines
+                .get_mut(name)
+                .ok_or(Error::InvalidMachine { name: name.into() })?
+                .last_started_at
+        })
+    }
+
+    /// Snapshots whose backing chain includes the given machine or snapshot.
+    pub fn linked_snapshots(&self, name: &str, snapshot: bool) -> Vec<&Snapshot> {
+        self.snapshots
+            .values()
+            .filter(|candidate| self.backed_by(candidate, name, snapshot))
+            .collect()
+    }
+
+    fn backed_by(&self, candidate: &Snapshot, name: &str, snapshot: bool) -> bool {
+        if candidate.full {
+            return false;
+        }
+        match &candidate.parent {
+            Some(parent) if snapshot && parent == name => true,
+            Some(parent) => self
+                .snapshots
+                .get(parent)
+                .is_some_and(|parent| self.backed_by(parent, name, snapshot)),
+            None => !snapshot && candidate.base == name,
+        }
+    }
+
+    fn backing_path(&self, snapshot: &Snapshot) -> PathBuf {
+        match &snapshot.parent {
+            Some(parent) => self.snapshot_path(parent),
+            None => self.machine_path(&snapshot.base),
+        }
+    }
+
+    /// Takes a full snapshot of a virtual machine before a risky operation, so that it can be
+    /// rolled back, then removes its oldest checkpoints beyond the number to keep.
+    pub fn checkpoint(&mut self, name: &str) -> Result<String> {
+        self.get_machine(name)?;
+        let stamp = format!("{name}-checkpoint-{}", time::now());
+        let checkpoint = iter::once(stamp.clone())
+            .chain((2..).map(|n| format!("{stamp}-{n}")))
+            .find(|checkpoint| !self.snapshots.contains_key(checkpoint))
+            .unwrap_or(stamp);
+        if let Err(err) = self.add_snapshot(&checkpoint, name, false, true) {
+            notify::send(
+                &self.notifications(name),
+                &format!("failed to checkpoint {name}: {err}"),
+            );
+            return Err(err);
+        }
+        if let Some(snapshot) = self.snapshots.get_mut(&checkpoint) {
+            snapshot.checkpoint = true;
+        }
+
+        let checkpoints = self
+            .checkpoints(name)
+            .iter()
+            .map(|snapshot| snapshot.name.clone())
+            .collect::<Vec<_>>();
+        let excess = checkpoints
+            .len()
+            .saturating_sub(self.config.checkpoints.keep());
+        for old in &checkpoints[..excess] {
+            // Checkpoints someone started or snapshotted are kept until they are done with them.
+            if self.snapshot_in_use(old)? || !self.linked_snapshots(old, true).is_empty() {
+                continue;
+            }
+            self.remove_snapshot(old)?;
+        }
+
+        Ok(checkpoint)
+    }
+
+    /// Checkpoints of a virtual machine, oldest first.
+    pub fn checkpoints(&self, name: &str) -> Vec<&Snapshot> {
+        let mut checkpoints = self
+            .snapshots
+            .values()
+            .filter(|snapshot| snapshot.checkpoint && snapshot.base == name)
+            .collect::<Vec<_>>();
+        checkpoints.sort_by_key(|snapshot| snapshot.created_at);
+        checkpoints
+    }
+
+    /// Restores a virtual machine to a checkpoint, keeping its settings.
+    pub fn rollback(&mut self, name: &str, checkpoint: &str) -> Result<()> {
+        if !self
+            .checkpoints(name)
+            .iter()
+            .any(|snapshot| snapshot.name == checkpoint)
+        {
+            return Err(Error::InvalidCheckpoint {
+                name: checkpoint.into(),
+            });
+        }
+        let path = self.snapshot_path(checkpoint);
+        let provisioned_at = self.get_machine(name)?.provisioned_at;
+        self.rebuild_machine(name, Some((&path, ImageFormat::Qcow2)), false)?;
+        // Unlike a rebuilt machine, one rolled back to a checkpoint isn't fresh.
+        if let Some(machine) = self.machines.get_mut(name) {
+            machine.provisioned_at = provisioned_at;
+        }
+        Ok(())
+    }
+
+    /// Bytes the guest wrote to a linked snapshot since it was taken, or `None` for a full copy,
+    /// which shares nothing with its base.
+    pub fn snapshot_delta(&self, name: &str) -> Result<Option<u64>> {
+        let snapshot = self.get_snapshot(name)?;
+        if snapshot.full {
+            return Ok(None);
+        }
+        self.images()
+            .allocated(&self.snapshot_path(&snapshot.name))
+            .map(Some)
+    }
+
+    /// The snapshots a snapshot was taken from, nearest first, ending with its base machine.
+    pub fn snapshot_ancestors(&self, name: &str) -> Result<Vec<String>> {
+        let mut snapshot = self.get_snapshot(name)?;
+        let mut ancestors = Vec::new();
+        while let Some(parent) = &snapshot.parent {
+            ancestors.push(parent.clone());
+            snapshot = self.get_snapshot(parent)?;
+        }
+        ancestors.push(snapshot.base.clone());
+        Ok(ancestors)
+    }
+
+    /// Whether anything a snapshot is backed by was written to after the snapshot was taken, or
+    /// `None` if the snapshot predates creation times being recorded.
+    pub fn snapshot_stale(&self, name: &str) -> Result<Option<bool>> {
+        let snapshot = self.get_snapshot(name)?;
+        let Some(created) = snapshot.created_at else {
+            return Ok(None);
+        };
+        if snapshot.full {
+            return Ok(Some(false));
+        }
+
+        let modified = fs::metadata(self.backing_path(snapshot))
+            .map(|metadata| metadata.mtime() as u64)
+            .unwrap_or_default();
+        if modified > created {
+            return Ok(Some(true));
+        }
+
+        match &snapshot.parent {
+            Some(parent) => self.snapshot_stale(parent),
+            None => Ok(Some(false)),
+        }
+    }
+
+    pub fn remove_snapshot(&mut self, name: &str) -> Result<()> {
+        let _lock = self.lock_resource(name, true)?;
+        self.record_exit(name, true)?;
+        if self.snapshot_in_use(name)? {
+            return Err(Error::SnapshotInUse { name: name.into() });
+        }
+        if self
+            .snapshots
+            .values()
+            .any(|snapshot| snapshot.parent.as_deref() == Some(name))
+        {
+            return Err(Error::SnapshotHasChildren { name: name.into() });
+        }
+        let snapshot = self.get_snapshot(name)?.clone();
+        self.trash(Resource::Snapshot(snapshot), &self.snapshot_path(name))?;
+        self.snapshots.remove(name);
+        Ok(())
+    }
+
+    /// Moves the image of a resource being removed into the trash, recording enough to restore it.
+    fn trash(&self, resource: Resource, image: &Path) -> Result<()> {
+        let removed_at = time::now();
+        let id = format!(
+            "{removed_at}-{}-{}",
+            resource.kind(),
+            namespace::encode(resource.name())
+        );
+        if self.remote.is_some() || image.exists() {
+            self.rename_image(image, &self.trash_image_path(&id))?;
+        }
+        Trashed {
+            id: id.clone(),
+            removed_at,
+            resource,
+        }
+        .save(self.trash_record_path(&id))
+    }
+
+    /// Everything in the trash, oldest first.
+    pub fn trashed(&self) -> Result<Vec<Trashed>> {
+        let Ok(entries) = fs::read_dir(path![self.path / TRASH_DIR_PATH]) else {
+            return Ok(Vec::new());
+        };
+        let mut trashed = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) == Some("json") {
+                trashed.push(Trashed::load(path)?);
+            }
+        }
+        trashed.sort_by_key(|trashed| trashed.removed_at);
+        Ok(trashed)
+    }
+
+    /// Puts the most recently removed resource with the given name back, or the most recently
+    /// removed resource of all if no name is given. Returns the name of what was restored.
+    pub fn restore_trashed(&mut self, name: Option<&str>) -> Result<String> {
+        let trashed = self
+            .trashed()?
+            .into_iter()
+            .rev()
+            .find(|trashed| name.is_none_or(|name| trashed.resource.name() == name))
+            .ok_or_else(|| match name {
+                Some(name) => Error::InvalidTrash { name: name.into() },
+                None => Error::TrashEmpty,
+            })?;
+
+        let name = trashed.resource.name().to_string();
+        let image = match &trashed.resource {
+            Resource::Disk(_) if self.disks.contains_key(&name) => {
+                return Err(Error::DiskExists { name });
+            }
+            Resource::Machine(_) if self.machines.contains_key(&name) => {
+                return Err(Error::MachineExists { name });
+            }
+            Resource::Snapshot(_) if self.snapshots.contains_key(&name) => {
+                return Err(Error::SnapshotExists { name });
+            }
+            Resource::Disk(_) => self.disk_path(&name),
+            Resource::Machine(_) => self.machine_path(&name),
+            Resource::Snapshot(_) => self.snapshot_path(&name),
+        };
+
+        let trashed_image = self.trash_image_path(&trashed.id);
+        if self.remote.is_some() || trashed_image.exists() {
+            self.rename_image(&trashed_image, &image)?;
+        }
+        fs::remove_file(self.trash_record_path(&trashed.id))?;
+
+        match trashed.resource {
+            Resource::Disk(disk) => {
+                self.disks.insert(name.clone(), disk);
+            }
+            Resource::Machine(machine) => {
+                self.machines.insert(name.clone(), *machine);
+            }
+            Resource::Snapshot(snapshot) => {
+                self.snapshots.insert(name.clone(), snapshot);
+            }
+        }
+        Ok(name)
+    }
+
+    /// Permanently deletes trashed resources, either all of them or those past the retention
+    /// window. Returns the names of what was deleted.
+    pub fn empty_trash(&self, all: bool) -> Result<Vec<String>> {
+        let retention = self.config.trash.retention().as_secs();
+        let mut emptied = Vec::new();
+        for trashed in self.trashed()? {
+            if !all && time::now().saturating_sub(trashed.removed_at) < retention {
+                continue;
+            }
+            self.remove_image(&self.trash_image_path(&trashed.id));
+            fs::remove_file(self.trash_record_path(&trashed.id))?;
+            emptied.push(trashed.resource.name().to_string());
+        }
+        Ok(emptied)
+    }
+
+    pub fn add_probe(&mut self, machine: &str, name: &str, probe: Probe) -> Result<()> {
+        let machine = self
+            .machines
+            .get_mut(machine)
+            .ok_or(Error::InvalidMachine {
+                name: machine.into(),
+            })?;
+
+        if machine.probes.contains_key(name) {
+            return Err(Error::ProbeExists { name: name.into() });
+        }
+
+        machine.probes.insert(name.into(), probe);
+        Ok(())
+    }
+
+    pub fn remove_probe(&mut self, machine: &str, name: &str) -> Result<()> {
+        self.machines
+            .get_mut(machine)
+            .ok_or(Error::InvalidMachine {
+                name: machine.into(),
+            })?
+            .probes
+            .remove(name)
+            .ok_or(Error::InvalidProbe { name: name.into() })?;
+        Ok(())
+    }
+
+    pub fn add_channel(&mut self, machine: &str, name: &str) -> Result<()> {
+        // Names become device paths in the guest; the guest agent's port is taken.
+        if name.is_empty()
+            || name == AGENT_CHANNEL
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+        {
+            return Err(Error::InvalidChannel { name: name.into() });
+        }
+        let machine = self
+            .machines
+            .get_mut(machine)
+            .ok_or(Error::InvalidMachine {
+                name: machine.into(),
+            })?;
+        if machine.channels.iter().any(|channel| channel == name) {
+            return Err(Error::ChannelExists { name: name.into() });
+        }
+        machine.channels.push(name.into());
+        Ok(())
+    }
+
+    pub fn remove_channel(&mut self, machine: &str, name: &str) -> Result<()> {
+        let machine = self
+            .machines
+            .get_mut(machine)
+            .ok_or(Error::InvalidMachine {
+                name: machine.into(),
+            })?;
+        let len = machine.channels.len();
+        machine.channels.retain(|channel| channel != name);
+        if machine.channels.len() == len {
+            return Err(Error::InvalidChannel { name: name.into() });
+        }
+        Ok(())
+    }
+
+    /// The channels of a virtual machine or snapshot, with the host socket each is exposed on.
+    pub fn channels(&self, name: &str, snapshot: bool) -> Result<Vec<(String, PathBuf)>> {
+        Ok(self
+            .base_machine(name, snapshot)?
+            .channels
+            .iter()
+            .map(|channel| (channel.clone(), self.channel_path(name, snapshot, channel)))
+            .collect())
+    }
+
+    pub fn probes(&self, name: &str, snapshot: bool) -> Result<&BTreeMap<String, Probe>> {
+        Ok(&self.base_machine(name, snapshot)?.probes)
+    }
+
+    fn base_machine(&self, name: &str, snapshot: bool) -> Result<&Machine> {
+        if snapshot {
+            self.get_machine(&self.get_snapshot(name)?.base)
+        } else {
+            self.get_machine(name)
+        }
+    }
+
+    pub fn timeouts(&self, name: &str, snapshot: bool) -> Result<Timeouts> {
+        Ok(self
+            .base_machine(name, snapshot)?
+            .timeouts
+            .or(&self.config.timeouts))
+    }
+
+    pub fn retry(&self, name: &str, snapshot: bool) -> Result<Retry> {
+        Ok(self
+            .base_machine(name, snapshot)?
+            .retry
+            .or(&self.config.retry))
+    }
+
+    pub fn set_machine_timeouts(&mut self, name: &str, timeouts: Timeouts) -> Result<()> {
+        let machine = self
+            .machines
+            .get_mut(name)
+            .ok_or(Error::InvalidMachine { name: name.into() })?;
+        machine.timeouts = timeouts.or(&machine.timeouts);
+        Ok(())
+    }
+
+    /// Notification settings of a virtual machine or the machine behind a snapshot, falling back
+    /// to the global ones for anything else.
+    fn notifications(&self, name: &str) -> Notifications {
+        let machine = self.machines.get(name).or_else(|| {
+            self.snapshots
+                .get(name)
+                .and_then(|snapshot| self.machines.get(&snapshot.base))
+        });
+        match machine {
+            Some(machine) => machine.notifications.or(&self.config.notifications),
+            None => self.config.notifications.clone(),
+        }
+    }
+
+    pub fn set_machine_notifications(
+        &mut self,
+        name: &str,
+        notifications: Notifications,
+    ) -> Result<()> {
+        let machine = self
+            .machines
+            .get_mut(name)
+            .ok_or(Error::InvalidMachine { name: name.into() })?;
+        machine.notifications = notifications.or(&machine.notifications);
+        Ok(())
+    }
+
+    pub fn set_machine_retry(&mut self, name: &str, retry: Retry) -> Result<()> {
+        let machine = self
+            .machines
+            .get_mut(name)
+            .ok_or(Error::InvalidMachine { name: name.into() })?;
+        machine.retry = retry.or(&machine.retry);
+        Ok(())
+    }
+
+    pub fn set_machine_firmware(&mut self, name: &str, firmware: FirmwareMode) -> Result<()> {
+        self.machines
+            .get_mut(name)
+            .ok_or(Error::InvalidMachine { name: name.into() })?
+            .firmware = firmware;
+        Ok(())
+    }
+
+    /// Records what the guest runs, or forgets it with `None`.
+    pub fn set_machine_os(&mut self, name: &str, os: Option<GuestOs>) -> Result<()> {
+        self.machines
+            .get_mut(name)
+            .ok_or(Error::InvalidMachine { name: name.into() })?
+            .os = os;
+        Ok(())
+    }
+
+    pub fn set_machine_input(&mut self, name: &str, input: InputDevice) -> Result<()> {
+        self.machines
+            .get_mut(name)
+            .ok_or(Error::InvalidMachine { name: name.into() })?
+            .input = input;
+        Ok(())
+    }
+
+    pub fn set_machine_provision(&mut self, name: &str, steps: Vec<String>) -> Result<()> {
+        self.machines
+            .get_mut(name)
+            .ok_or(Error::InvalidMachine { name: name.into() })?
+            .provision = steps;
+        Ok(())
+    }
+
+    /// Whether a virtual machine has provisioning steps that haven't run yet.
+    pub fn needs_provisioning(&self, name: &str) -> Result<bool> {
+        let machine = self.get_machine(name)?;
+        Ok(!machine.provision.is_empty() && machine.provisioned_at.is_none())
+    }
+
+    pub fn mark_provisioned(&mut self, name: &str) -> Result<()> {
+        self.machines
+            .get_mut(name)
+            .ok_or(Error::InvalidMachine { name: name.into() })?
+            .provisioned_at = Some(time::now());
+        Ok(())
+    }
+
+    /// Runs the provisioning steps of a running virtual machine over SSH in order, stopping at
+    /// the first that fails.
+    pub fn provision(&self, name: &str, username: Option<String>) -> Result<()> {
+        let steps = self.get_machine(name)?.provision.clone();
+        self.wait_ssh(name, false)?;
+        self.track("provision", name, || {
+            for step in &steps {
+                let status = self
+                    .step_command(name, false, username.clone(), &Step::parse(step))?
+                    .traced()?
+                    .spawn()?
+                    .wait()?;
+                if !status.success() {
+                    return Err(Error::ProvisionFailed {
+                        name: name.into(),
+                        step: step.clone(),
+                        code: status.code().unwrap_or(-1),
+                    });
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Runs a script or command in a running machine, killing it if it outlasts `timeout`.
+    pub fn run_step(
+        &self,
+        name: &str,
+        snapshot: bool,
+        username: Option<String>,
+        step: &Step,
+        timeout: Option<Duration>,
+    ) -> Result<ExitStatus> {
+        self.wait_ssh(name, snapshot)?;
+        let mut child = self
+            .step_command(name, snapshot, username, step)?
+            .traced()?
+            .spawn()?;
+        let Some(timeout) = timeout else {
+            return Ok(child.wait()?);
+        };
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(status);
+            }
+            if Instant::now() >= deadline {
+                child.kill()?;
+                child.wait()?;
+                return Err(Error::JobTimeout { name: name.into() });
+            }
+            thread::sleep(PROBE_INTERVAL / 10);
+        }
+    }
+
+    /// An ssh command running a step, with scripts piped into `sh -s`.
+    fn step_command(
+        &self,
+        name: &str,
+        snapshot: bool,
+        username: Option<String>,
+        step: &Step,
+    ) -> Result<Command> {
+        let mut cmd = Command::new("ssh");
+        cmd.args(["-p", &format!("{}", self.port(name, snapshot)?)])
+            .args(["-o", "StrictHostKeyChecking=no"])
+            .args(["-o", "UserKnownHostsFile=/dev/null"])
+            .args(self.identity_args(name, snapshot)?)
+            .arg(format!(
+                "{}@localhost",
+                self.ssh_user(name, snapshot, username)?
+            ))
+            .arg("--");
+        match step {
+            Step::Script(path) => {
+                cmd.args(["sh", "-s"]).stdin(File::open(path).at(path)?);
+            }
+            Step::Command(command) => {
+                cmd.arg(command);
+            }
+        }
+        Ok(cmd)
+    }
+
+    pub fn set_machine_restart(&mut self, name: &str, restart: RestartPolicy) -> Result<()> {
+        self.machines
+            .get_mut(name)
+            .ok_or(Error::InvalidMachine { name: name.into() })?
+            .restart = restart;
+        Ok(())
+    }
+
+    /// Overrides the QEMU and wrapper a virtual machine runs with, where empty strings go back
+    /// to the configured ones and `None` leaves them be.
+    pub fn set_machine_binaries(
+        &mut self,
+        name: &str,
+        qemu: Option<String>,
+        wrapper: Option<String>,
+    ) -> Result<()> {
+        let machine = self
+            .machines
+            .get_mut(name)
+            .ok_or(Error::InvalidMachine { name: name.into() })?;
+        if let Some(qemu) = qemu {
+            machine.qemu = Some(qemu).filter(|qemu| !qemu.is_empty());
+        }
+        if let Some(wrapper) = wrapper {
+            let wrapper = wrapper
+                .split_whitespace()
+                .map(Into::into)
+                .collect::<Vec<_>>();
+            machine.wrapper = Some(wrapper).filter(|wrapper| !wrapper.is_empty());
+        }
+        Ok(())
+    }
+
+    /// Records the digest of a verified source image, keeping the previous one when unverified.
+    pub fn set_machine_sha256(&mut self, name: &str, sha256: Option<String>) -> Result<()> {
+        let machine = self
+            .machines
+            .get_mut(name)
+            .ok_or(Error::InvalidMachine { name: name.into() })?;
+        if sha256.is_some() {
+            machine.sha256 = sha256;
+        }
+        Ok(())
+    }
+
+    /// Sets the description of a disk, or clears it with `None`.
+    pub fn set_disk_description(&mut self, name: &str, description: Option<String>) -> Result<()> {
+        self.disks
+            .get_mut(name)
+            .ok_or(Error::InvalidDisk { name: name.into() })?
+            .description = description;
+        Ok(())
+    }
+
+    pub fn set_machine_description(
+        &mut self,
+        name: &str,
+        description: Option<String>,
+    ) -> Result<()> {
+        self.machines
+            .get_mut(name)
+            .ok_or(Error::InvalidMachine { name: name.into() })?
+            .description = description;
+        Ok(())
+    }
+
+    pub fn set_snapshot_description(
+        &mut self,
+        name: &str,
+        description: Option<String>,
+    ) -> Result<()> {
+        self.snapshots
+            .get_mut(name)
+            .ok_or(Error::InvalidSnapshot { name: name.into() })?
+            .description = description;
+        Ok(())
+    }
+
+    pub fn set_disk_sha256(&mut self, name: &str, sha256: Option<String>) -> Result<()> {
+        let disk = self
+            .disks
+            .get_mut(name)
+            .ok_or(Error::InvalidDisk { name: name.into() })?;
+        if sha256.is_some() {
+            disk.sha256 = sha256;
+        }
+        Ok(())
+    }
+
+    /// Checks an ISO or image against its expected digest before it is used, returning the digest.
+    pub fn verify(&self, name: &str, path: &Path, checksum: &Checksum) -> Result<String> {
+        let expected = checksum.expected(path)?;
+        let actual = self.track("verify", name, || match &self.remote {
+            Some(_) => {
+                let output = self.command("sha256sum").arg(path).traced()?.output()?;
+                if !output.status.success() {
+                    return Err(Error::CommandFailed {
+                        command: "sha256sum".into(),
+                        stderr: String::from_utf8_lossy(&output.stderr).trim().into(),
+                    });
+                }
+                String::from_utf8_lossy(&output.stdout)
+                    .split_whitespace()
+                    .next()
+                    .map(String::from)
+                    .ok_or(Error::InvalidImage { path: path.into() })
+            }
+            None => checksum::sha256(path, |fraction| {
+                self.progress.emit(&Event::Progress {
+                    operation: "verify",
+                    name,
+                    percent: fraction * 100.0,
+                })
+            }),
+        })?;
+
+        if actual != expected {
+            return Err(Error::ChecksumMismatch {
+                path: path.into(),
+                expected,
+                actual,
+            });
+        }
+        Ok(actual)
+    }
+
+    pub fn set_machine_meta(&mut self, name: &str, key: &str, value: &str) -> Result<()> {
+        self.machines
+            .get_mut(name)
+            .ok_or(Error::InvalidMachine { name: name.into() })?
+            .meta
+            .insert(key.into(), value.into());
+        Ok(())
+    }
+
+    pub fn unset_machine_meta(&mut self, name: &str, key: &str) -> Result<()> {
+        self.machines
+            .get_mut(name)
+            .ok_or(Error::InvalidMachine { name: name.into() })?
+            .meta
+            .remove(key)
+            .ok_or(Error::InvalidMetaKey {
+                name: name.into(),
+                key: key.into(),
+            })?;
+        Ok(())
+    }
+
+    pub fn machine_meta(&self, name: &str, key: &str) -> Result<&str> {
+        self.get_machine(name)?
+            .meta
+            .get(key)
+            .map(String::as_str)
+            .ok_or(Error::InvalidMetaKey {
+                name: name.into(),
+                key: key.into(),
+            })
+    }
+
+    /// Changes the network shaping of a machine, merging with what it had unless `clear` is
+    /// set, and applies it right away if the machine is running.
+    pub fn set_machine_shaping(&mut self, name: &str, shaping: Shaping, clear: bool) -> Result<()> {
+        shaping.validate()?;
+        let machine = self
+            .machines
+            .get_mut(name)
+            .ok_or(Error::InvalidMachine { name: name.into() })?;
+        let Network::Tap(iface) = machine.network.clone() else {
+            return Err(Error::ShapingUnsupported { name: name.into() });
+        };
+        machine.shaping = if clear {
+            shaping
+        } else {
+            shaping.or(&machine.shaping)
+        };
+        let shaping = machine.shaping.clone();
+        if self.remote.is_none() && self.machine_in_use(name)? {
+            shaping.apply(&iface)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_machine_network(&mut self, name: &str, network: Network) -> Result<()> {
+        let machine = self
+            .machines
+            .get_mut(name)
+            .ok_or(Error::InvalidMachine { name: name.into() })?;
+        if machine.isolated && !network.is_user() {
+            return Err(Error::IsolatedNetwork { name: name.into() });
+        }
+        machine.network = network;
+        Ok(())
+    }
+
+    /// Cuts a machine off from the host and internet, or reconnects it, from the next start.
+    /// Non-empty `allow` replaces the `host:port`s it may still reach.
+    pub fn set_machine_isolation(
+        &mut self,
+        name: &str,
+        isolated: Option<bool>,
+        allow: Vec<String>,
+    ) -> Result<()> {
+        network::isolation_args(&allow)?;
+        let machine = self
+            .machines
+            .get_mut(name)
+            .ok_or(Error::InvalidMachine { name: name.into() })?;
+        let isolated = isolated.unwrap_or(machine.isolated);
+        if isolated && !machine.network.is_user() {
+            return Err(Error::IsolatedNetwork { name: name.into() });
+        }
+        machine.isolated = isolated;
+        if !allow.is_empty() {
+            machine.allow = allow;
+        }
+        Ok(())
+    }
+
+    pub fn wait_ready(&self, name: &str, snapshot: bool, timeout: Duration) -> Result<()> {
+        let probes = self.probes(name, snapshot)?;
+        let deadline = Instant::now() + timeout;
+
+        self.track("wait", name, || {
+            while !probes.values().all(Probe::ready) {
+                if Instant::now() >= deadline {
+                    return Err(Error::ProbeTimeout { name: name.into() });
+                }
+                thread::sleep(PROBE_INTERVAL);
+            }
+            Ok(())
+        })
+    }
+
+    pub fn child_snapshots<'a>(
+        &'a self,
+        base: &'a str,
+        parent: Option<&'a str>,
+    ) -> impl Iterator<Item = &'a Snapshot> {
+        self.snapshots
+            .values()
+            .filter(move |snapshot| snapshot.base == base && snapshot.parent.as_deref() == parent)
+    }
+
+    pub fn disks(&self) -> Values<'_, String, Disk> {
+        self.disks.values()
+    }
+
+    pub fn machines(&self) -> Values<'_, String, Machine> {
+        self.machines.values()
+    }
+
+    pub fn snapshots(&self) -> Values<'_, String, Snapshot> {
+        self.snapshots.values()
+    }
+
+    pub fn machine(&mut self, name: &str) -> MachineBuilder<'_> {
+        MachineBuilder::new(self, name)
+    }
+
+    /// Built-in presets, overridden and extended by those in the config.
+    pub fn presets(&self) -> BTreeMap<String, Preset> {
+        let mut presets = preset::builtin();
+        presets.extend(self.config.presets.clone());
+        presets
+    }
+
+    pub fn preset(&self, name: &str) -> Result<Preset> {
+        self.presets()
+            .remove(name)
+            .ok_or(Error::MissingPreset { name: name.into() })
+    }
+
+    /// What the host has and how much of it is committed to virtual machines.
+    pub fn host_report(&self) -> Result<HostReport> {
+        if self.remote.is_some() {
+            return Err(Error::RemoteUnsupported {
+                operation: "host".into(),
+            });
+        }
+        let runtimes = self.runtimes()?;
+        let pools = [
+            (
+                "disks",
+                self.disk_dir_path(),
+                self.disks.values().map(|disk| disk.size.as_bytes()).sum(),
+            ),
+            (
+                "machines",
+                self.machine_dir_path(),
+                self.machines
+                    .values()
+                    .map(|machine| machine.size.as_bytes())
+                    .sum(),
+            ),
+            (
+                "snapshots",
+                self.snapshot_dir_path(),
+                self.snapshots
+                    .values()
+                    .map(|snapshot| snapshot.size.as_bytes())
+                    .sum(),
+            ),
+        ];
+        let storage = pools
+            .into_iter()
+            .filter_map(|(kind, path, committed)| {
+                let (total, free) = system::filesystem(&path)?;
+                Some(Storage {
+                    kind,
+                    path,
+                    total,
+                    free,
+                    committed,
+                })
+            })
+            .collect();
+
+        Ok(HostReport {
+            cpus: system::cpus(),
+            memory: system::memory(),
+            accelerator: arch::ACCEL,
+            accelerated: system::accelerated(),
+            versions: self.versions(),
+            committed_cores: runtimes.iter().map(|runtime| runtime.cores).sum(),
+            committed_ram: runtimes.iter().map(|runtime| runtime.ram.as_bytes()).sum(),
+            max_cores: self.config.quotas.max_cores,
+            max_ram: self.config.quotas.max_ram.map(|max_ram| max_ram.as_bytes()),
+            storage,
+        })
+    }
+
+    /// Checks that running another machine with `cores` and `ram` stays within the quotas, and
+    /// warns if it would take more than the host has.
+    fn admit(&self, cores: usize, ram: Size) -> Result<()> {
+        let quotas = &self.config.quotas;
+        let runtimes = self.runtimes()?;
+
+        if self.remote.is_none() {
+            let cpus = system::cpus();
+            if cores > cpus {
+                tracing::warn!("{cores} cores requested, but the host only has {cpus}");
+            }
+            if let Some(memory) =
+                system::memory().filter(|memory| ram.as_bytes() > memory.available)
+            {
+                tracing::warn!(
+                    "{ram} of RAM requested, but only {}M is available on the host",
+                    memory.available >> 20
+                );
+            }
+        }
+
+        if let Some(max_cores) = quotas.max_cores {
+            let used = runtimes.iter().map(|runtime| runtime.cores).sum::<usize>() + cores;
+            if used > max_cores {
+                return Err(Error::QuotaExceeded {
+                    resource: "cores".into(),
+                    used: used.to_string(),
+                    limit: max_cores.to_string(),
+                });
+            }
+        }
+
+        if let Some(max_ram) = quotas.max_ram {
+            let used = runtimes
+                .iter()
+                .map(|runtime| runtime.ram.as_bytes())
+                .sum::<u64>()
+                + ram.as_bytes();
+            if used > max_ram.as_bytes() {
+                return Err(Error::QuotaExceeded {
+                    resource: "ram".into(),
+                    used: Size::bytes(used).to_string(),
+                    limit: max_ram.to_string(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Starts `rhea log-writer` appending what QEMU writes to `output` to the log at `path`,
+    /// timestamped and rotated as configured. It exits once QEMU does.
+    fn spawn_log_writer(&self, path: &Path, output: io::PipeReader) -> Result<()> {
+        let config = &self.config.logs;
+        let mut cmd = Command::new(env::current_exe()?);
+        cmd.arg(logs::WRITER_SUBCOMMAND)
+            .arg(path)
+            .args(["--keep", &config.keep().to_string()]);
+        if config.timestamps {
+            cmd.arg("--timestamps");
+        }
+        if let Some(max_size) = config.max_size {
+            cmd.args(["--max-size", &max_size.to_string()]);
+        }
+        cmd.stdin(output)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        process::detach(&mut cmd);
+        self.processes().spawn(&mut cmd)?;
+        Ok(())
+    }
+
+    pub fn start(&self, name: &str, options: &StartOptions) -> Result<()> {
+        let StartOptions {
+            cores,
+            ram,
+            foreground,
+            snapshot,
+            force,
+            ref disks,
+            ref iso,
+            ref boot,
+            boot_device,
+            wait_qmp,
+            gdb,
+            freeze,
+            ref pcap,
+        } = *options;
+
+        if !force && !self.linked_snapshots(name, snapshot).is_empty() {
+            return Err(Error::BackingInUse { name: name.into() });
+        }
+
+        // A guest that powered off keeps QEMU around until `status` asks it to quit.
+        self.status(name, snapshot)?;
+
+        // A suspended machine can only resume on the hardware it was saved with.
+        let suspended = Runtime::load(self.suspended_path(name, snapshot))?;
+        let (cores, ram, disks) = match &suspended {
+            Some(runtime) => (runtime.cores, runtime.ram, &runtime.disks),
+            None => (cores, ram, disks),
+        };
+
+        let (resource, port) = if snapshot {
+            let snapshot = self.get_snapshot(name)?;
+            (self.snapshot_path(&snapshot.name), snapshot.port)
+        } else {
+            let machine = self.get_machine(name)?;
+            (self.machine_path(&machine.name), machine.port)
+        };
+        let machine = self.base_machine(name, snapshot)?;
+        let network = &machine.network;
+
+        let mut cmd = self.base_qemu_command(&resource, port, machine, cores, ram, boot_device)?;
+        cmd.args(self.base_machine(name, snapshot)?.input.qemu_args());
+
+        if let Some(gdb) = gdb {
+            if self.remote.is_some() {
+                return Err(Error::RemoteUnsupported {
+                    operation: "gdb".into(),
+                });
+            }
+            cmd.args(["-gdb", &format!("tcp:localhost:{gdb}")]);
+            if freeze {
+                cmd.arg("-S");
+            }
+        }
+
+        if let Some(pcap) = pcap {
+            if self.remote.is_some() {
+                return Err(Error::RemoteUnsupported {
+                    operation: "pcap".into(),
+                });
+            }
+            self.machine_versions(machine)
+                .require(Feature::FilterDump)?;
+            cmd.args([
+                "-object",
+                &format!(
+                    "filter-dump,id={PCAP_FILTER},netdev={},file={}",
+                    network.capture_netdev(),
+                    pcap.to_str()
+                        .ok_or(Error::InvalidPath { path: pcap.clone() })?
+                ),
+            ]);
+        }
+
+        for disk in disks {
+            cmd.args([
+                "-drive",
+                &format!(
+                    "file={},format=qcow2,media=disk",
+                    self.disk_path(disk).to_str().ok_or(Error::InvalidPath {
+                        path: self.disk_path(disk)
+                    })?
+                ),
+            ]);
+        }
+
+        cmd.args(
+            boot.or(&self.base_machine(name, snapshot)?.boot)
+                .qemu_args()?,
+        );
+
+        // The drive is always present, so media can be changed over QMP while running.
+        let iso = match iso {
+            Some(iso) => Some(iso),
+            None if !snapshot => self.get_machine(name)?.iso.as_ref(),
+            None => None,
+        };
+        let file = match iso {
+            Some(iso) => format!(
+                ",file={},format=raw",
+                iso.to_str()
+                    .ok_or(Error::InvalidPath { path: iso.clone() })?
+            ),
+            None => String::new(),
+        };
+        cmd.args(["-device", &format!("{},id=scsi0", arch::virtio("scsi"))])
+            .args([
+                "-drive",
+                &format!("if=none,id=cd0,media=cdrom,readonly=on{file}"),
+            ])
+            .args([
+                "-device",
+                &format!(
+                    "scsi-cd,bus=scsi0.0,drive=cd0,id={CDROM_DEVICE}{}",
+                    if boot_device == Some(BootDevice::Cdrom) {
+                        ",bootindex=0"
+                    } else {
+                        ""
+                    }
+                ),
+            ]);
+
+        if suspended.is_some() {
+            cmd.args(["-loadvm", SUSPEND_TAG]);
+        }
+
+        if self.remote.is_none() {
+            let qmp_path = self.qmp_path(name, snapshot);
+            let agent_path = self.agent_path(name, snapshot);
+            // QEMU stays up when the guest powers off, so that QMP can tell it from a crash. In
+            // the foreground its exit status does.
+            if !foreground {
+                cmd.arg("-no-shutdown");
+            }
+            cmd.args([
+                "-qmp",
+                &format!(
+                    "unix:{},server=on,wait=off",
+                    qmp_path.to_str().ok_or(Error::InvalidPath {
+                        path: qmp_path.clone()
+                    })?
+                ),
+            ])
+            .args([
+                "-chardev",
+                &format!(
+                    "socket,id=agent0,path={},server=on,wait=off",
+                    agent_path.to_str().ok_or(Error::InvalidPath {
+                        path: agent_path.clone()
+                    })?
+                ),
+            ])
+            .args(["-device", &arch::virtio("serial")])
+            .args([
+                "-device",
+                &format!("virtserialport,chardev=agent0,name={AGENT_CHANNEL}"),
+            ]);
+
+            for (i, (channel, socket)) in self.channels(name, snapshot)?.into_iter().enumerate() {
+                cmd.args([
+                    "-chardev",
+                    &format!(
+                        "socket,id=channel{i},path={},server=on,wait=off",
+                        socket.to_str().ok_or(Error::InvalidPath {
+                            path: socket.clone()
+                        })?
+                    ),
+                ])
+                .args([
+                    "-device",
+                    &format!("virtserialport,chardev=channel{i},name={channel}"),
+                ]);
+            }
+        } else if !self.channels(name, snapshot)?.is_empty() {
+            tracing::warn!("channels of {name} aren't available on remote hosts");
+        }
+
+        let log_path = self.log_path(name, snapshot);
+        let log_start = fs::metadata(&log_path).map_or(0, |metadata| metadata.len());
+        if !foreground {
+            let console_path = self.console_path(name, snapshot);
+            // Remote guests keep the serial console on stdio, which ends up in the local log.
+            if self.remote.is_none() {
+                // A log rhea writes itself gets the console through QEMU's stdout.
+                let logfile = if self.config.logs.is_managed() {
+                    "/dev/stdout"
+                } else {
+                    log_path.to_str().ok_or(Error::InvalidPath {
+                        path: log_path.clone(),
+                    })?
+                };
+                cmd.args([
+                    "-chardev",
+                    &format!(
+                        "socket,id=serial0,path={},server=on,wait=off,logfile={logfile},logappend=on",
+                        console_path.to_str().ok_or(Error::InvalidPath {
+                            path: console_path.clone()
+                        })?,
+                    ),
+                ])
+                .args(["-serial", "chardev:serial0"]);
+            }
+
+            if self.config.logs.is_managed() {
+                let (output, input) = io::pipe()?;
+                self.spawn_log_writer(&log_path, output)?;
+                cmd.stdin(Stdio::null())
+                    .stdout(input.try_clone()?)
+                    .stderr(input);
+            } else {
+                let log = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&log_path)
+                    .at(&log_path)?;
+                cmd.stdin(Stdio::null())
+                    .stdout(log.try_clone()?)
+                    .stderr(log);
+            }
+            process::detach(&mut cmd);
+        }
+
+        // Disks are locked in name order, so that starts attaching the same ones can't deadlock.
+        let mut locks = vec![self.lock_resource(name, snapshot)?];
+        for disk in disks.iter().collect::<BTreeSet<_>>() {
+            locks.push(self.lock_disk(disk)?);
+        }
+
+        self.record_exit(name, snapshot)?;
+        if self.runtime(name, snapshot)?.is_some() {
+            return Err(if snapshot {
+                Error::SnapshotInUse { name: name.into() }
+            } else {
+                Error::MachineInUse { name: name.into() }
+            });
+        }
+        for disk in disks {
+            if self.disk_in_use(disk)? {
+                return Err(Error::DiskInUse { name: disk.into() });
+            }
+        }
+
+        // Admission is host-wide, so that machines started side by side can't both pass the
+        // quota or port checks before either is running.
+        let admission = wait_for_lock(&self.admission_lock_path(), "the host")?;
+        self.ensure_port_free(port)?;
+        if let Some(gdb) = gdb {
+            self.ensure_port_free(gdb)?;
+        }
+
+        // Tap devices for rhea are created up front, so they can be shaped before QEMU opens them.
+        let machine = self.base_machine(name, snapshot)?;
+        if let (Network::Tap(iface), false) = (&machine.network, machine.shaping.is_empty()) {
+            if self.remote.is_some() {
+                return Err(Error::RemoteUnsupported {
+                    operation: "network shaping".into(),
+                });
+            }
+            machine.shaping.apply(iface)?;
+        }
+
+        if !force {
+            self.admit(cores, ram)?;
+        }
+
+        let mut child = self.processes().spawn(&mut cmd)?;
+        let runtime_path = self.runtime_path(name, snapshot);
+        let runtime = Runtime {
+            pid: child.id(),
+            process_started: self.processes().started(child.id()),
+            started_at: time::now(),
+            cores,
+            ram,
+            disks: disks.clone(),
+            gdb,
+            pcap: pcap.clone(),
+            exit: None,
+        };
+        runtime.save(&runtime_path)?;
+        fs::remove_file(self.exited_path(name, snapshot)).ok();
+        fs::remove_file(self.suspended_path(name, snapshot)).ok();
+
+        drop(admission);
+
+        if foreground {
+            drop(locks);
+            self.refresh_hosts()?;
+            let exit = if child.wait()?.success() {
+                ExitReason::Stopped
+            } else {
+                ExitReason::Crashed
+            };
+            let _lock = self.lock_resource(name, snapshot)?;
+            if runtime_path.exists() {
+                Runtime {
+                    exit: Some(exit),
+                    ..runtime
+                }
+                .save(&runtime_path)?;
+            }
+            return self.record_exit(name, snapshot);
+        }
+
+        // The resources stay locked until QEMU is up, so nobody else handles it failing.
+        let timeouts = self.timeouts(name, snapshot)?;
+        let deadline = Instant::now() + timeouts.start_grace();
+        let handshake = wait_qmp && self.remote.is_none();
+        let handshake_deadline = deadline + timeouts.qmp();
+        loop {
+            if child.try_wait()?.is_some() {
+                fs::remove_file(runtime_path)?;
+                journal::append(
+                    self.journal_path(name, snapshot),
+                    &Boot::new(&runtime, ExitReason::Failed),
+                )?;
+                return Err(Error::StartFailed {
+                    name: name.into(),
+                    message: start_failure(&log_path, log_start),
+                });
+            }
+            let now = Instant::now();
+            if now >= deadline && (!handshake || self.qmp(name, snapshot).is_ok()) {
+                break;
+            }
+            if now >= handshake_deadline {
+                // A QEMU that never answers is of no use, and would hold on to the port.
+                self.processes().kill(child.id())?;
+                child.wait()?;
+                fs::remove_file(runtime_path)?;
+                journal::append(
+                    self.journal_path(name, snapshot),
+                    &Boot::new(&runtime, ExitReason::Failed),
+                )?;
+                return Err(Error::StartFailed {
+                    name: name.into(),
+                    message: format!(
+                        "no QMP handshake within {}s",
+                        (timeouts.start_grace() + timeouts.qmp()).as_secs()
+                    ),
+                });
+            }
+            thread::sleep(PROBE_INTERVAL / 10);
+        }
+
+        self.refresh_hosts()
+    }
+
+    /// How often `sample_usage` should be called.
+    pub fn usage_interval(&self) -> Duration {
+        self.config.usage.interval()
+    }
+
+    /// Records what every running machine and snapshot uses right now, see `usage::Sample`.
+    pub fn sample_usage(&self) -> Result<()> {
+        if self.remote.is_some() {
+            return Err(Error::RemoteUnsupported {
+                operation: "usage".into(),
+            });
+        }
+        let resources = self
+            .machines
+            .keys()
+            .map(|name| (name, false))
+            .chain(self.snapshots.keys().map(|name| (name, true)));
+        for (name, snapshot) in resources {
+            let Some(runtime) = self.runtime(name, snapshot)? else {
+                continue;
+            };
+            let Some((cpu_ms, rss)) = usage::measure(runtime.pid) else {
+                continue;
+            };
+            let path = if snapshot {
+                self.snapshot_path(name)
+            } else {
+                self.machine_path(name)
+            };
+            let sample = Sample {
+                at: time::now(),
+                cpu_ms,
+                rss,
+                disk: allocated(&path),
+                ram: runtime.ram.as_bytes(),
+                cores: runtime.cores as u32,
+            };
+            usage::record(
+                self.usage_path(name, snapshot),
+                sample,
+                self.config.usage.samples(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Summarizes the usage samples of a machine or snapshot taken in the last `since`, or ever.
+    pub fn usage(
+        &self,
+        name: &str,
+        snapshot: bool,
+        since: Option<Duration>,
+    ) -> Result<Option<Summary>> {
+        let size = if snapshot {
+            self.get_snapshot(name)?.size
+        } else {
+            self.get_machine(name)?.size
+        };
+        let cutoff = since.map_or(0, |since| time::now().saturating_sub(since.as_secs()));
+        let samples = usage::load(self.usage_path(name, snapshot))?
+            .into_iter()
+            .filter(|sample| sample.at >= cutoff)
+            .collect::<Vec<_>>();
+        // Allow for a late sample before treating the gap as downtime.
+        let gap = self.usage_interval().as_secs() * 2;
+        Ok(usage::summarize(&samples, gap, size.as_bytes()))
+    }
+
+    /// Starts capturing the traffic of a running machine's NIC to `file`.
+    pub fn start_pcap(&self, name: &str, snapshot: bool, file: &Path) -> Result<()> {
+        let mut runtime = self.running_runtime(name, snapshot)?;
+        if runtime.pcap.is_some() {
+            return Err(Error::CaptureActive { name: name.into() });
+        }
+
+        let machine = self.base_machine(name, snapshot)?;
+        let versions = self.machine_versions(machine);
+        versions.require(Feature::FilterDump)?;
+        let network = &machine.network;
+        let props = json!({ "netdev": network.capture_netdev(), "file": file });
+        let arguments = if versions.supports(Feature::FlatObjectAdd) {
+            let mut arguments = props;
+            arguments["qom-type"] = json!("filter-dump");
+            arguments["id"] = json!(PCAP_FILTER);
+            arguments
+        } else {
+            json!({ "qom-type": "filter-dump", "id": PCAP_FILTER, "props": props })
         };
+        self.qmp(name, snapshot)?.execute("object-add", arguments)?;
+        runtime.pcap = Some(file.into());
+        runtime.save(self.runtime_path(name, snapshot))
+    }
 
-        let mut cmd = self.base_qemu_command(&resource, port, cores, ram)?;
+    /// Stops capturing the traffic of a running machine, returning the file it went to.
+    pub fn stop_pcap(&self, name: &str, snapshot: bool) -> Result<PathBuf> {
+        let mut runtime = self.running_runtime(name, snapshot)?;
+        let file = runtime
+            .pcap
+            .take()
+            .ok_or(Error::CaptureInactive { name: name.into() })?;
 
-        for disk in disks {
-            if self.disk_in_use(disk)? {
-                return Err(Error::DiskInUse { name: disk.into() });
+        self.qmp(name, snapshot)?
+            .execute("object-del", json!({ "id": PCAP_FILTER }))?;
+        runtime.save(self.runtime_path(name, snapshot))?;
+        Ok(file)
+    }
+
+    fn running_runtime(&self, name: &str, snapshot: bool) -> Result<Runtime> {
+        self.runtime(name, snapshot)?.ok_or_else(|| {
+            if snapshot {
+                Error::SnapshotNotInUse { name: name.into() }
+            } else {
+                Error::MachineNotInUse { name: name.into() }
             }
+        })
+    }
 
-            cmd.args([
-                "-drive",
-                &format!(
-                    "file={},format=qcow2,media=disk",
-                    self.disk_path(disk).to_str().ok_or(Error::InvalidPath {
-                        path: self.disk_path(disk)
-                    })?
-                ),
-            ]);
-        }
+    /// Runs gdb, or lldb, connected to the GDB stub of a running machine.
+    pub fn debug(
+        &self,
+        name: &str,
+        snapshot: bool,
+        symbols: Option<&Path>,
+        lldb: bool,
+    ) -> Result<()> {
+        let gdb = self
+            .running_runtime(name, snapshot)?
+            .gdb
+            .ok_or(Error::GdbDisabled { name: name.into() })?;
 
-        if let Some(iso) = iso {
-            cmd.args([
-                "-cdrom",
-                iso.to_str()
-                    .ok_or(Error::InvalidPath { path: iso.clone() })?,
-            ]);
+        let mut cmd = if lldb {
+            let mut cmd = Command::new("lldb");
+            cmd.args(["--arch", env::consts::ARCH])
+                .args(["-o", &format!("gdb-remote localhost:{gdb}")]);
+            cmd
+        } else {
+            let mut cmd = Command::new("gdb");
+            cmd.args(["-ex", &format!("set architecture {}", arch::GDB_ARCH)])
+                .args(["-ex", &format!("target remote localhost:{gdb}")]);
+            cmd
+        };
+        if let Some(symbols) = symbols {
+            cmd.arg(symbols);
         }
+        cmd.traced()?.status()?;
+        Ok(())
+    }
 
-        if !foreground {
-            cmd.stdout(Stdio::null());
+    pub fn console(&self, name: &str, snapshot: bool) -> Result<()> {
+        if self.runtime(name, snapshot)?.is_none() {
+            return Err(if snapshot {
+                Error::SnapshotNotInUse { name: name.into() }
+            } else {
+                Error::MachineNotInUse { name: name.into() }
+            });
+        }
+        if self.remote.is_some() {
+            return Err(Error::RemoteUnsupported {
+                operation: "console".into(),
+            });
         }
+        console::attach(&self.console_path(name, snapshot))
+    }
 
-        let mut child = cmd.spawn()?;
+    pub fn logs(&self, name: &str, snapshot: bool, follow: bool, filter: Filter) -> Result<()> {
+        if snapshot {
+            self.get_snapshot(name)?;
+        } else {
+            self.get_machine(name)?;
+        }
 
-        if foreground {
-            child.wait()?;
+        let path = self.log_path(name, snapshot);
+        if !path.exists() {
+            return Ok(());
         }
 
-        Ok(())
+        logs::print(&path, filter, follow, PROBE_INTERVAL / 4, &mut io::stdout())
     }
 
     pub fn stop(&self, name: &str, snapshot: bool) -> Result<()> {
-        if snapshot && !self.snapshot_in_use(name)? {
-            return Err(Error::SnapshotNotInUse { name: name.into() });
+        if snapshot {
+            if !self.snapshot_in_use(name)? {
+                return Err(Error::SnapshotNotInUse { name: name.into() });
+            }
         } else if !self.machine_in_use(name)? {
             return Err(Error::MachineNotInUse { name: name.into() });
         }
 
-        let resource = if snapshot {
-            self.snapshot_path(name)
+        let Some(mut runtime) = self.runtime(name, snapshot)? else {
+            return Err(Error::MachineNotInUse { name: name.into() });
+        };
+        runtime.exit = Some(ExitReason::Stopped);
+        runtime.save(self.runtime_path(name, snapshot))?;
+
+        let timeout = self.timeouts(name, snapshot)?.shutdown();
+        let retry = self.retry(name, snapshot)?;
+
+        for _ in 0..retry.attempts() {
+            if self.process_alive(&runtime) {
+                self.processes().kill(runtime.pid)?;
+            }
+
+            let deadline = Instant::now() + timeout;
+            while Instant::now() < deadline {
+                if self.runtime(name, snapshot)?.is_none() {
+                    fs::remove_file(self.exited_path(name, snapshot)).ok();
+                    let _lock = self.lock_resource(name, snapshot)?;
+                    return self.record_exit(name, snapshot);
+                }
+                thread::sleep(PROBE_INTERVAL);
+            }
+
+            thread::sleep(retry.delay());
+        }
+
+        Err(Error::ShutdownTimeout { name: name.into() })
+    }
+
+    /// Stops a running machine and starts it again with the cores, RAM and disks it had.
+    pub fn restart(&self, name: &str, snapshot: bool) -> Result<()> {
+        let Some(runtime) = self.runtime(name, snapshot)? else {
+            return Err(if snapshot {
+                Error::SnapshotNotInUse { name: name.into() }
+            } else {
+                Error::MachineNotInUse { name: name.into() }
+            });
+        };
+
+        self.stop(name, snapshot)?;
+        // It was already allowed to run, so linked snapshots and quotas don't stop it coming back.
+        let options = StartOptions::default()
+            .snapshot(snapshot)
+            .force(true)
+            .cores(runtime.cores)
+            .ram(runtime.ram)
+            .disks(runtime.disks);
+        self.start(name, &options)
+    }
+
+    /// Resets a running machine without shutting its guest down.
+    pub fn reset(&self, name: &str, snapshot: bool) -> Result<()> {
+        if self.runtime(name, snapshot)?.is_none() {
+            return Err(if snapshot {
+                Error::SnapshotNotInUse { name: name.into() }
+            } else {
+                Error::MachineNotInUse { name: name.into() }
+            });
+        }
+        self.qmp(name, snapshot)?
+            .execute("system_reset", json!({}))?;
+        Ok(())
+    }
+
+    /// Saves the memory of a running machine into its image and stops it. The next start
+    /// resumes it where it left off.
+    pub fn suspend(&self, name: &str, snapshot: bool) -> Result<()> {
+        let Some(runtime) = self.runtime(name, snapshot)? else {
+            return Err(if snapshot {
+                Error::SnapshotNotInUse { name: name.into() }
+            } else {
+                Error::MachineNotInUse { name: name.into() }
+            });
+        };
+
+        let timeout = self.timeouts(name, snapshot)?.shutdown();
+        let mut qmp = self.qmp(name, snapshot)?;
+        qmp.set_timeout(timeout)?;
+        // Monitor commands report failure as output rather than as an error.
+        let output = qmp.execute(
+            "human-monitor-command",
+            json!({ "command-line": format!("savevm {SUSPEND_TAG}") }),
+        )?;
+        if let Some(message) = output.as_str().filter(|output| !output.trim().is_empty()) {
+            return Err(Error::QmpFailed {
+                command: "savevm".into(),
+                message: message.trim().into(),
+            });
+        }
+        runtime.save(self.suspended_path(name, snapshot))?;
+        Runtime {
+            exit: Some(ExitReason::Suspended),
+            ..runtime
+        }
+        .save(self.runtime_path(name, snapshot))?;
+        qmp.execute("quit", json!({}))?;
+
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if self.runtime(name, snapshot)?.is_none() {
+                fs::remove_file(self.exited_path(name, snapshot)).ok();
+                let _lock = self.lock_resource(name, snapshot)?;
+                return self.record_exit(name, snapshot);
+            }
+            thread::sleep(PROBE_INTERVAL);
+        }
+
+        Err(Error::ShutdownTimeout { name: name.into() })
+    }
+
+    /// Running machines and snapshots, ordered so that each is stopped before whatever backs it.
+    pub fn running(&self) -> Result<Vec<(String, bool)>> {
+        let mut snapshots = Vec::new();
+        for snapshot in self.snapshots.values() {
+            if self.runtime(&snapshot.name, true)?.is_some() {
+                snapshots.push((self.depth(snapshot), snapshot.name.clone()));
+            }
+        }
+        snapshots.sort_by_key(|(depth, _)| Reverse(*depth));
+
+        let mut running = snapshots
+            .into_iter()
+            .map(|(_, name)| (name, true))
+            .collect::<Vec<_>>();
+        for machine in self.machines.values() {
+            if self.runtime(&machine.name, false)?.is_some() {
+                running.push((machine.name.clone(), false));
+            }
+        }
+        Ok(running)
+    }
+
+    /// The virtual machines and snapshots an argument like `web`, `snapshot/web-1`, `project/` or
+    /// `snapshot/ci-*` stands for; bare names refer to snapshots if `snapshot` is set. Names are
+    /// kept as given, for whatever uses them to reject if they don't exist.
+    pub fn resolve(&self, arg: &str, snapshot: bool) -> Result<Vec<ResourceRef>> {
+        let resource = ResourceRef::parse(arg, snapshot);
+        if namespace::is_name(&resource.name) {
+            return Ok(vec![resource]);
+        }
+        let names = if resource.snapshot {
+            self.snapshots.keys().collect::<Vec<_>>()
         } else {
-            self.machine_path(name)
+            self.machines.keys().collect()
         };
+        let matched = namespace::expand(&resource.name, names.into_iter().map(String::as_str));
+        if matched.is_empty() {
+            return Err(Error::NoMatches {
+                pattern: arg.into(),
+            });
+        }
+        Ok(matched
+            .into_iter()
+            .map(|name| ResourceRef {
+                name,
+                snapshot: resource.snapshot,
+            })
+            .collect())
+    }
 
-        let output = PipedCommand::run(format!(
-            "ps aux | grep -v grep | grep {}",
-            resource.to_str().ok_or(Error::InvalidPath {
-                path: resource.clone()
-            })?
-        ))?;
+    /// Orders snapshots so that children come before their parents, e.g. to remove them in turn.
+    pub fn children_first(&self, mut names: Vec<String>) -> Vec<String> {
+        names.sort_by_key(|name| Reverse(self.snapshots.get(name).map(|s| self.depth(s))));
+        names
+    }
+
+    fn depth(&self, snapshot: &Snapshot) -> usize {
+        match &snapshot.parent {
+            Some(parent) => self
+                .snapshots
+                .get(parent)
+                .map_or(1, |parent| self.depth(parent) + 1),
+            None => 1,
+        }
+    }
+
+    fn port(&self, name: &str, snapshot: bool) -> Result<u16> {
+        Ok(if snapshot {
+            self.get_snapshot(name)?.port
+        } else {
+            self.get_machine(name)?.port
+        })
+    }
+
+    pub fn wait_ssh(&self, name: &str, snapshot: bool) -> Result<u16> {
+        let port = self.port(name, snapshot)?;
+        let deadline = Instant::now() + self.timeouts(name, snapshot)?.ssh_wait();
+        while !probe::ssh_ready(port) {
+            if Instant::now() >= deadline {
+                return Err(Error::SshTimeout { name: name.into() });
+            }
+            thread::sleep(PROBE_INTERVAL);
+        }
+        Ok(port)
+    }
+
+    pub fn ssh_host_block(
+        &self,
+        name: &str,
+        snapshot: bool,
+        user: &str,
+        identity: Option<&Path>,
+        proxy_jump: Option<&str>,
+    ) -> Result<String> {
+        let port = self.port(name, snapshot)?;
+        let identity = identity.or(self.base_machine(name, snapshot)?.identity.as_deref());
+        Ok(ssh::host_block(name, port, user, identity, proxy_jump))
+    }
+
+    pub fn write_ssh_config(
+        &self,
+        name: &str,
+        snapshot: bool,
+        user: &str,
+        identity: Option<&Path>,
+        proxy_jump: Option<&str>,
+    ) -> Result<PathBuf> {
+        let path = self.ssh_config_path(name);
+        fs::write(
+            &path,
+            self.ssh_host_block(name, snapshot, user, identity, proxy_jump)?,
+        )?;
+        Ok(path)
+    }
+
+    /// The user to log into a guest as: `username` if given, else the account images of its OS
+    /// come with, else the local user.
+    pub fn ssh_user(&self, name: &str, snapshot: bool, username: Option<String>) -> Result<String> {
+        if let Some(username) = username {
+            return Ok(username);
+        }
+        match self
+            .base_machine(name, snapshot)?
+            .os
+            .and_then(|os| os.default_user())
+        {
+            Some(user) => Ok(user.into()),
+            None => Ok(env::var("USER")?),
+        }
+    }
+
+    pub fn generate_key(&mut self, name: &str, force: bool) -> Result<PathBuf> {
+        self.get_machine(name)?;
+
+        let path = self.key_path(name);
+        if path.exists() {
+            if !force {
+                return Err(Error::KeyExists { name: name.into() });
+            }
+            fs::remove_file(&path)?;
+            fs::remove_file(path.with_extension("pub")).ok();
+        }
+
+        let status = Command::new("ssh-keygen")
+            .arg("-q")
+            .args(["-t", "ed25519"])
+            .args(["-N", ""])
+            .args(["-C", &ssh::host_alias(name)])
+            .arg("-f")
+            .arg(&path)
+            .traced()?
+            .status()?;
+        if !status.success() {
+            return Err(Error::KeyGenerationFailed { name: name.into() });
+        }
+
+        self.machines
+            .get_mut(name)
+            .ok_or(Error::InvalidMachine { name: name.into() })?
+            .identity = Some(path.clone());
+        Ok(path)
+    }
 
-        let pid = str::from_utf8(&output.stdout)
-            .map_err(|_| Error::MachineNotInUse { name: name.into() })?
-            .lines()
-            .next()
-            .ok_or(Error::MachineNotInUse { name: name.into() })?
-            .split_whitespace()
-            .skip(1)
-            .next()
-            .unwrap();
+    /// Copies a public key into the running guest with `ssh-copy-id` and records it as the
+    /// identity of the base machine.
+    pub fn add_key(
+        &mut self,
+        name: &str,
+        snapshot: bool,
+        username: Option<String>,
+        identity: Option<PathBuf>,
+    ) -> Result<()> {
+        let machine = self.base_machine(name, snapshot)?.name.clone();
+        let identity = identity
+            .or_else(|| self.base_machine(name, snapshot).ok()?.identity.clone())
+            .ok_or(Error::MissingKey {
+                name: machine.clone(),
+            })?;
 
-        Command::new("kill").arg(&pid).spawn()?.wait()?;
+        let port = self.wait_ssh(name, snapshot)?;
+        let status = Command::new("ssh-copy-id")
+            .arg("-i")
+            .arg(&identity)
+            .args(["-p", &format!("{port}")])
+            .args(["-o", "StrictHostKeyChecking=no"])
+            .args(["-o", "UserKnownHostsFile=/dev/null"])
+            .arg(format!(
+                "{}@localhost",
+                self.ssh_user(name, snapshot, username)?
+            ))
+            .traced()?
+            .status()?;
+        if !status.success() {
+            return Err(Error::KeyInjectionFailed { name: name.into() });
+        }
 
+        self.machines
+            .get_mut(&machine)
+            .ok_or(Error::InvalidMachine {
+                name: machine.clone(),
+            })?
+            .identity = Some(identity);
         Ok(())
     }
 
-    pub fn connect(
+    fn identity_args(&self, name: &str, snapshot: bool) -> Result<Vec<String>> {
+        Ok(match &self.base_machine(name, snapshot)?.identity {
+            Some(identity) => vec![
+                "-i".into(),
+                identity
+                    .to_str()
+                    .ok_or(Error::InvalidPath {
+                        path: identity.clone(),
+                    })?
+                    .into(),
+            ],
+            None => Vec::new(),
+        })
+    }
+
+    pub fn exec(
         &self,
         name: &str,
+        snapshot: bool,
         username: Option<String>,
-        forward_keys: bool,
+        command: &[String],
+    ) -> Result<ExitStatus> {
+        let port = self.wait_ssh(name, snapshot)?;
+        Ok(Command::new("ssh")
+            .args(["-p", &format!("{port}")])
+            .args(["-o", "StrictHostKeyChecking=no"])
+            .args(["-o", "UserKnownHostsFile=/dev/null"])
+            .args(self.identity_args(name, snapshot)?)
+            .arg(format!(
+                "{}@localhost",
+                self.ssh_user(name, snapshot, username)?
+            ))
+            .arg("--")
+            .args(command)
+            .traced()?
+            .spawn()?
+            .wait()?)
+    }
+
+    pub fn copy_from(
+        &self,
+        name: &str,
         snapshot: bool,
+        username: Option<String>,
+        remote: &str,
+        local: &Path,
     ) -> Result<()> {
-        let port = if snapshot {
-            self.get_snapshot(name)?.port
+        let port = self.wait_ssh(name, snapshot)?;
+        Command::new("scp")
+            .args(["-P", &format!("{port}")])
+            .args(["-o", "StrictHostKeyChecking=no"])
+            .args(["-o", "UserKnownHostsFile=/dev/null"])
+            .args(self.identity_args(name, snapshot)?)
+            .arg("-r")
+            .arg(format!(
+                "{}@localhost:{remote}",
+                self.ssh_user(name, snapshot, username)?
+            ))
+            .arg(local)
+            .traced()?
+            .spawn()?
+            .wait()?;
+        Ok(())
+    }
+
+    pub fn mount(
+        &self,
+        name: &str,
+        snapshot: bool,
+        username: Option<String>,
+        remote: &str,
+        mountpoint: &Path,
+    ) -> Result<()> {
+        let port = self.wait_ssh(name, snapshot)?;
+
+        let mut options = String::from("StrictHostKeyChecking=no,UserKnownHostsFile=/dev/null");
+        if let Some(identity) = &self.base_machine(name, snapshot)?.identity {
+            options += &format!(",IdentityFile={}", identity.display());
+        }
+
+        let status = Command::new("sshfs")
+            .args(["-p", &format!("{port}")])
+            .args(["-o", &options])
+            .arg(format!(
+                "{}@localhost:{remote}",
+                self.ssh_user(name, snapshot, username)?
+            ))
+            .arg(mountpoint)
+            .traced()?
+            .status()?;
+        if !status.success() {
+            return Err(Error::MountFailed {
+                path: mountpoint.into(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn umount(&self, mountpoint: &Path) -> Result<()> {
+        #[cfg(target_os = "linux")]
+        let status = Command::new("fusermount")
+            .arg("-u")
+            .arg(mountpoint)
+            .traced()?
+            .status()?;
+        #[cfg(not(target_os = "linux"))]
+        let status = Command::new("umount").arg(mountpoint).traced()?.status()?;
+
+        if !status.success() {
+            return Err(Error::UnmountFailed {
+                path: mountpoint.into(),
+            });
+        }
+        Ok(())
+    }
+
+    fn qmp(&self, name: &str, snapshot: bool) -> Result<Qmp> {
+        if self.remote.is_some() {
+            return Err(Error::RemoteUnsupported {
+                operation: "qmp".into(),
+            });
+        }
+        Qmp::connect(
+            &self.qmp_path(name, snapshot),
+            self.timeouts(name, snapshot)?.qmp(),
+        )
+    }
+
+    /// Every host port forwarded to a machine or snapshot, with who is holding it.
+    pub fn ports(&self) -> Result<Vec<PortUse>> {
+        let mut uses = Vec::new();
+        // Snapshots share the probes of their base, which can't run at the same time.
+        let mut owners = BTreeMap::<u16, BTreeSet<&str>>::new();
+        let resources = self
+            .machines
+            .values()
+            .map(|machine| (&machine.name, false, machine.port))
+            .chain(
+                self.snapshots
+                    .values()
+                    .map(|snapshot| (&snapshot.name, true, snapshot.port)),
+            );
+        for (name, snapshot, port) in resources {
+            let runtime = self.runtime(name, snapshot)?;
+            let base = self.base_machine(name, snapshot)?;
+            owners.entry(port).or_default().insert(name);
+            for probe in base.probes.values() {
+                owners
+                    .entry(probe.host_port)
+                    .or_default()
+                    .insert(&base.name);
+            }
+
+            let forwards = iter::once(("ssh".to_string(), port, 22)).chain(
+                base.probes
+                    .iter()
+                    .map(|(probe, p)| (probe.clone(), p.host_port, p.guest_port)),
+            );
+            for (forward, port, guest_port) in forwards {
+                let pid = ports::listener(port);
+                let status = match (&runtime, ports::bound(port)) {
+                    (_, false) if runtime.is_none() => PortStatus::Free,
+                    (_, false) => PortStatus::NotListening,
+                    (Some(runtime), true) if pid.is_none_or(|pid| pid == runtime.pid) => {
+                        PortStatus::Ok
+                    }
+                    _ => PortStatus::Conflict,
+                };
+                uses.push(PortUse {
+                    name: name.clone(),
+                    snapshot,
+                    forward,
+                    port,
+                    guest_port,
+                    pid,
+                    status,
+                });
+            }
+        }
+
+        for port_use in &mut uses {
+            if owners[&port_use.port].len() > 1 {
+                port_use.status = PortStatus::Duplicate;
+            }
+        }
+        Ok(uses)
+    }
+
+    /// Addresses of a running guest, from its guest agent if one answers and otherwise from the
+    /// host's DHCP leases and ARP table for the extra NIC.
+    pub fn guest_addresses(&self, name: &str, snapshot: bool) -> Result<Vec<GuestAddress>> {
+        if self.runtime(name, snapshot)?.is_none() {
+            return Err(if snapshot {
+                Error::SnapshotNotInUse { name: name.into() }
+            } else {
+                Error::MachineNotInUse { name: name.into() }
+            });
+        }
+        if self.remote.is_some() {
+            return Err(Error::RemoteUnsupported {
+                operation: "ip".into(),
+            });
+        }
+
+        let from_agent = Qmp::connect_agent(
+            &self.agent_path(name, snapshot),
+            self.timeouts(name, snapshot)?.qmp(),
+        )
+        .and_then(|mut agent| agent.execute("guest-network-get-interfaces", json!({})))
+        .map(|interfaces| ip::from_agent(&interfaces))
+        .unwrap_or_default();
+        if !from_agent.is_empty() {
+            return Ok(from_agent);
+        }
+
+        Ok(ip::from_host(&network::mac_address(
+            self.port(name, snapshot)?,
+        )))
+    }
+
+    /// Writes a hosts file mapping `<name>.<domain>` to each running machine. Machines on the
+    /// user-mode network are reached through their forwarded ports on localhost.
+    pub fn write_hosts(&self) -> Result<PathBuf> {
+        let domain = self.config.dns.domain();
+        let mut hosts = String::from("# Generated by rhea, e.g. for dnsmasq's addn-hosts.\n");
+        for (name, snapshot) in self.running()? {
+            let address = if self.base_machine(&name, snapshot)?.network.is_user() {
+                Some(IpAddr::V4(Ipv4Addr::LOCALHOST))
+            } else {
+                self.guest_addresses(&name, snapshot)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|address| address.address)
+                    .find(IpAddr::is_ipv4)
+            };
+            if let Some(address) = address {
+                hosts.push_str(&format!("{address} {name}.{domain}\n"));
+            }
+        }
+
+        let path = self.hosts_path();
+        fs::write(&path, hosts)?;
+        Ok(path)
+    }
+
+    /// Keeps the hosts file current when DNS publishing is enabled.
+    fn refresh_hosts(&self) -> Result<()> {
+        if self.config.dns.enabled {
+            self.write_hosts()?;
+        }
+        Ok(())
+    }
+
+    /// Inserts an ISO into the CD-ROM drive of a machine, changing the media if it is running.
+    pub fn attach_iso(&mut self, name: &str, iso: &Path) -> Result<()> {
+        let iso = if self.remote.is_some() {
+            iso.to_path_buf()
         } else {
-            self.get_machine(name)?.port
+            iso.canonicalize()?
         };
 
-        let mut cmd = Command::new("ssh");
+        if self.machine_in_use(name)? {
+            self.qmp(name, false)?.execute(
+                "blockdev-change-medium",
+                json!({ "id": CDROM_DEVICE, "filename": iso, "format": "raw" }),
+            )?;
+        }
+
+        self.machines
+            .get_mut(name)
+            .ok_or(Error::InvalidMachine { name: name.into() })?
+            .iso = Some(iso);
+        Ok(())
+    }
 
-        if forward_keys {
-            cmd.arg("-A");
+    pub fn eject_iso(&mut self, name: &str) -> Result<()> {
+        if self.machine_in_use(name)? {
+            self.qmp(name, false)?
+                .execute("eject", json!({ "id": CDROM_DEVICE, "force": true }))?;
         }
 
-        cmd.arg(&format!("-p {}", port))
-            .arg(&format!(
-                "{}@localhost",
-                if let Some(username) = username {
-                    username
-                } else {
-                    env::var("USER")?
-                }
+        self.machines
+            .get_mut(name)
+            .ok_or(Error::InvalidMachine { name: name.into() })?
+            .iso = None;
+        Ok(())
+    }
+
+    pub fn set_machine_boot(&mut self, name: &str, boot: KernelBoot) -> Result<()> {
+        self.machines
+            .get_mut(name)
+            .ok_or(Error::InvalidMachine { name: name.into() })?
+            .boot = boot;
+        Ok(())
+    }
+
+    pub fn set_machine_connect(&mut self, name: &str, options: ConnectOptions) -> Result<()> {
+        self.machines
+            .get_mut(name)
+            .ok_or(Error::InvalidMachine { name: name.into() })?
+            .connect = options;
+        Ok(())
+    }
+
+    /// Connects over SSH, filling in options that weren't given from the machine's defaults.
+    pub fn connect(
+        &self,
+        name: &str,
+        username: Option<String>,
+        snapshot: bool,
+        options: &ConnectOptions,
+    ) -> Result<()> {
+        let port = self.wait_ssh(name, snapshot)?;
+        let options = options.or(&self.base_machine(name, snapshot)?.connect);
+        let identity = if options.identity.is_none() {
+            self.identity_args(name, snapshot)?
+        } else {
+            Vec::new()
+        };
+        let destination = format!("{}@localhost", self.ssh_user(name, snapshot, username)?);
+
+        // Guests forward the UDP port matching their SSH port, so mosh-server is pinned to it.
+        let mut cmd = if options.mosh {
+            let mut cmd = Command::new("mosh");
+            cmd.arg(format!(
+                "--ssh=ssh -p {port} -o StrictHostKeyChecking=no -o UserKnownHostsFile=/dev/null {}",
+                [options.transport_args(), identity].concat().join(" ")
             ))
-            .spawn()?
-            .wait()?;
+            .arg(format!("--port={port}"))
+            .arg(&destination);
+            if let Some(command) = &options.command {
+                cmd.arg("--").args(command.split_whitespace());
+            }
+            cmd
+        } else {
+            let mut cmd = Command::new("ssh");
+            cmd.args(options.args())
+                .args(identity)
+                .arg(format!("-p {}", port))
+                .arg(&destination);
+            if let Some(command) = &options.command {
+                cmd.arg(command);
+            }
+            cmd
+        };
+
+        let retry = self.retry(name, snapshot)?;
+        for _ in 1..retry.attempts() {
+            if cmd.traced()?.spawn()?.wait()?.code() != Some(SSH_CONNECTION_FAILED) {
+                return Ok(());
+            }
+            thread::sleep(retry.delay());
+        }
+        cmd.traced()?.spawn()?.wait()?;
 
         Ok(())
     }
 }
+
+/// Bytes allocated to an image, which is less than its size until it fills up.
+fn allocated(path: &Path) -> u64 {
+    fs::metadata(path)
+        .map(|metadata| metadata.blocks() * 512)
+        .unwrap_or(0)
+}
+
+fn wait_for_lock(path: &Path, name: &str) -> Result<LockFile> {
+    let mut lock = LockFile::open(path).at(path)?;
+    if !lock.try_lock_with_pid()? {
+        tracing::info!("waiting for another rhea working on {name}");
+        lock.lock_with_pid()?;
+    }
+    Ok(lock)
+}
+
+/// Why QEMU exited during the start grace period: the last lines it wrote to its log since
+/// `start`, which is where its stderr goes.
+fn start_failure(log_path: &Path, start: u64) -> String {
+    let output = fs::read(log_path)
+        .ok()
+        .and_then(|log| log.get(start as usize..).map(<[u8]>::to_vec))
+        .map(|output| String::from_utf8_lossy(&output).into_owned())
+        .unwrap_or_default();
+    let lines = output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>();
+    match lines.len() {
+        0 => "qemu exited during startup".into(),
+        n => lines[n.saturating_sub(START_FAILURE_LINES)..].join("\n"),
+    }
+}
@@ -0,0 +1,48 @@
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MachineStatus {
+    Stopped,
+    Running,
+    Paused,
+    Saved,
+    Crashed,
+}
+
+impl MachineStatus {
+    /// Maps a run state reported by the QMP `query-status` command.
+    pub fn from_run_state(state: &str) -> Self {
+        match state {
+            "paused" | "suspended" | "debug" | "prelaunch" | "inmigrate" | "restore-vm" => {
+                Self::Paused
+            }
+            "postmigrate" | "finish-migrate" | "save-vm" => Self::Saved,
+            "internal-error" | "io-error" | "guest-panicked" => Self::Crashed,
+            "shutdown" => Self::Stopped,
+            _ => Self::Running,
+        }
+    }
+
+    /// Whether a QEMU process exists for the machine, whatever state it is in.
+    pub fn is_active(&self) -> bool {
+        *self != Self::Stopped
+    }
+}
+
+impl fmt::Display for MachineStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Stopped => "stopped",
+                Self::Running => "running",
+                Self::Paused => "paused",
+                Self::Saved => "saved",
+                Self::Crashed => "crashed",
+            }
+        )
+    }
+}
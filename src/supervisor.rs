@@ -0,0 +1,94 @@
+use crate::{
+    progress::ProgressFormat, restart, restart::RestartPolicy, result::Result, start::StartOptions,
+    state::State, status::MachineStatus,
+};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long a restarted machine has to stay up for its backoff to reset.
+const STABLE_AFTER: Duration = Duration::from_secs(600);
+
+struct Restarts {
+    attempts: u32,
+    next: Instant,
+    last: Instant,
+}
+
+/// Watches the machines under `path`, restarting them according to their restart policy and
+/// sampling their usage. Runs until interrupted.
+pub fn run(path: PathBuf, progress: ProgressFormat, interval: Duration) -> Result<()> {
+    let mut restarts: HashMap<String, Restarts> = HashMap::new();
+    let mut first = true;
+    let mut sampled: Option<Instant> = None;
+
+    loop {
+        let mut state = State::load_read_only(path.clone())?;
+        state.set_progress(progress);
+
+        let machines = state
+            .machines()
+            .filter(|machine| !machine.restart.is_never())
+            .map(|machine| (machine.name.clone(), machine.restart))
+            .collect::<Vec<_>>();
+
+        for (name, policy) in machines {
+            let status = state.status(&name, false)?;
+            let wanted = match status {
+                MachineStatus::Crashed => true,
+                MachineStatus::Stopped => first && policy == RestartPolicy::Always,
+                _ => false,
+            };
+            if !wanted {
+                if restarts
+                    .get(&name)
+                    .is_some_and(|entry| entry.last.elapsed() > STABLE_AFTER)
+                {
+                    restarts.remove(&name);
+                }
+                continue;
+            }
+
+            let now = Instant::now();
+            let entry = restarts.entry(name.clone()).or_insert(Restarts {
+                attempts: 0,
+                next: now,
+                last: now,
+            });
+            if now < entry.next {
+                continue;
+            }
+
+            let options = state
+                .exited(&name, false)?
+                .map(|runtime| {
+                    StartOptions::default()
+                        .cores(runtime.cores)
+                        .ram(runtime.ram)
+                        .disks(runtime.disks)
+                })
+                .unwrap_or_default();
+            tracing::warn!("restarting {name} (restart policy: {policy})");
+            if let Err(err) = state.start(&name, &options) {
+                tracing::error!("failed to restart {name}: {err}");
+            }
+
+            entry.next = now + restart::backoff(entry.attempts);
+            entry.last = now;
+            entry.attempts = entry.attempts.saturating_add(1);
+        }
+
+        if sampled.is_none_or(|sampled| sampled.elapsed() >= state.usage_interval()) {
+            if let Err(err) = state.sample_usage() {
+                tracing::warn!("failed to sample usage: {err}");
+            }
+            sampled = Some(Instant::now());
+        }
+
+        first = false;
+        thread::sleep(interval);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::version::Versions;
+use std::{
+    ffi::CString,
+    mem,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    thread,
+};
+
+#[cfg(target_os = "macos")]
+use std::process::Command;
+
+/// Physical memory of the host, in bytes.
+#[derive(Clone, Copy)]
+pub struct Memory {
+    pub total: u64,
+    /// What can be handed out without swapping, including reclaimable caches.
+    pub available: u64,
+}
+
+/// A directory rhea keeps images in and the filesystem it lives on.
+pub struct Storage {
+    pub kind: &'static str,
+    pub path: PathBuf,
+    pub total: u64,
+    pub free: u64,
+    /// Combined virtual size of the images in it, which is what they can grow to.
+    pub committed: u64,
+}
+
+/// What the host has to offer and how much of it virtual machines already claim.
+pub struct HostReport {
+    pub cpus: usize,
+    pub memory: Option<Memory>,
+    pub accelerator: &'static str,
+    pub accelerated: bool,
+    pub versions: Versions,
+    /// Cores and RAM of running virtual machines.
+    pub committed_cores: usize,
+    pub committed_ram: u64,
+    pub max_cores: Option<usize>,
+    pub max_ram: Option<u64>,
+    pub storage: Vec<Storage>,
+}
+
+pub fn cpus() -> usize {
+    thread::available_parallelism().map_or(1, |cpus| cpus.get())
+}
+
+#[cfg(target_os = "linux")]
+pub fn memory() -> Option<Memory> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let field = |name: &str| {
+        meminfo
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .and_then(|value| value.split_whitespace().next()?.parse::<u64>().ok())
+            .map(|kib| kib * 1024)
+    };
+    Some(Memory {
+        total: field("MemTotal")?,
+        available: field("MemAvailable")?,
+    })
+}
+
+#[cfg(target_os = "macos")]
+pub fn memory() -> Option<Memory> {
+    let output = |cmd: &mut Command| {
+        cmd.output()
+            .ok()
+            .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+    let total = output(Command::new("sysctl").args(["-n", "hw.memsize"]))?
+        .trim()
+        .parse()
+        .ok()?;
+
+    // vm_stat counts pages, and says how large they are in its first line.
+    let vm_stat = output(&mut Command::new("vm_stat"))?;
+    let page_size = vm_stat
+        .lines()
+        .next()?
+        .split_once("page size of ")?
+        .1
+        .split_whitespace()
+        .next()?
+        .parse::<u64>()
+        .ok()?;
+    let pages = |name: &str| {
+        vm_stat
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .and_then(|value| value.trim().trim_end_matches('.').parse::<u64>().ok())
+            .unwrap_or(0)
+    };
+    Some(Memory {
+        total,
+        available: (pages("Pages free") + pages("Pages inactive") + pages("Pages speculative"))
+            * page_size,
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn memory() -> Option<Memory> {
+    None
+}
+
+/// Total and free bytes of the filesystem holding `path`.
+pub fn filesystem(path: &Path) -> Option<(u64, u64)> {
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    let block = stat.f_frsize as u64;
+    Some((stat.f_blocks as u64 * block, stat.f_bavail as u64 * block))
+}
+
+/// Whether QEMU can use the hardware accelerator, see `arch::ACCEL`.
+#[cfg(target_os = "linux")]
+pub fn accelerated() -> bool {
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/kvm")
+        .is_ok()
+}
+
+#[cfg(target_os = "macos")]
+pub fn accelerated() -> bool {
+    Command::new("sysctl")
+        .args(["-n", "kern.hv_support"])
+        .output()
+        .is_ok_and(|output| output.stdout.starts_with(b"1"))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn accelerated() -> bool {
+    false
+}
@@ -1,138 +1,1550 @@
-use rhea::state::State;
-use std::fmt;
+use clap::ValueEnum;
+use rhea::{
+    crash::Report, history::Entry, ip::GuestAddress, journal::ExitReason, namespace,
+    ports::PortUse, preset::Preset, probe, result::Result, size::Size, state::State,
+    status::MachineStatus, time, trash::Trashed, usage, version::Version,
+};
+use serde::Serialize;
+use std::{cmp::Ordering, collections::BTreeMap, fmt, iter, time::Duration};
 use tabled::{settings::Style, Table, Tabled};
 
-#[derive(Tabled)]
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    /// A table with extra columns
+    Wide,
+    Json,
+    Yaml,
+    Csv,
+    Tsv,
+}
+
+const HIGHLIGHT: &str = "\x1b[7m";
+const RESET: &str = "\x1b[0m";
+
+/// How a listing is printed: its format, the column it is sorted by and the columns shown.
+#[derive(Clone, Default)]
+pub struct View {
+    pub format: OutputFormat,
+    /// A column name, prefixed with `-` to sort in descending order.
+    pub sort: Option<String>,
+    pub columns: Vec<String>,
+    /// Rows to highlight in tables, by name.
+    pub highlight: Vec<String>,
+}
+
+impl View {
+    fn is_table(&self) -> bool {
+        matches!(self.format, OutputFormat::Table | OutputFormat::Wide)
+    }
+
+    /// Shows every column of a table, for views of a single resource.
+    pub fn detailed(self) -> Self {
+        match self.format {
+            OutputFormat::Table => Self {
+                format: OutputFormat::Wide,
+                ..self
+            },
+            _ => self,
+        }
+    }
+}
+
+/// Column names are matched loosely, so `last-boot`, `last_boot` and `LAST BOOT` are the same.
+fn column_key(name: &str) -> String {
+    name.trim().to_lowercase().replace([' ', '-'], "_")
+}
+
+/// How a cell sorts: sizes, durations and counts by magnitude, anything else as text, and
+/// placeholders like `-` and `never` last.
+#[derive(PartialEq, PartialOrd)]
+enum SortKey {
+    Number(f64),
+    Text(String),
+    Missing,
+}
+
+impl SortKey {
+    fn new(cell: &str) -> Self {
+        const UNITS: [(&str, f64); 10] = [
+            ("B", 1.0),
+            ("K", 1024.0),
+            ("M", 1024.0 * 1024.0),
+            ("G", 1024.0 * 1024.0 * 1024.0),
+            ("T", 1024.0 * 1024.0 * 1024.0 * 1024.0),
+            ("s", 1.0),
+            ("m", 60.0),
+            ("h", 60.0 * 60.0),
+            ("d", 24.0 * 60.0 * 60.0),
+            ("w", 7.0 * 24.0 * 60.0 * 60.0),
+        ];
+
+        let cell = cell.trim();
+        let value = cell.strip_prefix("in ").unwrap_or(cell);
+        let value = value.strip_suffix(" ago").unwrap_or(value);
+        if matches!(value, "" | "-" | "never") {
+            return Self::Missing;
+        }
+        if let Ok(number) = value.parse() {
+            return Self::Number(number);
+        }
+        UNITS
+            .iter()
+            .find_map(|(unit, multiplier)| {
+                let number = value.strip_suffix(unit)?.parse::<f64>().ok()?;
+                Some(Self::Number(number * multiplier))
+            })
+            .unwrap_or_else(|| Self::Text(cell.to_lowercase()))
+    }
+}
+
+fn render<T>(f: &mut fmt::Formatter<'_>, rows: &[T], view: &View) -> fmt::Result
+where
+    T: Tabled + Serialize,
+{
+    render_wide(f, rows, view, &[])
+}
+
+/// Renders rows in the order and with the columns asked for. Columns in `wide` are only shown
+/// in wide tables, unless asked for by name.
+fn render_wide<T>(f: &mut fmt::Formatter<'_>, rows: &[T], view: &View, wide: &[&str]) -> fmt::Result
+where
+    T: Tabled + Serialize,
+{
+    let headers = T::headers()
+        .into_iter()
+        .map(|header| header.into_owned())
+        .collect::<Vec<_>>();
+    let cells = rows
+        .iter()
+        .map(|row| {
+            row.fields()
+                .into_iter()
+                .map(|cell| cell.into_owned())
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let find = |name: &str| {
+        let column = headers
+            .iter()
+            .position(|header| column_key(header) == column_key(name));
+        if column.is_none() {
+            tracing::warn!("no column named {name}");
+        }
+        column
+    };
+
+    let mut order = (0..rows.len()).collect::<Vec<_>>();
+    if let Some(sort) = &view.sort {
+        let (descending, name) = match sort.strip_prefix('-') {
+            Some(name) => (true, name),
+            None => (false, sort.as_str()),
+        };
+        if let Some(column) = find(name) {
+            order.sort_by(|&a, &b| {
+                let (a, b) = (
+                    SortKey::new(&cells[a][column]),
+                    SortKey::new(&cells[b][column]),
+                );
+                // Placeholders stay last whichever way the rest is sorted.
+                match (&a, &b) {
+                    (SortKey::Missing, _) | (_, SortKey::Missing) => a.partial_cmp(&b),
+                    _ if descending => b.partial_cmp(&a),
+                    _ => a.partial_cmp(&b),
+                }
+                .unwrap_or(Ordering::Equal)
+            });
+        }
+    }
+
+    if matches!(view.format, OutputFormat::Json | OutputFormat::Yaml) {
+        let mut values = order
+            .iter()
+            .map(|&row| serde_json::to_value(&rows[row]))
+            .collect::<serde_json::Result<Vec<_>>>()
+            .map_err(|_| fmt::Error)?;
+        if !view.columns.is_empty() {
+            let keep = view
+                .columns
+                .iter()
+                .map(|column| column_key(column))
+                .collect::<Vec<_>>();
+            for value in &mut values {
+                if let serde_json::Value::Object(fields) = value {
+                    fields.retain(|key, _| keep.contains(&column_key(key)));
+                }
+            }
+        }
+        return match view.format {
+            OutputFormat::Yaml => write!(
+                f,
+                "{}",
+                serde_yaml::to_string(&values).map_err(|_| fmt::Error)?
+            ),
+            _ => write!(
+                f,
+                "{}",
+                serde_json::to_string_pretty(&values).map_err(|_| fmt::Error)?
+            ),
+        };
+    }
+
+    let columns = if view.columns.is_empty() {
+        (0..headers.len())
+            .filter(|&column| {
+                !matches!(view.format, OutputFormat::Table) || !wide.contains(&&*headers[column])
+            })
+            .collect::<Vec<_>>()
+    } else {
+        view.columns.iter().filter_map(|name| find(name)).collect()
+    };
+    let lines = iter::once(&headers)
+        .chain(order.iter().map(|&row| &cells[row]))
+        .map(|row| {
+            columns
+                .iter()
+                .map(|&column| row[column].clone())
+                .collect::<Vec<_>>()
+        });
+
+    match view.format {
+        OutputFormat::Csv => {
+            let lines = lines
+                .map(|row| {
+                    row.iter()
+                        .map(|cell| csv_field(cell))
+                        .collect::<Vec<_>>()
+                        .join(",")
+                })
+                .collect::<Vec<_>>();
+            write!(f, "{}", lines.join("\n"))
+        }
+        OutputFormat::Tsv => {
+            let lines = lines
+                .map(|row| {
+                    row.iter()
+                        .map(|cell| cell.replace(['\t', '\n'], " "))
+                        .collect::<Vec<_>>()
+                        .join("\t")
+                })
+                .collect::<Vec<_>>();
+            write!(f, "{}", lines.join("\n"))
+        }
+        _ => {
+            let mut table = lines.collect::<Table>();
+            table.with(Style::blank());
+            if view.highlight.is_empty() {
+                return write!(f, "{table}");
+            }
+
+            // The first line is the header, and the rest follow the order of the rows.
+            let table = table.to_string();
+            let lines = table
+                .lines()
+                .enumerate()
+                .map(
+                    |(line, text)| match line.checked_sub(1).map(|row| order[row]) {
+                        Some(row) if view.highlight.contains(&cells[row][0]) => {
+                            format!("{HIGHLIGHT}{text}{RESET}")
+                        }
+                        _ => text.into(),
+                    },
+                )
+                .collect::<Vec<_>>();
+            write!(f, "{}", lines.join("\n"))
+        }
+    }
+}
+
+/// Quotes a CSV field if it contains a separator, quote or line break (RFC 4180).
+fn csv_field(cell: &str) -> String {
+    if cell.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.into()
+    }
+}
+
+fn age(created_at: Option<u64>) -> String {
+    created_at
+        .map(|created_at| time::format_duration(time::now().saturating_sub(created_at)))
+        .unwrap_or_else(|| "-".into())
+}
+
+/// Resources with a creation time were created after boots started being recorded, so a missing
+/// boot time on them means they have never been started.
+fn last_boot(created_at: Option<u64>, last_started_at: Option<u64>) -> String {
+    match (created_at, last_started_at) {
+        (_, Some(last_started_at)) => format!(
+            "{} ago",
+            time::format_duration(time::now().saturating_sub(last_started_at))
+        ),
+        (Some(_), None) => "never".into(),
+        (None, None) => "-".into(),
+    }
+}
+
+#[derive(Tabled, Serialize)]
 struct DiskInfo {
     #[tabled(rename = "NAME")]
     name: String,
-    #[tabled(rename = "SIZE (GB)")]
-    size: usize,
+    #[tabled(rename = "SIZE")]
+    size: Size,
+    #[tabled(rename = "AGE")]
+    age: String,
+    #[tabled(rename = "LAST BOOT")]
+    last_boot: String,
     #[tabled(rename = "IN-USE")]
     in_use: bool,
+    #[tabled(rename = "SHA256", display_with = "display_digest")]
+    sha256: Option<String>,
+    #[tabled(rename = "DESCRIPTION", display_with = "display_optional")]
+    description: Option<String>,
 }
 
+const DISK_WIDE: &[&str] = &["SHA256", "DESCRIPTION"];
+
 pub struct DiskTable {
-    table: Table,
+    rows: Vec<DiskInfo>,
+    view: View,
 }
 
 impl DiskTable {
-    pub fn new(state: &State) -> Self {
-        Self::filtered(state, &[])
-    }
-
-    pub fn filtered(state: &State, filter: &[&str]) -> Self {
-        let mut table = Table::new(
-            state
-                .disks()
-                .filter(|disk| filter.is_empty() || filter.contains(&disk.name.as_ref()))
-                .map(|disk| DiskInfo {
-                    name: disk.name.clone(),
-                    size: disk.size,
-                    in_use: state.disk_in_use(&disk.name).unwrap(),
-                })
-                .collect::<Vec<_>>(),
-        );
-        table.with(Style::blank());
-        Self { table }
+    pub fn new(state: &State, view: View) -> Result<Self> {
+        Self::filtered(state, &[], view)
+    }
+
+    pub fn filtered(state: &State, filter: &[&str], view: View) -> Result<Self> {
+        let rows = state
+            .disks()
+            .filter(|disk| filter.is_empty() || filter.contains(&disk.name.as_ref()))
+            .map(|disk| state.describe_disk(&disk.name))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .map(|disk| DiskInfo {
+                age: age(disk.created_at),
+                last_boot: last_boot(disk.created_at, disk.last_started_at),
+                name: disk.name,
+                size: disk.size,
+                in_use: disk.in_use,
+                sha256: disk.sha256,
+                description: disk.description,
+            })
+            .collect::<Vec<_>>();
+        Ok(Self { rows, view })
+    }
+
+    /// Keeps only disks in the namespace `prefix` names, or matching it if it is a pattern.
+    pub fn within(mut self, prefix: Option<&str>) -> Self {
+        match prefix {
+            Some(pattern) if namespace::is_pattern(pattern) => {
+                self.rows
+                    .retain(|row| namespace::matches(pattern, &row.name));
+            }
+            Some(prefix) => {
+                let prefix = namespace::prefix(prefix);
+                self.rows
+                    .retain(|row| namespace::contains(&prefix, &row.name));
+            }
+            None => {}
+        }
+        self
     }
 }
 
 impl fmt::Display for DiskTable {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.table)
+        render_wide(f, &self.rows, &self.view, DISK_WIDE)
     }
 }
 
-#[derive(Tabled)]
+#[derive(Tabled, Serialize)]
 pub struct MachineInfo {
     #[tabled(rename = "NAME")]
     name: String,
     #[tabled(rename = "PORT")]
     port: u16,
-    #[tabled(rename = "SIZE (GB)")]
-    size: usize,
-    #[tabled(rename = "IN-USE")]
-    in_use: bool,
+    #[tabled(rename = "SIZE")]
+    size: Size,
+    #[tabled(rename = "NETWORK")]
+    network: String,
+    #[tabled(rename = "AGE")]
+    age: String,
+    #[tabled(rename = "LAST BOOT")]
+    last_boot: String,
+    #[tabled(rename = "STATUS")]
+    status: MachineStatus,
+    #[tabled(rename = "RESTART")]
+    restart: String,
+    #[tabled(rename = "FIRMWARE")]
+    firmware: String,
+    #[tabled(rename = "OS", display_with = "display_optional")]
+    os: Option<String>,
+    #[tabled(rename = "ISO", display_with = "display_optional")]
+    iso: Option<String>,
+    #[tabled(rename = "SHA256", display_with = "display_digest")]
+    sha256: Option<String>,
+    #[tabled(rename = "META", display_with = "display_meta")]
+    meta: BTreeMap<String, String>,
+    #[tabled(rename = "DESCRIPTION", display_with = "display_optional")]
+    description: Option<String>,
+}
+
+const MACHINE_WIDE: &[&str] = &[
+    "RESTART",
+    "FIRMWARE",
+    "ISO",
+    "SHA256",
+    "META",
+    "DESCRIPTION",
+];
+
+fn display_optional(value: &Option<String>) -> String {
+    value.clone().unwrap_or_else(|| "-".into())
+}
+
+/// Digests are shortened to what is needed to tell them apart at a glance.
+fn display_digest(digest: &Option<String>) -> String {
+    digest
+        .as_ref()
+        .map_or_else(|| "-".into(), |digest| digest.chars().take(12).collect())
+}
+
+fn display_meta(meta: &BTreeMap<String, String>) -> String {
+    if meta.is_empty() {
+        return "-".into();
+    }
+    meta.iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect::<Vec<_>>()
+        .join(",")
 }
 
 pub struct MachineTable {
-    table: Table,
+    rows: Vec<MachineInfo>,
+    view: View,
 }
 
 impl MachineTable {
-    pub fn new(state: &State) -> Self {
-        Self::filtered(state, &[])
-    }
-
-    pub fn filtered(state: &State, filter: &[&str]) -> Self {
-        let mut table = Table::new(
-            state
-                .machines()
-                .filter(|machine| filter.is_empty() || filter.contains(&machine.name.as_ref()))
-                .map(|machine| MachineInfo {
-                    name: machine.name.clone(),
-                    port: machine.port,
-                    size: machine.size,
-                    in_use: state.machine_in_use(&machine.name).unwrap(),
-                })
-                .collect::<Vec<_>>(),
-        );
-        table.with(Style::blank());
-        Self { table }
+    pub fn new(state: &State, view: View) -> Result<Self> {
+        Self::filtered(state, &[], view)
+    }
+
+    pub fn filtered(state: &State, filter: &[&str], view: View) -> Result<Self> {
+        let rows = state
+            .machines()
+            .filter(|machine| filter.is_empty() || filter.contains(&machine.name.as_ref()))
+            .map(|machine| state.describe_machine(&machine.name))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .map(|machine| MachineInfo {
+                age: age(machine.created_at),
+                last_boot: last_boot(machine.created_at, machine.last_started_at),
+                name: machine.name,
+                port: machine.port,
+                size: machine.size,
+                network: if machine.isolated {
+                    format!("{} (isolated)", machine.network)
+                } else {
+                    machine.network.to_string()
+                },
+                status: machine.status,
+                restart: machine.restart.to_string(),
+                firmware: machine.firmware.to_string(),
+                os: machine.os.map(|os| os.to_string()),
+                iso: machine.iso.map(|iso| iso.display().to_string()),
+                sha256: machine.sha256,
+                meta: machine.meta,
+                description: machine.description,
+            })
+            .collect::<Vec<_>>();
+        Ok(Self { rows, view })
+    }
+
+    /// Keeps only machines in the namespace `prefix` names, or matching it if it is a pattern.
+    pub fn within(mut self, prefix: Option<&str>) -> Self {
+        match prefix {
+            Some(pattern) if namespace::is_pattern(pattern) => {
+                self.rows
+                    .retain(|row| namespace::matches(pattern, &row.name));
+            }
+            Some(prefix) => {
+                let prefix = namespace::prefix(prefix);
+                self.rows
+                    .retain(|row| namespace::contains(&prefix, &row.name));
+            }
+            None => {}
+        }
+        self
+    }
+
+    /// Keeps only machines whose metadata has every given key set to the given value.
+    pub fn matching(mut self, meta: &[(String, String)]) -> Self {
+        self.rows.retain(|row| {
+            meta.iter()
+                .all(|(key, value)| row.meta.get(key) == Some(value))
+        });
+        self
     }
 }
 
 impl fmt::Display for MachineTable {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.table)
+        render_wide(f, &self.rows, &self.view, MACHINE_WIDE)
     }
 }
 
-#[derive(Tabled)]
+#[derive(Tabled, Serialize)]
+struct BootInfo {
+    #[tabled(rename = "STARTED")]
+    started: String,
+    #[tabled(rename = "UPTIME")]
+    uptime: String,
+    #[tabled(rename = "REASON")]
+    reason: String,
+    #[tabled(rename = "CORES")]
+    cores: usize,
+    #[tabled(rename = "RAM")]
+    ram: Size,
+}
+
+/// The most recent boots of a virtual machine, newest first, summarized over its whole journal.
+pub struct BootTable {
+    rows: Vec<BootInfo>,
+    view: View,
+    summary: String,
+}
+
+impl BootTable {
+    pub fn new(state: &State, name: &str, last: usize, view: View) -> Result<Self> {
+        let boots = state.boots(name, false)?;
+        let rows = boots
+            .iter()
+            .rev()
+            .take(last)
+            .map(|boot| BootInfo {
+                started: format!("{} ago", age(Some(boot.started_at))),
+                uptime: time::format_duration(boot.uptime()),
+                reason: boot.reason.to_string(),
+                cores: boot.cores,
+                ram: boot.ram,
+            })
+            .collect();
+
+        let crashes = boots
+            .iter()
+            .filter(|boot| boot.reason == ExitReason::Crashed)
+            .count();
+        let average =
+            boots.iter().map(|boot| boot.uptime()).sum::<u64>() / boots.len().max(1) as u64;
+        let summary = format!(
+            "{} boots, average uptime {}, {crashes} crashed",
+            boots.len(),
+            time::format_duration(average)
+        );
+
+        Ok(Self {
+            rows,
+            view: View {
+                format: view.format,
+                ..View::default()
+            },
+            summary,
+        })
+    }
+
+    /// Whether there is nothing to print, which is always the case outside of tables.
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty() || !self.view.is_table()
+    }
+}
+
+impl fmt::Display for BootTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        render(f, &self.rows, &self.view)?;
+        write!(f, "\n\n{}", self.summary)
+    }
+}
+
+#[derive(Tabled, Serialize)]
+pub struct HostInfo {
+    #[tabled(rename = "RESOURCE")]
+    resource: String,
+    #[tabled(rename = "TOTAL")]
+    total: String,
+    #[tabled(rename = "FREE")]
+    free: String,
+    #[tabled(rename = "COMMITTED")]
+    committed: String,
+    #[tabled(rename = "QUOTA", display_with = "display_optional")]
+    quota: Option<String>,
+    #[tabled(rename = "PATH", display_with = "display_optional")]
+    path: Option<String>,
+}
+
+const HOST_WIDE: &[&str] = &["PATH"];
+
+/// What the host has to offer, with the accelerator and QEMU version below the table.
+pub struct HostTable {
+    rows: Vec<HostInfo>,
+    view: View,
+    summary: String,
+}
+
+impl HostTable {
+    pub fn new(state: &State, view: View) -> Result<Self> {
+        let report = state.host_report()?;
+        let mut rows = vec![
+            HostInfo {
+                resource: "cpu".into(),
+                total: report.cpus.to_string(),
+                free: "-".into(),
+                committed: report.committed_cores.to_string(),
+                quota: report.max_cores.map(|max_cores| max_cores.to_string()),
+                path: None,
+            },
+            HostInfo {
+                resource: "ram".into(),
+                total: report
+                    .memory
+                    .map_or_else(|| "-".into(), |memory| format_bytes(memory.total)),
+                free: report
+                    .memory
+                    .map_or_else(|| "-".into(), |memory| format_bytes(memory.available)),
+                committed: format_bytes(report.committed_ram),
+                quota: report.max_ram.map(format_bytes),
+                path: None,
+            },
+        ];
+        rows.extend(report.storage.into_iter().map(|storage| HostInfo {
+            resource: storage.kind.into(),
+            total: format_bytes(storage.total),
+            free: format_bytes(storage.free),
+            committed: format_bytes(storage.committed),
+            quota: None,
+            path: Some(storage.path.display().to_string()),
+        }));
+
+        let version = |version: Option<Version>| {
+            version.map_or_else(|| "not installed".into(), |version| version.to_string())
+        };
+        let summary = format!(
+            "accelerator: {} ({})\nqemu: {}\nqemu-img: {}",
+            report.accelerator,
+            if report.accelerated {
+                "available"
+            } else {
+                "unavailable"
+            },
+            version(report.versions.qemu),
+            version(report.versions.qemu_img)
+        );
+        Ok(Self {
+            rows,
+            view,
+            summary,
+        })
+    }
+}
+
+impl fmt::Display for HostTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        render_wide(f, &self.rows, &self.view, HOST_WIDE)?;
+        if self.view.is_table() {
+            write!(f, "\n\n{}", self.summary)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Tabled, Serialize)]
+pub struct UsageInfo {
+    #[tabled(rename = "RESOURCE")]
+    resource: &'static str,
+    #[tabled(rename = "AVERAGE")]
+    average: String,
+    #[tabled(rename = "PEAK")]
+    peak: String,
+    #[tabled(rename = "ALLOCATED")]
+    allocated: String,
+}
+
+pub struct UsageTable {
+    rows: Vec<UsageInfo>,
+    view: View,
+    summary: String,
+}
+
+impl UsageTable {
+    pub fn new(
+        state: &State,
+        name: &str,
+        snapshot: bool,
+        since: Option<Duration>,
+        view: View,
+    ) -> Result<Self> {
+        let Some(summary) = state.usage(name, snapshot, since)? else {
+            return Ok(Self {
+                rows: Vec::new(),
+                view,
+                summary: format!("no usage recorded for {name} (sampled by `rhea supervise`)"),
+            });
+        };
+
+        let cores = |cores: f64| format!("{cores:.2} cores");
+        let bytes = |bytes: f64| format_bytes(bytes as u64);
+        let row = |resource, usage: &usage::Usage, format: &dyn Fn(f64) -> String| UsageInfo {
+            resource,
+            average: format(usage.average),
+            peak: format(usage.peak),
+            allocated: format(usage.allocated),
+        };
+        let rows = vec![
+            row("cpu", &summary.cpu, &cores),
+            row("ram", &summary.ram, &bytes),
+            row("disk", &summary.disk, &bytes),
+        ];
+        let growth = format_bytes(summary.disk_growth.unsigned_abs());
+        let sign = if summary.disk_growth < 0 { "-" } else { "+" };
+
+        Ok(Self {
+            rows,
+            view: View {
+                format: view.format,
+                ..View::default()
+            },
+            summary: format!(
+                "{} samples over {}, disk {sign}{growth}",
+                summary.samples,
+                age(Some(summary.since))
+            ),
+        })
+    }
+}
+
+impl fmt::Display for UsageTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.rows.is_empty() {
+            return write!(f, "{}", self.summary);
+        }
+        render(f, &self.rows, &self.view)?;
+        if self.view.is_table() {
+            write!(f, "\n\n{}", self.summary)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Tabled, Serialize)]
 pub struct SnapshotInfo {
     #[tabled(rename = "NAME")]
     name: String,
     #[tabled(rename = "BASE")]
     base: String,
+    #[tabled(rename = "PARENT")]
+    parent: String,
     #[tabled(rename = "PORT")]
     port: u16,
-    #[tabled(rename = "SIZE (GB)")]
-    size: usize,
-    #[tabled(rename = "IN-USE")]
-    in_use: bool,
+    #[tabled(rename = "SIZE")]
+    size: Size,
+    #[tabled(rename = "AGE")]
+    age: String,
+    #[tabled(rename = "LAST BOOT")]
+    last_boot: String,
+    #[tabled(rename = "STALE")]
+    stale: String,
+    #[tabled(rename = "STATUS")]
+    status: MachineStatus,
+    #[tabled(rename = "FULL")]
+    full: bool,
+    #[tabled(rename = "DESCRIPTION", display_with = "display_optional")]
+    description: Option<String>,
 }
 
+const SNAPSHOT_WIDE: &[&str] = &["FULL", "DESCRIPTION"];
+
 pub struct SnapshotTable {
-    table: Table,
+    rows: Vec<SnapshotInfo>,
+    view: View,
 }
 
 impl SnapshotTable {
-    pub fn new(state: &State) -> Self {
-        Self::filtered(state, &[])
-    }
-
-    pub fn filtered(state: &State, filter: &[&str]) -> Self {
-        let mut table = Table::new(
-            state
-                .snapshots()
-                .filter(|snapshot| filter.is_empty() || filter.contains(&snapshot.name.as_ref()))
-                .map(|snapshot| SnapshotInfo {
-                    name: snapshot.name.clone(),
-                    base: snapshot.base.clone(),
-                    port: snapshot.port,
-                    size: snapshot.size,
-                    in_use: state.snapshot_in_use(&snapshot.name).unwrap(),
-                })
-                .collect::<Vec<_>>(),
-        );
-        table.with(Style::blank());
-        Self { table }
+    pub fn new(state: &State, view: View) -> Result<Self> {
+        Self::filtered(state, &[], view)
+    }
+
+    pub fn filtered(state: &State, filter: &[&str], view: View) -> Result<Self> {
+        let rows = state
+            .snapshots()
+            .filter(|snapshot| filter.is_empty() || filter.contains(&snapshot.name.as_ref()))
+            .map(|snapshot| state.describe_snapshot(&snapshot.name))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .map(|snapshot| SnapshotInfo {
+                age: age(snapshot.created_at),
+                last_boot: last_boot(snapshot.created_at, snapshot.last_started_at),
+                name: snapshot.name,
+                base: snapshot.base,
+                parent: snapshot.parent.unwrap_or_default(),
+                port: snapshot.port,
+                size: snapshot.size,
+                stale: snapshot
+                    .stale
+                    .map_or_else(|| "-".into(), |stale| stale.to_string()),
+                status: snapshot.status,
+                full: snapshot.full,
+                description: snapshot.description,
+            })
+            .collect::<Vec<_>>();
+        Ok(Self { rows, view })
+    }
+
+    /// Keeps only snapshots in the namespace `prefix` names, or matching it if it is a pattern.
+    pub fn within(mut self, prefix: Option<&str>) -> Self {
+        match prefix {
+            Some(pattern) if namespace::is_pattern(pattern) => {
+                self.rows
+                    .retain(|row| namespace::matches(pattern, &row.name));
+            }
+            Some(prefix) => {
+                let prefix = namespace::prefix(prefix);
+                self.rows
+                    .retain(|row| namespace::contains(&prefix, &row.name));
+            }
+            None => {}
+        }
+        self
     }
 }
 
 impl fmt::Display for SnapshotTable {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.table)
+        render_wide(f, &self.rows, &self.view, SNAPSHOT_WIDE)
+    }
+}
+
+#[derive(Tabled, Serialize)]
+struct SnapshotDetailInfo {
+    #[tabled(rename = "NAME")]
+    name: String,
+    #[tabled(rename = "BASE")]
+    base: String,
+    #[tabled(rename = "PARENT", display_with = "display_optional")]
+    parent: Option<String>,
+    #[tabled(rename = "BACKING", display_with = "display_optional")]
+    backing: Option<String>,
+    #[tabled(rename = "VIRTUAL SIZE")]
+    virtual_size: Size,
+    #[tabled(rename = "ALLOCATED")]
+    allocated: String,
+    #[tabled(rename = "DELTA")]
+    delta: String,
+    #[tabled(rename = "CREATED")]
+    created: String,
+    #[tabled(skip)]
+    #[serde(skip)]
+    delta_bytes: Option<u64>,
+}
+
+impl SnapshotDetailInfo {
+    fn new(state: &State, name: &str) -> Result<Self> {
+        let snapshot = state.describe_snapshot(name)?;
+        let delta = state.snapshot_delta(name)?;
+        Ok(Self {
+            name: snapshot.name,
+            base: snapshot.base,
+            parent: snapshot.parent,
+            backing: snapshot.backing_path.map(|path| path.display().to_string()),
+            virtual_size: snapshot.size,
+            allocated: format_bytes(snapshot.size_on_disk),
+            delta: delta.map_or_else(|| "-".into(), format_bytes),
+            created: snapshot.created_at.map_or_else(
+                || "-".into(),
+                |created_at| format!("{} ago", age(Some(created_at))),
+            ),
+            delta_bytes: delta,
+        })
+    }
+}
+
+/// Where a snapshot comes from and how much it holds on top of what it is backed by; the delta
+/// of a full copy is unknown.
+pub struct SnapshotDetailTable {
+    rows: Vec<SnapshotDetailInfo>,
+    view: View,
+    summary: Option<String>,
+}
+
+impl SnapshotDetailTable {
+    pub fn new(state: &State, name: &str, view: View) -> Result<Self> {
+        Ok(Self {
+            rows: vec![SnapshotDetailInfo::new(state, name)?],
+            view: view.detailed(),
+            summary: None,
+        })
+    }
+
+    /// Compares two snapshots, summarizing where their chains meet and how their deltas differ.
+    pub fn diff(state: &State, name: &str, other: &str, view: View) -> Result<Self> {
+        let chain = |name: &str| -> Result<Vec<String>> {
+            Ok(iter::once(name.to_string())
+                .chain(state.snapshot_ancestors(name)?)
+                .collect())
+        };
+        let (ancestors, others) = (chain(name)?, chain(other)?);
+        let mut summary = match ancestors
+            .into_iter()
+            .find(|ancestor| others.contains(ancestor))
+        {
+            Some(ancestor) => format!("common ancestor: {ancestor}"),
+            None => "no common ancestor".into(),
+        };
+
+        let rows = vec![
+            SnapshotDetailInfo::new(state, name)?,
+            SnapshotDetailInfo::new(state, other)?,
+        ];
+        if let (Some(first), Some(second)) = (rows[0].delta_bytes, rows[1].delta_bytes) {
+            let (difference, comparison) = match second.cmp(&first) {
+                Ordering::Less => (first - second, "less"),
+                _ => (second - first, "more"),
+            };
+            summary += &format!(
+                "\n{other} holds {} {comparison} than {name}",
+                format_bytes(difference)
+            );
+        }
+
+        Ok(Self {
+            rows,
+            view,
+            summary: Some(summary),
+        })
+    }
+}
+
+impl fmt::Display for SnapshotDetailTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        render(f, &self.rows, &self.view)?;
+        match &self.summary {
+            Some(summary) if self.view.is_table() => write!(f, "\n\n{summary}"),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SnapshotNode {
+    name: String,
+    children: Vec<SnapshotNode>,
+}
+
+impl SnapshotNode {
+    fn build(state: &State, base: &str, name: &str, parent: Option<&str>) -> Self {
+        Self {
+            name: name.into(),
+            children: state
+                .child_snapshots(base, parent)
+                .map(|snapshot| Self::build(state, base, &snapshot.name, Some(&snapshot.name)))
+                .collect(),
+        }
+    }
+
+    fn lines(&self, prefix: &str, lines: &mut Vec<String>) {
+        for (i, child) in self.children.iter().enumerate() {
+            let last = i + 1 == self.children.len();
+            lines.push(format!(
+                "{prefix}{}{}",
+                if last { "└── " } else { "├── " },
+                child.name
+            ));
+            child.lines(
+                &format!("{prefix}{}", if last { "    " } else { "│   " }),
+                lines,
+            );
+        }
+    }
+}
+
+pub struct SnapshotTree {
+    root: SnapshotNode,
+    format: OutputFormat,
+}
+
+impl SnapshotTree {
+    pub fn new(state: &State, machine: &str, format: OutputFormat) -> Result<Self> {
+        let machine = &state.get_machine(machine)?.name;
+        Ok(Self {
+            root: SnapshotNode::build(state, machine, machine, None),
+            format,
+        })
+    }
+}
+
+impl fmt::Display for SnapshotTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.format {
+            OutputFormat::Table | OutputFormat::Wide | OutputFormat::Csv | OutputFormat::Tsv => {
+                let mut lines = vec![self.root.name.clone()];
+                self.root.lines("", &mut lines);
+                write!(f, "{}", lines.join("\n"))
+            }
+            OutputFormat::Json => write!(
+                f,
+                "{}",
+                serde_json::to_string_pretty(&self.root).map_err(|_| fmt::Error)?
+            ),
+            OutputFormat::Yaml => write!(
+                f,
+                "{}",
+                serde_yaml::to_string(&self.root).map_err(|_| fmt::Error)?
+            ),
+        }
+    }
+}
+
+#[derive(Tabled, Serialize)]
+pub struct ProbeInfo {
+    #[tabled(rename = "PROBE")]
+    name: String,
+    #[tabled(rename = "KIND")]
+    kind: &'static str,
+    #[tabled(rename = "HOST PORT")]
+    host_port: u16,
+    #[tabled(rename = "GUEST PORT")]
+    guest_port: u16,
+    #[tabled(rename = "PATH")]
+    path: String,
+    #[tabled(rename = "READY")]
+    ready: bool,
+}
+
+pub struct ProbeTable {
+    rows: Vec<ProbeInfo>,
+    view: View,
+}
+
+impl ProbeTable {
+    pub fn new(state: &State, name: &str, snapshot: bool, view: View) -> Result<Self> {
+        let rows = state
+            .probes(name, snapshot)?
+            .iter()
+            .map(|(name, probe)| ProbeInfo {
+                name: name.clone(),
+                kind: probe.kind(),
+                host_port: probe.host_port,
+                guest_port: probe.guest_port,
+                path: probe.path.clone().unwrap_or_default(),
+                ready: probe.ready(),
+            })
+            .collect::<Vec<_>>();
+        Ok(Self { rows, view })
+    }
+}
+
+impl fmt::Display for ProbeTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        render(f, &self.rows, &self.view)
+    }
+}
+
+#[derive(Tabled, Serialize)]
+struct ChannelInfo {
+    #[tabled(rename = "CHANNEL")]
+    name: String,
+    #[tabled(rename = "SOCKET")]
+    socket: String,
+}
+
+pub struct ChannelTable {
+    rows: Vec<ChannelInfo>,
+    view: View,
+}
+
+impl ChannelTable {
+    pub fn new(state: &State, name: &str, snapshot: bool, view: View) -> Result<Self> {
+        let rows = state
+            .channels(name, snapshot)?
+            .into_iter()
+            .map(|(name, socket)| ChannelInfo {
+                name,
+                socket: socket.display().to_string(),
+            })
+            .collect();
+        Ok(Self { rows, view })
+    }
+}
+
+impl fmt::Display for ChannelTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        render(f, &self.rows, &self.view)
+    }
+}
+
+#[derive(Tabled, Serialize)]
+struct RemoteInfo {
+    #[tabled(rename = "NAME")]
+    name: String,
+    #[tabled(rename = "URL")]
+    url: String,
+}
+
+pub struct RemoteTable {
+    rows: Vec<RemoteInfo>,
+    view: View,
+}
+
+impl RemoteTable {
+    pub fn new(state: &State, view: View) -> Self {
+        let rows = state
+            .remotes()
+            .map(|(name, remote)| RemoteInfo {
+                name: name.clone(),
+                url: remote.to_string(),
+            })
+            .collect::<Vec<_>>();
+        Self { rows, view }
+    }
+}
+
+impl fmt::Display for RemoteTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        render(f, &self.rows, &self.view)
+    }
+}
+
+#[derive(Tabled, Serialize)]
+struct AddressInfo {
+    #[tabled(rename = "ADDRESS")]
+    address: String,
+    #[tabled(rename = "INTERFACE")]
+    interface: String,
+    #[tabled(rename = "SOURCE")]
+    source: String,
+}
+
+pub struct AddressTable {
+    rows: Vec<AddressInfo>,
+    view: View,
+}
+
+impl AddressTable {
+    pub fn new(addresses: &[GuestAddress], view: View) -> Self {
+        let rows = addresses
+            .iter()
+            .map(|address| AddressInfo {
+                address: address.address.to_string(),
+                interface: address.interface.clone().unwrap_or_else(|| "-".into()),
+                source: address.source.to_string(),
+            })
+            .collect();
+        Self { rows, view }
+    }
+}
+
+impl fmt::Display for AddressTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        render(f, &self.rows, &self.view)
+    }
+}
+
+#[derive(Tabled, Serialize)]
+struct PortInfo {
+    #[tabled(rename = "NAME")]
+    name: String,
+    #[tabled(rename = "FORWARD")]
+    forward: String,
+    #[tabled(rename = "PORT")]
+    port: u16,
+    #[tabled(rename = "GUEST PORT")]
+    guest_port: u16,
+    #[tabled(rename = "PID", display_with = "display_pid")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pid: Option<u32>,
+    #[tabled(rename = "STATUS")]
+    status: String,
+}
+
+fn display_pid(pid: &Option<u32>) -> String {
+    pid.map_or_else(|| "-".into(), |pid| pid.to_string())
+}
+
+pub struct PortTable {
+    rows: Vec<PortInfo>,
+    view: View,
+}
+
+impl PortTable {
+    pub fn new(uses: &[PortUse], view: View) -> Self {
+        let rows = uses
+            .iter()
+            .map(|port_use| PortInfo {
+                name: if port_use.snapshot {
+                    format!("{} (snapshot)", port_use.name)
+                } else {
+                    port_use.name.clone()
+                },
+                forward: port_use.forward.clone(),
+                port: port_use.port,
+                guest_port: port_use.guest_port,
+                pid: port_use.pid,
+                status: port_use.status.to_string(),
+            })
+            .collect();
+        Self { rows, view }
+    }
+}
+
+impl fmt::Display for PortTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        render(f, &self.rows, &self.view)
+    }
+}
+
+#[derive(Tabled, Serialize)]
+struct HistoryInfo {
+    #[tabled(skip)]
+    at: u64,
+    #[tabled(rename = "WHEN")]
+    when: String,
+    #[tabled(rename = "USER")]
+    user: String,
+    #[tabled(rename = "COMMAND")]
+    command: String,
+    #[tabled(rename = "RESULT")]
+    result: String,
+}
+
+pub struct HistoryTable {
+    rows: Vec<HistoryInfo>,
+    view: View,
+}
+
+impl HistoryTable {
+    pub fn new(entries: &[Entry], view: View) -> Self {
+        let rows = entries
+            .iter()
+            .map(|entry| HistoryInfo {
+                at: entry.at,
+                when: format!("{} ago", age(Some(entry.at))),
+                user: entry.user.clone(),
+                command: entry.command.join(" "),
+                result: entry.result.clone(),
+            })
+            .collect();
+        Self { rows, view }
+    }
+}
+
+impl fmt::Display for HistoryTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        render(f, &self.rows, &self.view)
+    }
+}
+
+#[derive(Tabled, Serialize)]
+struct CrashInfo {
+    #[tabled(rename = "ID")]
+    id: String,
+    #[tabled(rename = "NAME")]
+    name: String,
+    #[tabled(rename = "REASON")]
+    reason: String,
+    #[tabled(rename = "UPTIME")]
+    uptime: String,
+    #[tabled(rename = "DETECTED")]
+    detected: String,
+    #[tabled(rename = "CORES")]
+    cores: usize,
+    #[tabled(rename = "RAM")]
+    ram: Size,
+    #[tabled(rename = "MEMORY DUMP")]
+    memory_dump: bool,
+}
+
+const CRASH_WIDE: &[&str] = &["CORES", "RAM", "MEMORY DUMP"];
+
+pub struct CrashTable {
+    rows: Vec<CrashInfo>,
+    view: View,
+    log: Option<String>,
+}
+
+impl CrashTable {
+    pub fn new(reports: &[Report], view: View) -> Self {
+        let rows = reports
+            .iter()
+            .rev()
+            .map(|report| CrashInfo {
+                id: report.id.clone(),
+                name: report.name.clone(),
+                reason: report.reason.clone(),
+                uptime: time::format_duration(report.detected_at.saturating_sub(report.started_at)),
+                detected: format!("{} ago", age(Some(report.detected_at))),
+                cores: report.cores,
+                ram: report.ram,
+                memory_dump: report.memory_dump,
+            })
+            .collect();
+        Self {
+            rows,
+            view,
+            log: None,
+        }
+    }
+
+    /// A single report, followed in tables by the end of its log.
+    pub fn show(report: &Report, log: String, view: View) -> Self {
+        Self {
+            log: Some(log),
+            ..Self::new(std::slice::from_ref(report), view.detailed())
+        }
+    }
+}
+
+impl fmt::Display for CrashTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        render_wide(f, &self.rows, &self.view, CRASH_WIDE)?;
+        match &self.log {
+            Some(log) if self.view.is_table() => write!(f, "\n\n{}", log.trim_end()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Tabled, Serialize)]
+struct TrashInfo {
+    #[tabled(rename = "NAME")]
+    name: String,
+    #[tabled(rename = "KIND")]
+    kind: &'static str,
+    #[tabled(rename = "REMOVED")]
+    removed: String,
+    #[tabled(skip)]
+    removed_at: u64,
+}
+
+pub struct TrashTable {
+    rows: Vec<TrashInfo>,
+    view: View,
+}
+
+impl TrashTable {
+    pub fn new(trashed: &[Trashed], view: View) -> Self {
+        let rows = trashed
+            .iter()
+            .rev()
+            .map(|trashed| TrashInfo {
+                name: trashed.resource.name().into(),
+                kind: trashed.resource.kind(),
+                removed: format!("{} ago", age(Some(trashed.removed_at))),
+                removed_at: trashed.removed_at,
+            })
+            .collect();
+        Self { rows, view }
+    }
+}
+
+impl fmt::Display for TrashTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        render(f, &self.rows, &self.view)
+    }
+}
+
+#[derive(Tabled, Serialize)]
+struct PresetInfo {
+    #[tabled(rename = "NAME")]
+    name: String,
+    #[tabled(rename = "CORES")]
+    cores: usize,
+    #[tabled(rename = "RAM")]
+    ram: Size,
+    #[tabled(rename = "SIZE")]
+    size: Size,
+}
+
+pub struct PresetTable {
+    rows: Vec<PresetInfo>,
+    view: View,
+}
+
+impl PresetTable {
+    pub fn new(presets: &BTreeMap<String, Preset>, view: View) -> Self {
+        let rows = presets
+            .iter()
+            .map(|(name, preset)| PresetInfo {
+                name: name.clone(),
+                cores: preset.cores,
+                ram: preset.ram,
+                size: preset.size,
+            })
+            .collect();
+        Self { rows, view }
+    }
+}
+
+impl fmt::Display for PresetTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        render(f, &self.rows, &self.view)
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "K", "M", "G", "T"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1}{}", UNITS[unit])
+}
+
+#[derive(Tabled, Serialize)]
+pub struct StatusInfo {
+    #[tabled(rename = "NAME")]
+    name: String,
+    #[tabled(rename = "KIND")]
+    kind: &'static str,
+    #[tabled(rename = "STATUS")]
+    status: MachineStatus,
+    #[tabled(rename = "PID")]
+    pid: String,
+    #[tabled(rename = "UPTIME")]
+    uptime: String,
+    #[tabled(rename = "PORT")]
+    port: u16,
+    #[tabled(rename = "SSH")]
+    ssh: bool,
+    #[tabled(rename = "READY")]
+    ready: bool,
+    #[tabled(rename = "DISK USAGE")]
+    disk_usage: String,
+    #[tabled(rename = "EXPIRES")]
+    expires: String,
+    #[tabled(rename = "CORES", display_with = "display_count")]
+    cores: Option<usize>,
+    #[tabled(rename = "RAM", display_with = "display_size")]
+    ram: Option<Size>,
+    #[tabled(rename = "DISKS", display_with = "display_list")]
+    disks: Vec<String>,
+    #[tabled(skip)]
+    #[serde(skip)]
+    bytes: u64,
+    #[tabled(skip)]
+    expired: bool,
+}
+
+const STATUS_WIDE: &[&str] = &["CORES", "RAM", "DISKS"];
+
+fn display_count(count: &Option<usize>) -> String {
+    count.map_or_else(|| "-".into(), |count| count.to_string())
+}
+
+fn display_size(size: &Option<Size>) -> String {
+    size.map_or_else(|| "-".into(), |size| size.to_string())
+}
+
+fn display_list(list: &[String]) -> String {
+    if list.is_empty() {
+        return "-".into();
+    }
+    list.join(",")
+}
+
+pub struct StatusTable {
+    rows: Vec<StatusInfo>,
+    view: View,
+}
+
+impl StatusTable {
+    pub fn new(state: &State, view: View) -> Result<Self> {
+        let resources = state
+            .machines()
+            .map(|machine| (machine.name.clone(), machine.port, false))
+            .chain(
+                state
+                    .snapshots()
+                    .map(|snapshot| (snapshot.name.clone(), snapshot.port, true)),
+            )
+            .collect::<Vec<_>>();
+
+        let mut rows = Vec::new();
+        for (name, port, snapshot) in resources {
+            let runtime = state.runtime(&name, snapshot)?;
+            let status = state.status(&name, snapshot)?;
+            let running = status == MachineStatus::Running;
+            let bytes = state.disk_usage(&name, snapshot)?;
+            let expiry = state.expiry(&name, snapshot)?;
+            // Probing below can take a while, so the remaining time is measured from the same
+            // moment the expiry was checked at.
+            let now = time::now();
+            let expired = expiry.is_some_and(|expiry| expiry.at <= now);
+            rows.push(StatusInfo {
+                kind: if snapshot { "snapshot" } else { "machine" },
+                status,
+                pid: runtime
+                    .as_ref()
+                    .map(|runtime| format!("{}", runtime.pid))
+                    .unwrap_or_default(),
+                uptime: runtime
+                    .as_ref()
+                    .map(|runtime| time::format_duration(now.saturating_sub(runtime.started_at)))
+                    .unwrap_or_default(),
+                port,
+                ssh: running && probe::port_open(port),
+                ready: running
+                    && state
+                        .probes(&name, snapshot)?
+                        .values()
+                        .all(|probe| probe.ready()),
+                disk_usage: format_bytes(bytes),
+                expires: match expiry {
+                    Some(_) if expired => "EXPIRED".into(),
+                    Some(expiry) => {
+                        format!("in {}", time::format_duration(expiry.at - now))
+                    }
+                    None => "-".into(),
+                },
+                cores: runtime.as_ref().map(|runtime| runtime.cores),
+                ram: runtime.as_ref().map(|runtime| runtime.ram),
+                disks: runtime
+                    .as_ref()
+                    .map(|runtime| runtime.disks.clone())
+                    .unwrap_or_default(),
+                bytes,
+                expired,
+                name,
+            });
+        }
+
+        Ok(Self { rows, view })
+    }
+}
+
+impl fmt::Display for StatusTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        render_wide(f, &self.rows, &self.view, STATUS_WIDE)?;
+        if self.view.is_table() {
+            write!(
+                f,
+                "\n\n{}/{} running, {} on disk",
+                self.rows
+                    .iter()
+                    .filter(|row| row.status.is_active())
+                    .count(),
+                self.rows.len(),
+                format_bytes(self.rows.iter().map(|row| row.bytes).sum())
+            )?;
+            let expired = self.rows.iter().filter(|row| row.expired).count();
+            if expired > 0 {
+                write!(
+                    f,
+                    ", {expired} expired (remove with `rhea prune --expired`)"
+                )?;
+            }
+        }
+        Ok(())
     }
 }
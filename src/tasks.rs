@@ -0,0 +1,51 @@
+use crate::{result::Result, start::StartOptions, state::State};
+use std::{future::Future, sync::Arc};
+use tokio::task;
+
+impl State {
+    /// Starts a virtual machine or snapshot on the blocking thread pool, so that several can
+    /// boot at once. Progress is reported per machine.
+    pub fn start_async(
+        self: &Arc<Self>,
+        name: &str,
+        options: StartOptions,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let state = Arc::clone(self);
+        let name = name.to_string();
+        async move {
+            task::spawn_blocking(move || {
+                state.track("start", &name, || state.start(&name, &options))
+            })
+            .await?
+        }
+    }
+
+    /// Stops a virtual machine or snapshot on the blocking thread pool.
+    pub fn stop_async(
+        self: &Arc<Self>,
+        name: &str,
+        snapshot: bool,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let state = Arc::clone(self);
+        let name = name.to_string();
+        async move {
+            task::spawn_blocking(move || state.track("stop", &name, || state.stop(&name, snapshot)))
+                .await?
+        }
+    }
+}
+
+/// Runs `futures` concurrently, returning their results in order.
+pub async fn join_all<I, F, T>(futures: I) -> Vec<Result<T>>
+where
+    I: IntoIterator<Item = F>,
+    F: Future<Output = Result<T>> + Send + 'static,
+    T: Send + 'static,
+{
+    let handles = futures.into_iter().map(task::spawn).collect::<Vec<_>>();
+    let mut results = Vec::with_capacity(handles.len());
+    for handle in handles {
+        results.push(handle.await.map_err(Into::into).and_then(|result| result));
+    }
+    results
+}
@@ -0,0 +1,218 @@
+use crate::{error::Error, result::Result};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+
+/// The length of a timestamp written by `format_timestamp`.
+pub const TIMESTAMP_LEN: usize = 24;
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
+pub struct Expiry {
+    pub at: u64,
+    pub remove: bool,
+}
+
+impl Expiry {
+    pub fn after(ttl: Duration, remove: bool) -> Result<Self> {
+        let at = now()
+            .checked_add(ttl.as_secs())
+            .ok_or_else(|| Error::InvalidDuration {
+                duration: format!("{}s", ttl.as_secs()),
+            })?;
+        Ok(Self { at, remove })
+    }
+
+    pub fn expired(&self) -> bool {
+        now() >= self.at
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Milliseconds since the epoch as a UTC RFC 3339 timestamp, e.g. `2024-05-01T12:00:00.000Z`.
+pub fn format_timestamp(millis: u64) -> String {
+    let secs = millis / 1000;
+    let (year, month, day) = civil_date(secs / DAY);
+    let time = secs % DAY;
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        time / HOUR,
+        time % HOUR / MINUTE,
+        time % MINUTE,
+        millis % 1000
+    )
+}
+
+/// Reads a timestamp written by `format_timestamp` back into milliseconds since the epoch.
+pub fn parse_timestamp(timestamp: &str) -> Option<u64> {
+    let field = |range: std::ops::Range<usize>| timestamp.get(range)?.parse::<u64>().ok();
+    let bytes = timestamp.as_bytes();
+    if bytes.len() != TIMESTAMP_LEN
+        || [
+            (4, b'-'),
+            (7, b'-'),
+            (10, b'T'),
+            (13, b':'),
+            (16, b':'),
+            (19, b'.'),
+            (23, b'Z'),
+        ]
+        .iter()
+        .any(|&(i, c)| bytes[i] != c)
+    {
+        return None;
+    }
+    let year = field(0..4).filter(|&year| year >= 1970)?;
+    let month = field(5..7).filter(|month| (1..=12).contains(month))?;
+    let day = field(8..10).filter(|day| (1..=31).contains(day))?;
+    let days = days_from_civil(year, month, day);
+    let secs = days * DAY + field(11..13)? * HOUR + field(14..16)? * MINUTE + field(17..19)?;
+    Some(secs * 1000 + field(20..23)?)
+}
+
+/// The year, month and day of a day counted from the epoch.
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    // Counts in 400-year eras starting on 0000-03-01, so that leap days end each year.
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+/// The day counted from the epoch of a year, month and day, the inverse of `civil_date`.
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = year - u64::from(month <= 2);
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    (era * 146_097 + day_of_era).saturating_sub(719_468)
+}
+
+pub fn parse_duration(duration: &str) -> Result<Duration> {
+    let invalid = || Error::InvalidDuration {
+        duration: duration.into(),
+    };
+
+    let split = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(duration.len());
+    let (value, unit) = duration.split_at(split);
+    let value = value.parse::<u64>().map_err(|_| invalid())?;
+
+    let scale = match unit {
+        "" | "s" => 1,
+        "m" => MINUTE,
+        "h" => HOUR,
+        "d" => DAY,
+        "w" => WEEK,
+        _ => return Err(invalid()),
+    };
+
+    Ok(Duration::from_secs(
+        value.checked_mul(scale).ok_or_else(invalid)?,
+    ))
+}
+
+pub fn format_duration(secs: u64) -> String {
+    match secs {
+        secs if secs >= DAY => format!("{}d", secs / DAY),
+        secs if secs >= HOUR => format!("{}h", secs / HOUR),
+        secs if secs >= MINUTE => format!("{}m", secs / MINUTE),
+        secs => format!("{secs}s"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_take_a_unit() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+        assert_eq!(parse_duration("1d").unwrap(), Duration::from_secs(DAY));
+        assert_eq!(parse_duration("2w").unwrap(), Duration::from_secs(2 * WEEK));
+    }
+
+    #[test]
+    fn invalid_durations_are_rejected() {
+        for duration in ["", "m", "-5m", "5 m", "5y", "1.5h", "5mm"] {
+            assert!(
+                matches!(parse_duration(duration), Err(Error::InvalidDuration { .. })),
+                "{duration}"
+            );
+        }
+    }
+
+    #[test]
+    fn overflowing_durations_are_rejected() {
+        assert!(parse_duration(&u64::MAX.to_string()).is_ok());
+        assert!(matches!(
+            parse_duration(&format!("{}w", u64::MAX / WEEK + 1)),
+            Err(Error::InvalidDuration { .. })
+        ));
+        assert!(matches!(
+            parse_duration("99999999999999999999s"),
+            Err(Error::InvalidDuration { .. })
+        ));
+        assert!(matches!(
+            Expiry::after(Duration::from_secs(u64::MAX), false),
+            Err(Error::InvalidDuration { .. })
+        ));
+    }
+
+    #[test]
+    fn timestamps_round_trip() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            format_timestamp(1_714_564_800_123),
+            "2024-05-01T12:00:00.123Z"
+        );
+        assert_eq!(
+            format_timestamp(1_709_164_800_000),
+            "2024-02-29T00:00:00.000Z"
+        );
+        for millis in [0, 1_709_164_800_000, 1_714_564_800_123, 4_102_444_799_999] {
+            assert_eq!(parse_timestamp(&format_timestamp(millis)), Some(millis));
+        }
+    }
+
+    #[test]
+    fn malformed_timestamps_are_ignored() {
+        assert_eq!(parse_timestamp(""), None);
+        assert_eq!(parse_timestamp("2024-05-01 12:00:00.000Z"), None);
+        assert_eq!(parse_timestamp("2024-05-01T12:00:00Z"), None);
+        assert_eq!(parse_timestamp("console output, no time"), None);
+    }
+}
@@ -0,0 +1,50 @@
+use crate::{disk::Disk, machine::Machine, result::Result, snapshot::Snapshot};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+/// A removed resource, kept along with its image until it is restored or its retention
+/// window passes.
+#[derive(Deserialize, Serialize)]
+pub struct Trashed {
+    pub id: String,
+    pub removed_at: u64,
+    #[serde(flatten)]
+    pub resource: Resource,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Resource {
+    Disk(Disk),
+    Machine(Box<Machine>),
+    Snapshot(Snapshot),
+}
+
+impl Resource {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Disk(disk) => &disk.name,
+            Self::Machine(machine) => &machine.name,
+            Self::Snapshot(snapshot) => &snapshot.name,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Disk(_) => "disk",
+            Self::Machine(_) => "machine",
+            Self::Snapshot(_) => "snapshot",
+        }
+    }
+}
+
+impl Trashed {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
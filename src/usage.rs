@@ -0,0 +1,183 @@
+use crate::{error::IoContext, result::Result};
+use std::{
+    fs::{self, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+    process::Command,
+};
+
+/// The header holds how many samples were ever recorded, which tells where the next one goes.
+const HEADER_LEN: u64 = 8;
+const SAMPLE_LEN: u64 = 48;
+
+/// Resources used by a running virtual machine at one point in time.
+#[derive(Clone, Copy)]
+pub struct Sample {
+    pub at: u64,
+    /// CPU time used by the QEMU process since it started.
+    pub cpu_ms: u64,
+    pub rss: u64,
+    /// Bytes allocated to the image on the host.
+    pub disk: u64,
+    pub ram: u64,
+    pub cores: u32,
+}
+
+impl Sample {
+    fn to_bytes(self) -> [u8; SAMPLE_LEN as usize] {
+        let mut bytes = [0; SAMPLE_LEN as usize];
+        let fields = [self.at, self.cpu_ms, self.rss, self.disk, self.ram];
+        for (chunk, field) in bytes.chunks_exact_mut(8).zip(fields) {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+        bytes[40..44].copy_from_slice(&self.cores.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let field = |i: usize| u64::from_le_bytes(bytes[i * 8..i * 8 + 8].try_into().unwrap());
+        Self {
+            at: field(0),
+            cpu_ms: field(1),
+            rss: field(2),
+            disk: field(3),
+            ram: field(4),
+            cores: u32::from_le_bytes(bytes[40..44].try_into().unwrap()),
+        }
+    }
+}
+
+/// CPU time in milliseconds and resident memory in bytes of a process, if it is running.
+pub fn measure(pid: u32) -> Option<(u64, u64)> {
+    let output = Command::new("ps")
+        .args(["-o", "cputime=,rss=", "-p", &pid.to_string()])
+        .output()
+        .ok()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    let mut fields = output.split_whitespace();
+    let cpu_ms = parse_cputime(fields.next()?)?;
+    let rss = fields.next()?.parse::<u64>().ok()? * 1024;
+    Some((cpu_ms, rss))
+}
+
+/// Parses ps's `[dd-]hh:mm:ss` (Linux) or `mm:ss.ss` (macOS) into milliseconds.
+fn parse_cputime(time: &str) -> Option<u64> {
+    let (days, time) = match time.split_once('-') {
+        Some((days, time)) => (days.parse::<f64>().ok()?, time),
+        None => (0.0, time),
+    };
+    let mut seconds = days * 86400.0;
+    for part in time.split(':') {
+        seconds = seconds * 60.0 + part.parse::<f64>().ok()?;
+    }
+    Some((seconds * 1000.0) as u64)
+}
+
+/// Records a sample in the ring buffer at `path`, overwriting the oldest once it holds `capacity`.
+pub fn record<P: AsRef<Path>>(path: P, sample: Sample, capacity: u64) -> Result<()> {
+    let path = path.as_ref();
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .at(path)?;
+    let mut header = [0; HEADER_LEN as usize];
+    let count = match file.read_exact(&mut header) {
+        Ok(()) => u64::from_le_bytes(header),
+        Err(_) => 0,
+    };
+
+    let slot = count % capacity.max(1);
+    file.seek(SeekFrom::Start(HEADER_LEN + slot * SAMPLE_LEN))?;
+    file.write_all(&sample.to_bytes())?;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&(count + 1).to_le_bytes())?;
+    Ok(())
+}
+
+/// Reads the samples at `path`, oldest first.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Sample>> {
+    let path = path.as_ref();
+    if fs::metadata(path).is_err() {
+        return Ok(Vec::new());
+    }
+    let bytes = fs::read(path).at(path)?;
+    let mut samples = bytes
+        .get(HEADER_LEN as usize..)
+        .unwrap_or_default()
+        .chunks_exact(SAMPLE_LEN as usize)
+        .map(Sample::from_bytes)
+        .collect::<Vec<_>>();
+    samples.sort_by_key(|sample| sample.at);
+    Ok(samples)
+}
+
+/// Average and peak use of one resource, next to what was allocated for it.
+pub struct Usage {
+    pub average: f64,
+    pub peak: f64,
+    pub allocated: f64,
+}
+
+/// What a machine used over a series of samples.
+pub struct Summary {
+    pub samples: usize,
+    pub since: u64,
+    /// In cores busy.
+    pub cpu: Usage,
+    pub ram: Usage,
+    pub disk: Usage,
+    /// How much the image grew on the host from the first sample to the last.
+    pub disk_growth: i64,
+}
+
+/// Summarizes samples of a machine whose image is `size` bytes. CPU use is only measured between
+/// consecutive samples of the same boot, taken no more than `gap` seconds apart.
+pub fn summarize(samples: &[Sample], gap: u64, size: u64) -> Option<Summary> {
+    let first = samples.first()?;
+    let last = samples.last()?;
+
+    let cpu = samples
+        .windows(2)
+        .filter(|pair| pair[1].at > pair[0].at && pair[1].at - pair[0].at <= gap)
+        .filter(|pair| pair[1].cpu_ms >= pair[0].cpu_ms)
+        .map(|pair| {
+            let busy = (pair[1].cpu_ms - pair[0].cpu_ms) as f64 / 1000.0;
+            (busy, (pair[1].at - pair[0].at) as f64)
+        })
+        .collect::<Vec<_>>();
+    let (busy, elapsed) = cpu.iter().fold((0.0, 0.0), |(busy, elapsed), (b, e)| {
+        (busy + b, elapsed + e)
+    });
+
+    let average = |f: fn(&Sample) -> u64| {
+        samples.iter().map(|sample| f(sample) as f64).sum::<f64>() / samples.len() as f64
+    };
+    let peak = |f: fn(&Sample) -> u64| samples.iter().map(f).max().unwrap_or(0) as f64;
+
+    Some(Summary {
+        samples: samples.len(),
+        since: first.at,
+        cpu: Usage {
+            average: if elapsed > 0.0 { busy / elapsed } else { 0.0 },
+            peak: cpu
+                .iter()
+                .map(|(busy, elapsed)| busy / elapsed)
+                .fold(0.0, f64::max),
+            allocated: last.cores as f64,
+        },
+        ram: Usage {
+            average: average(|sample| sample.rss),
+            peak: peak(|sample| sample.rss),
+            allocated: last.ram as f64,
+        },
+        disk: Usage {
+            average: average(|sample| sample.disk),
+            peak: peak(|sample| sample.disk),
+            allocated: size as f64,
+        },
+        disk_growth: last.disk as i64 - first.disk as i64,
+    })
+}
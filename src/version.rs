@@ -0,0 +1,239 @@
+use crate::{
+    config::Binaries,
+    error::Error,
+    remote::{self, Remote},
+    result::Result,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    result,
+    str::FromStr,
+    time::UNIX_EPOCH,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Finds the version in the first line of `--version` output, e.g.
+    /// `QEMU emulator version 8.2.2 (Debian 1:8.2.2+ds-0ubuntu1)`, ignoring suffixes like `-rc1`.
+    pub fn from_output(output: &str) -> Option<Self> {
+        let (_, version) = output.lines().next()?.split_once("version ")?;
+        let end = version
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(version.len());
+        version[..end].trim_end_matches('.').parse().ok()
+    }
+}
+
+impl FromStr for Version {
+    type Err = Error;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        let invalid = || Error::InvalidVersion { version: s.into() };
+        let mut parts = s.split('.').map(|part| part.parse::<u32>());
+        let mut next = || parts.next().unwrap_or(Ok(0)).map_err(|_| invalid());
+        Ok(Self {
+            major: next()?,
+            minor: next()?,
+            patch: next()?,
+        })
+    }
+}
+
+impl TryFrom<String> for Version {
+    type Error = Error;
+
+    fn try_from(s: String) -> result::Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Version> for String {
+    fn from(version: Version) -> Self {
+        version.to_string()
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Something rhea asks of QEMU that not every version has.
+#[derive(Clone, Copy)]
+pub enum Feature {
+    /// `-accel`, instead of `-machine accel=`.
+    Accel,
+    /// The `filter-dump` object, for `rhea pcap`.
+    FilterDump,
+    /// `object-add` taking properties as arguments, instead of under `props`.
+    FlatObjectAdd,
+}
+
+impl Feature {
+    pub fn since(self) -> Version {
+        match self {
+            Self::Accel => Version::new(4, 2, 0),
+            Self::FilterDump => Version::new(2, 5, 0),
+            Self::FlatObjectAdd => Version::new(6, 0, 0),
+        }
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Accel => write!(f, "-accel"),
+            Self::FilterDump => write!(f, "packet capture"),
+            Self::FlatObjectAdd => write!(f, "flat object-add"),
+        }
+    }
+}
+
+/// Installed versions of QEMU and qemu-img, `None` when they couldn't be found.
+#[derive(Clone, Copy, Default, Deserialize, Serialize)]
+pub struct Versions {
+    pub qemu: Option<Version>,
+    pub qemu_img: Option<Version>,
+}
+
+impl Versions {
+    /// Runs both with `--version` on the host they run on.
+    pub fn probe(remote: Option<&Remote>, binaries: &Binaries) -> Self {
+        let version = |program| {
+            let output = remote::command(remote, program)
+                .arg("--version")
+                .output()
+                .ok()?;
+            Version::from_output(&String::from_utf8_lossy(&output.stdout))
+        };
+        Self {
+            qemu: version(binaries.qemu()),
+            qemu_img: version(binaries.qemu_img()),
+        }
+    }
+
+    /// Whether QEMU has `feature`, assuming it does if its version is unknown.
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.qemu.is_none_or(|qemu| qemu >= feature.since())
+    }
+
+    pub fn require(&self, feature: Feature) -> Result<()> {
+        match self.qemu {
+            Some(qemu) if !self.supports(feature) => Err(Error::QemuTooOld {
+                feature: feature.to_string(),
+                required: feature.since().to_string(),
+                installed: qemu.to_string(),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Versions probed before, valid as long as the same binaries are configured and QEMU hasn't
+/// changed.
+#[derive(Deserialize, Serialize)]
+struct Cache {
+    binary: PathBuf,
+    qemu_img: String,
+    modified: u64,
+    versions: Versions,
+}
+
+/// Probes the local versions, or reads them from `cache` if QEMU hasn't changed since.
+pub fn cached<P: AsRef<Path>>(cache: P, binaries: &Binaries) -> Versions {
+    let Some((binary, modified)) = which(binaries.qemu()).and_then(|binary| {
+        let modified = fs::metadata(&binary).ok()?.modified().ok()?;
+        Some((binary, modified.duration_since(UNIX_EPOCH).ok()?.as_secs()))
+    }) else {
+        return Versions::probe(None, binaries);
+    };
+
+    let hit = fs::read_to_string(&cache)
+        .ok()
+        .and_then(|cache| toml::from_str::<Cache>(&cache).ok())
+        .filter(|cache| {
+            cache.binary == binary
+                && cache.qemu_img == binaries.qemu_img()
+                && cache.modified == modified
+        });
+    if let Some(hit) = hit {
+        return hit.versions;
+    }
+
+    let versions = Versions::probe(None, binaries);
+    let entry = Cache {
+        binary,
+        qemu_img: binaries.qemu_img().into(),
+        modified,
+        versions,
+    };
+    if let Ok(entry) = toml::to_string(&entry) {
+        fs::write(cache, entry).ok();
+    }
+    versions
+}
+
+/// Where `program` is, either given as a path or found in PATH.
+pub(crate) fn which(program: &str) -> Option<PathBuf> {
+    if program.contains('/') {
+        return Some(remote::expand_home(program).into());
+    }
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_are_read_from_version_output() {
+        assert_eq!(
+            Version::from_output(
+                "QEMU emulator version 8.2.2 (Debian 1:8.2.2+ds-0ubuntu1)\nCopyright (c) 2003-2023"
+            ),
+            Some(Version::new(8, 2, 2))
+        );
+        assert_eq!(
+            Version::from_output("qemu-img version 9.0.0-rc1"),
+            Some(Version::new(9, 0, 0))
+        );
+        assert_eq!(
+            Version::from_output("QEMU emulator version 7.2."),
+            Some(Version::new(7, 2, 0))
+        );
+    }
+
+    #[test]
+    fn unversioned_output_is_ignored() {
+        assert_eq!(Version::from_output(""), None);
+        assert_eq!(Version::from_output("QEMU emulator"), None);
+        assert_eq!(Version::from_output("QEMU emulator version x"), None);
+        assert_eq!(Version::from_output("\nQEMU emulator version 8.2.2"), None);
+    }
+
+    #[test]
+    fn versions_compare_numerically() {
+        assert!("10.0".parse::<Version>().unwrap() > "9.2.1".parse().unwrap());
+        assert_eq!("8".parse::<Version>().unwrap(), Version::new(8, 0, 0));
+    }
+}
@@ -0,0 +1,57 @@
+use crate::tables::View;
+use anyhow::Result;
+use rhea::{state::State, status::MachineStatus};
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    thread,
+    time::Duration,
+};
+
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+/// Redraws a listing every `interval` until interrupted, highlighting the rows of virtual
+/// machines and snapshots whose status changed since the last refresh.
+pub fn watch<F>(state: &mut State, interval: Duration, view: View, render: F) -> Result<()>
+where
+    F: Fn(&State, View) -> Result<String>,
+{
+    let mut previous: Option<BTreeMap<String, MachineStatus>> = None;
+    loop {
+        state.reload()?;
+        let current = statuses(state)?;
+        let highlight = match &previous {
+            Some(previous) => current
+                .iter()
+                .filter(|(name, status)| previous.get(*name) != Some(*status))
+                .map(|(name, _)| name.clone())
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let output = render(
+            state,
+            View {
+                highlight,
+                ..view.clone()
+            },
+        )?;
+        let mut stdout = io::stdout().lock();
+        writeln!(stdout, "{CLEAR_SCREEN}{output}")?;
+        stdout.flush()?;
+
+        previous = Some(current);
+        thread::sleep(interval);
+    }
+}
+
+fn statuses(state: &State) -> Result<BTreeMap<String, MachineStatus>> {
+    let mut statuses = BTreeMap::new();
+    for machine in state.machines() {
+        statuses.insert(machine.name.clone(), state.status(&machine.name, false)?);
+    }
+    for snapshot in state.snapshots() {
+        statuses.insert(snapshot.name.clone(), state.status(&snapshot.name, true)?);
+    }
+    Ok(statuses)
+}
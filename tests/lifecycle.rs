@@ -0,0 +1,1221 @@
+use rhea::{
+    arch,
+    config::{Config, Logs, Timeouts},
+    error::Error,
+    firmware::FirmwareMode,
+    journal::ExitReason,
+    logs::{self, Filter},
+    mock::{MockImages, MockProcesses},
+    namespace,
+    os::GuestOs,
+    probe::{self, Probe},
+    process::{Host, ProcessBackend},
+    profile,
+    reference::ResourceRef,
+    shared::SharedState,
+    size::Size,
+    start::StartOptions,
+    state::State,
+    status::MachineStatus,
+    time,
+    usage::{self, Sample},
+    version::{Feature, Version, Versions},
+};
+use std::{
+    env, fs,
+    io::Write,
+    net::TcpListener,
+    process::Command,
+    thread,
+    time::{Duration, Instant},
+};
+use tempfile::TempDir;
+
+struct Fixture {
+    dir: TempDir,
+    state: State,
+    processes: MockProcesses,
+    images: MockImages,
+}
+
+fn fixture() -> Fixture {
+    let dir = TempDir::new().unwrap();
+    // Only passed along to QEMU, which never runs.
+    env::set_var("RHEA_UEFI_PATH", "OVMF.fd");
+    // Mock processes can't fail to boot, so there is no need to wait for them.
+    fs::write(
+        dir.path().join("config.toml"),
+        "[timeouts]\nstart_grace = 0\n",
+    )
+    .unwrap();
+
+    let processes = MockProcesses::new();
+    let images = MockImages::new();
+    let mut state = State::load(dir.path()).unwrap();
+    state.set_process_backend(Box::new(processes.clone()));
+    state.set_image_backend(Box::new(images.clone()));
+
+    Fixture {
+        dir,
+        state,
+        processes,
+        images,
+    }
+}
+
+#[test]
+fn add_start_stop_machine() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        processes,
+        ..
+    } = fixture();
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    assert_eq!(state.get_machine("foo").unwrap().size, Size::gigabytes(32));
+    assert!(matches!(
+        state.status("foo", false).unwrap(),
+        MachineStatus::Stopped
+    ));
+
+    state.start("foo", &StartOptions::default()).unwrap();
+    assert!(state.machine_in_use("foo").unwrap());
+    assert!(matches!(
+        state.status("foo", false).unwrap(),
+        MachineStatus::Running
+    ));
+    let spawned = processes.spawned();
+    assert_eq!(spawned.len(), 1);
+    assert!(spawned[0].iter().any(|arg| arg.contains("foo.qcow2")));
+    assert!(spawned[0]
+        .iter()
+        .any(|arg| arg.contains("hostfwd=tcp::8192-:22")));
+    // A guest powering off is left for QMP to report rather than taken for a crash.
+    assert!(spawned[0].iter().any(|arg| arg == "-no-shutdown"));
+
+    assert!(matches!(
+        state.start("foo", &StartOptions::default()),
+        Err(Error::MachineInUse { .. })
+    ));
+
+    state.stop("foo", false).unwrap();
+    assert!(!state.machine_in_use("foo").unwrap());
+    assert!(processes.running().is_empty());
+}
+
+#[test]
+fn crashed_machine_is_detected() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        processes,
+        ..
+    } = fixture();
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    state.start("foo", &StartOptions::default()).unwrap();
+    let pid = state.runtime("foo", false).unwrap().unwrap().pid;
+
+    processes.exit(pid);
+    assert!(matches!(
+        state.status("foo", false).unwrap(),
+        MachineStatus::Crashed
+    ));
+    assert_eq!(state.exited("foo", false).unwrap().unwrap().pid, pid);
+
+    let crashes = state.crashes().unwrap();
+    assert_eq!(crashes.len(), 1);
+    assert_eq!(crashes[0].pid, pid);
+    assert_eq!(crashes[0].reason, "exited");
+    state.crash(&crashes[0].id).unwrap();
+
+    state.start("foo", &StartOptions::default()).unwrap();
+    assert!(state.exited("foo", false).unwrap().is_none());
+}
+
+#[test]
+fn reused_pids_are_not_taken_for_the_machine() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        processes,
+        ..
+    } = fixture();
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    state.start("foo", &StartOptions::default()).unwrap();
+    let pid = state.runtime("foo", false).unwrap().unwrap().pid;
+
+    processes.reuse(pid);
+    assert!(state.runtime("foo", false).unwrap().is_none());
+    assert!(matches!(
+        state.stop("foo", false),
+        Err(Error::MachineNotInUse { .. })
+    ));
+    assert_eq!(processes.running(), [pid]);
+    assert!(matches!(
+        state.status("foo", false).unwrap(),
+        MachineStatus::Crashed
+    ));
+}
+
+#[test]
+fn disks_are_attached_exclusively() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        processes,
+        ..
+    } = fixture();
+
+    state.add_disk("scratch", Size::gigabytes(8)).unwrap();
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    state.add_machine("bar", 8193, Size::gigabytes(32)).unwrap();
+
+    let options = StartOptions::default().disk("scratch");
+    state.start("foo", &options).unwrap();
+    assert!(state.disk_in_use("scratch").unwrap());
+    assert!(processes.spawned()[0]
+        .iter()
+        .any(|arg| arg.contains("scratch.qcow2")));
+
+    assert!(matches!(
+        state.start("bar", &options),
+        Err(Error::DiskInUse { .. })
+    ));
+    assert!(matches!(
+        state.remove_disk("scratch"),
+        Err(Error::DiskInUse { .. })
+    ));
+
+    state.stop("foo", false).unwrap();
+    assert!(!state.disk_in_use("scratch").unwrap());
+}
+
+#[test]
+fn snapshots_protect_their_base() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        processes,
+        images,
+    } = fixture();
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    state.add_snapshot("foo-1", "foo", false, false).unwrap();
+
+    let snapshot = state.get_snapshot("foo-1").unwrap();
+    assert_eq!(snapshot.size, Size::gigabytes(32));
+    let overlay = images
+        .get(&state.path().join("snapshots").join("foo-1.qcow2"))
+        .unwrap();
+    assert_eq!(
+        overlay.backing,
+        Some(state.path().join("machines").join("foo.qcow2"))
+    );
+
+    assert!(matches!(
+        state.start("foo", &StartOptions::default()),
+        Err(Error::BackingInUse { .. })
+    ));
+
+    let options = StartOptions::default().snapshot(true);
+    state.start("foo-1", &options).unwrap();
+    assert!(state.snapshot_in_use("foo-1").unwrap());
+    assert!(matches!(
+        state.remove_snapshot("foo-1"),
+        Err(Error::SnapshotInUse { .. })
+    ));
+
+    let pid = state.runtime("foo-1", true).unwrap().unwrap().pid;
+    processes.exit(pid);
+    assert!(!state.snapshot_in_use("foo-1").unwrap());
+    state.remove_snapshot("foo-1").unwrap();
+    state.start("foo", &StartOptions::default()).unwrap();
+    state.stop("foo", false).unwrap();
+}
+
+#[test]
+fn removed_machines_can_be_restored() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        ..
+    } = fixture();
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    state.remove_machine("foo").unwrap();
+    assert!(state.get_machine("foo").is_err());
+
+    assert_eq!(state.restore_trashed(None).unwrap(), "foo");
+    assert_eq!(state.get_machine("foo").unwrap().port, 8192);
+}
+
+#[test]
+fn machines_backing_snapshots_are_not_removed() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        images,
+        ..
+    } = fixture();
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    state.add_snapshot("foo-1", "foo", false, false).unwrap();
+    assert!(matches!(
+        state.remove_machine("foo"),
+        Err(Error::MachineHasSnapshots { .. })
+    ));
+    assert!(images
+        .get(&state.path().join("machines").join("foo.qcow2"))
+        .is_some());
+
+    state.remove_snapshot("foo-1").unwrap();
+    state.remove_machine("foo").unwrap();
+}
+
+#[test]
+fn state_round_trips_through_save() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        ..
+    } = fixture();
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    state.add_disk("scratch", Size::gigabytes(8)).unwrap();
+    state
+        .set_machine_description("foo", Some("build host".into()))
+        .unwrap();
+    state.save().unwrap();
+
+    let path = state.path().to_path_buf();
+    drop(state);
+    let state = State::load_read_only(&path).unwrap();
+    assert_eq!(state.get_machine("foo").unwrap().port, 8192);
+    assert_eq!(state.get_disk("scratch").unwrap().size, Size::gigabytes(8));
+    assert_eq!(
+        state.get_machine("foo").unwrap().description.as_deref(),
+        Some("build host")
+    );
+}
+
+#[test]
+fn starts_without_a_qmp_handshake_are_cleaned_up() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        processes,
+        ..
+    } = fixture();
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    let timeouts = Timeouts {
+        qmp: Some(0),
+        ..Timeouts::default()
+    };
+    state.set_machine_timeouts("foo", timeouts).unwrap();
+
+    // Mock processes never answer QMP.
+    let options = StartOptions::default().wait_qmp(true);
+    assert!(matches!(
+        state.start("foo", &options),
+        Err(Error::StartFailed { .. })
+    ));
+    assert!(processes.running().is_empty());
+    assert!(state.runtime("foo", false).unwrap().is_none());
+    assert!(state.exited("foo", false).unwrap().is_none());
+    let reasons = state
+        .boots("foo", false)
+        .unwrap()
+        .into_iter()
+        .map(|boot| boot.reason)
+        .collect::<Vec<_>>();
+    assert_eq!(reasons, [ExitReason::Failed]);
+}
+
+#[test]
+fn failed_starts_are_not_stamped() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        ..
+    } = fixture();
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    state.add_disk("data", Size::gigabytes(8)).unwrap();
+    let timeouts = Timeouts {
+        qmp: Some(0),
+        ..Timeouts::default()
+    };
+    state.set_machine_timeouts("foo", timeouts).unwrap();
+    let shared = SharedState::new(state);
+
+    // Mock processes never answer QMP.
+    let options = StartOptions::default().wait_qmp(true).disks(["data"]);
+    assert!(shared.start("foo", &options).is_err());
+    let state = shared.read();
+    assert!(state.get_machine("foo").unwrap().last_started_at.is_none());
+    assert!(state.get_disk("data").unwrap().last_started_at.is_none());
+}
+
+#[test]
+fn restart_keeps_the_running_configuration() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        processes,
+        ..
+    } = fixture();
+
+    state.add_disk("scratch", Size::gigabytes(8)).unwrap();
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    let options = StartOptions::default()
+        .cores(2)
+        .ram(Size::gigabytes(1))
+        .disk("scratch");
+    state.start("foo", &options).unwrap();
+    let before = state.runtime("foo", false).unwrap().unwrap();
+
+    state.restart("foo", false).unwrap();
+    let after = state.runtime("foo", false).unwrap().unwrap();
+    assert_ne!(before.pid, after.pid);
+    assert_eq!(after.cores, 2);
+    assert_eq!(after.ram, Size::gigabytes(1));
+    assert_eq!(after.disks, vec!["scratch".to_string()]);
+    assert_eq!(processes.running(), vec![after.pid]);
+}
+
+#[test]
+fn describe_reports_status_and_paths() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        ..
+    } = fixture();
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+
+    let machine = state.describe_machine("foo").unwrap();
+    assert!(matches!(machine.status, MachineStatus::Stopped));
+    assert!(machine.pid.is_none());
+    assert!(machine.path.ends_with("foo.qcow2"));
+
+    state.start("foo", &StartOptions::default()).unwrap();
+    let machine = state.describe_machine("foo").unwrap();
+    assert!(matches!(machine.status, MachineStatus::Running));
+    assert!(machine.pid.is_some());
+    state.stop("foo", false).unwrap();
+
+    state.add_snapshot("foo-1", "foo", false, false).unwrap();
+    let snapshot = state.describe_snapshot("foo-1").unwrap();
+    assert_eq!(snapshot.base, "foo");
+    assert_eq!(snapshot.backing_path, Some(machine.path));
+
+    assert!(matches!(
+        state.describe_disk("foo"),
+        Err(Error::InvalidDisk { .. })
+    ));
+}
+
+#[test]
+fn shared_state_serves_threads() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        ..
+    } = fixture();
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    state.add_machine("bar", 8193, Size::gigabytes(32)).unwrap();
+    let shared = SharedState::new(state);
+
+    let handles = ["foo", "bar"]
+        .map(|name| {
+            let shared = shared.clone();
+            thread::spawn(move || shared.start(name, &StartOptions::default()))
+        })
+        .into_iter()
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.join().unwrap().unwrap();
+    }
+
+    let state = shared.read();
+    for name in ["foo", "bar"] {
+        assert!(matches!(
+            state.status(name, false).unwrap(),
+            MachineStatus::Running
+        ));
+        assert!(state.get_machine(name).unwrap().last_started_at.is_some());
+    }
+}
+
+#[test]
+fn snapshot_provenance() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        ..
+    } = fixture();
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    state.add_snapshot("foo-1", "foo", false, false).unwrap();
+    state.add_snapshot("foo-2", "foo-1", true, false).unwrap();
+    state.add_snapshot("foo-3", "foo-1", true, true).unwrap();
+
+    assert_eq!(state.snapshot_ancestors("foo-2").unwrap(), ["foo-1", "foo"]);
+    assert_eq!(state.snapshot_ancestors("foo-3").unwrap(), ["foo"]);
+    assert_eq!(state.snapshot_delta("foo-2").unwrap(), Some(0));
+    assert_eq!(state.snapshot_delta("foo-3").unwrap(), None);
+}
+
+#[test]
+fn checkpoints_are_pruned_and_rolled_back_to() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        images,
+        ..
+    } = fixture();
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    let first = state.checkpoint("foo").unwrap();
+    state
+        .resize_machine("foo", Size::gigabytes(64), false)
+        .unwrap();
+    for _ in 0..3 {
+        state.checkpoint("foo").unwrap();
+    }
+
+    let checkpoints = state.checkpoints("foo");
+    assert_eq!(checkpoints.len(), 3);
+    assert!(checkpoints
+        .iter()
+        .all(|checkpoint| checkpoint.name != first));
+
+    // Checkpoints are full copies, so they don't keep the machine from starting.
+    state.start("foo", &StartOptions::default()).unwrap();
+    state.stop("foo", false).unwrap();
+
+    let latest = state.checkpoints("foo").last().unwrap().name.clone();
+    state.rollback("foo", &latest).unwrap();
+    assert_eq!(state.get_machine("foo").unwrap().size, Size::gigabytes(64));
+    assert!(images
+        .get(&state.path().join("machines/foo.qcow2"))
+        .is_some());
+    assert!(matches!(
+        state.rollback("foo", &first),
+        Err(Error::InvalidCheckpoint { .. })
+    ));
+}
+
+#[test]
+fn boots_are_journaled() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        processes,
+        ..
+    } = fixture();
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    assert!(state.boots("foo", false).unwrap().is_empty());
+
+    state.start("foo", &StartOptions::default()).unwrap();
+    state.stop("foo", false).unwrap();
+    state.start("foo", &StartOptions::default()).unwrap();
+    let pid = state.runtime("foo", false).unwrap().unwrap().pid;
+    processes.exit(pid);
+    state.status("foo", false).unwrap();
+    state.status("foo", false).unwrap();
+
+    let reasons = state
+        .boots("foo", false)
+        .unwrap()
+        .into_iter()
+        .map(|boot| boot.reason)
+        .collect::<Vec<_>>();
+    assert_eq!(reasons, [ExitReason::Stopped, ExitReason::Crashed]);
+}
+
+#[test]
+fn exits_are_recorded_by_whoever_holds_the_lock() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        processes,
+        ..
+    } = fixture();
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    state.start("foo", &StartOptions::default()).unwrap();
+    let pid = state.runtime("foo", false).unwrap().unwrap().pid;
+    processes.exit(pid);
+
+    // Reading leaves the exit alone while another rhea works on the machine.
+    let lock = state.lock_resource("foo", false).unwrap();
+    assert!(state.runtime("foo", false).unwrap().is_none());
+    assert!(matches!(
+        state.status("foo", false).unwrap(),
+        MachineStatus::Crashed
+    ));
+    assert!(state.boots("foo", false).unwrap().is_empty());
+    assert!(state.crashes().unwrap().is_empty());
+
+    drop(lock);
+    state.status("foo", false).unwrap();
+    state.status("foo", false).unwrap();
+    assert_eq!(state.boots("foo", false).unwrap().len(), 1);
+    assert_eq!(state.crashes().unwrap().len(), 1);
+}
+
+#[test]
+fn probes_need_the_guest_to_answer() {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let probe = Probe {
+        host_port: listener.local_addr().unwrap().port(),
+        guest_port: 80,
+        path: None,
+    };
+
+    // Like QEMU forwarding to a guest port that nothing listens on.
+    let refusing = thread::spawn(move || {
+        drop(listener.accept().unwrap());
+        listener
+    });
+    assert!(!probe.ready());
+    let listener = refusing.join().unwrap();
+
+    let listening = thread::spawn(move || {
+        let _stream = listener.accept().unwrap();
+        thread::sleep(Duration::from_secs(1));
+    });
+    assert!(probe.ready());
+    listening.join().unwrap();
+}
+
+#[test]
+fn ssh_is_ready_once_the_banner_arrives() {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    // QEMU accepts the connection before sshd in the guest is up.
+    let silent = thread::spawn(move || {
+        let _stream = listener.accept().unwrap();
+        thread::sleep(Duration::from_secs(2));
+        listener
+    });
+    assert!(!probe::ssh_ready(port));
+    let listener = silent.join().unwrap();
+
+    let sshd = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"SSH-2.0-OpenSSH_9.6\r\n").unwrap();
+    });
+    assert!(probe::ssh_ready(port));
+    sshd.join().unwrap();
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn host_processes_report_when_they_started() {
+    let pid = std::process::id();
+    assert!(Host.started(pid).is_some());
+    assert_eq!(Host.started(pid), Host.started(pid));
+}
+
+#[test]
+fn exited_children_are_not_alive() {
+    // Never waited on, so it stays a zombie until `alive` reaps it.
+    let pid = Host.spawn(&mut Command::new("true")).unwrap().id();
+    thread::sleep(Duration::from_millis(200));
+    assert!(!Host.alive(pid));
+}
+
+#[test]
+fn channels_are_exposed_as_sockets() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        processes,
+        ..
+    } = fixture();
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    state.add_channel("foo", "telemetry").unwrap();
+    assert!(matches!(
+        state.add_channel("foo", "telemetry"),
+        Err(Error::ChannelExists { .. })
+    ));
+    assert!(matches!(
+        state.add_channel("foo", "bad,name"),
+        Err(Error::InvalidChannel { .. })
+    ));
+
+    state.start("foo", &StartOptions::default()).unwrap();
+    let (_, socket) = state.channels("foo", false).unwrap().remove(0);
+    let args = processes.spawned().remove(0);
+    assert!(args
+        .iter()
+        .any(|arg| arg.contains(&*socket.to_string_lossy())));
+    assert!(args.iter().any(|arg| arg.ends_with("name=telemetry")));
+}
+
+#[test]
+fn isolated_machines_only_reach_allowed_hosts() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        processes,
+        ..
+    } = fixture();
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    assert!(matches!(
+        state.set_machine_isolation("foo", Some(true), vec!["proctor".into()]),
+        Err(Error::InvalidForward { .. })
+    ));
+    state
+        .set_machine_isolation("foo", Some(true), vec!["proctor:443".into()])
+        .unwrap();
+    assert!(matches!(
+        state.set_machine_network("foo", "tap:tap0".parse().unwrap()),
+        Err(Error::IsolatedNetwork { .. })
+    ));
+
+    state.start("foo", &StartOptions::default()).unwrap();
+    let args = processes.spawned().remove(0);
+    let user = args.iter().find(|arg| arg.starts_with("user,")).unwrap();
+    assert!(user.contains(",restrict=on"));
+    assert!(user.contains(",guestfwd=tcp:10.0.2.100:443-tcp:proctor:443"));
+}
+
+#[test]
+fn usage_samples_wrap_around() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("foo.usage");
+    for i in 0..5 {
+        let sample = Sample {
+            at: 1000 + i * 60,
+            cpu_ms: i * 30_000,
+            rss: (i + 1) << 30,
+            disk: 1 << 30,
+            ram: 4 << 30,
+            cores: 2,
+        };
+        usage::record(&path, sample, 3).unwrap();
+    }
+
+    let samples = usage::load(&path).unwrap();
+    assert_eq!(
+        samples.iter().map(|sample| sample.at).collect::<Vec<_>>(),
+        [1120, 1180, 1240]
+    );
+    let summary = usage::summarize(&samples, 120, 32 << 30).unwrap();
+    assert_eq!(summary.cpu.average, 0.5);
+    assert_eq!(summary.ram.peak, (5u64 << 30) as f64);
+    assert_eq!(summary.disk_growth, 0);
+}
+
+#[test]
+fn qemu_versions_gate_features() {
+    let version = |output: &str| Version::from_output(output).unwrap();
+    assert_eq!(
+        version("QEMU emulator version 8.2.2 (Debian 1:8.2.2+ds-0ubuntu1)"),
+        Version::new(8, 2, 2)
+    );
+    assert_eq!(
+        version("qemu-img version 9.1.0-rc1\n"),
+        Version::new(9, 1, 0)
+    );
+    assert_eq!(version("QEMU emulator version 4.2"), Version::new(4, 2, 0));
+
+    let old = Versions {
+        qemu: Some(Version::new(2, 4, 1)),
+        qemu_img: None,
+    };
+    assert!(!old.supports(Feature::Accel));
+    assert!(matches!(
+        old.require(Feature::FilterDump),
+        Err(Error::QemuTooOld { .. })
+    ));
+    // Unknown versions are assumed to be recent.
+    assert!(Versions::default().require(Feature::FilterDump).is_ok());
+}
+
+#[test]
+fn machines_run_their_own_qemu_under_a_wrapper() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        processes,
+        ..
+    } = fixture();
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    state
+        .set_machine_binaries(
+            "foo",
+            Some("/opt/qemu/bin/qemu-system".into()),
+            Some("nice -n 10".into()),
+        )
+        .unwrap();
+    state.start("foo", &StartOptions::default()).unwrap();
+    let args = processes.spawned().remove(0);
+    assert_eq!(args[..4], ["nice", "-n", "10", "/opt/qemu/bin/qemu-system"]);
+
+    // Empty overrides go back to QEMU from PATH.
+    state
+        .set_machine_binaries("foo", Some(String::new()), Some(String::new()))
+        .unwrap();
+    let binaries = state.binaries(state.get_machine("foo").unwrap());
+    assert!(binaries.wrapper().is_empty());
+    assert!(binaries.qemu().starts_with("qemu-system-"));
+}
+
+#[test]
+fn discovered_firmware_is_remembered_in_config() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("config.toml");
+    fs::write(&path, "[timeouts]\nstart_grace = 0").unwrap();
+
+    Config::remember_firmware(&path, "/usr/share/OVMF/OVMF_CODE.fd".as_ref()).unwrap();
+    Config::remember_firmware(&path, "/elsewhere/OVMF.fd".as_ref()).unwrap();
+    let config = Config::load(&path).unwrap();
+    assert_eq!(config.timeouts.start_grace, Some(0));
+    assert_eq!(
+        config.firmware.path.unwrap().to_str(),
+        Some("/usr/share/OVMF/OVMF_CODE.fd")
+    );
+}
+
+#[test]
+fn bios_machines_boot_without_uefi_firmware() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        processes,
+        ..
+    } = fixture();
+
+    if !arch::HAS_BIOS {
+        assert!(matches!(
+            "bios".parse::<FirmwareMode>(),
+            Err(Error::BiosUnsupported)
+        ));
+        return;
+    }
+
+    state
+        .add_machine("uefi", 8192, Size::gigabytes(32))
+        .unwrap();
+    state
+        .add_machine("bios", 8193, Size::gigabytes(32))
+        .unwrap();
+    state
+        .set_machine_firmware("bios", "bios".parse().unwrap())
+        .unwrap();
+    state.start("uefi", &StartOptions::default()).unwrap();
+    state.start("bios", &StartOptions::default()).unwrap();
+
+    let spawned = processes.spawned();
+    assert!(spawned[0].iter().any(|arg| arg == "-bios"));
+    assert!(!spawned[1].iter().any(|arg| arg == "-bios"));
+}
+
+#[test]
+fn guest_os_is_detected_and_applied() {
+    let Fixture {
+        dir,
+        mut state,
+        processes,
+        ..
+    } = fixture();
+
+    // An ISO 9660 image is only recognised by its volume descriptor at sector 16.
+    let mut iso = vec![0; 16 * 2048 + 72];
+    iso[16 * 2048 + 1..16 * 2048 + 6].copy_from_slice(b"CD001");
+    iso[16 * 2048 + 40..16 * 2048 + 63].copy_from_slice(b"CCCOMA_X64FRE_EN-US_DV9");
+    let path = dir.path().join("install.iso");
+    fs::write(&path, iso).unwrap();
+    assert!(matches!(GuestOs::detect(&path), Some(GuestOs::Windows)));
+    assert!(matches!(
+        GuestOs::detect("ubuntu-24.04-server-cloudimg-amd64.img".as_ref()),
+        Some(GuestOs::Ubuntu)
+    ));
+    assert!(matches!(
+        GuestOs::detect("ubuntu-24.04-live-server-amd64.iso".as_ref()),
+        Some(GuestOs::Linux)
+    ));
+    assert!(GuestOs::detect("disk.qcow2".as_ref()).is_none());
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    state.set_machine_os("foo", Some(GuestOs::Windows)).unwrap();
+    assert_eq!(state.ssh_user("foo", false, None).unwrap(), "Administrator");
+    state.start("foo", &StartOptions::default()).unwrap();
+    let args = processes.spawned().remove(0);
+    assert!(args.iter().any(|arg| arg == "nic,model=e1000"));
+    if arch::HAS_BIOS {
+        assert!(args.iter().any(|arg| arg.starts_with("ide-hd,drive=hd0")));
+    }
+}
+
+#[test]
+fn snapshots_run_side_by_side_on_their_own_ports() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        processes,
+        ..
+    } = fixture();
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    state.add_snapshot("foo-1", "foo", false, false).unwrap();
+    state.add_snapshot("foo-2", "foo", false, false).unwrap();
+    let port = state.next_port(8192).unwrap();
+    state
+        .set_snapshot_resources("foo-2", Some(port), Some(2), Some(Size::gigabytes(1)))
+        .unwrap();
+    assert_eq!(port, 8193);
+
+    assert_eq!(
+        state.start_resources("foo-2", true, None, None).unwrap(),
+        (2, Size::gigabytes(1))
+    );
+    assert_eq!(
+        state.start_resources("foo-2", true, Some(8), None).unwrap(),
+        (8, Size::gigabytes(1))
+    );
+
+    let options = StartOptions::default().snapshot(true);
+    state.start("foo-1", &options).unwrap();
+    state.start("foo-2", &options).unwrap();
+    let spawned = processes.spawned();
+    assert!(spawned[0]
+        .iter()
+        .any(|arg| arg.contains("hostfwd=tcp::8192-:22")));
+    assert!(spawned[1]
+        .iter()
+        .any(|arg| arg.contains("hostfwd=tcp::8193-:22")));
+}
+
+#[test]
+fn imported_machines_start_with_the_exported_resources() {
+    let Fixture { dir, mut state, .. } = fixture();
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    let image = dir.path().join("foo.qcow2");
+    state
+        .export("foo", false, &image, 2, Size::gigabytes(1), false)
+        .unwrap();
+
+    state.import(&image, Some("bar")).unwrap();
+    assert_eq!(
+        state.start_resources("bar", false, None, None).unwrap(),
+        (2, Size::gigabytes(1))
+    );
+    assert_eq!(
+        state.start_resources("foo", false, None, None).unwrap(),
+        (4, Size::gigabytes(4))
+    );
+}
+
+#[test]
+fn instances_are_removed_once_they_stop() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        processes,
+        ..
+    } = fixture();
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    state.add_snapshot("golden", "foo", false, false).unwrap();
+    state
+        .set_snapshot_resources("golden", None, Some(2), None)
+        .unwrap();
+    let instances = state.add_instances("golden", true, 2).unwrap();
+    assert_eq!(instances, ["golden-1", "golden-2"]);
+    assert!(matches!(
+        state.start("golden", &StartOptions::default().snapshot(true)),
+        Err(Error::BackingInUse { .. })
+    ));
+
+    for instance in &instances {
+        let snapshot = state.get_snapshot(instance).unwrap();
+        assert_eq!(snapshot.parent.as_deref(), Some("golden"));
+        assert_eq!(snapshot.cores, Some(2));
+        // Not started yet, so not to be reaped either.
+        assert!(state.reap_instances().unwrap().is_empty());
+        state
+            .start(instance, &StartOptions::default().snapshot(true))
+            .unwrap();
+        state.mark_started(instance, true, &[]).unwrap();
+    }
+    let ports = instances
+        .iter()
+        .map(|instance| state.get_snapshot(instance).unwrap().port)
+        .collect::<Vec<_>>();
+    assert_eq!(ports, [8193, 8194]);
+
+    assert!(state.reap_instances().unwrap().is_empty());
+    let pid = state.runtime("golden-1", true).unwrap().unwrap().pid;
+    processes.exit(pid);
+    assert_eq!(state.reap_instances().unwrap(), ["golden-1"]);
+    assert!(state.get_snapshot("golden-1").is_err());
+}
+
+#[test]
+fn namespaced_names_are_stored_flat_and_expand() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        ..
+    } = fixture();
+
+    for name in ["/web", "project//web", "project/../web", "50%"] {
+        assert!(matches!(
+            state.add_machine(name, 8192, Size::gigabytes(32)),
+            Err(Error::InvalidName { .. })
+        ));
+    }
+
+    state
+        .add_machine("project/web-1", 8192, Size::gigabytes(32))
+        .unwrap();
+    state
+        .start("project/web-1", &StartOptions::default())
+        .unwrap();
+    let log = state.log_path("project/web-1", false);
+    assert_eq!(log.file_name().unwrap(), "project%2Fweb-1.log");
+    assert!(state.runtime("project/web-1", false).unwrap().is_some());
+
+    state
+        .add_snapshot("project/base", "project/web-1", false, false)
+        .unwrap();
+    state
+        .add_snapshot("project/child", "project/base", true, false)
+        .unwrap();
+    state
+        .add_snapshot("other", "project/web-1", false, false)
+        .unwrap();
+    let names = state.snapshots().map(|snapshot| snapshot.name.as_str());
+    let names = namespace::expand("project/", names);
+    assert_eq!(
+        state.children_first(names),
+        ["project/child", "project/base"]
+    );
+}
+
+#[test]
+fn patterns_match_names_within_a_namespace() {
+    let names = ["ci-1", "ci-2", "ci-10", "web", "ci/web"];
+    let expand = |arg| namespace::expand(arg, names);
+
+    assert_eq!(expand("ci-*"), ["ci-1", "ci-2", "ci-10"]);
+    assert_eq!(expand("ci-?"), ["ci-1", "ci-2"]);
+    assert_eq!(expand("ci-[!1]"), ["ci-2"]);
+    assert_eq!(expand("*"), ["ci-1", "ci-2", "ci-10", "web"]);
+    assert_eq!(expand("ci/"), ["ci/web"]);
+    assert_eq!(expand("ci*web"), Vec::<String>::new());
+    assert_eq!(expand("missing"), ["missing"]);
+}
+
+#[test]
+fn snapshots_are_addressed_by_reference() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        ..
+    } = fixture();
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    state.add_snapshot("bar", "foo", false, false).unwrap();
+    assert!(matches!(
+        state.add_machine("snapshot/bar", 8193, Size::gigabytes(32)),
+        Err(Error::InvalidName { .. })
+    ));
+
+    assert_eq!(
+        state.resolve("snapshot/b*", false).unwrap(),
+        [ResourceRef::snapshot("bar")]
+    );
+    assert_eq!(
+        state.resolve("bar", true).unwrap(),
+        [ResourceRef::snapshot("bar")]
+    );
+    assert_eq!(
+        state.resolve("machine/foo", true).unwrap(),
+        [ResourceRef::machine("foo")]
+    );
+    assert!(matches!(
+        state.resolve("snapshot/f*", false),
+        Err(Error::NoMatches { .. })
+    ));
+
+    // Stopping a snapshot used to look its name up among the virtual machines.
+    state.mark_started("bar", true, &[]).unwrap();
+    state
+        .start("bar", &StartOptions::default().snapshot(true))
+        .unwrap();
+    state.stop("bar", true).unwrap();
+    assert!(state.runtime("bar", true).unwrap().is_none());
+}
+
+#[test]
+fn console_logs_are_timestamped_rotated_and_filtered() {
+    let Fixture { dir, .. } = fixture();
+    fs::write(
+        dir.path().join("config.toml"),
+        "[timeouts]\nstart_grace = 0\n\n[logs]\ntimestamps = true\nmax_size = \"1K\"\nkeep = 2\n",
+    )
+    .unwrap();
+    let processes = MockProcesses::new();
+    let mut state = State::load(dir.path()).unwrap();
+    state.set_process_backend(Box::new(processes.clone()));
+    state.set_image_backend(Box::new(MockImages::new()));
+
+    // The console reaches the log through QEMU's stdout and `rhea log-writer`.
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    state.start("foo", &StartOptions::default()).unwrap();
+    let spawned = processes.spawned();
+    assert_eq!(spawned.len(), 2);
+    assert_eq!(spawned[0][1], logs::WRITER_SUBCOMMAND);
+    assert!(spawned[0].ends_with(&["--timestamps".into(), "--max-size".into(), "1K".into()]));
+    assert!(spawned[1]
+        .iter()
+        .any(|arg| arg.contains("logfile=/dev/stdout")));
+
+    let path = state.log_path("foo", false);
+    let output = (1..=50).map(|i| format!("line {i}\n")).collect::<String>();
+    let config = Logs {
+        timestamps: true,
+        max_size: Some(Size::bytes(1024)),
+        keep: Some(2),
+    };
+    logs::write(output.as_bytes(), &path, &config).unwrap();
+    assert_eq!(logs::files(&path).len(), 2);
+    let log = fs::read_to_string(&path).unwrap();
+    let first = log.lines().next().unwrap();
+    assert!(logs::timestamp(first.as_bytes()).unwrap() <= time::now_millis());
+
+    let timestamp = time::format_timestamp(1_709_210_096_123);
+    assert_eq!(timestamp, "2024-02-29T12:34:56.123Z");
+    assert_eq!(time::parse_timestamp(&timestamp), Some(1_709_210_096_123));
+
+    let print = |filter| {
+        let mut out = Vec::new();
+        logs::print(&path, filter, false, Duration::ZERO, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    assert_eq!(print(Filter::default()).lines().count(), 50);
+    assert_eq!(print(Filter::new(None, Some("line 4"))).lines().count(), 11);
+    assert_eq!(
+        print(Filter::new(Some(time::now_millis() + 1000), None)),
+        ""
+    );
+}
+
+#[test]
+fn machines_are_copied_into_another_profile() {
+    let Fixture {
+        dir,
+        mut state,
+        images,
+        ..
+    } = fixture();
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    let key = state.generate_key("foo", false).unwrap();
+
+    let path = profile::path(dir.path(), "scratch").unwrap();
+    assert!(path.starts_with(dir.path()));
+    assert_eq!(
+        profile::path(dir.path(), profile::DEFAULT).unwrap(),
+        dir.path()
+    );
+    assert!(matches!(
+        profile::path(dir.path(), "a/b"),
+        Err(Error::InvalidProfile { .. })
+    ));
+
+    let mut target = State::load(&path).unwrap();
+    target.set_image_backend(Box::new(images.clone()));
+    target
+        .add_machine("bar", 8192, Size::gigabytes(32))
+        .unwrap();
+    state.copy_machine("foo", &mut target).unwrap();
+
+    // The copy is moved off the port taken in the other profile, and its key is its own.
+    let copy = target.get_machine("foo").unwrap();
+    assert_eq!(copy.port, 8193);
+    let identity = copy.identity.clone().unwrap();
+    assert!(identity.starts_with(&path));
+    assert_eq!(fs::read(identity).unwrap(), fs::read(key).unwrap());
+    assert!(matches!(
+        state.copy_machine("foo", &mut target),
+        Err(Error::MachineExists { .. })
+    ));
+}
+
+#[test]
+fn starts_only_wait_for_their_own_resources() {
+    let Fixture {
+        dir,
+        mut state,
+        processes,
+        ..
+    } = fixture();
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    state.add_machine("bar", 8193, Size::gigabytes(32)).unwrap();
+    state.add_disk("data", Size::gigabytes(8)).unwrap();
+
+    // Another rhea busy with foo doesn't hold up starting bar with a disk of its own.
+    let lock = state.lock_resource("foo", false).unwrap();
+    assert!(dir.path().join("run/machines/foo.lock").exists());
+    state
+        .start("bar", &StartOptions::default().disks(["data"]))
+        .unwrap();
+    assert_eq!(processes.spawned().len(), 1);
+
+    // Once it is done, foo can be started too.
+    drop(lock);
+    state.start("foo", &StartOptions::default()).unwrap();
+    assert_eq!(processes.spawned().len(), 2);
+}
+
+#[test]
+fn images_are_only_changed_under_their_lock() {
+    let Fixture {
+        dir: _dir,
+        mut state,
+        ..
+    } = fixture();
+
+    state.add_machine("foo", 8192, Size::gigabytes(32)).unwrap();
+    state.add_disk("data", Size::gigabytes(8)).unwrap();
+
+    // Another rhea starting foo with data holds both locks until QEMU has opened the images.
+    let machine = state.lock_resource("foo", false).unwrap();
+    let disk = state.lock_disk("data").unwrap();
+    let starting = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(200));
+        drop((machine, disk));
+    });
+
+    let waited = Instant::now();
+    state
+        .resize_machine("foo", Size::gigabytes(64), false)
+        .unwrap();
+    state
+        .resize_disk("data", Size::gigabytes(16), false)
+        .unwrap();
+    assert!(waited.elapsed() >= Duration::from_millis(200));
+    starting.join().unwrap();
+
+    state.rebuild_machine("foo", None, false).unwrap();
+    assert_eq!(state.get_machine("foo").unwrap().size, Size::gigabytes(64));
+}